@@ -2,13 +2,17 @@ use anyhow::Result;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::Json,
+    response::{IntoResponse, Json, Response},
     routing::{get, post, put},
     Router,
 };
-use common::{LendingReserve, MintAsset, ObligationType, UserObligation};
+use common::{
+    ApiError, CollateralAsset, ErrorKind, ExitLiquidity, LendingReserve, MarginSummary, MintAsset,
+    ObligationHealth, ObligationType, ProtocolState, ProtocolStatus, ResponseEnvelope,
+    RiskParameters, UserObligation, WithdrawLimit,
+};
 use log::{debug, error, info};
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{Pool, Sqlite};
 use tower_http::cors::{Any, CorsLayer};
 
@@ -21,10 +25,20 @@ fn format_rate(rate: u128) -> String {
 pub struct ApiLendingReserve {
     pub protocol_name: String,
     pub market_name: String,
+    pub reserve_pubkey: String,
+    pub market_pubkey: String,
     #[serde(serialize_with = "serialize_token_amount")]
     pub total_supply: u128,
     #[serde(serialize_with = "serialize_token_amount")]
     pub total_borrows: u128,
+    #[serde(serialize_with = "serialize_optional_token_amount")]
+    pub deposit_cap: Option<u128>,
+    #[serde(serialize_with = "serialize_optional_token_amount")]
+    pub borrow_cap: Option<u128>,
+    #[serde(serialize_with = "serialize_optional_token_amount")]
+    pub remaining_deposit_capacity: Option<u128>,
+    #[serde(serialize_with = "serialize_optional_token_amount")]
+    pub remaining_borrow_capacity: Option<u128>,
     #[serde(skip_serializing)]
     pub borrow_rate: String,
     pub supply_rate: String,
@@ -36,21 +50,64 @@ pub struct ApiLendingReserve {
     pub borrow_apy: String,
     #[serde(skip_serializing)]
     pub supply_apy: String,
+    pub reward_apy: Option<String>,
+    pub borrow_reward_apy: Option<String>,
+    pub total_apy: String,
+    pub collateral_assets: Vec<CollateralAsset>,
+    pub exit_liquidity: ApiExitLiquidity,
+    pub risk: RiskParameters,
     #[serde(skip_serializing)]
     pub slot: u64,
 }
 
+#[derive(Serialize)]
+pub struct ApiExitLiquidity {
+    #[serde(serialize_with = "serialize_token_amount")]
+    pub available_liquidity: u128,
+    #[serde(serialize_with = "serialize_token_amount")]
+    pub withdrawable_now: u128,
+    #[serde(serialize_with = "serialize_optional_token_amount")]
+    pub max_outflow: Option<u128>,
+    pub window_seconds: Option<u64>,
+    // Time needed to withdraw all the available liquidity, 0 when it can be withdrawn at once
+    pub seconds_to_withdraw_all: Option<u64>,
+}
+
+impl From<ExitLiquidity> for ApiExitLiquidity {
+    fn from(exit: ExitLiquidity) -> Self {
+        Self {
+            available_liquidity: exit.available_liquidity,
+            withdrawable_now: exit.withdrawable_now,
+            max_outflow: exit.outflow_cap.map(|cap| cap.max_outflow),
+            window_seconds: exit.outflow_cap.map(|cap| cap.window_seconds),
+            seconds_to_withdraw_all: exit.seconds_to_withdraw(exit.available_liquidity),
+        }
+    }
+}
+
 impl From<LendingReserve> for ApiLendingReserve {
     fn from(reserve: LendingReserve) -> Self {
         Self {
             protocol_name: reserve.protocol_name,
             market_name: reserve.market_name,
+            reserve_pubkey: reserve.reserve_pubkey,
+            market_pubkey: reserve.market_pubkey,
             total_supply: reserve.total_supply,
             total_borrows: reserve.total_borrows,
+            deposit_cap: reserve.deposit_cap,
+            borrow_cap: reserve.borrow_cap,
+            remaining_deposit_capacity: reserve.remaining_deposit_capacity,
+            remaining_borrow_capacity: reserve.remaining_borrow_capacity,
             borrow_rate: format_rate(reserve.borrow_rate),
             supply_rate: format_rate(reserve.supply_rate),
             borrow_apy: format_rate(reserve.borrow_apy),
             supply_apy: format_rate(reserve.supply_apy),
+            reward_apy: reserve.reward_apy.map(format_rate),
+            borrow_reward_apy: reserve.borrow_reward_apy.map(format_rate),
+            total_apy: format_rate(reserve.total_apy),
+            collateral_assets: reserve.collateral_assets,
+            exit_liquidity: reserve.exit_liquidity.into(),
+            risk: reserve.risk,
             slot: reserve.slot,
             supply_rate_30d: 0.0,
             supply_rate_7d: 0.0,
@@ -90,6 +147,9 @@ pub struct ApiUserObligation {
     pub mint: String,
     pub protocol_name: String,
     pub market_name: String,
+    pub account: String,
+    pub sub_account: Option<u64>,
+    pub reserve_pubkey: String,
     #[serde(serialize_with = "serialize_dollar_amount")]
     pub amount: (u64, u32), // (amount, mint_decimals)
     pub obligation_type: String,
@@ -107,12 +167,140 @@ impl From<UserObligation> for ApiUserObligation {
             mint: obligation.mint,
             protocol_name: obligation.protocol_name,
             market_name: obligation.market_name,
+            account: obligation.account,
+            sub_account: obligation.sub_account,
+            reserve_pubkey: obligation.reserve_pubkey,
             amount: (obligation.amount, obligation.mint_decimals),
             obligation_type: obligation_type.to_string(),
         }
     }
 }
 
+#[derive(Serialize)]
+pub struct ApiObligationHealth {
+    pub protocol_name: String,
+    pub market_name: String,
+    pub account: String,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub deposited_value: f64,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub borrowed_value: f64,
+    #[serde(serialize_with = "serialize_ratio")]
+    pub loan_to_value: f64,
+    #[serde(serialize_with = "serialize_ratio")]
+    pub max_loan_to_value: f64,
+    #[serde(serialize_with = "serialize_ratio")]
+    pub liquidation_loan_to_value: f64,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub remaining_borrow_value: f64,
+    pub health_factor: Option<f64>,
+    pub elevation_group: Option<u8>,
+    pub initial_health: Option<f64>,
+    pub maintenance_health: Option<f64>,
+    pub max_withdraw: Vec<ApiWithdrawLimit>,
+    pub margin: Option<ApiMarginSummary>,
+}
+
+#[derive(Serialize)]
+pub struct ApiMarginSummary {
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub total_collateral: f64,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub initial_margin_requirement: f64,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub maintenance_margin_requirement: f64,
+    #[serde(serialize_with = "serialize_usd_value")]
+    pub free_collateral: f64,
+    pub health: u8,
+}
+
+impl From<MarginSummary> for ApiMarginSummary {
+    fn from(margin: MarginSummary) -> Self {
+        Self {
+            total_collateral: margin.total_collateral,
+            initial_margin_requirement: margin.initial_margin_requirement,
+            maintenance_margin_requirement: margin.maintenance_margin_requirement,
+            free_collateral: margin.free_collateral,
+            health: margin.health,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiWithdrawLimit {
+    pub symbol: String,
+    #[serde(skip)]
+    pub mint: String,
+    #[serde(serialize_with = "serialize_dollar_amount")]
+    pub amount: (u64, u32), // (amount, mint_decimals)
+}
+
+impl From<WithdrawLimit> for ApiWithdrawLimit {
+    fn from(limit: WithdrawLimit) -> Self {
+        Self { symbol: limit.symbol, mint: limit.mint, amount: (limit.amount, limit.mint_decimals) }
+    }
+}
+
+impl From<ObligationHealth> for ApiObligationHealth {
+    fn from(health: ObligationHealth) -> Self {
+        Self {
+            protocol_name: health.protocol_name,
+            market_name: health.market_name,
+            account: health.account,
+            deposited_value: health.deposited_value,
+            borrowed_value: health.borrowed_value,
+            loan_to_value: health.loan_to_value,
+            max_loan_to_value: health.max_loan_to_value,
+            liquidation_loan_to_value: health.liquidation_loan_to_value,
+            remaining_borrow_value: health.remaining_borrow_value,
+            health_factor: health.health_factor,
+            elevation_group: health.elevation_group,
+            initial_health: health.initial_health,
+            maintenance_health: health.maintenance_health,
+            max_withdraw: health.max_withdraw.into_iter().map(ApiWithdrawLimit::from).collect(),
+            margin: health.margin.map(ApiMarginSummary::from),
+        }
+    }
+}
+
+/// A request chain-api failed, with the status and the error it answered
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub status: StatusCode,
+    pub error: ApiError,
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {}: {}", self.status, self.error.message)
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// Severity of a protocol state, the highest of a protocol's states describes the merged response
+fn severity(state: ProtocolState) -> u8 {
+    match state {
+        ProtocolState::Ok => 0,
+        ProtocolState::Partial => 1,
+        ProtocolState::Stale => 2,
+        ProtocolState::Failed => 3,
+    }
+}
+
+/// Merge the protocol statuses of several responses, keeping the worst status of each protocol
+fn merge_protocols(statuses: impl IntoIterator<Item = ProtocolStatus>) -> Vec<ProtocolStatus> {
+    let mut merged: Vec<ProtocolStatus> = Vec::new();
+    for status in statuses {
+        match merged.iter_mut().find(|merged| merged.protocol == status.protocol) {
+            Some(merged) if severity(status.state) > severity(merged.state) => *merged = status,
+            Some(_) => {}
+            None => merged.push(status),
+        }
+    }
+    merged
+}
+
 #[derive(Clone)]
 pub struct ApiService {
     db_pool: Pool<Sqlite>,
@@ -130,18 +318,33 @@ impl ApiService {
         Ok(Self { db_pool: pool, client })
     }
 
-    pub async fn get_current_markets(&self) -> Result<Vec<ApiMintAsset>> {
+    /// Forward a request to chain-api, turning the error it answers into an [`UpstreamError`]
+    async fn fetch_chain_api<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let response = self.client.get(url).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let status = StatusCode::from_u16(status.as_u16())?;
+            let error = response.json::<ApiError>().await.unwrap_or_else(|e| ApiError {
+                kind: ErrorKind::Other,
+                message: format!("Unexpected response from chain-api: {}", e),
+            });
+            return Err(UpstreamError { status, error }.into());
+        }
+
+        Ok(response.json::<T>().await?)
+    }
+
+    pub async fn get_current_markets(&self) -> Result<ResponseEnvelope<Vec<ApiMintAsset>>> {
         debug!("Fetching current markets from chain-api");
         // Forward request to chain-api
-        let response =
-            self.client.get("http://localhost:3000/current_lending_markets").send().await?;
-
-        let markets = response
-            .json::<Vec<MintAsset>>()
-            .await?
-            .into_iter()
-            .map(ApiMintAsset::from)
-            .collect::<Vec<ApiMintAsset>>();
+        let envelope = self
+            .fetch_chain_api::<ResponseEnvelope<Vec<MintAsset>>>(
+                "http://localhost:3000/current_lending_markets",
+            )
+            .await?;
+        let markets =
+            envelope.data.into_iter().map(ApiMintAsset::from).collect::<Vec<ApiMintAsset>>();
 
         // Get historical market data to populate 7d and 30d averages
         let historical_markets = self.get_historical_markets().await?;
@@ -155,12 +358,20 @@ impl ApiService {
                     .lending_reserves
                     .into_iter()
                     .map(|mut reserve| {
-                        // Find matching historical data
-                        if let Some(historical) = historical_markets.iter().find(|h| {
-                            h.protocol_name == reserve.protocol_name
-                                && h.market_name == reserve.market_name
-                                && h.token_mint == market.mint
-                        }) {
+                        // Find matching historical data, falling back to the names for the rows
+                        // stored before reserves had an identity
+                        let historical = historical_markets
+                            .iter()
+                            .find(|h| h.reserve_pubkey.as_ref() == Some(&reserve.reserve_pubkey))
+                            .or_else(|| {
+                                historical_markets.iter().find(|h| {
+                                    h.reserve_pubkey.is_none()
+                                        && h.protocol_name == reserve.protocol_name
+                                        && h.market_name == reserve.market_name
+                                        && h.token_mint == market.mint
+                                })
+                            });
+                        if let Some(historical) = historical {
                             reserve.supply_rate_7d = historical.supply_rate_7d;
                             reserve.supply_rate_30d = historical.supply_rate_30d;
                         }
@@ -172,16 +383,18 @@ impl ApiService {
             .collect();
 
         info!("Retrieved {} current markets from chain-api", markets.len());
-        Ok(markets)
+        Ok(ResponseEnvelope { data: markets, slot: envelope.slot, protocols: envelope.protocols })
     }
 
     pub async fn get_historical_markets(&self) -> Result<Vec<HistoricalMarketDataAverage>> {
         debug!("Fetching historical markets from database");
-        // Query average supply rates for 7 and 30 day periods
+        // Query average supply rates for 7 and 30 day periods per reserve, with the names of the
+        // reserves table. Rows stored before reserves had an identity are grouped by their names.
         let markets = sqlx::query_as::<_, HistoricalMarketDataAverage>(
             r#"
             WITH recent_data AS (
                 SELECT 
+                    reserve_pubkey,
                     protocol_name,
                     market_name,
                     token_mint,
@@ -194,6 +407,7 @@ impl ApiService {
             ),
             averages AS (
                 SELECT 
+                    reserve_pubkey,
                     protocol_name,
                     market_name,
                     token_mint,
@@ -205,17 +419,22 @@ impl ApiService {
                         THEN CAST(supply_rate AS FLOAT) 
                     END) as supply_rate_7d
                 FROM recent_data
-                GROUP BY protocol_name, market_name, token_mint
+                GROUP BY COALESCE(
+                    reserve_pubkey,
+                    protocol_name || ':' || market_name || ':' || token_mint
+                )
             )
             SELECT 
-                protocol_name,
-                market_name,
-                token_name,
-                token_symbol,
-                token_mint,
-                supply_rate_7d,
-                supply_rate_30d
-            FROM averages
+                a.reserve_pubkey,
+                COALESCE(r.protocol_name, a.protocol_name) AS protocol_name,
+                COALESCE(r.market_name, a.market_name) AS market_name,
+                COALESCE(r.token_name, a.token_name) AS token_name,
+                COALESCE(r.token_symbol, a.token_symbol) AS token_symbol,
+                a.token_mint,
+                a.supply_rate_7d,
+                a.supply_rate_30d
+            FROM averages a
+            LEFT JOIN reserves r ON r.reserve_pubkey = a.reserve_pubkey
             ORDER BY protocol_name, market_name
             "#,
         )
@@ -226,59 +445,91 @@ impl ApiService {
         Ok(markets)
     }
 
-    pub async fn get_user_obligations(&self, pubkey: &str) -> Result<Vec<ApiUserObligation>> {
+    pub async fn get_user_obligations(
+        &self,
+        pubkey: &str,
+    ) -> Result<ResponseEnvelope<Vec<ApiUserObligation>>> {
         debug!("Fetching user obligations from chain-api for pubkey: {}", pubkey);
 
         // Forward request to chain-api
         let url = format!("http://localhost:3000/obligations/{}", pubkey);
-        let response = self.client.get(&url).send().await?;
+        let obligations: ResponseEnvelope<Vec<ApiUserObligation>> = self
+            .fetch_chain_api::<ResponseEnvelope<Vec<UserObligation>>>(&url)
+            .await
+            .inspect_err(|e| error!("Failed to fetch obligations: {}", e))?
+            .map(|obligations| obligations.into_iter().map(ApiUserObligation::from).collect());
 
-        if !response.status().is_success() {
-            error!("Failed to fetch obligations: HTTP {}", response.status());
-            return Err(anyhow::anyhow!("Failed to fetch obligations: HTTP {}", response.status()));
-        }
+        info!("Retrieved {} obligations for pubkey {}", obligations.data.len(), pubkey);
+        Ok(obligations)
+    }
 
-        let obligations = response
-            .json::<Vec<UserObligation>>()
-            .await?
-            .into_iter()
-            .map(ApiUserObligation::from)
-            .collect::<Vec<ApiUserObligation>>();
+    pub async fn get_obligation_health(
+        &self,
+        pubkey: &str,
+    ) -> Result<ResponseEnvelope<Vec<ApiObligationHealth>>> {
+        debug!("Fetching obligation health from chain-api for pubkey: {}", pubkey);
 
-        info!("Retrieved {} obligations for pubkey {}", obligations.len(), pubkey);
-        Ok(obligations)
+        // Forward request to chain-api
+        let url = format!("http://localhost:3000/obligation_health/{}", pubkey);
+        let health: ResponseEnvelope<Vec<ApiObligationHealth>> = self
+            .fetch_chain_api::<ResponseEnvelope<Vec<ObligationHealth>>>(&url)
+            .await
+            .inspect_err(|e| error!("Failed to fetch obligation health: {}", e))?
+            .map(|health| health.into_iter().map(ApiObligationHealth::from).collect());
+
+        info!("Retrieved health for {} accounts of pubkey {}", health.data.len(), pubkey);
+        Ok(health)
     }
 
-    pub async fn get_wallet_balances(&self, pubkey: &str) -> Result<Vec<common::TokenBalance>> {
+    pub async fn get_wallet_balances(
+        &self,
+        pubkey: &str,
+    ) -> Result<ResponseEnvelope<Vec<common::TokenBalance>>> {
         debug!("Fetching wallet balances from chain-api for pubkey: {}", pubkey);
 
         // Forward request to chain-api
         let url = format!("http://localhost:3000/wallet_balance/{}", pubkey);
-        let response = self.client.get(&url).send().await?;
+        let balances = self
+            .fetch_chain_api::<ResponseEnvelope<Vec<common::TokenBalance>>>(&url)
+            .await
+            .inspect_err(|e| error!("Failed to fetch wallet balances: {}", e))?;
 
-        if !response.status().is_success() {
-            error!("Failed to fetch wallet balances: HTTP {}", response.status());
-            return Err(anyhow::anyhow!(
-                "Failed to fetch wallet balances: HTTP {}",
-                response.status()
-            ));
-        }
-
-        let balances = response.json::<Vec<common::TokenBalance>>().await?;
-        info!("Retrieved {} token balances for pubkey {}", balances.len(), pubkey);
+        info!("Retrieved {} token balances for pubkey {}", balances.data.len(), pubkey);
         Ok(balances)
     }
 
-    pub async fn get_wallet_data(&self, pubkey: &str) -> Result<WalletData> {
-        // Get both wallet balances and positions in parallel
-        let (balances, positions) =
-            tokio::join!(self.get_wallet_balances(pubkey), self.get_user_obligations(pubkey));
+    /// Balances, positions and health of a wallet, with the statuses of the protocols merged
+    /// over the positions and their health
+    pub async fn get_wallet_data(&self, pubkey: &str) -> Result<ResponseEnvelope<WalletData>> {
+        // Get wallet balances, positions and their health in parallel
+        let (balances, positions, health) = tokio::join!(
+            self.get_wallet_balances(pubkey),
+            self.get_user_obligations(pubkey),
+            self.get_obligation_health(pubkey)
+        );
 
         // Convert common::TokenBalance to ApiTokenBalance
         let api_balances =
-            balances?.into_iter().map(ApiTokenBalance::from).collect::<Vec<ApiTokenBalance>>();
+            balances?.data.into_iter().map(ApiTokenBalance::from).collect::<Vec<ApiTokenBalance>>();
+        let positions = positions?;
+
+        // Health is informational, don't fail the whole wallet view without it
+        let (wallet_health, health_protocols) = match health {
+            Ok(health) => (health.data, health.protocols),
+            Err(e) => {
+                error!("Error fetching obligation health for pubkey {}: {}", pubkey, e);
+                (Vec::new(), Vec::new())
+            }
+        };
 
-        Ok(WalletData { wallet_balances: api_balances, wallet_positions: positions? })
+        Ok(ResponseEnvelope {
+            data: WalletData {
+                wallet_balances: api_balances,
+                wallet_accounts: group_by_account(positions.data, wallet_health),
+            },
+            slot: positions.slot,
+            protocols: merge_protocols(positions.protocols.into_iter().chain(health_protocols)),
+        })
     }
 }
 
@@ -310,6 +561,8 @@ pub struct HistoricalMarketData {
 
 #[derive(serde::Serialize, sqlx::FromRow)]
 pub struct HistoricalMarketDataAverage {
+    /// None for the rows stored before reserves had an identity
+    pub reserve_pubkey: Option<String>,
     pub protocol_name: String,
     pub market_name: String,
     pub token_name: String,
@@ -342,6 +595,33 @@ where
     serializer.serialize_str(&amount_f64.to_string())
 }
 
+fn serialize_optional_token_amount<S>(
+    amount: &Option<u128>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match amount {
+        Some(amount) => serialize_token_amount(amount, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_usd_value<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format!("{:.2}", value))
+}
+
+fn serialize_ratio<S>(ratio: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format!("{:.4}", ratio))
+}
+
 fn serialize_dollar_amount<S>(amount_data: &(u64, u32), serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -371,32 +651,63 @@ pub async fn create_router(service: ApiService) -> Router {
         .with_state(service)
 }
 
+/// A failed request, answered with its status and the kind of the error
+struct ErrorResponse {
+    status: StatusCode,
+    error: ApiError,
+}
+
+impl From<anyhow::Error> for ErrorResponse {
+    /// Forward the status of chain-api's client errors, other failures are internal errors
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<UpstreamError>() {
+            Ok(UpstreamError { status, error }) if status.is_client_error() => {
+                Self { status, error }
+            }
+            Ok(UpstreamError { error, .. }) => {
+                Self { status: StatusCode::INTERNAL_SERVER_ERROR, error }
+            }
+            // The error is logged by the handler, its text may hold database or upstream details
+            Err(_) => Self {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                error: ApiError::from(ErrorKind::Other),
+            },
+        }
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.error)).into_response()
+    }
+}
+
 async fn get_current_markets(
     State(service): State<ApiService>,
-) -> (StatusCode, Json<Vec<ApiMintAsset>>) {
+) -> Result<Json<ResponseEnvelope<Vec<ApiMintAsset>>>, ErrorResponse> {
     match service.get_current_markets().await {
         Ok(markets) => {
-            info!("Successfully returned {} current markets", markets.len());
-            (StatusCode::OK, Json(markets))
+            info!("Successfully returned {} current markets", markets.data.len());
+            Ok(Json(markets))
         }
         Err(e) => {
             error!("Error fetching current markets: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+            Err(e.into())
         }
     }
 }
 
 async fn get_historical_markets(
     State(service): State<ApiService>,
-) -> (StatusCode, Json<Vec<HistoricalMarketDataAverage>>) {
+) -> Result<Json<Vec<HistoricalMarketDataAverage>>, ErrorResponse> {
     match service.get_historical_markets().await {
         Ok(markets) => {
             info!("Successfully returned {} historical market entries", markets.len());
-            (StatusCode::OK, Json(markets))
+            Ok(Json(markets))
         }
         Err(e) => {
             error!("Error fetching historical markets: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+            Err(e.into())
         }
     }
 }
@@ -404,23 +715,20 @@ async fn get_historical_markets(
 async fn get_wallet_data(
     State(service): State<ApiService>,
     Path(pubkey): Path<String>,
-) -> (StatusCode, Json<WalletData>) {
+) -> Result<Json<ResponseEnvelope<WalletData>>, ErrorResponse> {
     match service.get_wallet_data(&pubkey).await {
         Ok(wallet_data) => {
             info!(
-                "Successfully returned wallet data for pubkey {}: {} balances, {} positions",
+                "Successfully returned wallet data for pubkey {}: {} balances, {} accounts",
                 pubkey,
-                wallet_data.wallet_balances.len(),
-                wallet_data.wallet_positions.len()
+                wallet_data.data.wallet_balances.len(),
+                wallet_data.data.wallet_accounts.len()
             );
-            (StatusCode::OK, Json(wallet_data))
+            Ok(Json(wallet_data))
         }
         Err(e) => {
             error!("Error fetching wallet data for pubkey {}: {}", pubkey, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(WalletData { wallet_balances: vec![], wallet_positions: vec![] }),
-            )
+            Err(e.into())
         }
     }
 }
@@ -428,15 +736,19 @@ async fn get_wallet_data(
 async fn get_user_obligations(
     State(service): State<ApiService>,
     Path(pubkey): Path<String>,
-) -> (StatusCode, Json<Vec<ApiUserObligation>>) {
+) -> Result<Json<ResponseEnvelope<Vec<ApiUserObligation>>>, ErrorResponse> {
     match service.get_user_obligations(&pubkey).await {
         Ok(obligations) => {
-            info!("Successfully returned {} obligations for pubkey {}", obligations.len(), pubkey);
-            (StatusCode::OK, Json(obligations))
+            info!(
+                "Successfully returned {} obligations for pubkey {}",
+                obligations.data.len(),
+                pubkey
+            );
+            Ok(Json(obligations))
         }
         Err(e) => {
             error!("Error fetching obligations for pubkey {}: {}", pubkey, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+            Err(e.into())
         }
     }
 }
@@ -444,7 +756,66 @@ async fn get_user_obligations(
 #[derive(serde::Serialize)]
 pub struct WalletData {
     pub wallet_balances: Vec<ApiTokenBalance>,
-    pub wallet_positions: Vec<ApiUserObligation>,
+    pub wallet_accounts: Vec<ApiWalletAccount>,
+}
+
+/// Positions and health of one obligation, margin account or Drift sub-account of the wallet
+#[derive(serde::Serialize)]
+pub struct ApiWalletAccount {
+    pub protocol_name: String,
+    pub market_name: String,
+    pub account: String,
+    pub sub_account: Option<u64>,
+    pub positions: Vec<ApiUserObligation>,
+    pub health: Option<ApiObligationHealth>,
+}
+
+/// Market of the account holding a position. Drift positions are named after their spot market,
+/// so their account is named after its sub-account instead.
+fn account_market_name(position: &ApiUserObligation) -> String {
+    match (position.protocol_name.as_str(), position.sub_account) {
+        ("Drift", Some(sub_account)) => format!("Drift sub-account {}", sub_account),
+        _ => position.market_name.clone(),
+    }
+}
+
+/// Group the positions with the health of their account, keeping the order accounts first
+/// appear in. Accounts with health but no position left are kept too.
+fn group_by_account(
+    positions: Vec<ApiUserObligation>,
+    health: Vec<ApiObligationHealth>,
+) -> Vec<ApiWalletAccount> {
+    let mut accounts: Vec<ApiWalletAccount> = Vec::new();
+
+    for position in positions {
+        match accounts.iter_mut().find(|a| a.account == position.account) {
+            Some(account) => account.positions.push(position),
+            None => accounts.push(ApiWalletAccount {
+                protocol_name: position.protocol_name.clone(),
+                market_name: account_market_name(&position),
+                account: position.account.clone(),
+                sub_account: position.sub_account,
+                positions: vec![position],
+                health: None,
+            }),
+        }
+    }
+
+    for health in health {
+        match accounts.iter_mut().find(|a| a.account == health.account) {
+            Some(account) => account.health = Some(health),
+            None => accounts.push(ApiWalletAccount {
+                protocol_name: health.protocol_name.clone(),
+                market_name: health.market_name.clone(),
+                account: health.account.clone(),
+                sub_account: None,
+                positions: Vec::new(),
+                health: Some(health),
+            }),
+        }
+    }
+
+    accounts
 }
 
 #[derive(serde::Serialize)]
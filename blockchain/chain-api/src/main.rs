@@ -1,97 +1,275 @@
+mod snapshot;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
-use common::{MintAsset, TokenBalance, UserObligation};
+use common::{
+    ApiError, ErrorKind, MintAsset, ObligationHealth, RateImpact, ResponseEnvelope, RpcCounters,
+    RpcEndpointHealth, TokenBalance, UnsignedTransaction, UserObligation,
+};
+use log::error;
+use serde::Deserialize;
+use snapshot::{duration_from_env, MarketRefresher, MarketSnapshot, RefreshConfig};
 use sol_interface::{
-    aggregator::client::LendingMarketAggregator, common::client_trait::ClientError,
+    aggregator::simulate::LiquidityAction,
+    common::{
+        client_trait::ClientError,
+        rpc_utils::{probe_rpc_endpoints, rpc_counters, rpc_endpoint_health},
+    },
+    transactions::TransactionAction,
 };
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::{sync::Arc, time::Duration};
+
+/// A deposit or borrow of `amount` native tokens into the reserve of `mint` in a market
+#[derive(Deserialize)]
+struct RateImpactQuery {
+    mint: String,
+    protocol: String,
+    market: String,
+    action: LiquidityAction,
+    amount: u64,
+}
+
+/// A deposit, withdrawal, borrow or repay of `amount` native tokens of `mint` by `wallet`, from
+/// the Drift sub-account `sub_account` when given
+#[derive(Deserialize)]
+struct TransactionQuery {
+    wallet: String,
+    mint: String,
+    protocol: String,
+    market: String,
+    sub_account: Option<u16>,
+    action: TransactionAction,
+    amount: u64,
+}
+
+/// `refresh=true` reloads the markets instead of serving the latest snapshot
+#[derive(Deserialize)]
+struct MarketsQuery {
+    #[serde(default)]
+    refresh: bool,
+}
+
+/// A failed request, answered with its status and the kind of the error
+struct ErrorResponse {
+    status: StatusCode,
+    error: ApiError,
+}
+
+impl ErrorResponse {
+    /// Answers with the fixed message of the error's kind, the error itself is only logged
+    fn new(status: StatusCode, error: &ClientError) -> Self {
+        Self { status, error: ApiError::from(error.kind()) }
+    }
+}
+
+impl From<ClientError> for ErrorResponse {
+    fn from(error: ClientError) -> Self {
+        let status = match error.kind() {
+            ErrorKind::InvalidPubkey => StatusCode::BAD_REQUEST,
+            ErrorKind::MarketNotFound | ErrorKind::AccountNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, &error)
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.error)).into_response()
+    }
+}
 
 #[derive(Clone)]
 struct LendingService {
-    aggregator: Arc<RwLock<LendingMarketAggregator>>,
+    /// One RPC URL, or several weighted URLs to fail over between
+    rpc_url: String,
+    markets: Arc<MarketRefresher>,
 }
 
 impl LendingService {
-    pub fn new() -> Self {
-        let rpc_url = std::env::var("RPC_URL")
-            .unwrap_or_else(|_| "https://api.mainnet-beta.solana.com".to_string());
+    pub fn new(rpc_url: &str) -> Self {
+        Self { markets: Arc::new(MarketRefresher::new(rpc_url)), rpc_url: rpc_url.to_string() }
+    }
 
-        Self { aggregator: Arc::new(RwLock::new(LendingMarketAggregator::new(&rpc_url))) }
+    pub async fn get_current_lending_markets(
+        &self,
+        refresh: bool,
+    ) -> Result<Arc<MarketSnapshot>, ClientError> {
+        if refresh {
+            self.markets.refresh().await
+        } else {
+            self.markets.loaded().await
+        }
     }
 
-    pub async fn get_current_lending_markets(&self) -> Result<Vec<MintAsset>, ClientError> {
-        let mut aggregator = self.aggregator.write().await;
+    pub async fn simulate_rate_impact(
+        &self,
+        query: &RateImpactQuery,
+    ) -> Result<RateImpact, ClientError> {
+        let snapshot = self.markets.loaded().await?;
 
-        aggregator.load_markets_async().await?;
+        snapshot.aggregator.simulate_rate_impact(
+            &query.mint,
+            &query.protocol,
+            &query.market,
+            query.action,
+            query.amount,
+        )
+    }
 
-        let assets = aggregator.assets.values().cloned().collect();
+    pub async fn build_transaction(
+        &self,
+        query: &TransactionQuery,
+    ) -> Result<UnsignedTransaction, ClientError> {
+        let snapshot = self.markets.loaded().await?;
 
-        Ok(assets)
+        snapshot
+            .aggregator
+            .build_transaction(
+                &query.wallet,
+                &query.mint,
+                &query.protocol,
+                &query.market,
+                query.sub_account,
+                query.action,
+                query.amount,
+            )
+            .await
     }
 
     pub async fn get_user_obligations(
         &self,
         pubkey: &str,
-    ) -> Result<Vec<UserObligation>, ClientError> {
-        let aggregator = self.aggregator.read().await;
-        aggregator.get_user_obligations_async(pubkey).await
+    ) -> Result<ResponseEnvelope<Vec<UserObligation>>, ClientError> {
+        let snapshot = self.markets.loaded().await?;
+        snapshot.aggregator.get_user_obligations_async(pubkey).await
+    }
+
+    pub async fn get_obligation_health(
+        &self,
+        pubkey: &str,
+    ) -> Result<ResponseEnvelope<Vec<ObligationHealth>>, ClientError> {
+        let snapshot = self.markets.loaded().await?;
+        snapshot.aggregator.get_obligation_health_async(pubkey).await
     }
 
     pub async fn get_wallet_token_balances(
         &self,
         wallet_pubkey: &str,
-    ) -> Result<Vec<TokenBalance>, ClientError> {
-        let aggregator = self.aggregator.read().await;
-
-        // Call the fetch_wallet_token_balances method and convert any errors to ClientError
-        aggregator
-            .fetch_wallet_token_balances(wallet_pubkey)
-            .await
-            .map_err(|e| ClientError::Other(format!("Failed to fetch wallet balances: {}", e)))
+    ) -> Result<ResponseEnvelope<Vec<TokenBalance>>, ClientError> {
+        let snapshot = self.markets.loaded().await?;
+        snapshot.aggregator.fetch_wallet_token_balances(wallet_pubkey).await
     }
 }
 
+/// Assets of the latest market snapshot with the status of every protocol, and the slot it was read
+/// at and its age in milliseconds in the `x-market-slot` and `x-market-age-ms` headers
 async fn get_current_lending_markets(
     State(service): State<LendingService>,
-) -> (StatusCode, Json<Vec<MintAsset>>) {
-    match service.get_current_lending_markets().await {
-        Ok(markets) => (StatusCode::OK, Json(markets)),
-        Err(e) => {
-            eprintln!("Error fetching assets: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
-        }
-    }
+    Query(query): Query<MarketsQuery>,
+) -> Result<(HeaderMap, Json<ResponseEnvelope<Vec<MintAsset>>>), ErrorResponse> {
+    let snapshot = service.get_current_lending_markets(query.refresh).await.map_err(|e| {
+        error!("Error fetching assets: {}", e);
+        e
+    })?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-market-slot", HeaderValue::from(snapshot.slot()));
+    headers.insert("x-market-age-ms", HeaderValue::from(snapshot.age().as_millis() as u64));
+
+    Ok((
+        headers,
+        Json(ResponseEnvelope {
+            data: snapshot.aggregator.assets.values().cloned().collect(),
+            slot: Some(snapshot.slot()),
+            protocols: snapshot.aggregator.protocols.clone(),
+        }),
+    ))
+}
+
+async fn simulate_rate_impact(
+    State(service): State<LendingService>,
+    Query(query): Query<RateImpactQuery>,
+) -> Result<Json<RateImpact>, ErrorResponse> {
+    service.simulate_rate_impact(&query).await.map(Json).map_err(|e| {
+        error!("Error simulating rate impact: {}", e);
+        let status = match e {
+            ClientError::MarketNotFound(_) => StatusCode::NOT_FOUND,
+            ClientError::ProtocolError(_) | ClientError::Other(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ErrorResponse::new(status, &e)
+    })
+}
+
+async fn build_transaction(
+    State(service): State<LendingService>,
+    Query(query): Query<TransactionQuery>,
+) -> Result<Json<UnsignedTransaction>, ErrorResponse> {
+    service.build_transaction(&query).await.map(Json).map_err(|e| {
+        error!("Error building {} transaction for {}: {}", query.action, query.wallet, e);
+        let status = match e {
+            ClientError::MarketNotFound(_) | ClientError::AccountNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            ClientError::InvalidPubkey(_) | ClientError::ProtocolError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ErrorResponse::new(status, &e)
+    })
 }
 
 async fn get_user_obligations(
     State(service): State<LendingService>,
     Path(pubkey): Path<String>,
-) -> (StatusCode, Json<Vec<UserObligation>>) {
-    match service.get_user_obligations(&pubkey).await {
-        Ok(obligations) => (StatusCode::OK, Json(obligations)),
-        Err(e) => {
-            eprintln!("Error fetching obligations: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
-        }
-    }
+) -> Result<Json<ResponseEnvelope<Vec<UserObligation>>>, ErrorResponse> {
+    service.get_user_obligations(&pubkey).await.map(Json).map_err(|e| {
+        error!("Error fetching obligations: {}", e);
+        e.into()
+    })
+}
+
+async fn get_obligation_health(
+    State(service): State<LendingService>,
+    Path(pubkey): Path<String>,
+) -> Result<Json<ResponseEnvelope<Vec<ObligationHealth>>>, ErrorResponse> {
+    service.get_obligation_health(&pubkey).await.map(Json).map_err(|e| {
+        error!("Error fetching obligation health for {}: {}", pubkey, e);
+        e.into()
+    })
 }
 
 async fn get_wallet_balance(
     State(service): State<LendingService>,
     Path(pubkey): Path<String>,
-) -> (StatusCode, Json<Vec<TokenBalance>>) {
-    match service.get_wallet_token_balances(&pubkey).await {
-        Ok(balances) => (StatusCode::OK, Json(balances)),
-        Err(e) => {
-            eprintln!("Error fetching wallet balances for {}: {}", pubkey, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
-        }
+) -> Result<Json<ResponseEnvelope<Vec<TokenBalance>>>, ErrorResponse> {
+    service.get_wallet_token_balances(&pubkey).await.map(Json).map_err(|e| {
+        error!("Error fetching wallet balances for {}: {}", pubkey, e);
+        e.into()
+    })
+}
+
+async fn get_rpc_health(State(service): State<LendingService>) -> Json<Vec<RpcEndpointHealth>> {
+    Json(rpc_endpoint_health(&service.rpc_url))
+}
+
+async fn get_rpc_counters() -> Json<RpcCounters> {
+    Json(rpc_counters())
+}
+
+/// Probes the RPC endpoints forever so endpoints that stopped getting requests after failing can
+/// recover
+async fn probe_rpc(rpc_url: String, interval: Duration) {
+    loop {
+        probe_rpc_endpoints(&rpc_url).await;
+        tokio::time::sleep(interval).await;
     }
 }
 
@@ -104,7 +282,14 @@ async fn root() -> &'static str {
 async fn main() {
     env_logger::init();
 
-    let service = LendingService::new();
+    let rpc_url = std::env::var("RPC_URL")
+        .unwrap_or_else(|_| "https://api.mainnet-beta.solana.com".to_string());
+    let service = LendingService::new(&rpc_url);
+    tokio::spawn(service.markets.clone().run(RefreshConfig::from_env()));
+    tokio::spawn(probe_rpc(
+        service.rpc_url.clone(),
+        duration_from_env("RPC_HEALTH_INTERVAL_SECS", 15),
+    ));
 
     // build our application with a route
     let app = Router::new()
@@ -112,8 +297,13 @@ async fn main() {
         .route("/", get(root))
         // `POST /users` goes to `create_user`
         .route("/current_lending_markets", get(get_current_lending_markets))
+        .route("/rate_impact", get(simulate_rate_impact))
+        .route("/transaction", get(build_transaction))
         .route("/obligations/{pubkey}", get(get_user_obligations))
+        .route("/obligation_health/{pubkey}", get(get_obligation_health))
         .route("/wallet_balance/{pubkey}", get(get_wallet_balance))
+        .route("/rpc_health", get(get_rpc_health))
+        .route("/rpc_counters", get(get_rpc_counters))
         .with_state(service);
 
     // run our app with hyper, listening globally on port 3000
@@ -121,3 +311,55 @@ async fn main() {
     println!("Server listening on http://0.0.0.0:3000");
     axum::serve(listener, app).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_rpc::{assert_golden, REPLAY_PREFIX};
+    use std::path::PathBuf;
+
+    /// The mainnet capture recorded by sol-interface's `record_mainnet_fixture`
+    fn capture_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../sol-interface/fixtures/mainnet")
+            .join(name)
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/mainnet").join(name)
+    }
+
+    fn service() -> LendingService {
+        LendingService::new(&format!("{}{}", REPLAY_PREFIX, capture_path("replay.json").display()))
+    }
+
+    #[tokio::test]
+    #[ignore = "replays sol-interface/fixtures/mainnet/replay.json"]
+    async fn test_current_lending_markets_match_golden() {
+        let query = MarketsQuery { refresh: false };
+        let Ok((headers, Json(mut response))) =
+            get_current_lending_markets(State(service()), Query(query)).await
+        else {
+            panic!("markets failed to load from the fixture");
+        };
+
+        assert_eq!(headers["x-market-slot"], response.slot.unwrap().to_string());
+        response.data.sort_by(|a, b| a.mint.cmp(&b.mint));
+        assert_golden(golden_path("current_lending_markets.golden.json"), &response);
+    }
+
+    #[tokio::test]
+    #[ignore = "replays sol-interface/fixtures/mainnet/replay.json"]
+    async fn test_obligations_match_golden() {
+        let wallet = std::fs::read_to_string(capture_path("wallet.txt")).unwrap();
+        let service = service();
+        assert!(service.get_current_lending_markets(false).await.is_ok());
+
+        let Ok(Json(response)) =
+            get_user_obligations(State(service), Path(wallet.trim().to_string())).await
+        else {
+            panic!("obligations failed to load from the fixture");
+        };
+        assert_golden(golden_path("obligations.golden.json"), &response);
+    }
+}
@@ -0,0 +1,146 @@
+use log::{info, warn};
+use sol_interface::{
+    aggregator::client::LendingMarketAggregator,
+    common::{client_trait::ClientError, rpc_utils::flush_rpc_recording},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{watch, Mutex};
+
+/// Markets loaded at one point in time. A snapshot is never modified once published, refreshes
+/// publish a new one.
+pub struct MarketSnapshot {
+    pub aggregator: LendingMarketAggregator,
+    /// When the load of the markets started, None for the empty snapshot served before the first
+    /// load
+    pub fetched_at: Option<Instant>,
+}
+
+impl MarketSnapshot {
+    pub fn is_loaded(&self) -> bool {
+        self.fetched_at.is_some()
+    }
+
+    /// Slot the markets were read at
+    pub fn slot(&self) -> u64 {
+        self.aggregator.slot
+    }
+
+    /// Time since the load of the markets started
+    pub fn age(&self) -> Duration {
+        self.fetched_at.map(|fetched_at| fetched_at.elapsed()).unwrap_or_default()
+    }
+}
+
+/// How often the markets are reloaded in the background
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshConfig {
+    /// Delay between two successful refreshes
+    pub interval: Duration,
+    /// Delay before retrying a failed refresh
+    pub retry_interval: Duration,
+}
+
+impl RefreshConfig {
+    /// Reads `MARKET_REFRESH_INTERVAL_SECS` and `MARKET_REFRESH_RETRY_SECS`, defaulting to 60 and
+    /// 10 seconds
+    pub fn from_env() -> Self {
+        Self {
+            interval: duration_from_env("MARKET_REFRESH_INTERVAL_SECS", 60),
+            retry_interval: duration_from_env("MARKET_REFRESH_RETRY_SECS", 10),
+        }
+    }
+}
+
+pub fn duration_from_env(name: &str, default_secs: u64) -> Duration {
+    let secs = match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Invalid {} {:?}, using {} seconds", name, value, default_secs);
+            default_secs
+        }),
+        Err(_) => default_secs,
+    };
+
+    Duration::from_secs(secs)
+}
+
+/// Loads market snapshots and publishes the latest one. Readers take the current snapshot without
+/// waiting on a refresh in progress.
+pub struct MarketRefresher {
+    rpc_url: String,
+    snapshot: watch::Sender<Arc<MarketSnapshot>>,
+    /// Held while markets load so concurrent refreshes do not load them twice
+    loading: Mutex<()>,
+}
+
+impl MarketRefresher {
+    pub fn new(rpc_url: &str) -> Self {
+        let empty =
+            MarketSnapshot { aggregator: LendingMarketAggregator::new(rpc_url), fetched_at: None };
+
+        Self {
+            rpc_url: rpc_url.to_string(),
+            snapshot: watch::Sender::new(Arc::new(empty)),
+            loading: Mutex::new(()),
+        }
+    }
+
+    /// The latest published snapshot
+    pub fn current(&self) -> Arc<MarketSnapshot> {
+        self.snapshot.borrow().clone()
+    }
+
+    /// The latest snapshot, loading the markets first when they never were
+    pub async fn loaded(&self) -> Result<Arc<MarketSnapshot>, ClientError> {
+        let snapshot = self.current();
+        if snapshot.is_loaded() {
+            return Ok(snapshot);
+        }
+
+        self.refresh().await
+    }
+
+    /// Loads the markets and publishes them. A caller waiting on a refresh that started after its
+    /// call gets that refresh's snapshot instead of loading again.
+    pub async fn refresh(&self) -> Result<Arc<MarketSnapshot>, ClientError> {
+        let requested_at = Instant::now();
+        let _loading = self.loading.lock().await;
+
+        let current = self.current();
+        if current.fetched_at.is_some_and(|fetched_at| fetched_at >= requested_at) {
+            return Ok(current);
+        }
+
+        let fetched_at = Instant::now();
+        let mut aggregator = LendingMarketAggregator::new(&self.rpc_url);
+        let loaded = aggregator.load_markets_async().await;
+        flush_rpc_recording();
+        loaded?;
+        if current.is_loaded() {
+            aggregator.keep_stale_reserves(&current.aggregator);
+        }
+
+        let snapshot = Arc::new(MarketSnapshot { aggregator, fetched_at: Some(fetched_at) });
+        self.snapshot.send_replace(snapshot.clone());
+        info!("Published markets at slot {} loaded in {:?}", snapshot.slot(), fetched_at.elapsed());
+
+        Ok(snapshot)
+    }
+
+    /// Refreshes the markets forever, waiting `config.interval` after a refresh and
+    /// `config.retry_interval` after a failure
+    pub async fn run(self: Arc<Self>, config: RefreshConfig) {
+        loop {
+            let delay = match self.refresh().await {
+                Ok(_) => config.interval,
+                Err(e) => {
+                    warn!("Failed to refresh markets: {}", e);
+                    config.retry_interval
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
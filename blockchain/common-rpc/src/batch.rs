@@ -1,5 +1,7 @@
+use crate::retry::{with_retry, RetryPolicy};
 use crate::RpcError;
-use solana_client::rpc_client::RpcClient;
+use futures::future::try_join_all;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{account::Account, pubkey::Pubkey};
 use std::collections::HashMap;
 
@@ -8,45 +10,50 @@ pub const DEFAULT_BATCH_SIZE: usize = 100;
 
 /// Fetch multiple accounts in batches to avoid RPC request size limits
 ///
-/// This function splits the pubkeys into smaller batches and makes multiple
-/// RPC calls if necessary, combining the results into a single HashMap.
-pub fn get_multiple_accounts_batched(
+/// This function splits the pubkeys into smaller batches and sends the RPC calls
+/// concurrently, combining the results into a single HashMap. Each batch is retried on its own
+/// as the policy says.
+pub async fn get_multiple_accounts_batched(
     client: &RpcClient,
     pubkeys: &[Pubkey],
     batch_size: usize,
+    retry_policy: &RetryPolicy,
 ) -> Result<HashMap<Pubkey, Account>, RpcError> {
-    let mut accounts = HashMap::with_capacity(pubkeys.len());
-
     // Process in batches to avoid RPC request size limits
-    for chunk in pubkeys.chunks(batch_size) {
+    let batches = try_join_all(pubkeys.chunks(batch_size).map(|chunk| async move {
         let batch_accounts =
-            client.get_multiple_accounts(chunk).map_err(|e| RpcError::RpcError(Box::new(e)))?;
+            with_retry(retry_policy, &client.url(), || client.get_multiple_accounts(chunk))
+                .await
+                .map_err(|e| RpcError::RpcError(Box::new(e)))?;
+        Ok::<_, RpcError>(chunk.iter().zip(batch_accounts))
+    }))
+    .await?;
 
-        for (i, account_option) in batch_accounts.into_iter().enumerate() {
-            if let Some(account) = account_option {
-                accounts.insert(chunk[i], account);
-            }
+    let mut accounts = HashMap::with_capacity(pubkeys.len());
+    for (pubkey, account_option) in batches.into_iter().flatten() {
+        if let Some(account) = account_option {
+            accounts.insert(*pubkey, account);
         }
     }
 
     Ok(accounts)
 }
 
-/// Fetch multiple accounts in batches with a default batch size
-pub fn get_multiple_accounts(
+/// Fetch multiple accounts in batches with a default batch size and retry policy
+pub async fn get_multiple_accounts(
     client: &RpcClient,
     pubkeys: &[Pubkey],
 ) -> Result<HashMap<Pubkey, Account>, RpcError> {
-    get_multiple_accounts_batched(client, pubkeys, DEFAULT_BATCH_SIZE)
+    get_multiple_accounts_batched(client, pubkeys, DEFAULT_BATCH_SIZE, &RetryPolicy::global()).await
 }
 
 /// Fetch multiple accounts in batches and convert the error type
-pub fn get_multiple_accounts_with_conversion<E, C>(
+pub async fn get_multiple_accounts_with_conversion<E, C>(
     client: &RpcClient,
     pubkeys: &[Pubkey],
 ) -> Result<HashMap<Pubkey, Account>, E>
 where
     C: crate::RpcErrorConverter<E>,
 {
-    get_multiple_accounts(client, pubkeys).map_err(C::convert_error)
+    get_multiple_accounts(client, pubkeys).await.map_err(C::convert_error)
 }
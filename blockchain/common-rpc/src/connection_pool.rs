@@ -1,81 +1,136 @@
+use crate::failover::FailoverSender;
+use crate::rate_limit::RateLimit;
+use crate::replay::{RecordingSender, ReplaySender, RpcRecorder, RECORD_FILE_ENV, REPLAY_PREFIX};
+use common::RpcEndpointHealth;
 use lazy_static::lazy_static;
-use solana_client::rpc_client::RpcClient;
-use std::collections::VecDeque;
-use std::sync::Mutex;
+use log::warn;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_sender::RpcSender;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+/// A client and the transport whose endpoint health it reports, None when replaying a fixture
+#[derive(Clone)]
+struct PooledClient {
+    client: Arc<RpcClient>,
+    sender: Option<FailoverSender>,
+}
+
 /// A thread-safe pool of RPC clients for reuse
 ///
-/// This pool maintains a set of RPC clients for different endpoints,
-/// allowing them to be reused instead of creating new connections for each request.
+/// The nonblocking client multiplexes concurrent requests over its HTTP connections, so the
+/// pool keeps a single client per endpoint spec and hands out shared references to it instead of
+/// creating new connections for each request. A spec is either a single URL or several weighted
+/// URLs, see [`crate::parse_endpoints`], between which the client fails over. A spec starting with
+/// [`REPLAY_PREFIX`] answers requests from a fixture file instead, see [`ReplaySender`].
 pub struct RpcConnectionPool {
-    clients: Mutex<VecDeque<(String, RpcClient)>>,
-    max_clients_per_endpoint: usize,
+    clients: Mutex<HashMap<String, PooledClient>>,
     timeout: Duration,
+    /// Limit of the requests sent to each endpoint without a limit of its own
+    rate_limit: Option<RateLimit>,
+    /// Records the responses of the endpoints when set
+    recorder: Option<Arc<RpcRecorder>>,
 }
 
 impl RpcConnectionPool {
-    /// Create a new connection pool with the specified maximum clients per endpoint and timeout
-    pub fn new(max_clients_per_endpoint: usize, timeout: Duration) -> Self {
-        Self { clients: Mutex::new(VecDeque::new()), max_clients_per_endpoint, timeout }
+    /// Create a new connection pool whose clients use the specified timeout
+    pub fn new(timeout: Duration) -> Self {
+        Self { clients: Mutex::new(HashMap::new()), timeout, rate_limit: None, recorder: None }
     }
 
-    /// Get a client for the specified endpoint
-    ///
-    /// If a client for this endpoint is available in the pool, it will be returned.
-    /// Otherwise, a new client will be created.
-    pub fn get_client(&self, endpoint: &str) -> RpcClient {
-        let endpoint_str = endpoint.to_string();
-        let mut clients = self.clients.lock().unwrap();
+    /// Limit the requests sent to each endpoint that has no limit in its spec
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimit>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
 
-        // Try to find an existing client for this endpoint
-        for i in 0..clients.len() {
-            if clients[i].0 == endpoint_str {
-                let (_, client) = clients.remove(i).unwrap();
-                return client;
-            }
+    /// Record the responses of the endpoints into a fixture file
+    pub fn with_recorder(mut self, recorder: Option<RpcRecorder>) -> Self {
+        self.recorder = recorder.map(Arc::new);
+        self
+    }
+
+    fn new_client(sender: impl RpcSender + Send + Sync + 'static) -> Arc<RpcClient> {
+        Arc::new(RpcClient::new_sender(
+            sender,
+            RpcClientConfig::with_commitment(CommitmentConfig::default()),
+        ))
+    }
+
+    fn create_pooled(&self, endpoint: &str) -> PooledClient {
+        if let Some(path) = endpoint.strip_prefix(REPLAY_PREFIX) {
+            let sender = ReplaySender::from_file(path).unwrap_or_else(|e| {
+                warn!("Failed to load RPC fixture {}: {}", path, e);
+                ReplaySender::new(Default::default(), endpoint)
+            });
+            return PooledClient { client: Self::new_client(sender), sender: None };
         }
 
-        // Create a new client if none available
-        RpcClient::new_with_timeout(endpoint_str, self.timeout)
+        let sender = FailoverSender::from_spec(endpoint, self.timeout, self.rate_limit);
+        let client = match &self.recorder {
+            Some(recorder) => {
+                Self::new_client(RecordingSender::new(sender.clone(), recorder.clone()))
+            }
+            None => Self::new_client(sender.clone()),
+        };
+
+        PooledClient { client, sender: Some(sender) }
     }
 
-    /// Return a client to the pool for future reuse
-    ///
-    /// The client will only be kept if we're under the maximum limit for this endpoint.
-    pub fn return_client(&self, endpoint: &str, client: RpcClient) {
-        let endpoint_str = endpoint.to_string();
+    fn get_pooled(&self, endpoint: &str) -> PooledClient {
         let mut clients = self.clients.lock().unwrap();
 
-        // Count existing clients for this endpoint
-        let count = clients.iter().filter(|(url, _)| url == &endpoint_str).count();
+        clients.entry(endpoint.to_string()).or_insert_with(|| self.create_pooled(endpoint)).clone()
+    }
+
+    /// Answer the requests to an endpoint spec with `sender` instead of connecting to it, e.g. to
+    /// record a fixture from accounts built in process
+    pub fn register_sender(&self, endpoint: &str, sender: impl RpcSender + Send + Sync + 'static) {
+        let pooled = PooledClient { client: Self::new_client(sender), sender: None };
+        self.clients.lock().unwrap().insert(endpoint.to_string(), pooled);
+    }
+
+    /// Get the client for the specified endpoint spec
+    ///
+    /// The client is created on the first request to the endpoint and shared afterwards.
+    pub fn get_client(&self, endpoint: &str) -> Arc<RpcClient> {
+        self.get_pooled(endpoint).client
+    }
 
-        // Only keep the client if we're under the limit
-        if count < self.max_clients_per_endpoint {
-            clients.push_back((endpoint_str, client));
+    /// Health of the endpoints of the specified endpoint spec
+    pub fn endpoint_health(&self, endpoint: &str) -> Vec<RpcEndpointHealth> {
+        self.get_pooled(endpoint).sender.map(|sender| sender.health()).unwrap_or_default()
+    }
+
+    /// Write the responses recorded so far to the fixture file, when recording
+    pub fn flush_recording(&self) -> std::io::Result<()> {
+        match &self.recorder {
+            Some(recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Probe the endpoints of the specified endpoint spec to refresh their health
+    pub async fn probe(&self, endpoint: &str) {
+        if let Some(sender) = self.get_pooled(endpoint).sender {
+            sender.probe().await
         }
-        // Otherwise let it drop
     }
 }
 
-// Global singleton instance
+// Global singleton instance, which is never dropped so its recording has to be flushed
 lazy_static! {
     pub static ref CONNECTION_POOL: RpcConnectionPool = RpcConnectionPool::new(
-        5, // 5 clients per endpoint
         Duration::from_secs(30), // 30 second timeout
-    );
+    )
+    .with_rate_limit(RateLimit::from_env())
+    .with_recorder(std::env::var_os(RECORD_FILE_ENV).map(RpcRecorder::new));
 }
 
-/// Helper function to get a client and automatically return it when done
-///
-/// This function handles getting a client from the pool, executing the provided
-/// function with it, and then returning the client to the pool.
-pub fn with_rpc_client<F, R>(endpoint: &str, f: F) -> R
-where
-    F: FnOnce(&RpcClient) -> R,
-{
-    let client = CONNECTION_POOL.get_client(endpoint);
-    let result = f(&client);
-    CONNECTION_POOL.return_client(endpoint, client);
-    result
+/// Helper function to get the shared client of an endpoint from the pool
+pub fn rpc_client(endpoint: &str) -> Arc<RpcClient> {
+    CONNECTION_POOL.get_client(endpoint)
 }
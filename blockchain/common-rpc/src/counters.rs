@@ -0,0 +1,31 @@
+use common::RpcCounters;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static RETRIES_EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+static THROTTLED: AtomicU64 = AtomicU64::new(0);
+static THROTTLED_MS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn record_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_retries_exhausted() {
+    RETRIES_EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_throttled(wait: Duration) {
+    THROTTLED.fetch_add(1, Ordering::Relaxed);
+    THROTTLED_MS.fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Retries and throttling of the RPC calls made since the process started
+pub fn rpc_counters() -> RpcCounters {
+    RpcCounters {
+        retries: RETRIES.load(Ordering::Relaxed),
+        retries_exhausted: RETRIES_EXHAUSTED.load(Ordering::Relaxed),
+        throttled: THROTTLED.load(Ordering::Relaxed),
+        throttled_ms: THROTTLED_MS.load(Ordering::Relaxed),
+    }
+}
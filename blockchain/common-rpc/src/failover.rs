@@ -0,0 +1,473 @@
+use crate::counters::record_throttled;
+use crate::rate_limit::{RateLimit, TokenBucket};
+use async_trait::async_trait;
+use common::RpcEndpointHealth;
+use futures::future::join_all;
+use serde_json::{json, Value};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_request::{RpcError as RpcRequestError, RpcRequest, RpcResponseErrorData},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client::http_sender::HttpSender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Weight of the latest request in the latency and error rate moving averages
+const SMOOTHING: f64 = 0.2;
+/// Endpoints that failed this many requests in a row are tried after the healthy ones
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// Endpoints failing a larger share of their requests are tried after the healthy ones
+const MAX_ERROR_RATE: f64 = 0.5;
+/// Endpoints further behind the most advanced endpoint are tried after the healthy ones
+pub const MAX_SLOT_LAG: u64 = 50;
+
+/// An RPC endpoint and its weight, a preference relative to the other endpoints of a pool that
+/// multiplies the endpoint's score when ranking them. Every request goes to the top ranked
+/// endpoint, so a higher weight makes an endpoint preferred rather than giving it a larger share of
+/// the traffic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcEndpoint {
+    pub url: String,
+    pub weight: f64,
+    /// Limit of the requests sent to the endpoint, the pool's default when None
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Parse a comma separated list of endpoints, each a URL optionally followed by whitespace, its
+/// weight and its limit in requests per second, e.g. `https://a.example.com 3 50, https://b.example.com`
+///
+/// Endpoints without a valid positive weight get a weight of 1.
+pub fn parse_endpoints(spec: &str) -> Vec<RpcEndpoint> {
+    let positive = |value: &str| value.parse::<f64>().ok().filter(|v| v.is_finite() && *v > 0.0);
+
+    spec.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split_whitespace();
+            let url = parts.next()?.to_string();
+            let weight = parts.next().and_then(positive).unwrap_or(1.0);
+            let rate_limit = parts.next().and_then(positive).map(RateLimit::per_second);
+
+            Some(RpcEndpoint { url, weight, rate_limit })
+        })
+        .collect()
+}
+
+/// Whether the error comes from the endpoint rather than from the request, in which case another
+/// endpoint may answer it
+fn is_endpoint_failure(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::Io(_)
+            | ClientErrorKind::Reqwest(_)
+            | ClientErrorKind::RpcError(RpcRequestError::RpcRequestError(_))
+            | ClientErrorKind::RpcError(RpcRequestError::RpcResponseError {
+                data: RpcResponseErrorData::NodeUnhealthy { .. },
+                ..
+            })
+    )
+}
+
+/// Remove the path and query of a URL, as providers put API keys in either
+pub(crate) fn redact_url(url: &str) -> String {
+    let host_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    match url[host_start..].find(['/', '?']) {
+        Some(i) if host_start + i + 1 < url.len() => format!("{}/…", &url[..host_start + i]),
+        _ => url.to_string(),
+    }
+}
+
+/// The error message with the endpoint URL redacted, as HTTP errors mention the URL they failed on
+fn error_message(error: &ClientError) -> String {
+    let message = error.to_string();
+    match error.kind() {
+        ClientErrorKind::Reqwest(e) => match e.url() {
+            Some(url) => message.replace(url.as_str(), &redact_url(url.as_str())),
+            None => message,
+        },
+        _ => message,
+    }
+}
+
+#[derive(Debug, Default)]
+struct EndpointStats {
+    /// Moving average of the request latency in milliseconds
+    latency_ms: Option<f64>,
+    /// Moving average of the share of failed requests
+    error_rate: f64,
+    /// Latest slot the endpoint reported
+    slot: Option<u64>,
+    requests: u64,
+    errors: u64,
+    /// Requests held back by the endpoint's rate limit
+    throttled: u64,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+impl EndpointStats {
+    fn record(&mut self, latency: Duration, error: Option<&ClientError>) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(average) => average + SMOOTHING * (latency_ms - average),
+            None => latency_ms,
+        });
+        self.requests += 1;
+
+        let failed = if error.is_some() { 1.0 } else { 0.0 };
+        self.error_rate += SMOOTHING * (failed - self.error_rate);
+
+        match error {
+            Some(error) => {
+                self.errors += 1;
+                self.consecutive_failures += 1;
+                self.last_error = Some(error_message(error));
+            }
+            None => self.consecutive_failures = 0,
+        }
+    }
+
+    fn record_slot(&mut self, slot: u64) {
+        self.slot = Some(self.slot.map_or(slot, |current| current.max(slot)));
+    }
+
+    fn slot_lag(&self, highest_slot: u64) -> u64 {
+        self.slot.map_or(0, |slot| highest_slot.saturating_sub(slot))
+    }
+
+    fn is_healthy(&self, highest_slot: u64) -> bool {
+        self.consecutive_failures < MAX_CONSECUTIVE_FAILURES
+            && self.error_rate < MAX_ERROR_RATE
+            && self.slot_lag(highest_slot) <= MAX_SLOT_LAG
+    }
+
+    /// Higher for endpoints that are weighted higher, fail less and answer faster. Endpoints
+    /// without requests yet score as if they answered instantly so they get tried.
+    fn score(&self, weight: f64) -> f64 {
+        weight * (1.0 - self.error_rate) / (1.0 + self.latency_ms.unwrap_or(0.0) / 100.0)
+    }
+}
+
+struct EndpointState {
+    endpoint: RpcEndpoint,
+    sender: Box<dyn RpcSender + Send + Sync>,
+    limiter: Option<TokenBucket>,
+    stats: Mutex<EndpointStats>,
+}
+
+impl EndpointState {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        if let Some(limiter) = &self.limiter {
+            let waited = limiter.acquire().await;
+            if !waited.is_zero() {
+                record_throttled(waited);
+                self.stats.lock().unwrap().throttled += 1;
+            }
+        }
+
+        let started = Instant::now();
+        let result = self.sender.send(request, params).await;
+
+        let mut stats = self.stats.lock().unwrap();
+        match &result {
+            Ok(value) => {
+                stats.record(started.elapsed(), None);
+                let slot = match request {
+                    RpcRequest::GetSlot => value.as_u64(),
+                    _ => value["context"]["slot"].as_u64(),
+                };
+                if let Some(slot) = slot {
+                    stats.record_slot(slot);
+                }
+            }
+            Err(e) if is_endpoint_failure(e) => stats.record(started.elapsed(), Some(e)),
+            // The endpoint answered, the request itself was rejected
+            Err(_) => stats.record(started.elapsed(), None),
+        }
+
+        result
+    }
+}
+
+/// An RPC transport spreading requests over several endpoints
+///
+/// Each request goes to the healthiest endpoint and fails over to the next one when the endpoint
+/// cannot be reached, times out or reports itself unhealthy. Endpoints are ranked by their weight,
+/// latency and error rate, with the endpoints that keep failing or lag behind the others ranked
+/// last. Clones share the endpoints and their statistics.
+#[derive(Clone)]
+pub struct FailoverSender {
+    endpoints: Arc<Vec<EndpointState>>,
+}
+
+impl FailoverSender {
+    /// Create a sender for the given endpoints whose requests time out after `timeout`. Endpoints
+    /// without a rate limit of their own are limited to `default_rate_limit`.
+    pub fn new(
+        endpoints: Vec<RpcEndpoint>,
+        timeout: Duration,
+        default_rate_limit: Option<RateLimit>,
+    ) -> Self {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let sender = HttpSender::new_with_timeout(&endpoint.url, timeout);
+                (endpoint, Box::new(sender) as Box<dyn RpcSender + Send + Sync>)
+            })
+            .collect();
+
+        Self::with_senders(endpoints, default_rate_limit)
+    }
+
+    /// Create a sender sending the requests of each endpoint through the given transport
+    fn with_senders(
+        endpoints: Vec<(RpcEndpoint, Box<dyn RpcSender + Send + Sync>)>,
+        default_rate_limit: Option<RateLimit>,
+    ) -> Self {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(endpoint, sender)| EndpointState {
+                sender,
+                limiter: endpoint.rate_limit.or(default_rate_limit).map(TokenBucket::new),
+                endpoint,
+                stats: Mutex::new(EndpointStats::default()),
+            })
+            .collect();
+
+        Self { endpoints: Arc::new(endpoints) }
+    }
+
+    /// Create a sender for the endpoints of a spec accepted by [`parse_endpoints`]
+    pub fn from_spec(spec: &str, timeout: Duration, default_rate_limit: Option<RateLimit>) -> Self {
+        Self::new(parse_endpoints(spec), timeout, default_rate_limit)
+    }
+
+    fn highest_slot(&self) -> u64 {
+        self.endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.stats.lock().unwrap().slot)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Endpoints in the order requests try them
+    fn ranked(&self) -> Vec<&EndpointState> {
+        let highest_slot = self.highest_slot();
+        let mut ranked: Vec<_> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let stats = endpoint.stats.lock().unwrap();
+                (stats.is_healthy(highest_slot), stats.score(endpoint.endpoint.weight), endpoint)
+            })
+            .collect();
+        ranked.sort_by(|(a_healthy, a_score, _), (b_healthy, b_score, _)| {
+            b_healthy.cmp(a_healthy).then(b_score.total_cmp(a_score))
+        });
+
+        ranked.into_iter().map(|(_, _, endpoint)| endpoint).collect()
+    }
+
+    /// Health of every endpoint, in the order they were configured
+    pub fn health(&self) -> Vec<RpcEndpointHealth> {
+        let highest_slot = self.highest_slot();
+
+        self.endpoints
+            .iter()
+            .map(|endpoint| {
+                let stats = endpoint.stats.lock().unwrap();
+                RpcEndpointHealth {
+                    url: redact_url(&endpoint.endpoint.url),
+                    weight: endpoint.endpoint.weight,
+                    healthy: stats.is_healthy(highest_slot),
+                    score: stats.score(endpoint.endpoint.weight),
+                    latency_ms: stats.latency_ms,
+                    error_rate: stats.error_rate,
+                    slot: stats.slot,
+                    slot_lag: stats.slot_lag(highest_slot),
+                    requests: stats.requests,
+                    errors: stats.errors,
+                    throttled: stats.throttled,
+                    last_error: stats.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Request the slot of every endpoint to refresh their latency and slot lag, which also lets
+    /// endpoints ranked last for failing recover once they answer again
+    pub async fn probe(&self) {
+        join_all(
+            self.endpoints.iter().map(|endpoint| endpoint.send(RpcRequest::GetSlot, json!([]))),
+        )
+        .await;
+    }
+}
+
+#[async_trait]
+impl RpcSender for FailoverSender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut last_error = None;
+
+        for endpoint in self.ranked() {
+            match endpoint.send(request, params.clone()).await {
+                Ok(value) => return Ok(value),
+                Err(e) if is_endpoint_failure(&e) => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ClientErrorKind::Custom("No RPC endpoint configured".to_string()).into()
+        }))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.endpoints.iter().map(|endpoint| endpoint.sender.get_transport_stats()).fold(
+            RpcTransportStats::default(),
+            |mut total, stats| {
+                total.request_count += stats.request_count;
+                total.elapsed_time += stats.elapsed_time;
+                total.rate_limited_time += stats.rate_limited_time;
+                total
+            },
+        )
+    }
+
+    /// The preferred endpoint's URL, redacted as it ends up in logs and errors
+    fn url(&self) -> String {
+        self.ranked().first().map(|endpoint| redact_url(&endpoint.endpoint.url)).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    /// An endpoint at slot 100 that fails to connect while `down` is set
+    #[derive(Clone, Default)]
+    struct MockSender {
+        down: Arc<AtomicBool>,
+        requests: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl RpcSender for MockSender {
+        async fn send(&self, request: RpcRequest, _params: Value) -> ClientResult<Value> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(
+                    ClientErrorKind::Io(std::io::ErrorKind::ConnectionRefused.into()).into()
+                );
+            }
+            match request {
+                RpcRequest::GetSlot => Ok(json!(100)),
+                _ => Ok(json!({ "context": { "slot": 100 }, "value": null })),
+            }
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            String::new()
+        }
+    }
+
+    fn endpoint(url: &str, weight: f64) -> RpcEndpoint {
+        RpcEndpoint { url: url.to_string(), weight, rate_limit: None }
+    }
+
+    /// A pool of a preferred endpoint and a fallback
+    fn pool() -> (FailoverSender, MockSender, MockSender) {
+        let (preferred, fallback) = (MockSender::default(), MockSender::default());
+        let sender = FailoverSender::with_senders(
+            vec![
+                (
+                    endpoint("https://preferred.example.com/api-key", 2.0),
+                    Box::new(preferred.clone()),
+                ),
+                (endpoint("https://fallback.example.com", 1.0), Box::new(fallback.clone())),
+            ],
+            None,
+        );
+
+        (sender, preferred, fallback)
+    }
+
+    #[test]
+    fn test_parse_endpoints() {
+        let endpoints = parse_endpoints(
+            "https://a.example.com/key 3 50, https://b.example.com,, https://c.example.com -1 x",
+        );
+
+        assert_eq!(
+            endpoints,
+            vec![
+                RpcEndpoint {
+                    url: "https://a.example.com/key".to_string(),
+                    weight: 3.0,
+                    rate_limit: Some(RateLimit::per_second(50.0)),
+                },
+                endpoint("https://b.example.com", 1.0),
+                endpoint("https://c.example.com", 1.0),
+            ]
+        );
+        assert_eq!(
+            parse_endpoints("https://a.example.com"),
+            vec![endpoint("https://a.example.com", 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(redact_url("https://rpc.example.com/api-key"), "https://rpc.example.com/…");
+        assert_eq!(redact_url("https://rpc.example.com?api-key=1"), "https://rpc.example.com/…");
+        assert_eq!(redact_url("https://rpc.example.com/"), "https://rpc.example.com/");
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_to_next_endpoint() {
+        let (sender, preferred, fallback) = pool();
+        preferred.down.store(true, Ordering::SeqCst);
+
+        assert!(sender.send(RpcRequest::GetAccountInfo, json!([])).await.is_ok());
+        assert_eq!(preferred.requests.load(Ordering::SeqCst), 1);
+        assert_eq!(fallback.requests.load(Ordering::SeqCst), 1);
+
+        let health = sender.health();
+        assert_eq!(health[0].errors, 1);
+        assert!(health[0].last_error.is_some());
+        assert_eq!(health[1].errors, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fails_when_every_endpoint_fails() {
+        let (sender, preferred, fallback) = pool();
+        preferred.down.store(true, Ordering::SeqCst);
+        fallback.down.store(true, Ordering::SeqCst);
+
+        assert!(sender.send(RpcRequest::GetAccountInfo, json!([])).await.is_err());
+        assert_eq!(fallback.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_endpoint_ranks_last_until_it_recovers() {
+        let (sender, preferred, _) = pool();
+        assert_eq!(sender.url(), "https://preferred.example.com/…");
+
+        preferred.down.store(true, Ordering::SeqCst);
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            assert!(sender.send(RpcRequest::GetAccountInfo, json!([])).await.is_ok());
+        }
+        assert!(!sender.health()[0].healthy);
+        assert_eq!(sender.url(), "https://fallback.example.com");
+
+        preferred.down.store(false, Ordering::SeqCst);
+        sender.probe().await;
+        assert!(sender.health()[0].healthy);
+        assert_eq!(sender.url(), "https://preferred.example.com/…");
+    }
+}
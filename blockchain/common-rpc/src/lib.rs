@@ -6,3 +6,18 @@ pub use connection_pool::*;
 
 pub mod batch;
 pub use batch::*;
+
+pub mod failover;
+pub use failover::*;
+
+pub mod retry;
+pub use retry::*;
+
+pub mod rate_limit;
+pub use rate_limit::*;
+
+pub mod counters;
+pub use counters::*;
+
+pub mod replay;
+pub use replay::*;
@@ -0,0 +1,127 @@
+use crate::retry::parse_env;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Requests per second allowed to each RPC endpoint without a rate of its own, unlimited when unset
+pub const REQUESTS_PER_SECOND_ENV: &str = "RPC_REQUESTS_PER_SECOND";
+/// Requests an endpoint may receive at once after being idle, defaults to one second of requests
+pub const REQUEST_BURST_ENV: &str = "RPC_REQUEST_BURST";
+
+/// Rate of outbound requests to an endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    /// Requests allowed at once after being idle
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// A limit allowing one second of requests at once
+    pub fn per_second(requests_per_second: f64) -> Self {
+        Self { requests_per_second, burst: requests_per_second.max(1.0) }
+    }
+
+    /// Reads the limit from the environment, None when no valid rate is set
+    pub fn from_env() -> Option<Self> {
+        let requests_per_second = parse_env::<f64>(REQUESTS_PER_SECOND_ENV)
+            .filter(|rate| rate.is_finite() && *rate > 0.0)?;
+        let limit = Self::per_second(requests_per_second);
+
+        Some(Self {
+            burst: parse_env::<f64>(REQUEST_BURST_ENV)
+                .filter(|burst| burst.is_finite() && *burst >= 1.0)
+                .unwrap_or(limit.burst),
+            ..limit
+        })
+    }
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// Requests that can be sent right away, negative when callers are already waiting
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket holding outbound requests back to a rate limit
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    /// Create a full bucket
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BucketState { tokens: limit.burst, updated: Instant::now() }),
+        }
+    }
+
+    /// Take a token, waiting for it when the bucket is empty. Returns how long the call waited.
+    pub async fn acquire(&self) -> Duration {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill =
+                now.duration_since(state.updated).as_secs_f64() * self.limit.requests_per_second;
+            state.tokens = (state.tokens + refill).min(self.limit.burst) - 1.0;
+            state.updated = now;
+
+            if state.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-state.tokens / self.limit.requests_per_second)
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_allows_burst_then_waits_for_refill() {
+        let bucket = TokenBucket::new(RateLimit { requests_per_second: 10.0, burst: 2.0 });
+
+        assert_eq!(bucket.acquire().await, Duration::ZERO);
+        assert_eq!(bucket.acquire().await, Duration::ZERO);
+
+        let start = Instant::now();
+        assert_eq!(bucket.acquire().await, Duration::from_millis(100));
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_refills_up_to_burst() {
+        let bucket = TokenBucket::new(RateLimit { requests_per_second: 10.0, burst: 2.0 });
+        for _ in 0..2 {
+            bucket.acquire().await;
+        }
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+
+        assert_eq!(bucket.acquire().await, Duration::ZERO);
+        assert_eq!(bucket.acquire().await, Duration::ZERO);
+        assert_eq!(bucket.acquire().await, Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiting_callers_queue_behind_each_other() {
+        let bucket = TokenBucket::new(RateLimit::per_second(1.0));
+        bucket.acquire().await;
+
+        let (first, second) = tokio::join!(bucket.acquire(), bucket.acquire());
+
+        assert_eq!(first, Duration::from_secs(1));
+        assert_eq!(second, Duration::from_secs(2));
+    }
+}
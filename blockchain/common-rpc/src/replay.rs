@@ -0,0 +1,256 @@
+use async_trait::async_trait;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::{
+    client_error::{ClientErrorKind, Result as ClientResult},
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Spec prefix of an RPC URL replaying a fixture file, e.g. `replay:fixtures/mainnet.json`
+pub const REPLAY_PREFIX: &str = "replay:";
+/// File the RPC responses of the process are recorded to, nothing is recorded when unset
+pub const RECORD_FILE_ENV: &str = "RPC_RECORD_FILE";
+/// When set, [`assert_golden`] rewrites the golden files instead of comparing with them
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// A request and the response the endpoint answered it with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub method: String,
+    pub params: Value,
+    pub result: Value,
+}
+
+/// RPC responses captured from an endpoint, replayed by [`ReplaySender`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RpcFixture {
+    pub calls: Vec<RecordedCall>,
+}
+
+impl RpcFixture {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        Ok(serde_json::to_writer(std::io::BufWriter::new(file), self)?)
+    }
+
+    /// Record a response, replacing the previous response to the same request
+    pub fn record(&mut self, method: String, params: Value, result: Value) {
+        match self.calls.iter_mut().find(|call| call.method == method && call.params == params) {
+            Some(call) => call.result = result,
+            None => self.calls.push(RecordedCall { method, params, result }),
+        }
+    }
+}
+
+/// Compare the output of a replayed test with its golden file at `path`, rewriting the file when
+/// `UPDATE_GOLDEN` is set
+///
+/// The text is compared rather than a `Value`, which can't hold `u128` amounts.
+pub fn assert_golden(path: impl AsRef<Path>, output: &impl Serialize) {
+    let path = path.as_ref();
+    let output = serde_json::to_string_pretty(output).unwrap();
+
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(path, &output).unwrap();
+        return;
+    }
+
+    let golden = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    assert!(output == golden, "output differs from {}:\n{output}", path.display());
+}
+
+fn call_key(method: &str, params: &Value) -> String {
+    format!("{method} {params}")
+}
+
+/// A fixture and whether it holds responses its file does not
+#[derive(Default)]
+struct RecordedFixture {
+    fixture: RpcFixture,
+    unsaved: bool,
+}
+
+/// Records the responses of every endpoint of the process into one fixture file
+///
+/// Responses are kept in memory and the file is written on [`RpcRecorder::flush`] and when the
+/// recorder is dropped.
+pub struct RpcRecorder {
+    path: PathBuf,
+    recorded: Mutex<RecordedFixture>,
+}
+
+impl RpcRecorder {
+    /// Record into the file at `path`, keeping the calls it already holds
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let fixture = RpcFixture::load(&path).unwrap_or_default();
+
+        Self { path, recorded: Mutex::new(RecordedFixture { fixture, unsaved: false }) }
+    }
+
+    /// Record a response
+    pub fn record(&self, request: RpcRequest, params: &Value, result: &Value) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.fixture.record(request.to_string(), params.clone(), result.clone());
+        recorded.unsaved = true;
+    }
+
+    /// Write the responses recorded since the last write to the fixture file
+    pub fn flush(&self) -> std::io::Result<()> {
+        let mut recorded = self.recorded.lock().unwrap();
+        if recorded.unsaved {
+            recorded.fixture.save(&self.path)?;
+            recorded.unsaved = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RpcRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("Failed to write RPC fixture {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// An RPC transport recording the successful responses of another one
+pub struct RecordingSender<S> {
+    inner: S,
+    recorder: Arc<RpcRecorder>,
+}
+
+impl<S> RecordingSender<S> {
+    pub fn new(inner: S, recorder: Arc<RpcRecorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for RecordingSender<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let result = self.inner.send(request, params.clone()).await?;
+        self.recorder.record(request, &params, &result);
+
+        Ok(result)
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// An RPC transport answering requests with the responses of a fixture instead of an endpoint
+///
+/// Requests are matched on their method and parameters. Account lookups are answered per account
+/// instead, so `getMultipleAccounts` calls listing recorded accounts in another order or in other
+/// batches still get an answer.
+pub struct ReplaySender {
+    label: String,
+    calls: HashMap<String, Value>,
+    /// Recorded accounts by address, null for the accounts that did not exist
+    accounts: HashMap<String, Value>,
+    /// Context of the latest recorded account lookup
+    context: Value,
+}
+
+impl ReplaySender {
+    pub fn new(fixture: RpcFixture, label: impl Into<String>) -> Self {
+        let mut calls = HashMap::with_capacity(fixture.calls.len());
+        let mut accounts = HashMap::new();
+        let mut context = Value::Null;
+
+        for call in fixture.calls {
+            let addresses: Vec<&Value> = match call.method.as_str() {
+                "getAccountInfo" => vec![&call.params[0]],
+                "getMultipleAccounts" => {
+                    call.params[0].as_array().map(|a| a.iter().collect()).unwrap_or_default()
+                }
+                _ => vec![],
+            };
+            let values: Vec<&Value> = match &call.result["value"] {
+                Value::Array(values) if call.method == "getMultipleAccounts" => {
+                    values.iter().collect()
+                }
+                value => vec![value],
+            };
+            for (address, value) in addresses.into_iter().zip(values) {
+                if let Some(address) = address.as_str() {
+                    accounts.insert(address.to_string(), value.clone());
+                    context = call.result["context"].clone();
+                }
+            }
+
+            calls.insert(call_key(&call.method, &call.params), call.result);
+        }
+
+        Self { label: label.into(), calls, accounts, context }
+    }
+
+    /// Replay the fixture file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Self::new(RpcFixture::load(path)?, format!("{REPLAY_PREFIX}{}", path.display())))
+    }
+
+    fn account(&self, address: &Value) -> Option<Value> {
+        self.accounts.get(address.as_str()?).cloned()
+    }
+
+    fn replay_accounts(&self, request: RpcRequest, params: &Value) -> Option<Value> {
+        let value = match request {
+            RpcRequest::GetAccountInfo => self.account(&params[0])?,
+            RpcRequest::GetMultipleAccounts => Value::Array(
+                params[0]
+                    .as_array()?
+                    .iter()
+                    .map(|address| self.account(address))
+                    .collect::<Option<_>>()?,
+            ),
+            _ => return None,
+        };
+
+        Some(serde_json::json!({ "context": self.context, "value": value }))
+    }
+}
+
+#[async_trait]
+impl RpcSender for ReplaySender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let method = request.to_string();
+
+        self.calls
+            .get(&call_key(&method, &params))
+            .cloned()
+            .or_else(|| self.replay_accounts(request, &params))
+            .ok_or_else(|| {
+                ClientErrorKind::Custom(format!("No recorded response to {method} {params}")).into()
+            })
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        self.label.clone()
+    }
+}
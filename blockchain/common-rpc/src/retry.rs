@@ -0,0 +1,303 @@
+use crate::counters::{record_retries_exhausted, record_retry};
+use crate::failover::redact_url;
+use lazy_static::lazy_static;
+use log::warn;
+use rand::Rng;
+use solana_client::{
+    client_error::{reqwest::StatusCode, ClientError, ClientErrorKind},
+    rpc_custom_error::{
+        JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+        JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+        JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    },
+    rpc_request::RpcError as RpcRequestError,
+};
+use std::future::Future;
+use std::time::Duration;
+
+/// Number of times a failed call is retried
+pub const MAX_RETRIES_ENV: &str = "RPC_MAX_RETRIES";
+/// Delay in milliseconds before the first retry, doubled for every following one
+pub const RETRY_BASE_DELAY_MS_ENV: &str = "RPC_RETRY_BASE_DELAY_MS";
+/// Upper bound in milliseconds of the delay between two attempts
+pub const RETRY_MAX_DELAY_MS_ENV: &str = "RPC_RETRY_MAX_DELAY_MS";
+
+/// JSON-RPC error codes of failures that may not happen again on a later attempt. Some providers
+/// report rate limiting with an HTTP status code as the JSON-RPC error code.
+const RETRYABLE_RPC_CODES: [i64; 5] = [
+    JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+    JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+    JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+    429,
+];
+
+/// How failed RPC calls are retried
+///
+/// The delay before retry `n` is `base_delay * 2^n` capped at `max_delay`, shortened by a random
+/// share of up to `jitter` so concurrent callers do not retry in lockstep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Share of the delay that is randomized, between 0 and 1
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+        }
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_RETRY_POLICY: RetryPolicy = RetryPolicy::from_env();
+}
+
+impl RetryPolicy {
+    /// A policy making a single attempt
+    pub fn none() -> Self {
+        Self { max_retries: 0, ..Self::default() }
+    }
+
+    /// Reads the policy from the environment, using the default of the unset or malformed values
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            max_retries: parse_env(MAX_RETRIES_ENV).unwrap_or(default.max_retries),
+            base_delay: parse_env(RETRY_BASE_DELAY_MS_ENV)
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_delay),
+            max_delay: parse_env(RETRY_MAX_DELAY_MS_ENV)
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_delay),
+            jitter: default.jitter,
+        }
+    }
+
+    /// The policy read from the environment once, used by calls without a policy of their own
+    pub fn global() -> Self {
+        DEFAULT_RETRY_POLICY.clone()
+    }
+
+    /// Delay before the given retry, counting from 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff =
+            self.base_delay.saturating_mul(2u32.saturating_pow(retry)).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::thread_rng().gen::<f64>();
+
+        backoff.mul_f64(1.0 - jitter)
+    }
+}
+
+pub(crate) fn parse_env<T: std::str::FromStr>(var: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = std::env::var(var).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            warn!("Ignoring invalid {} {:?}: {}", var, value, e);
+            None
+        }
+    }
+}
+
+/// Whether the call failed for a reason that may not happen again, like a timeout, rate limiting
+/// or a node that is behind, rather than because of the request itself
+pub fn is_retryable(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|status| {
+                    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+                })
+        }
+        ClientErrorKind::RpcError(RpcRequestError::RpcResponseError { code, .. }) => {
+            RETRYABLE_RPC_CODES.contains(code)
+        }
+        _ => false,
+    }
+}
+
+/// Short description of a retryable error for the logs, without the message that may contain the
+/// endpoint URL
+fn error_kind(error: &ClientError) -> String {
+    match error.kind() {
+        ClientErrorKind::Io(e) => format!("I/O error ({:?})", e.kind()),
+        ClientErrorKind::Reqwest(e) => match e.status() {
+            Some(status) => format!("HTTP {}", status.as_u16()),
+            None if e.is_timeout() => "timeout".to_string(),
+            None if e.is_connect() => "connection error".to_string(),
+            None => "HTTP error".to_string(),
+        },
+        ClientErrorKind::RpcError(RpcRequestError::RpcResponseError { code, .. }) => {
+            format!("RPC error {}", code)
+        }
+        _ => "error".to_string(),
+    }
+}
+
+/// Run the call to `endpoint` until it succeeds, fails with an error that is not retryable or runs
+/// out of retries, waiting between attempts as the policy says
+pub async fn with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    endpoint: &str,
+    mut call: F,
+) -> Result<T, ClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ClientError>>,
+{
+    let mut retry = 0;
+
+    loop {
+        match call().await {
+            Ok(value) => return Ok(value),
+            Err(e) if is_retryable(&e) => {
+                if retry >= policy.max_retries {
+                    if policy.max_retries > 0 {
+                        record_retries_exhausted();
+                    }
+                    return Err(e);
+                }
+
+                let delay = policy.delay(retry);
+                warn!(
+                    "Retrying RPC call to {} in {:?} after {}",
+                    redact_url(endpoint),
+                    delay,
+                    error_kind(&e)
+                );
+                record_retry();
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcResponseErrorData;
+    use std::cell::Cell;
+
+    fn rpc_error(code: i64) -> ClientError {
+        ClientErrorKind::RpcError(RpcRequestError::RpcResponseError {
+            code,
+            message: "failed".to_string(),
+            data: RpcResponseErrorData::Empty,
+        })
+        .into()
+    }
+
+    fn io_error() -> ClientError {
+        ClientErrorKind::Io(std::io::ErrorKind::ConnectionReset.into()).into()
+    }
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles_up_to_max() {
+        let policy = policy(0.0);
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+        assert_eq!(policy.delay(4), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_delay_jitter_only_shortens() {
+        let policy = policy(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&io_error()));
+        assert!(is_retryable(&rpc_error(429)));
+        assert!(is_retryable(&rpc_error(JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY)));
+        assert!(is_retryable(&rpc_error(JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED)));
+
+        // Invalid params and a failed simulation fail again on every attempt
+        assert!(!is_retryable(&rpc_error(-32602)));
+        assert!(!is_retryable(&rpc_error(-32002)));
+        assert!(!is_retryable(&ClientErrorKind::Custom("bad request".to_string()).into()));
+    }
+
+    #[test]
+    fn test_error_kind_leaves_out_message() {
+        let error: ClientError =
+            ClientErrorKind::Custom("https://rpc.example.com/secret-key".to_string()).into();
+
+        assert_eq!(error_kind(&error), "error");
+        assert_eq!(error_kind(&rpc_error(429)), "RPC error 429");
+        assert_eq!(error_kind(&io_error()), "I/O error (ConnectionReset)");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_retry_retries_retryable_errors() {
+        let attempts = Cell::new(0);
+        let start = tokio::time::Instant::now();
+
+        let result = with_retry(&policy(0.0), "https://rpc.example.com", || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err(io_error())
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_retry_gives_up() {
+        let attempts = Cell::new(0);
+        let call = || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(rpc_error(429)) }
+        };
+        assert!(with_retry(&policy(0.0), "https://rpc.example.com", call).await.is_err());
+        assert_eq!(attempts.get(), 4);
+
+        attempts.set(0);
+        let call = || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(rpc_error(-32602)) }
+        };
+        assert!(with_retry(&policy(0.0), "https://rpc.example.com", call).await.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,15 +1,19 @@
+use crate::retry::{with_retry, RetryPolicy};
 use solana_account_decoder::UiAccountEncoding;
-use solana_client::rpc_client::RpcClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
 use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
-use solana_sdk::account::Account;
+use solana_sdk::account::{from_account, Account};
+use solana_sdk::clock::Clock;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum RpcError {
     #[error("RPC error: {0}")]
-    RpcError(#[from] Box<dyn std::error::Error>),
+    RpcError(#[from] Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Account deserialization error: {0}")]
     DeserializationError(String),
@@ -34,6 +38,7 @@ pub struct SolanaRpcBuilder<'a> {
     filters: Vec<RpcFilterType>,
     encoding: Option<UiAccountEncoding>,
     with_context: Option<bool>,
+    retry_policy: RetryPolicy,
 }
 
 impl<'a> SolanaRpcBuilder<'a> {
@@ -45,6 +50,7 @@ impl<'a> SolanaRpcBuilder<'a> {
             filters: Vec::new(),
             encoding: Some(UiAccountEncoding::Base64),
             with_context: None,
+            retry_policy: RetryPolicy::global(),
         }
     }
 
@@ -100,6 +106,12 @@ impl<'a> SolanaRpcBuilder<'a> {
         self
     }
 
+    /// Set how failed calls are retried, the policy read from the environment by default
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// Optimize the order of filters for better RPC performance
     ///
     /// This method sorts filters by their restrictiveness, putting the most
@@ -140,35 +152,77 @@ impl<'a> SolanaRpcBuilder<'a> {
     }
 
     /// Get program accounts
-    pub fn get_program_accounts(self) -> Result<Vec<(Pubkey, Account)>, RpcError> {
+    pub async fn get_program_accounts(self) -> Result<Vec<(Pubkey, Account)>, RpcError> {
         let config = RpcProgramAccountsConfig {
             filters: if self.filters.is_empty() { None } else { Some(self.filters) },
             account_config: RpcAccountInfoConfig { encoding: self.encoding, ..Default::default() },
             with_context: self.with_context,
         };
 
-        self.rpc_client
-            .get_program_accounts_with_config(&self.program_id, config)
-            .map_err(|e| RpcError::RpcError(Box::new(e)))
+        with_retry(&self.retry_policy, &self.rpc_client.url(), || {
+            self.rpc_client.get_program_accounts_with_config(&self.program_id, config.clone())
+        })
+        .await
+        .map_err(|e| RpcError::RpcError(Box::new(e)))
     }
 
     /// Get program accounts with automatic error conversion
-    pub fn get_program_accounts_with_conversion<E, C: RpcErrorConverter<E>>(
+    pub async fn get_program_accounts_with_conversion<E, C: RpcErrorConverter<E>>(
         self,
     ) -> Result<Vec<(Pubkey, Account)>, E> {
-        self.get_program_accounts().map_err(C::convert_error)
+        self.get_program_accounts().await.map_err(C::convert_error)
     }
 
     /// Get a single account by pubkey
-    pub fn get_account(self, pubkey: &Pubkey) -> Result<Account, RpcError> {
-        self.rpc_client.get_account(pubkey).map_err(|e| RpcError::RpcError(Box::new(e)))
+    pub async fn get_account(self, pubkey: &Pubkey) -> Result<Account, RpcError> {
+        with_retry(&self.retry_policy, &self.rpc_client.url(), || {
+            self.rpc_client.get_account(pubkey)
+        })
+        .await
+        .map_err(|e| RpcError::RpcError(Box::new(e)))
     }
 
     /// Get a single account by pubkey with automatic error conversion
-    pub fn get_account_with_conversion<E, C: RpcErrorConverter<E>>(
+    pub async fn get_account_with_conversion<E, C: RpcErrorConverter<E>>(
         self,
         pubkey: &Pubkey,
     ) -> Result<Account, E> {
-        self.get_account(pubkey).map_err(C::convert_error)
+        self.get_account(pubkey).await.map_err(C::convert_error)
     }
 }
+
+/// The cluster clock, read from the clock sysvar so the slot and the unix timestamp interest is
+/// accrued to come from the same block
+pub async fn fetch_clock(rpc_client: &RpcClient) -> Result<Clock, RpcError> {
+    let clock_id = sysvar::clock::id();
+    let account =
+        with_retry(&RetryPolicy::global(), &rpc_client.url(), || rpc_client.get_account(&clock_id))
+            .await
+            .map_err(|e| RpcError::RpcError(Box::new(e)))?;
+
+    decode_clock(&account)
+}
+
+fn decode_clock(account: &Account) -> Result<Clock, RpcError> {
+    from_account::<Clock, _>(account)
+        .ok_or_else(|| RpcError::DeserializationError("Invalid clock sysvar".to_string()))
+}
+
+/// Fetch multiple accounts along with the cluster clock, read in the same request as the first
+/// batch of accounts. The accounts of that batch are no newer than the clock, so interest accrued
+/// to it never runs backwards.
+pub async fn get_multiple_accounts_with_clock(
+    rpc_client: &RpcClient,
+    pubkeys: &[Pubkey],
+) -> Result<(HashMap<Pubkey, Account>, Clock), RpcError> {
+    let clock_id = sysvar::clock::id();
+    let pubkeys: Vec<Pubkey> = std::iter::once(clock_id).chain(pubkeys.iter().copied()).collect();
+
+    let mut accounts = crate::get_multiple_accounts(rpc_client, &pubkeys).await?;
+    let clock = accounts
+        .remove(&clock_id)
+        .ok_or_else(|| RpcError::AccountNotFound("Clock sysvar not found".to_string()))
+        .and_then(|account| decode_clock(&account))?;
+
+    Ok((accounts, clock))
+}
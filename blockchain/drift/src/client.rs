@@ -1,21 +1,30 @@
 use crate::error::ErrorCode;
-use crate::models::idl::accounts::{SpotMarket, User};
-use crate::models::idl::types::{SpotBalanceType, SpotPosition};
+use crate::math::constants::QUOTE_PRECISION;
+use crate::math::margin::{
+    calculate_health, calculate_margin_requirement_and_total_collateral, is_perp_position_empty,
+    MarginCalculation,
+};
+use crate::models::idl::accounts::{PerpMarket, SpotMarket, User};
+use crate::models::idl::types::{MarginRequirementType, SpotBalanceType, SpotPosition};
 use anchor_lang::AccountDeserialize;
 use common::{
     asset_utils::get_symbol_for_mint,
     lending::{LendingClient, LendingError},
-    ObligationType, UserObligation,
+    MarginSummary, ObligationHealth, ObligationType, UserObligation,
 };
-use common_rpc::{with_rpc_client, RpcError, RpcErrorConverter};
-use log::{debug, info};
-use solana_sdk::pubkey::Pubkey;
+use common_rpc::{rpc_client, RpcError, RpcErrorConverter};
+use log::{debug, info, warn};
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
+use std::collections::HashMap;
 use std::str::FromStr;
 
 // Define discriminators as constants
 const DRIFT_SPOT_MARKET_DISCRIMINATOR: [u8; 8] = [100, 177, 8, 107, 168, 65, 65, 39];
 const DRIFT_USER_DISCRIMINATOR: [u8; 8] = [159, 117, 95, 227, 239, 151, 58, 236]; // Correct User discriminator
 
+// Spot and perp markets keyed by market index
+pub type UserMarkets = (HashMap<u16, SpotMarket>, HashMap<u16, PerpMarket>);
+
 // Implement the RpcErrorConverter trait for LendingError
 struct DriftErrorConverter;
 
@@ -30,6 +39,26 @@ impl RpcErrorConverter<LendingError> for DriftErrorConverter {
     }
 }
 
+/// The spot positions with a balance of each user, along with the user and its sub-account id
+fn spot_positions(users: &[(Pubkey, User)]) -> Vec<(Pubkey, u16, SpotPosition)> {
+    users
+        .iter()
+        .flat_map(|(pubkey, user)| {
+            user.spot_positions
+                .iter()
+                .filter(|p| p.scaled_balance > 0)
+                .map(move |p| (*pubkey, user.sub_account_id, *p))
+        })
+        .collect()
+}
+
+fn accrual_error(market_pubkey: &Pubkey, error: ErrorCode) -> LendingError {
+    LendingError::ProtocolError(format!(
+        "Failed to accrue interest of spot market {}: {}",
+        market_pubkey, error
+    ))
+}
+
 pub struct DriftClient {
     program_id: Pubkey,
     rpc_url: String,
@@ -59,15 +88,15 @@ impl DriftClient {
         self.spot_markets = spot_markets;
     }
 
-    pub fn fetch_spot_markets(&self) -> Result<Vec<(Pubkey, SpotMarket)>, LendingError> {
+    pub async fn fetch_spot_markets(&self) -> Result<Vec<(Pubkey, SpotMarket)>, LendingError> {
         // Use the RPC builder with optimized filters
-        let accounts = with_rpc_client(&self.rpc_url, |client| {
-            common_rpc::SolanaRpcBuilder::new(client, self.program_id)
-                .with_memcmp(0, DRIFT_SPOT_MARKET_DISCRIMINATOR.to_vec())
-                .with_context(true)
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, DriftErrorConverter>()
-        })?;
+        let client = rpc_client(&self.rpc_url);
+        let accounts = common_rpc::SolanaRpcBuilder::new(&client, self.program_id)
+            .with_memcmp(0, DRIFT_SPOT_MARKET_DISCRIMINATOR.to_vec())
+            .with_context(true)
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, DriftErrorConverter>()
+            .await?;
 
         // Pre-allocate with capacity
         let mut spot_markets = Vec::with_capacity(accounts.len());
@@ -88,33 +117,46 @@ impl DriftClient {
         Ok(spot_markets)
     }
 
-    pub fn load_spot_markets(&mut self) -> Result<(), LendingError> {
-        self.spot_markets = self.fetch_spot_markets()?;
+    /// Accrue the interest of the loaded spot markets up to the time of the clock
+    pub fn accrue_interest(&mut self, clock: &Clock) {
+        for (pubkey, market) in &mut self.spot_markets {
+            if let Err(e) = market.accrue_interest(clock.unix_timestamp) {
+                warn!("{}", accrual_error(pubkey, e));
+            }
+        }
+    }
+
+    pub async fn load_spot_markets(&mut self) -> Result<(), LendingError> {
+        self.spot_markets = self.fetch_spot_markets().await?;
         Ok(())
     }
 
-    pub fn get_user_obligations(
+    pub async fn get_user_obligations(
         &self,
         owner_pubkey: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        let spot_positions = self.fetch_raw_obligations(owner_pubkey)?;
+        let users = self.fetch_users(owner_pubkey).await?;
+        if users.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Read along with the clock and accrued to it, so the balances are current
+        let (spot_markets, _) = self.fetch_user_markets(&users).await?;
         let mut obligations = Vec::new();
 
         // Cache protocol name to avoid repeated allocations
         let protocol_name = self.protocol_name().to_string();
 
-        for position in spot_positions {
+        for (account, sub_account_id, position) in spot_positions(&users) {
             let obligation_type = if position.balance_type == SpotBalanceType::Deposit {
                 ObligationType::Asset
             } else {
                 ObligationType::Liability
             };
 
-            let (market_symbol, mint, mint_decimals, market_name, amount) = self
-                .spot_markets
-                .iter()
-                .find(|(_, m)| m.market_index == position.market_index)
-                .map(|(_, market)| {
+            let (market_symbol, mint, mint_decimals, market_name, amount) = spot_markets
+                .get(&position.market_index)
+                .map(|market| {
                     let name = String::from_utf8_lossy(&market.name).trim().to_string();
                     let token_amount = match crate::models::spot_market::get_token_amount(
                         position.scaled_balance as u128,
@@ -147,6 +189,12 @@ impl DriftClient {
                 amount,
                 protocol_name: protocol_name.clone(),
                 market_name,
+                account: account.to_string(),
+                sub_account: Some(sub_account_id as u64),
+                // Derived rather than looked up so positions in markets not loaded still have it
+                reserve_pubkey: self
+                    .market_address(b"spot_market", position.market_index)
+                    .to_string(),
                 obligation_type,
             });
         }
@@ -154,59 +202,216 @@ impl DriftClient {
         Ok(obligations)
     }
 
-    fn fetch_raw_obligations(&self, owner_pubkey: &str) -> Result<Vec<SpotPosition>, LendingError> {
+    /// Computes the margin and health of each Drift sub-account owned by the wallet, valuing
+    /// positions at the markets' last oracle price
+    pub async fn get_obligation_health(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<ObligationHealth>, LendingError> {
+        let users = self.fetch_users(owner_pubkey).await?;
+        if users.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (spot_markets, perp_markets) = self.fetch_user_markets(&users).await?;
+
+        users
+            .iter()
+            .map(|(pubkey, user)| {
+                self.obligation_health(pubkey, user, &spot_markets, &perp_markets)
+            })
+            .collect()
+    }
+
+    fn obligation_health(
+        &self,
+        pubkey: &Pubkey,
+        user: &User,
+        spot_markets: &HashMap<u16, SpotMarket>,
+        perp_markets: &HashMap<u16, PerpMarket>,
+    ) -> Result<ObligationHealth, LendingError> {
+        let margin_error = |e: ErrorCode| {
+            LendingError::ProtocolError(format!("Drift margin calculation failed: {}", e))
+        };
+        let calculate = |margin_requirement_type| {
+            calculate_margin_requirement_and_total_collateral(
+                user,
+                spot_markets,
+                perp_markets,
+                margin_requirement_type,
+            )
+            .map_err(margin_error)
+        };
+        let initial = calculate(MarginRequirementType::Initial)?;
+        let maintenance = calculate(MarginRequirementType::Maintenance)?;
+
+        let to_usd = |value: i128| value as f64 / QUOTE_PRECISION as f64;
+
+        let free_collateral = initial.get_free_collateral().map_err(margin_error)?;
+        let health = calculate_health(maintenance.total_collateral, maintenance.margin_requirement)
+            .map_err(margin_error)?;
+        let net_health = |calculation: &MarginCalculation| {
+            to_usd(calculation.total_collateral) - to_usd(calculation.margin_requirement as i128)
+        };
+
+        let deposited_value = to_usd(initial.spot_asset_value as i128);
+        let borrowed_value = to_usd(initial.spot_liability_value as i128);
+        let maintenance_requirement = to_usd(maintenance.margin_requirement as i128);
+
+        let name = String::from_utf8_lossy(&user.name)
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string();
+        let market_name =
+            if name.is_empty() { format!("Sub-account {}", user.sub_account_id) } else { name };
+
+        Ok(ObligationHealth {
+            protocol_name: self.protocol_name().to_string(),
+            market_name,
+            account: pubkey.to_string(),
+            deposited_value,
+            borrowed_value,
+            loan_to_value: if deposited_value > 0.0 {
+                borrowed_value / deposited_value
+            } else {
+                0.0
+            },
+            // Perp positions share the collateral, so Drift has no per-account LTV thresholds
+            max_loan_to_value: 0.0,
+            liquidation_loan_to_value: 0.0,
+            remaining_borrow_value: to_usd(free_collateral as i128),
+            health_factor: (maintenance_requirement > 0.0)
+                .then(|| to_usd(maintenance.total_collateral) / maintenance_requirement),
+            elevation_group: None,
+            initial_health: Some(net_health(&initial)),
+            maintenance_health: Some(net_health(&maintenance)),
+            max_withdraw: Vec::new(),
+            margin: Some(MarginSummary {
+                total_collateral: to_usd(initial.total_collateral),
+                initial_margin_requirement: to_usd(initial.margin_requirement as i128),
+                maintenance_margin_requirement: maintenance_requirement,
+                free_collateral: to_usd(free_collateral as i128),
+                health,
+            }),
+        })
+    }
+
+    /// Fetches the spot and perp markets the users have positions in, keyed by market index. The
+    /// spot markets' interest is accrued to the clock read along with them.
+    pub async fn fetch_user_markets(
+        &self,
+        users: &[(Pubkey, User)],
+    ) -> Result<UserMarkets, LendingError> {
+        let mut spot_indexes: Vec<u16> = users
+            .iter()
+            .flat_map(|(_, user)| user.spot_positions.iter())
+            .filter(|p| p.scaled_balance > 0)
+            .map(|p| p.market_index)
+            .collect();
+        spot_indexes.sort_unstable();
+        spot_indexes.dedup();
+
+        let mut perp_indexes: Vec<u16> = users
+            .iter()
+            .flat_map(|(_, user)| user.perp_positions.iter())
+            .filter(|p| !is_perp_position_empty(p))
+            .map(|p| p.market_index)
+            .collect();
+        perp_indexes.sort_unstable();
+        perp_indexes.dedup();
+
+        let spot_addresses: Vec<Pubkey> =
+            spot_indexes.iter().map(|index| self.market_address(b"spot_market", *index)).collect();
+        let perp_addresses: Vec<Pubkey> =
+            perp_indexes.iter().map(|index| self.market_address(b"perp_market", *index)).collect();
+        let addresses: Vec<Pubkey> =
+            spot_addresses.iter().chain(perp_addresses.iter()).copied().collect();
+
+        let client = rpc_client(&self.rpc_url);
+        let (accounts, clock) = common_rpc::get_multiple_accounts_with_clock(&client, &addresses)
+            .await
+            .map_err(DriftErrorConverter::convert_error)?;
+
+        let get_data = |address: &Pubkey| {
+            accounts.get(address).map(|account| account.data.as_slice()).ok_or_else(|| {
+                LendingError::MarketNotFound(format!("Drift market {} not found", address))
+            })
+        };
+
+        let mut spot_markets = HashMap::with_capacity(spot_indexes.len());
+        for address in &spot_addresses {
+            let mut market = SpotMarket::try_deserialize(&mut get_data(address)?)
+                .map_err(|e| LendingError::DeserializationError(e.to_string()))?;
+            market.accrue_interest(clock.unix_timestamp).map_err(|e| accrual_error(address, e))?;
+            spot_markets.insert(market.market_index, market);
+        }
+
+        let mut perp_markets = HashMap::with_capacity(perp_indexes.len());
+        for address in &perp_addresses {
+            let market = PerpMarket::try_deserialize(&mut get_data(address)?)
+                .map_err(|e| LendingError::DeserializationError(e.to_string()))?;
+            perp_markets.insert(market.market_index, market);
+        }
+
+        Ok((spot_markets, perp_markets))
+    }
+
+    fn market_address(&self, seed: &[u8], market_index: u16) -> Pubkey {
+        Pubkey::find_program_address(&[seed, &market_index.to_le_bytes()], &self.program_id).0
+    }
+
+    /// Fetches every Drift sub-account owned by the wallet
+    pub async fn fetch_users(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<(Pubkey, User)>, LendingError> {
         let owner = Pubkey::from_str(owner_pubkey)
             .map_err(|e| LendingError::InvalidAddress(e.to_string()))?;
 
         // Use the RPC builder with optimized filters
-        let accounts = with_rpc_client(&self.rpc_url, |client| {
-            common_rpc::SolanaRpcBuilder::new(client, self.program_id)
-                .with_memcmp(0, DRIFT_USER_DISCRIMINATOR.to_vec())
-                .with_memcmp_pubkey(8, &owner)
-                .with_data_size(std::mem::size_of::<User>() as u64 + 8)
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, DriftErrorConverter>()
-        })?;
+        let client = rpc_client(&self.rpc_url);
+        let accounts = common_rpc::SolanaRpcBuilder::new(&client, self.program_id)
+            .with_memcmp(0, DRIFT_USER_DISCRIMINATOR.to_vec())
+            .with_memcmp_pubkey(8, &owner)
+            .with_data_size(std::mem::size_of::<User>() as u64 + 8)
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, DriftErrorConverter>()
+            .await?;
 
         if accounts.is_empty() {
             debug!("No Drift accounts found for {}", owner_pubkey);
             return Ok(Vec::new());
         }
 
-        // Pre-allocate with estimated capacity
-        let mut result = Vec::with_capacity(accounts.len() * 5); // Estimate 5 positions per account
-
-        for (_pubkey, account) in accounts {
-            let user = User::try_deserialize(&mut &account.data[..])
-                .map_err(|e| LendingError::DeserializationError(e.to_string()))?;
-
-            // Filter and extend in one operation to avoid intermediate allocations
-            result.extend(user.spot_positions.iter().filter(|p| p.scaled_balance > 0).copied());
-        }
-
-        Ok(result)
+        accounts
+            .into_iter()
+            .map(|(pubkey, account)| {
+                User::try_deserialize(&mut &account.data[..])
+                    .map(|user| (pubkey, user))
+                    .map_err(|e| LendingError::DeserializationError(e.to_string()))
+            })
+            .collect()
     }
 }
 
 impl LendingClient<Pubkey, Vec<(Pubkey, SpotMarket)>> for DriftClient {
-    fn load_markets(&mut self) -> Result<(), LendingError> {
-        self.spot_markets = self.fetch_markets()?;
+    async fn load_markets(&mut self) -> Result<(), LendingError> {
+        self.spot_markets = self.fetch_markets().await?;
         Ok(())
     }
 
-    fn fetch_markets(&self) -> Result<Vec<(Pubkey, SpotMarket)>, LendingError> {
-        self.fetch_spot_markets()
+    async fn fetch_markets(&self) -> Result<Vec<(Pubkey, SpotMarket)>, LendingError> {
+        self.fetch_spot_markets().await
     }
 
     fn set_market_data(&mut self, data: Vec<(Pubkey, SpotMarket)>) {
         self.spot_markets = data;
     }
 
-    fn get_user_obligations(
+    async fn get_user_obligations(
         &self,
         wallet_address: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        self.get_user_obligations(wallet_address)
+        self.get_user_obligations(wallet_address).await
     }
 
     fn program_id(&self) -> Pubkey {
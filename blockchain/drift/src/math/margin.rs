@@ -0,0 +1,334 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+
+use crate::casting::Cast;
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::constants::{
+    AMM_RESERVE_PRECISION, AMM_RESERVE_PRECISION_I128, FUNDING_RATE_BUFFER, MARGIN_PRECISION_U128,
+    SPOT_IMF_PRECISION_U128, SPOT_WEIGHT_PRECISION_U128,
+};
+use crate::math::safe_math::SafeMath;
+use crate::models::idl::accounts::{PerpMarket, SpotMarket, User};
+use crate::models::idl::types::{MarginRequirementType, PerpPosition, SpotBalanceType};
+use crate::models::spot_market::get_token_amount;
+
+/// Collateral and margin requirement of a user account, in QUOTE_PRECISION
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarginCalculation {
+    /// Weighted spot deposits plus weighted unrealized perp pnl
+    pub total_collateral: i128,
+    /// Weighted spot borrows plus the margin required by perp positions
+    pub margin_requirement: u128,
+    /// Unweighted value of the spot deposits
+    pub spot_asset_value: u128,
+    /// Unweighted value of the spot borrows
+    pub spot_liability_value: u128,
+}
+
+impl MarginCalculation {
+    pub fn get_free_collateral(&self) -> DriftResult<u128> {
+        Ok(self.total_collateral.safe_sub(self.margin_requirement.cast()?)?.max(0).cast()?)
+    }
+
+    pub fn meets_margin_requirement(&self) -> bool {
+        self.total_collateral >= self.margin_requirement as i128
+    }
+}
+
+/// Sums the collateral and margin requirement of every spot and perp position of the user,
+/// valued at the markets' last oracle price. Open spot orders and LP shares are not included.
+pub fn calculate_margin_requirement_and_total_collateral(
+    user: &User,
+    spot_markets: &HashMap<u16, SpotMarket>,
+    perp_markets: &HashMap<u16, PerpMarket>,
+    margin_requirement_type: MarginRequirementType,
+) -> DriftResult<MarginCalculation> {
+    let mut calculation = MarginCalculation::default();
+
+    for spot_position in user.spot_positions.iter().filter(|p| p.scaled_balance > 0) {
+        let spot_market =
+            spot_markets.get(&spot_position.market_index).ok_or(ErrorCode::SpotMarketNotFound)?;
+        let oracle_price = spot_market.historical_oracle_data.last_oracle_price;
+
+        let token_amount = get_token_amount(
+            spot_position.scaled_balance.cast()?,
+            spot_market,
+            &spot_position.balance_type,
+        )?;
+        let token_value =
+            get_token_value(token_amount.cast()?, spot_market.decimals, oracle_price)?
+                .cast::<u128>()?;
+
+        match spot_position.balance_type {
+            SpotBalanceType::Deposit => {
+                let asset_weight = spot_market.get_asset_weight(
+                    token_amount,
+                    oracle_price,
+                    &margin_requirement_type,
+                )?;
+                let weighted_value = token_value
+                    .safe_mul(asset_weight.cast()?)?
+                    .safe_div(SPOT_WEIGHT_PRECISION_U128)?;
+
+                calculation.spot_asset_value =
+                    calculation.spot_asset_value.safe_add(token_value)?;
+                calculation.total_collateral =
+                    calculation.total_collateral.safe_add(weighted_value.cast()?)?;
+            }
+            SpotBalanceType::Borrow => {
+                let liability_weight =
+                    spot_market.get_liability_weight(token_amount, &margin_requirement_type)?;
+                let weighted_value = token_value
+                    .safe_mul(liability_weight.cast()?)?
+                    .safe_div_ceil(SPOT_WEIGHT_PRECISION_U128)?;
+
+                calculation.spot_liability_value =
+                    calculation.spot_liability_value.safe_add(token_value)?;
+                calculation.margin_requirement =
+                    calculation.margin_requirement.safe_add(weighted_value)?;
+            }
+        }
+    }
+
+    for perp_position in user.perp_positions.iter().filter(|p| !is_perp_position_empty(p)) {
+        let perp_market =
+            perp_markets.get(&perp_position.market_index).ok_or(ErrorCode::PerpMarketNotFound)?;
+        let oracle_price = perp_market.amm.historical_oracle_data.last_oracle_price;
+
+        // Open orders can grow the position up to the worst case base amount
+        let worst_case_base_asset_amount = max(
+            perp_position.base_asset_amount.safe_add(perp_position.open_bids)?.unsigned_abs(),
+            perp_position.base_asset_amount.safe_add(perp_position.open_asks)?.unsigned_abs(),
+        );
+        let worst_case_base_asset_value =
+            calculate_base_asset_value(worst_case_base_asset_amount.cast()?, oracle_price)?;
+
+        let margin_ratio = perp_market
+            .get_margin_ratio(worst_case_base_asset_amount.cast()?, &margin_requirement_type)?
+            .max(match margin_requirement_type {
+                MarginRequirementType::Initial => user.max_margin_ratio,
+                _ => 0,
+            });
+        let perp_margin_requirement = worst_case_base_asset_value
+            .safe_mul(margin_ratio.cast()?)?
+            .safe_div(MARGIN_PRECISION_U128)?;
+
+        calculation.margin_requirement =
+            calculation.margin_requirement.safe_add(perp_margin_requirement)?;
+
+        let unrealized_pnl = calculate_unrealized_pnl(perp_position, perp_market, oracle_price)?;
+        let weighted_unrealized_pnl = if unrealized_pnl > 0 {
+            let pnl_asset_weight = match margin_requirement_type {
+                MarginRequirementType::Maintenance => {
+                    perp_market.unrealized_pnl_maintenance_asset_weight
+                }
+                _ => perp_market.unrealized_pnl_initial_asset_weight,
+            };
+            unrealized_pnl
+                .safe_mul(pnl_asset_weight.cast()?)?
+                .safe_div(SPOT_WEIGHT_PRECISION_U128.cast()?)?
+        } else {
+            unrealized_pnl
+        };
+
+        calculation.total_collateral =
+            calculation.total_collateral.safe_add(weighted_unrealized_pnl)?;
+    }
+
+    Ok(calculation)
+}
+
+/// Health from 0 to 100, 0 meaning the account can be liquidated
+pub fn calculate_health(
+    total_collateral: i128,
+    maintenance_margin_requirement: u128,
+) -> DriftResult<u8> {
+    if maintenance_margin_requirement == 0 {
+        return Ok(100);
+    }
+    if total_collateral <= 0 {
+        return Ok(0);
+    }
+
+    let used =
+        maintenance_margin_requirement.safe_mul(100)?.safe_div(total_collateral.cast::<u128>()?)?;
+
+    Ok(100_u128.saturating_sub(used).cast::<u8>()?)
+}
+
+pub fn get_token_value(token_amount: i128, decimals: u32, oracle_price: i64) -> DriftResult<i128> {
+    if token_amount == 0 {
+        return Ok(0);
+    }
+
+    let precision_decrease = 10_i128.pow(decimals);
+
+    token_amount.safe_mul(oracle_price.cast()?)?.safe_div(precision_decrease)
+}
+
+/// Value of a base asset amount in QUOTE_PRECISION
+pub fn calculate_base_asset_value(base_asset_amount: u128, oracle_price: i64) -> DriftResult<u128> {
+    base_asset_amount.safe_mul(oracle_price.unsigned_abs().cast()?)?.safe_div(AMM_RESERVE_PRECISION)
+}
+
+/// Unrealized pnl of a perp position including the funding not yet settled, in QUOTE_PRECISION
+pub fn calculate_unrealized_pnl(
+    perp_position: &PerpPosition,
+    perp_market: &PerpMarket,
+    oracle_price: i64,
+) -> DriftResult<i128> {
+    let base_asset_value = perp_position
+        .base_asset_amount
+        .cast::<i128>()?
+        .safe_mul(oracle_price.cast()?)?
+        .safe_div(AMM_RESERVE_PRECISION_I128)?;
+
+    let amm_cumulative_funding_rate = if perp_position.base_asset_amount > 0 {
+        perp_market.amm.cumulative_funding_rate_long
+    } else {
+        perp_market.amm.cumulative_funding_rate_short
+    };
+    let funding_payment = calculate_funding_payment(amm_cumulative_funding_rate, perp_position)?;
+
+    base_asset_value.safe_add(perp_position.quote_asset_amount.cast()?)?.safe_add(funding_payment)
+}
+
+/// Funding owed to (positive) or by (negative) the position since it was last settled
+pub fn calculate_funding_payment(
+    amm_cumulative_funding_rate: i128,
+    perp_position: &PerpPosition,
+) -> DriftResult<i128> {
+    let funding_rate_delta =
+        amm_cumulative_funding_rate.safe_sub(perp_position.last_cumulative_funding_rate.cast()?)?;
+
+    if funding_rate_delta == 0 || perp_position.base_asset_amount == 0 {
+        return Ok(0);
+    }
+
+    let funding_payment_magnitude = funding_rate_delta
+        .unsigned_abs()
+        .safe_mul(perp_position.base_asset_amount.unsigned_abs().cast()?)?
+        .safe_div(AMM_RESERVE_PRECISION)?
+        .safe_div(FUNDING_RATE_BUFFER)?
+        .cast::<i128>()?;
+
+    // Longs pay shorts when the funding rate is positive
+    let same_sign = (funding_rate_delta > 0) == (perp_position.base_asset_amount > 0);
+
+    Ok(if same_sign { -funding_payment_magnitude } else { funding_payment_magnitude })
+}
+
+pub fn calculate_size_discount_asset_weight(
+    size: u128, // AMM_RESERVE_PRECISION
+    imf_factor: u32,
+    asset_weight: u32,
+) -> DriftResult<u32> {
+    if imf_factor == 0 {
+        return Ok(asset_weight);
+    }
+
+    let size_sqrt = size.safe_mul(10)?.safe_add(1)?.isqrt(); //1e9 -> 1e10 -> 1e5
+    let imf_numerator = SPOT_IMF_PRECISION_U128 + SPOT_IMF_PRECISION_U128 / 10;
+
+    let size_discount_asset_weight = imf_numerator
+        .safe_mul(SPOT_WEIGHT_PRECISION_U128)?
+        .safe_div(
+            SPOT_IMF_PRECISION_U128
+                .safe_add(size_sqrt.safe_mul(imf_factor.cast()?)?.safe_div(100_000)?)?,
+        )?
+        .cast::<u32>()?;
+
+    Ok(min(asset_weight, size_discount_asset_weight))
+}
+
+pub fn calculate_size_premium_liability_weight(
+    size: u128, // AMM_RESERVE_PRECISION
+    imf_factor: u32,
+    liability_weight: u32,
+    precision: u128,
+) -> DriftResult<u32> {
+    if imf_factor == 0 {
+        return Ok(liability_weight);
+    }
+
+    let size_sqrt = size.safe_mul(10)?.safe_add(1)?.isqrt(); //1e9 -> 1e10 -> 1e5
+    let liability_weight_u128 = liability_weight.cast::<u128>()?;
+    let liability_weight_numerator = liability_weight_u128.safe_sub(liability_weight_u128 / 5)?;
+
+    let denom = 100_000 * SPOT_IMF_PRECISION_U128 / precision;
+    let size_premium_liability_weight = liability_weight_numerator
+        .safe_add(size_sqrt.safe_mul(imf_factor.cast()?)?.safe_div(denom)?)?
+        .cast::<u32>()?;
+
+    Ok(max(liability_weight, size_premium_liability_weight))
+}
+
+pub fn is_perp_position_empty(perp_position: &PerpPosition) -> bool {
+    perp_position.base_asset_amount == 0
+        && perp_position.quote_asset_amount == 0
+        && perp_position.open_orders == 0
+        && perp_position.lp_shares == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::constants::BASE_PRECISION;
+
+    #[test]
+    fn size_adjusted_weights() {
+        let size = 1_000 * BASE_PRECISION;
+
+        assert_eq!(calculate_size_discount_asset_weight(size, 0, 8_000), Ok(8_000));
+        assert_eq!(calculate_size_discount_asset_weight(size, 1_000, 8_000), Ok(8_000));
+        assert_eq!(calculate_size_discount_asset_weight(size, 100_000, 8_000), Ok(2_642));
+
+        assert_eq!(
+            calculate_size_premium_liability_weight(
+                size,
+                1_000,
+                12_000,
+                SPOT_WEIGHT_PRECISION_U128
+            ),
+            Ok(12_000)
+        );
+        assert_eq!(
+            calculate_size_premium_liability_weight(
+                size,
+                100_000,
+                12_000,
+                SPOT_WEIGHT_PRECISION_U128
+            ),
+            Ok(41_222)
+        );
+    }
+
+    #[test]
+    fn funding_payment_sign() {
+        let long = PerpPosition { base_asset_amount: BASE_PRECISION as i64, ..Default::default() };
+        let short =
+            PerpPosition { base_asset_amount: -(BASE_PRECISION as i64), ..Default::default() };
+
+        // Positive funding: longs pay shorts
+        assert_eq!(calculate_funding_payment(1_000_000, &long), Ok(-1_000));
+        assert_eq!(calculate_funding_payment(1_000_000, &short), Ok(1_000));
+        assert_eq!(calculate_funding_payment(-1_000_000, &long), Ok(1_000));
+        assert_eq!(calculate_funding_payment(0, &long), Ok(0));
+    }
+
+    #[test]
+    fn health() {
+        assert_eq!(calculate_health(1_000, 0), Ok(100));
+        assert_eq!(calculate_health(1_000, 250), Ok(75));
+        assert_eq!(calculate_health(1_000, 2_000), Ok(0));
+        assert_eq!(calculate_health(-1, 1), Ok(0));
+
+        let calculation = MarginCalculation {
+            total_collateral: 1_000,
+            margin_requirement: 400,
+            ..Default::default()
+        };
+        assert_eq!(calculation.get_free_collateral(), Ok(600));
+        assert!(calculation.meets_margin_requirement());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ceil_div;
 pub mod constants;
 pub mod floor_div;
+pub mod margin;
 pub mod safe_math;
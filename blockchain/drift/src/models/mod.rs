@@ -1,2 +1,3 @@
 pub mod idl;
+pub mod perp_market;
 pub mod spot_market;
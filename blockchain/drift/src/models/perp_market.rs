@@ -0,0 +1,38 @@
+use crate::error::DriftResult;
+use crate::math::constants::MARGIN_PRECISION_U128;
+use crate::math::margin::calculate_size_premium_liability_weight;
+use crate::math::safe_math::SafeMath;
+
+use super::idl::accounts::PerpMarket;
+use super::idl::types::{MarginRequirementType, MarketStatus};
+
+impl PerpMarket {
+    /// Margin ratio for a position of `size` base asset, growing with the size once the market
+    /// has an initial margin fraction factor
+    pub fn get_margin_ratio(
+        &self,
+        size: u128,
+        margin_requirement_type: &MarginRequirementType,
+    ) -> DriftResult<u32> {
+        let default_margin_ratio = match margin_requirement_type {
+            MarginRequirementType::Initial => self.margin_ratio_initial,
+            MarginRequirementType::Fill => {
+                self.margin_ratio_initial.safe_add(self.margin_ratio_maintenance)? / 2
+            }
+            MarginRequirementType::Maintenance => self.margin_ratio_maintenance,
+        };
+
+        let size_adj_margin_ratio = calculate_size_premium_liability_weight(
+            size,
+            self.imf_factor,
+            default_margin_ratio,
+            MARGIN_PRECISION_U128,
+        )?;
+
+        Ok(default_margin_ratio.max(size_adj_margin_ratio))
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == MarketStatus::Active
+    }
+}
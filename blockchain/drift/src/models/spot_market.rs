@@ -2,11 +2,17 @@ use rust_decimal::Decimal;
 
 use crate::casting::Cast;
 use crate::error::{DriftResult, ErrorCode};
-use crate::math::constants::{PERCENTAGE_PRECISION, SPOT_UTILIZATION_PRECISION};
+use crate::math::constants::{
+    AMM_RESERVE_PRECISION, ONE_YEAR, PERCENTAGE_PRECISION, SPOT_RATE_PRECISION,
+    SPOT_UTILIZATION_PRECISION, SPOT_WEIGHT_PRECISION_U128,
+};
+use crate::math::margin::{
+    calculate_size_discount_asset_weight, calculate_size_premium_liability_weight, get_token_value,
+};
 use crate::math::safe_math::SafeMath;
 use rust_decimal::prelude::*;
 
-use super::idl::types::{MarketStatus, SpotBalanceType};
+use super::idl::types::{MarginRequirementType, MarketStatus, SpotBalanceType};
 use crate::models::idl::accounts::SpotMarket;
 
 impl SpotMarket {
@@ -66,6 +72,48 @@ impl SpotMarket {
         Ok(apy_u128)
     }
 
+    /// Accrue the interest since `last_interest_ts` into the cumulative interests, the way the
+    /// program updates them before any balance change of the market
+    pub fn accrue_interest(&mut self, now: i64) -> DriftResult<()> {
+        let now = now.max(0).cast::<u64>()?;
+        if now <= self.last_interest_ts {
+            return Ok(());
+        }
+
+        let utilization = self.get_utilization()?;
+        if utilization == 0 {
+            self.last_interest_ts = now;
+            return Ok(());
+        }
+
+        // The rates are multiplied by the elapsed time first and divided by a year at the end
+        let time_since_last_update = now.safe_sub(self.last_interest_ts)?;
+        let borrow_rate = calculate_borrow_rate(self, utilization)?;
+        let modified_borrow_rate = borrow_rate.safe_mul(time_since_last_update.cast()?)?;
+        let modified_deposit_rate =
+            calculate_deposit_rate(self, utilization, modified_borrow_rate)?;
+
+        let borrow_interest = self
+            .cumulative_borrow_interest
+            .safe_mul(modified_borrow_rate)?
+            .safe_div(ONE_YEAR)?
+            .safe_div(SPOT_RATE_PRECISION)?
+            .safe_add(1)?;
+        let deposit_interest = self
+            .cumulative_deposit_interest
+            .safe_mul(modified_deposit_rate)?
+            .safe_div(ONE_YEAR)?
+            .safe_div(SPOT_RATE_PRECISION)?;
+
+        self.cumulative_borrow_interest =
+            self.cumulative_borrow_interest.safe_add(borrow_interest)?;
+        self.cumulative_deposit_interest =
+            self.cumulative_deposit_interest.safe_add(deposit_interest)?;
+        self.last_interest_ts = now;
+
+        Ok(())
+    }
+
     pub fn get_deposits(&self) -> DriftResult<u128> {
         get_token_amount(self.deposit_balance, self, &SpotBalanceType::Deposit)
     }
@@ -111,6 +159,88 @@ impl SpotMarket {
     pub fn is_active(&self) -> bool {
         self.status == MarketStatus::Active
     }
+
+    pub fn get_asset_weight(
+        &self,
+        size: u128,
+        oracle_price: i64,
+        margin_requirement_type: &MarginRequirementType,
+    ) -> DriftResult<u32> {
+        let size_in_amm_reserve_precision = self.get_size_in_amm_reserve_precision(size)?;
+
+        let asset_weight = match margin_requirement_type {
+            MarginRequirementType::Initial => self.get_scaled_initial_asset_weight(oracle_price)?,
+            MarginRequirementType::Fill => {
+                self.get_scaled_initial_asset_weight(oracle_price)?
+                    .safe_add(self.maintenance_asset_weight)?
+                    / 2
+            }
+            MarginRequirementType::Maintenance => self.maintenance_asset_weight,
+        };
+
+        calculate_size_discount_asset_weight(
+            size_in_amm_reserve_precision,
+            self.imf_factor,
+            asset_weight,
+        )
+    }
+
+    pub fn get_liability_weight(
+        &self,
+        size: u128,
+        margin_requirement_type: &MarginRequirementType,
+    ) -> DriftResult<u32> {
+        let size_in_amm_reserve_precision = self.get_size_in_amm_reserve_precision(size)?;
+
+        let liability_weight = match margin_requirement_type {
+            MarginRequirementType::Initial => self.initial_liability_weight,
+            MarginRequirementType::Fill => {
+                self.initial_liability_weight.safe_add(self.maintenance_liability_weight)? / 2
+            }
+            MarginRequirementType::Maintenance => self.maintenance_liability_weight,
+        };
+
+        calculate_size_premium_liability_weight(
+            size_in_amm_reserve_precision,
+            self.imf_factor,
+            liability_weight,
+            SPOT_WEIGHT_PRECISION_U128,
+        )
+    }
+
+    /// Initial asset weight, scaled down once the market's deposits are worth more than
+    /// `scale_initial_asset_weight_start`
+    pub fn get_scaled_initial_asset_weight(&self, oracle_price: i64) -> DriftResult<u32> {
+        if self.scale_initial_asset_weight_start == 0 {
+            return Ok(self.initial_asset_weight);
+        }
+
+        let deposits = self.get_deposits()?;
+        let deposit_value =
+            get_token_value(deposits.cast()?, self.decimals, oracle_price)?.cast::<u128>()?;
+
+        let asset_weight = if deposit_value < self.scale_initial_asset_weight_start.cast()? {
+            self.initial_asset_weight
+        } else {
+            self.initial_asset_weight
+                .cast::<u128>()?
+                .safe_mul(self.scale_initial_asset_weight_start.cast()?)?
+                .safe_div(deposit_value)?
+                .cast::<u32>()?
+        };
+
+        Ok(asset_weight)
+    }
+
+    fn get_size_in_amm_reserve_precision(&self, size: u128) -> DriftResult<u128> {
+        let size_precision = 10_u128.pow(self.decimals);
+
+        if size_precision > AMM_RESERVE_PRECISION {
+            size.safe_div(size_precision / AMM_RESERVE_PRECISION)
+        } else {
+            size.safe_mul(AMM_RESERVE_PRECISION)?.safe_div(size_precision)
+        }
+    }
 }
 
 pub fn calculate_borrow_rate(spot_market: &SpotMarket, utilization: u128) -> DriftResult<u128> {
@@ -198,6 +328,27 @@ pub fn get_token_amount(
     Ok(token_amount)
 }
 
+/// Scaled balance worth `token_amount`, the inverse of `get_token_amount`
+pub fn get_spot_balance(
+    token_amount: u128,
+    spot_market: &SpotMarket,
+    balance_type: &SpotBalanceType,
+) -> DriftResult<u128> {
+    let precision_increase = 10_u128.pow(19_u32.safe_sub(spot_market.decimals)?);
+
+    let cumulative_interest = match balance_type {
+        SpotBalanceType::Deposit => spot_market.cumulative_deposit_interest,
+        SpotBalanceType::Borrow => spot_market.cumulative_borrow_interest,
+    };
+
+    let scaled_amount = token_amount.safe_mul(precision_increase)?;
+
+    match balance_type {
+        SpotBalanceType::Deposit => scaled_amount.safe_div(cumulative_interest),
+        SpotBalanceType::Borrow => scaled_amount.safe_div_ceil(cumulative_interest),
+    }
+}
+
 pub fn calculate_deposit_rate(
     spot_market: &SpotMarket,
     utilization: u128,
@@ -209,3 +360,80 @@ pub fn calculate_deposit_rate(
         .safe_div(SPOT_UTILIZATION_PRECISION)?
         .safe_div(PERCENTAGE_PRECISION)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::constants::{SPOT_BALANCE_PRECISION, SPOT_CUMULATIVE_INTEREST_PRECISION};
+
+    fn half_borrowed_market() -> SpotMarket {
+        SpotMarket {
+            decimals: 6,
+            deposit_balance: 1_000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 500 * SPOT_BALANCE_PRECISION,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            optimal_utilization: 500_000,
+            optimal_borrow_rate: 100_000,
+            max_borrow_rate: 1_000_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accrue_interest_grows_cumulative_interest() {
+        let mut market = half_borrowed_market();
+
+        // A year at 10% on borrows, half of which is lent out, pays depositors 5%
+        let now = ONE_YEAR as i64;
+        market.accrue_interest(now).unwrap();
+        assert_eq!(market.cumulative_borrow_interest, 11_000_000_001);
+        assert_eq!(market.cumulative_deposit_interest, 10_500_000_000);
+        assert_eq!(market.last_interest_ts, ONE_YEAR as u64);
+
+        // Accruing again to the same time changes nothing
+        let accrued = market;
+        market.accrue_interest(now).unwrap();
+        assert_eq!(market, accrued);
+    }
+
+    #[test]
+    fn accrue_interest_leaves_market_newer_than_now() {
+        let mut market = SpotMarket { last_interest_ts: 1_000, ..half_borrowed_market() };
+
+        let updated = market;
+        market.accrue_interest(900).unwrap();
+        assert_eq!(market, updated);
+    }
+
+    #[test]
+    fn get_spot_balance_inverts_get_token_amount() {
+        let market = SpotMarket {
+            cumulative_deposit_interest: 11_000_000_000,
+            cumulative_borrow_interest: 11_000_000_000,
+            ..half_borrowed_market()
+        };
+
+        // 110 tokens at 10% accrued interest are 100 tokens of balance
+        let balance = get_spot_balance(110_000_000, &market, &SpotBalanceType::Deposit).unwrap();
+        assert_eq!(balance, 100 * SPOT_BALANCE_PRECISION);
+        assert_eq!(
+            get_token_amount(balance, &market, &SpotBalanceType::Deposit).unwrap(),
+            110_000_000
+        );
+
+        // Deposits round down and borrows round up
+        assert_eq!(get_spot_balance(1, &market, &SpotBalanceType::Deposit).unwrap(), 909);
+        assert_eq!(get_spot_balance(1, &market, &SpotBalanceType::Borrow).unwrap(), 910);
+    }
+
+    #[test]
+    fn accrue_interest_without_borrows_only_moves_time() {
+        let mut market = SpotMarket { borrow_balance: 0, ..half_borrowed_market() };
+
+        market.accrue_interest(1_000).unwrap();
+        assert_eq!(market.cumulative_borrow_interest, SPOT_CUMULATIVE_INTEREST_PRECISION);
+        assert_eq!(market.cumulative_deposit_interest, SPOT_CUMULATIVE_INTEREST_PRECISION);
+        assert_eq!(market.last_interest_ts, 1_000);
+    }
+}
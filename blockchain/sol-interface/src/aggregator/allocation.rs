@@ -0,0 +1,249 @@
+use crate::{
+    aggregator::{
+        client::LendingMarketAggregator,
+        normalize::PoolLiquidityConfig,
+        simulate::{LiquidityAction, ReserveModel},
+    },
+    common::client_trait::ClientError,
+};
+use common::{AllocationPlan, ReserveAllocation};
+use log::info;
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::HashMap};
+
+/// Number of steps the amount is deposited in, each step going to a single reserve
+const ALLOCATION_STEPS: u64 = 100;
+
+/// Normalized rates are Kamino fractions (60 fractional bits) multiplied by 1000
+const NORMALIZED_RATE_SCALE: f64 = (1u128 << 60) as f64 * 1000.0;
+
+/// Limits applied when splitting a deposit across reserves
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AllocationConstraints {
+    /// Largest share of the amount a single protocol can receive, between 0 and 1
+    pub max_protocol_share: Option<f64>,
+    /// Reserves with a smaller total supply, in native tokens, are left out
+    pub min_reserve_tvl: u64,
+    /// Protocols left out, matched case-insensitively
+    pub excluded_protocols: Vec<String>,
+}
+
+/// A reserve taking part in the allocation
+struct Candidate<'a> {
+    protocol_name: &'static str,
+    market_name: String,
+    /// Supply APY of the reserve after depositing the given amount
+    supply_apy: Box<dyn Fn(u64) -> Option<f64> + 'a>,
+    /// Deposit the reserve takes before reaching its cap
+    capacity: u64,
+    allocated: u64,
+    /// Supply APY once `allocated` is deposited
+    current_apy: f64,
+}
+
+impl Candidate<'_> {
+    /// Yield per token added by depositing `amount` more, net of the rate drop it causes on the
+    /// tokens already allocated. Returned with the supply APY after the deposit.
+    fn marginal_rate(&self, amount: u64) -> Option<(f64, f64)> {
+        if amount == 0 {
+            return None;
+        }
+
+        let deposit = self.allocated.checked_add(amount)?;
+        let supply_apy = (self.supply_apy)(deposit)?;
+        let added_yield = deposit as f64 * supply_apy - self.allocated as f64 * self.current_apy;
+
+        Some((added_yield / amount as f64, supply_apy))
+    }
+}
+
+/// Deposits `amount` step by step into the candidate adding the most yield, returning the part
+/// left unallocated. Supply rates fall as deposits grow, so each step's best reserve is also
+/// part of the optimal split.
+fn fill(candidates: &mut [Candidate], amount: u64, protocol_limit: u64) -> u64 {
+    let step = amount.div_ceil(ALLOCATION_STEPS).max(1);
+    let mut protocol_allocated: HashMap<&'static str, u64> = HashMap::new();
+    let mut remaining = amount;
+
+    while remaining > 0 {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| {
+                let protocol_left = protocol_limit
+                    - protocol_allocated.get(candidate.protocol_name).copied().unwrap_or(0);
+                let deposit = step
+                    .min(remaining)
+                    .min(candidate.capacity - candidate.allocated)
+                    .min(protocol_left);
+                let (rate, supply_apy) = candidate.marginal_rate(deposit)?;
+                Some((index, deposit, rate, supply_apy))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+
+        let Some((index, deposit, _, supply_apy)) = best else {
+            break;
+        };
+
+        let candidate = &mut candidates[index];
+        candidate.allocated += deposit;
+        candidate.current_apy = supply_apy;
+        *protocol_allocated.entry(candidate.protocol_name).or_default() += deposit;
+        remaining -= deposit;
+    }
+
+    remaining
+}
+
+impl LendingMarketAggregator {
+    /// Splits `amount` native tokens of `mint` across the loaded reserves to maximise the blended
+    /// supply APY, reward APYs left out. Deposit caps are respected and each deposit's effect on
+    /// its reserve's rate is taken from the protocol's own interest rate model.
+    pub fn solve_allocation(
+        &self,
+        mint: &str,
+        amount: u64,
+        constraints: &AllocationConstraints,
+    ) -> Result<AllocationPlan, ClientError> {
+        let scale = PoolLiquidityConfig::SUPPLY_SCALE.as_u128();
+        let min_tvl = (constraints.min_reserve_tvl as u128).saturating_mul(scale);
+        let is_excluded = |model: &ReserveModel| {
+            constraints
+                .excluded_protocols
+                .iter()
+                .any(|protocol| protocol.eq_ignore_ascii_case(model.protocol_name()))
+        };
+
+        let models = self.reserve_models(mint);
+        let mut candidates: Vec<Candidate> = models
+            .iter()
+            .filter(|model| !is_excluded(model))
+            .filter_map(|model| {
+                let reserve = model.simulate(LiquidityAction::Deposit, 0).ok()?;
+                let capacity = reserve
+                    .remaining_deposit_capacity
+                    .map_or(u64::MAX, |capacity| (capacity / scale).try_into().unwrap_or(u64::MAX));
+
+                (reserve.total_supply >= min_tvl && capacity > 0).then(|| Candidate {
+                    protocol_name: model.protocol_name(),
+                    market_name: model.market_name().to_string(),
+                    supply_apy: Box::new(move |deposit| {
+                        let reserve = model.simulate(LiquidityAction::Deposit, deposit).ok()?;
+                        Some(reserve.supply_apy as f64)
+                    }),
+                    capacity,
+                    allocated: 0,
+                    current_apy: reserve.supply_apy as f64,
+                })
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            return Err(ClientError::MarketNotFound(format!(
+                "No reserve of {} matches the allocation constraints",
+                mint
+            )));
+        }
+
+        info!("Allocating {} of {} across {} reserves", amount, mint, candidates.len());
+        let protocol_limit = constraints
+            .max_protocol_share
+            .map_or(amount, |share| (amount as f64 * share.clamp(0.0, 1.0)) as u64);
+        let unallocated = fill(&mut candidates, amount, protocol_limit);
+
+        let allocated = amount - unallocated;
+        let blended_supply_apy = if allocated == 0 {
+            0.0
+        } else {
+            candidates.iter().map(|c| c.allocated as f64 * c.current_apy).sum::<f64>()
+                / allocated as f64
+        };
+
+        let marginal_step = amount.div_ceil(ALLOCATION_STEPS).max(1);
+        let mut allocations: Vec<ReserveAllocation> = candidates
+            .iter()
+            .map(|candidate| ReserveAllocation {
+                protocol_name: candidate.protocol_name.to_string(),
+                market_name: candidate.market_name.clone(),
+                amount: candidate.allocated,
+                supply_apy: candidate.current_apy as u128,
+                marginal_rate: candidate
+                    .marginal_rate(marginal_step)
+                    .map_or(0, |(rate, _)| rate.max(0.0) as u128),
+            })
+            .collect();
+        allocations.sort_by_key(|allocation| Reverse(allocation.amount));
+
+        Ok(AllocationPlan {
+            mint: mint.to_string(),
+            amount,
+            unallocated,
+            blended_supply_apy: blended_supply_apy as u128,
+            allocations,
+        })
+    }
+
+    pub fn print_allocation(&self, plan: &AllocationPlan) {
+        use prettytable::{row, Table};
+
+        let percent = |rate: u128| format!("{:.2}%", rate as f64 / NORMALIZED_RATE_SCALE * 100.0);
+
+        let mut table = Table::new();
+        table.add_row(row!["Protocol", "Market", "Amount", "Supply APY", "Marginal Rate"]);
+        for allocation in &plan.allocations {
+            table.add_row(row![
+                allocation.protocol_name,
+                allocation.market_name,
+                allocation.amount,
+                percent(allocation.supply_apy),
+                percent(allocation.marginal_rate)
+            ]);
+        }
+
+        table.printstd();
+        println!(
+            "Blended supply APY: {} ({} of {} unallocated)",
+            percent(plan.blended_supply_apy),
+            plan.unallocated,
+            plan.amount
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(protocol_name: &'static str, supply_apy: fn(u64) -> f64) -> Candidate<'static> {
+        Candidate {
+            protocol_name,
+            market_name: protocol_name.to_string(),
+            supply_apy: Box::new(move |deposit| Some(supply_apy(deposit))),
+            capacity: u64::MAX,
+            allocated: 0,
+            current_apy: supply_apy(0),
+        }
+    }
+
+    #[test]
+    fn fill_equalizes_marginal_rates() {
+        // Yield on A is 0.1x - 0.0001x², its marginal rate falls to B's 8% at x = 100
+        let mut candidates =
+            [candidate("A", |deposit| 0.10 - deposit as f64 * 0.0001), candidate("B", |_| 0.08)];
+
+        assert_eq!(fill(&mut candidates, 1_000, 1_000), 0);
+        assert!((90..=110).contains(&candidates[0].allocated));
+        assert_eq!(candidates[0].allocated + candidates[1].allocated, 1_000);
+    }
+
+    #[test]
+    fn fill_respects_protocol_share_and_capacity() {
+        let mut candidates = [candidate("A", |_| 0.10), candidate("B", |_| 0.05)];
+        candidates[1].capacity = 200;
+
+        assert_eq!(fill(&mut candidates, 1_000, 500), 300);
+        assert_eq!(candidates[0].allocated, 500);
+        assert_eq!(candidates[1].allocated, 200);
+    }
+}
@@ -1,11 +1,17 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use crate::{
-    aggregator::utils::get_valid_assets, common::client_trait::ClientError,
-    kamino::client::KaminoClient, marginfi::client::MarginfiClient, save::client::SaveClient,
+    aggregator::utils::get_valid_assets,
+    common::{client_trait::ClientError, rpc_utils::create_rpc_client},
+    kamino::client::KaminoClient,
+    marginfi::client::MarginfiClient,
+    rewards::config::RewardPriceConfig,
+    save::client::SaveClient,
 };
-use common::MintAsset;
+use common::{lending::LendingError, MintAsset, ProtocolStatus, ResponseEnvelope};
 use drift::client::DriftClient;
+use log::{error, info, warn};
+use solana_sdk::pubkey::Pubkey;
 
 // Type alias for results
 pub type ArrayResult<T> = Result<T, ClientError>;
@@ -17,8 +23,17 @@ pub struct LendingMarketAggregator {
     pub kamino_client: KaminoClient,
     pub drift_client: DriftClient,
     pub rpc_url: String, // Store the RPC URL for use with pooled clients
+    /// Price feeds of reward tokens no reserve lists
+    pub reward_prices: RewardPriceConfig,
+    /// Slot the loaded markets were read at, 0 until they are loaded
+    pub slot: u64,
+    /// Status of every protocol in the last load of the markets
+    pub protocols: Vec<ProtocolStatus>,
 }
 
+/// Protocols in the order their statuses are reported
+pub const PROTOCOLS: [&str; 4] = ["Save", "Marginfi", "Kamino", "Drift"];
+
 impl Default for LendingMarketAggregator {
     fn default() -> Self {
         Self::new("https://api.mainnet-beta.solana.com")
@@ -41,6 +56,9 @@ impl LendingMarketAggregator {
             kamino_client,
             drift_client,
             rpc_url: rpc_url.to_string(),
+            reward_prices: RewardPriceConfig::from_env(),
+            slot: 0,
+            protocols: Vec::new(),
         };
 
         // Initialize supported tokens
@@ -49,6 +67,21 @@ impl LendingMarketAggregator {
         aggregator
     }
 
+    /// Parses the wallet of a request, rejecting it before any protocol is queried
+    pub fn parse_wallet(wallet_pubkey: &str) -> Result<Pubkey, ClientError> {
+        Pubkey::from_str(wallet_pubkey)
+            .map_err(|e| ClientError::InvalidPubkey(format!("{}: {}", wallet_pubkey, e)))
+    }
+
+    /// The current slot, None when it cannot be read
+    pub async fn read_slot(&self) -> Option<u64> {
+        create_rpc_client(&self.rpc_url)
+            .get_slot()
+            .await
+            .inspect_err(|e| warn!("Failed to read the current slot: {}", e))
+            .ok()
+    }
+
     // Initialize supported tokens
     pub fn init_supported_tokens(&mut self) {
         // Clear existing assets first to avoid type mismatches
@@ -60,3 +93,29 @@ impl LendingMarketAggregator {
         }
     }
 }
+
+/// Collects the results of every protocol, in the order of [`PROTOCOLS`], keeping the data of the
+/// protocols that succeeded next to the status of each of them
+pub fn collect_protocol_results<T>(
+    what: &str,
+    slot: Option<u64>,
+    results: [Result<Vec<T>, LendingError>; 4],
+) -> ResponseEnvelope<Vec<T>> {
+    let mut envelope = ResponseEnvelope { data: Vec::new(), slot, protocols: Vec::new() };
+
+    for (protocol, result) in PROTOCOLS.iter().zip(results) {
+        match result {
+            Ok(data) => {
+                info!("Found {} {} {}", data.len(), protocol, what);
+                envelope.data.extend(data);
+                envelope.protocols.push(ProtocolStatus::ok(protocol, slot));
+            }
+            Err(e) => {
+                error!("Error fetching {} {}: {}", protocol, what, e);
+                envelope.protocols.push(ProtocolStatus::failed(protocol, e.kind()));
+            }
+        }
+    }
+
+    envelope
+}
@@ -0,0 +1,267 @@
+use crate::{
+    aggregator::utils::{extract_market_name, get_symbol_for_mint},
+    kamino::models::{
+        lending_market::LendingMarket,
+        reserve::{Reserve as KaminoReserve, ReserveStatus},
+    },
+    marginfi::{
+        models::group::{Bank, BankOperationalState},
+        utils::constants::{ASSET_TAG_DEFAULT, ASSET_TAG_SOL, ASSET_TAG_STAKED},
+    },
+    save::models::Reserve as SaveReserve,
+};
+use common::CollateralAsset;
+use drift::{math::constants::SPOT_WEIGHT_PRECISION, models::idl::accounts::SpotMarket};
+use fixed::types::I80F48;
+use solana_sdk::pubkey::Pubkey;
+
+fn collateral_asset(
+    mint: &Pubkey,
+    protocol_symbol: Option<String>,
+    loan_to_value: f64,
+    liquidation_threshold: f64,
+) -> CollateralAsset {
+    let mint = mint.to_string();
+    let symbol = get_symbol_for_mint(&mint)
+        .or(protocol_symbol.filter(|symbol| !symbol.is_empty()))
+        .unwrap_or_else(|| mint.clone());
+
+    CollateralAsset { name: symbol.clone(), symbol, mint, loan_to_value, liquidation_threshold }
+}
+
+/// Other reserves of the same Save pool that can be borrowed against, weighted by the borrow
+/// weight of `borrow`. In every protocol the borrowed reserve is left out of its own collateral.
+pub fn save_collateral(
+    pool: &[(Pubkey, SaveReserve)],
+    borrow_pubkey: &Pubkey,
+    borrow: &SaveReserve,
+) -> Vec<CollateralAsset> {
+    if borrow.config.borrow_limit == 0 {
+        return Vec::new();
+    }
+
+    let borrow_weight = 1.0 + borrow.config.added_borrow_weight_bps as f64 / 10_000.0;
+
+    pool.iter()
+        .filter(|(pubkey, _)| pubkey != borrow_pubkey)
+        .map(|(_, reserve)| reserve)
+        .filter(|reserve| reserve.config.loan_to_value_ratio > 0)
+        .map(|reserve| {
+            collateral_asset(
+                &reserve.liquidity.mint_pubkey,
+                None,
+                reserve.config.loan_to_value_ratio as f64 / 100.0 / borrow_weight,
+                reserve.config.liquidation_threshold as f64 / 100.0 / borrow_weight,
+            )
+        })
+        .collect()
+}
+
+/// Other reserves of the same Kamino market that can be borrowed against, using the elevation group
+/// with the highest LTV when the pairing is part of one
+pub fn kamino_collateral(
+    market: &LendingMarket,
+    reserves: &[(Pubkey, KaminoReserve)],
+    borrow_pubkey: &Pubkey,
+    borrow: &KaminoReserve,
+) -> Vec<CollateralAsset> {
+    if borrow.config.status() == ReserveStatus::Obsolete {
+        return Vec::new();
+    }
+
+    let borrow_factor = borrow.config.get_borrow_factor().to_num::<f64>();
+    let borrowable_outside_groups = borrow.config.borrow_limit_outside_elevation_group > 0;
+
+    reserves
+        .iter()
+        .filter(|(pubkey, _)| pubkey != borrow_pubkey)
+        .filter(|(_, reserve)| reserve.config.status() != ReserveStatus::Obsolete)
+        .filter_map(|(_, reserve)| {
+            let config = &reserve.config;
+
+            // Elevation groups ignore the borrow factor and replace the reserve ratios
+            let group_ratios = config
+                .elevation_groups
+                .iter()
+                .filter(|id| **id != 0)
+                .filter_map(|id| market.elevation_groups.get(*id as usize - 1))
+                .filter(|group| group.debt_reserve == *borrow_pubkey && group.allow_new_loans != 0)
+                .map(|group| (group.ltv_pct as f64, group.liquidation_threshold_pct as f64));
+
+            let default_ratios = (borrowable_outside_groups
+                && config.loan_to_value_pct > 0
+                && config.disable_usage_as_coll_outside_emode == 0)
+                .then(|| {
+                    (
+                        config.loan_to_value_pct as f64 / borrow_factor,
+                        config.liquidation_threshold_pct as f64 / borrow_factor,
+                    )
+                });
+
+            let (ltv_pct, threshold_pct) = group_ratios
+                .chain(default_ratios)
+                .filter(|(ltv_pct, _)| *ltv_pct > 0.0)
+                .max_by(|a, b| a.0.total_cmp(&b.0))?;
+
+            Some(collateral_asset(
+                &reserve.liquidity.mint_pubkey,
+                Some(reserve.token_symbol().to_string()),
+                ltv_pct / 100.0,
+                threshold_pct / 100.0,
+            ))
+        })
+        .collect()
+}
+
+/// Whether a Marginfi account can hold a deposit tagged `collateral` while borrowing a bank
+/// tagged `borrow`
+fn asset_tags_compatible(collateral: u8, borrow: u8) -> bool {
+    match collateral {
+        ASSET_TAG_STAKED => borrow == ASSET_TAG_SOL,
+        ASSET_TAG_DEFAULT => borrow != ASSET_TAG_STAKED,
+        _ => true,
+    }
+}
+
+/// Other banks of the same Marginfi group that can be borrowed against, asset weights divided by the
+/// liability weights of `borrow`
+pub fn marginfi_collateral(
+    banks: &[(Pubkey, Bank)],
+    borrow_pubkey: &Pubkey,
+    borrow: &Bank,
+) -> Vec<CollateralAsset> {
+    let liability_weight_init = I80F48::from(borrow.config.liability_weight_init).to_num::<f64>();
+    let liability_weight_maint = I80F48::from(borrow.config.liability_weight_maint).to_num::<f64>();
+
+    if borrow.config.operational_state != BankOperationalState::Operational
+        || borrow.config.borrow_limit == 0
+        || liability_weight_init <= 0.0
+    {
+        return Vec::new();
+    }
+
+    // An account cannot lend and borrow from the same bank
+    banks
+        .iter()
+        .filter(|(pubkey, _)| pubkey != borrow_pubkey)
+        .map(|(_, bank)| bank)
+        .filter(|bank| bank.config.operational_state == BankOperationalState::Operational)
+        .filter(|bank| asset_tags_compatible(bank.config.asset_tag, borrow.config.asset_tag))
+        .filter_map(|bank| {
+            let asset_weight_init = I80F48::from(bank.config.asset_weight_init).to_num::<f64>();
+            let asset_weight_maint = I80F48::from(bank.config.asset_weight_maint).to_num::<f64>();
+
+            (asset_weight_init > 0.0).then(|| {
+                collateral_asset(
+                    &bank.mint,
+                    None,
+                    asset_weight_init / liability_weight_init,
+                    asset_weight_maint / liability_weight_maint,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Other Drift spot markets that can be borrowed against, asset weights divided by the liability
+/// weights of `borrow`
+pub fn drift_collateral(
+    markets: &[(Pubkey, SpotMarket)],
+    borrow: &SpotMarket,
+) -> Vec<CollateralAsset> {
+    let precision = SPOT_WEIGHT_PRECISION as f64;
+    let liability_weight_init = borrow.initial_liability_weight as f64 / precision;
+    let liability_weight_maint = borrow.maintenance_liability_weight as f64 / precision;
+
+    if liability_weight_init <= 0.0 {
+        return Vec::new();
+    }
+
+    // A spot position is either a deposit or a borrow, never both
+    markets
+        .iter()
+        .map(|(_, market)| market)
+        .filter(|market| market.market_index != borrow.market_index)
+        .filter(|market| market.initial_asset_weight > 0)
+        .map(|market| {
+            collateral_asset(
+                &market.mint,
+                Some(extract_market_name(&market.name).trim().to_string()),
+                market.initial_asset_weight as f64 / precision / liability_weight_init,
+                market.maintenance_asset_weight as f64 / precision / liability_weight_maint,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staked_collateral_only_borrows_sol() {
+        assert!(asset_tags_compatible(ASSET_TAG_STAKED, ASSET_TAG_SOL));
+        assert!(!asset_tags_compatible(ASSET_TAG_STAKED, ASSET_TAG_DEFAULT));
+        assert!(asset_tags_compatible(ASSET_TAG_DEFAULT, ASSET_TAG_SOL));
+        assert!(!asset_tags_compatible(ASSET_TAG_DEFAULT, ASSET_TAG_STAKED));
+        assert!(asset_tags_compatible(ASSET_TAG_SOL, ASSET_TAG_DEFAULT));
+    }
+
+    fn mints(collateral: &[CollateralAsset]) -> Vec<String> {
+        collateral.iter().map(|asset| asset.mint.clone()).collect()
+    }
+
+    #[test]
+    fn borrowed_reserve_is_not_its_own_collateral() {
+        let (borrowed, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (borrowed_mint, other_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let expected = vec![other_mint.to_string()];
+
+        let save_reserve = |mint| {
+            let mut reserve = SaveReserve::default();
+            reserve.liquidity.mint_pubkey = mint;
+            reserve.config.loan_to_value_ratio = 75;
+            reserve.config.borrow_limit = 1;
+            reserve
+        };
+        let pool = [(borrowed, save_reserve(borrowed_mint)), (other, save_reserve(other_mint))];
+        assert_eq!(mints(&save_collateral(&pool, &borrowed, &pool[0].1)), expected);
+
+        let kamino_reserve = |mint| {
+            let mut reserve = KaminoReserve::default();
+            reserve.liquidity.mint_pubkey = mint;
+            reserve.config.loan_to_value_pct = 75;
+            reserve.config.borrow_limit_outside_elevation_group = 1;
+            reserve
+        };
+        let reserves =
+            [(borrowed, kamino_reserve(borrowed_mint)), (other, kamino_reserve(other_mint))];
+        let market = LendingMarket::default();
+        assert_eq!(
+            mints(&kamino_collateral(&market, &reserves, &borrowed, &reserves[0].1)),
+            expected
+        );
+
+        let bank = |mint| {
+            let mut bank = Bank { mint, ..Bank::default() };
+            bank.config.operational_state = BankOperationalState::Operational;
+            bank.config.borrow_limit = 1;
+            bank.config.asset_weight_init = I80F48::ONE.into();
+            bank.config.liability_weight_init = I80F48::ONE.into();
+            bank
+        };
+        let banks = [(borrowed, bank(borrowed_mint)), (other, bank(other_mint))];
+        assert_eq!(mints(&marginfi_collateral(&banks, &borrowed, &banks[0].1)), expected);
+
+        let spot_market = |market_index, mint| SpotMarket {
+            market_index,
+            mint,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            ..Default::default()
+        };
+        let markets =
+            [(borrowed, spot_market(0, borrowed_mint)), (other, spot_market(1, other_mint))];
+        assert_eq!(mints(&drift_collateral(&markets, &markets[0].1)), expected);
+    }
+}
@@ -1,16 +1,54 @@
-use super::{normalize::RateNormalizer, PoolLiquidityNormalizer};
+use super::{
+    normalize::RateNormalizer,
+    risk::{drift_risk, kamino_risk, marginfi_risk, save_risk},
+    PoolLiquidityNormalizer,
+};
 use crate::{
-    kamino::models::reserve::Reserve as KaminoReserve,
+    kamino::{models::reserve::Reserve as KaminoReserve, utils::fraction::Fraction},
     marginfi::models::group::{Bank, MarginfiGroup},
-    save::models::Reserve,
+    save::{math::Decimal, models::Reserve},
 };
-use common::LendingReserve;
+use common::{CollateralAsset, ExitLiquidity, LendingReserve, OutflowCap};
 use drift::models::idl::accounts::SpotMarket;
+use solana_sdk::pubkey::Pubkey;
+
+/// Reward APYs are computed as floats, they are normalized as a Kamino `Fraction` since Save,
+/// Marginfi and Kamino rates all end up in that unit. An unknown APY stays unknown
+fn normalize_reward_apy(reward_apy: Option<f64>) -> Option<u128> {
+    reward_apy
+        .and_then(Fraction::checked_from_num)
+        .and_then(|apy| RateNormalizer::kamino().normalize_rate(apy).ok())
+}
+
+/// Amount left under `cap`, `None` when there is no cap
+fn remaining_capacity(cap: Option<u128>, used: u128) -> Option<u128> {
+    cap.map(|cap| cap.saturating_sub(used))
+}
+
+/// Converts the native token amounts of `exit` with `normalize`
+fn normalize_exit_liquidity(
+    exit: ExitLiquidity,
+    normalize: impl Fn(u128) -> u128,
+) -> ExitLiquidity {
+    ExitLiquidity {
+        available_liquidity: normalize(exit.available_liquidity),
+        withdrawable_now: normalize(exit.withdrawable_now),
+        outflow_cap: exit.outflow_cap.map(|cap| OutflowCap {
+            max_outflow: normalize(cap.max_outflow),
+            sliding_refill: cap.sliding_refill.map(&normalize),
+            ..cap
+        }),
+    }
+}
 
 // Wrapper types for protocol reserves
 pub struct SaveReserveWrapper<'a> {
+    pub pubkey: &'a Pubkey,
     pub reserve: &'a Reserve,
     pub market_name: &'a str,
+    pub collateral_assets: Vec<CollateralAsset>,
+    // Exit liquidity in native token units
+    pub exit_liquidity: ExitLiquidity,
     pub slot: u64,
 }
 
@@ -23,30 +61,61 @@ impl<'a> From<SaveReserveWrapper<'a>> for LendingReserve {
 
         let rate_normalizer = RateNormalizer::save();
         let liquidity_normalizer = PoolLiquidityNormalizer::save();
+        let supply_apy = rate_normalizer.normalize_rate(supply_apy).unwrap();
+
+        let total_supply = liquidity_normalizer
+            .normalize_amount(wrapper.reserve.liquidity.total_supply().unwrap())
+            .unwrap();
+        let total_borrows = liquidity_normalizer
+            .normalize_amount(wrapper.reserve.liquidity.borrowed_amount_wads)
+            .unwrap();
+        // Limits are in native token units, u64::MAX leaves the reserve uncapped
+        let cap = |limit: u64| {
+            (limit != u64::MAX)
+                .then(|| liquidity_normalizer.normalize_amount(Decimal::from(limit)).unwrap())
+        };
+        let deposit_cap = cap(wrapper.reserve.config.deposit_limit);
+        let borrow_cap = cap(wrapper.reserve.config.borrow_limit);
 
         LendingReserve {
             protocol_name: "Save".to_string(),
             market_name: wrapper.market_name.to_string(),
-            total_supply: liquidity_normalizer
-                .normalize_amount(wrapper.reserve.liquidity.total_supply().unwrap())
-                .unwrap(),
-            total_borrows: liquidity_normalizer
-                .normalize_amount(wrapper.reserve.liquidity.borrowed_amount_wads)
-                .unwrap(),
+            reserve_pubkey: wrapper.pubkey.to_string(),
+            market_pubkey: wrapper.reserve.lending_market.to_string(),
+            total_supply,
+            total_borrows,
+            deposit_cap,
+            borrow_cap,
+            remaining_deposit_capacity: remaining_capacity(deposit_cap, total_supply),
+            remaining_borrow_capacity: remaining_capacity(borrow_cap, total_borrows),
             supply_rate: rate_normalizer.normalize_rate(supply_rate).unwrap(),
             borrow_rate: rate_normalizer.normalize_rate(borrow_rate).unwrap(),
             borrow_apy: rate_normalizer.normalize_rate(borrow_apy).unwrap(),
-            supply_apy: rate_normalizer.normalize_rate(supply_apy).unwrap(),
-            collateral_assets: vec![],
+            supply_apy,
+            reward_apy: None,
+            borrow_reward_apy: None,
+            total_apy: supply_apy,
+            collateral_assets: wrapper.collateral_assets,
+            exit_liquidity: normalize_exit_liquidity(wrapper.exit_liquidity, |amount| {
+                liquidity_normalizer.normalize_amount(Decimal::from(amount)).unwrap()
+            }),
+            risk: save_risk(wrapper.reserve),
             slot: wrapper.slot,
         }
     }
 }
 
 pub struct MarginfiReserveWrapper<'a> {
+    pub pubkey: &'a Pubkey,
     pub bank: &'a Bank,
     pub group: &'a MarginfiGroup,
     pub market_name: &'a str,
+    // APYs paid in reward tokens to suppliers and borrowers, None when unknown
+    pub reward_apy: Option<f64>,
+    pub borrow_reward_apy: Option<f64>,
+    pub collateral_assets: Vec<CollateralAsset>,
+    // Exit liquidity in native token units
+    pub exit_liquidity: ExitLiquidity,
     pub slot: u64,
 }
 
@@ -55,31 +124,64 @@ impl<'a> From<MarginfiReserveWrapper<'a>> for LendingReserve {
         let interest_rates = wrapper.bank.get_interest_rate(wrapper.group).unwrap();
         let rate_normalizer = RateNormalizer::marginfi();
         let liquidity_normalizer = PoolLiquidityNormalizer::marginfi();
+        let supply_apy = rate_normalizer.normalize_rate(interest_rates.lending_rate_apy()).unwrap();
+        let reward_apy = normalize_reward_apy(wrapper.reward_apy);
+
+        let total_supply = liquidity_normalizer
+            .normalize_amount(wrapper.bank.get_total_supply().unwrap())
+            .unwrap();
+        let total_borrows = liquidity_normalizer
+            .normalize_amount(wrapper.bank.get_total_borrowed().unwrap())
+            .unwrap();
+        // `total_asset_value_init_limit` only lowers the asset weight once crossed, it is not a cap
+        let config = &wrapper.bank.config;
+        let deposit_cap = config
+            .is_deposit_limit_active()
+            .then(|| liquidity_normalizer.normalize_amount(config.deposit_limit).unwrap());
+        let borrow_cap = config
+            .is_borrow_limit_active()
+            .then(|| liquidity_normalizer.normalize_amount(config.borrow_limit).unwrap());
 
         LendingReserve {
             protocol_name: "Marginfi".to_string(),
             market_name: wrapper.market_name.to_string(),
-            total_supply: liquidity_normalizer
-                .normalize_amount(wrapper.bank.get_total_supply().unwrap())
-                .unwrap(),
-            total_borrows: liquidity_normalizer
-                .normalize_amount(wrapper.bank.get_total_borrowed().unwrap())
-                .unwrap(),
+            reserve_pubkey: wrapper.pubkey.to_string(),
+            market_pubkey: wrapper.bank.group.to_string(),
+            total_supply,
+            total_borrows,
+            deposit_cap,
+            borrow_cap,
+            remaining_deposit_capacity: remaining_capacity(deposit_cap, total_supply),
+            remaining_borrow_capacity: remaining_capacity(borrow_cap, total_borrows),
             supply_rate: rate_normalizer.normalize_rate(interest_rates.lending_rate_apr).unwrap(),
             borrow_rate: rate_normalizer.normalize_rate(interest_rates.borrowing_rate_apr).unwrap(),
             borrow_apy: rate_normalizer
                 .normalize_rate(interest_rates.borrowing_rate_apy())
                 .unwrap(),
-            supply_apy: rate_normalizer.normalize_rate(interest_rates.lending_rate_apy()).unwrap(),
-            collateral_assets: vec![],
+            supply_apy,
+            reward_apy,
+            borrow_reward_apy: normalize_reward_apy(wrapper.borrow_reward_apy),
+            total_apy: supply_apy + reward_apy.unwrap_or_default(),
+            collateral_assets: wrapper.collateral_assets,
+            exit_liquidity: normalize_exit_liquidity(wrapper.exit_liquidity, |amount| {
+                liquidity_normalizer.normalize_amount(amount).unwrap()
+            }),
+            risk: marginfi_risk(wrapper.bank),
             slot: wrapper.slot,
         }
     }
 }
 
 pub struct KaminoReserveWrapper<'a> {
+    pub pubkey: &'a Pubkey,
     pub reserve: &'a KaminoReserve,
     pub market_name: &'a str,
+    // APYs paid in reward tokens to suppliers and borrowers, None when unknown
+    pub reward_apy: Option<f64>,
+    pub borrow_reward_apy: Option<f64>,
+    pub collateral_assets: Vec<CollateralAsset>,
+    // Exit liquidity in native token units
+    pub exit_liquidity: ExitLiquidity,
     pub slot: u64,
 }
 
@@ -91,21 +193,45 @@ impl<'a> From<KaminoReserveWrapper<'a>> for LendingReserve {
         let supply_apy = wrapper.reserve.current_supply_apy_unadjusted().unwrap();
         let rate_normalizer = RateNormalizer::kamino();
         let liquidity_normalizer = PoolLiquidityNormalizer::kamino();
+        let supply_apy = rate_normalizer.normalize_rate(supply_apy).unwrap();
+        let reward_apy = normalize_reward_apy(wrapper.reward_apy);
+
+        let total_supply = liquidity_normalizer
+            .normalize_amount(wrapper.reserve.liquidity.total_supply().unwrap())
+            .unwrap();
+        let total_borrows = liquidity_normalizer
+            .normalize_amount(wrapper.reserve.liquidity.total_borrow())
+            .unwrap();
+        // Limits are in native token units, u64::MAX leaves the reserve uncapped
+        let cap = |limit: u64| {
+            (limit != u64::MAX).then(|| liquidity_normalizer.normalize_amount(limit).unwrap())
+        };
+        let deposit_cap = cap(wrapper.reserve.config.deposit_limit);
+        let borrow_cap = cap(wrapper.reserve.config.borrow_limit);
 
         LendingReserve {
             protocol_name: "Kamino".to_string(),
             market_name: wrapper.market_name.to_string(),
-            total_supply: liquidity_normalizer
-                .normalize_amount(wrapper.reserve.liquidity.total_supply().unwrap())
-                .unwrap(),
-            total_borrows: liquidity_normalizer
-                .normalize_amount(wrapper.reserve.liquidity.total_borrow())
-                .unwrap(),
+            reserve_pubkey: wrapper.pubkey.to_string(),
+            market_pubkey: wrapper.reserve.lending_market.to_string(),
+            total_supply,
+            total_borrows,
+            deposit_cap,
+            borrow_cap,
+            remaining_deposit_capacity: remaining_capacity(deposit_cap, total_supply),
+            remaining_borrow_capacity: remaining_capacity(borrow_cap, total_borrows),
             supply_rate: rate_normalizer.normalize_rate(supply_rate).unwrap(),
             borrow_rate: rate_normalizer.normalize_rate(borrow_rate).unwrap(),
             borrow_apy: rate_normalizer.normalize_rate(borrow_apy).unwrap(),
-            supply_apy: rate_normalizer.normalize_rate(supply_apy).unwrap(),
-            collateral_assets: vec![],
+            supply_apy,
+            reward_apy,
+            borrow_reward_apy: normalize_reward_apy(wrapper.borrow_reward_apy),
+            total_apy: supply_apy + reward_apy.unwrap_or_default(),
+            collateral_assets: wrapper.collateral_assets,
+            exit_liquidity: normalize_exit_liquidity(wrapper.exit_liquidity, |amount| {
+                liquidity_normalizer.normalize_amount(amount).unwrap()
+            }),
+            risk: kamino_risk(wrapper.reserve),
             slot: wrapper.slot,
         }
     }
@@ -113,7 +239,12 @@ impl<'a> From<KaminoReserveWrapper<'a>> for LendingReserve {
 
 pub struct DriftReserveWrapper<'a> {
     pub market: &'a SpotMarket,
+    // Every spot market belongs to the program's single state account
+    pub state: &'a Pubkey,
     pub market_name: &'a str,
+    pub collateral_assets: Vec<CollateralAsset>,
+    // Exit liquidity in native token units
+    pub exit_liquidity: ExitLiquidity,
     pub slot: u64,
 }
 
@@ -125,21 +256,48 @@ impl<'a> From<DriftReserveWrapper<'a>> for LendingReserve {
         let supply_apy = wrapper.market.current_supply_apy_unadjusted().unwrap();
         let rate_normalizer = RateNormalizer::drift();
         let liquidity_normalizer = PoolLiquidityNormalizer::drift();
+        let supply_apy = rate_normalizer.normalize_rate(supply_apy).unwrap();
+
+        let total_supply = liquidity_normalizer
+            .normalize_amount(wrapper.market.get_deposits().unwrap_or(0))
+            .unwrap();
+        let total_borrows = liquidity_normalizer
+            .normalize_amount(wrapper.market.get_borrows().unwrap_or(0))
+            .unwrap();
+        // A zero `max_token_deposits` leaves the market uncapped, borrows are capped to a fraction
+        // of it in basis points
+        let max_deposits = wrapper.market.max_token_deposits;
+        let max_borrows_fraction = wrapper.market.max_token_borrows_fraction;
+        let deposit_cap = (max_deposits > 0)
+            .then(|| liquidity_normalizer.normalize_amount(max_deposits).unwrap());
+        let borrow_cap = (max_deposits > 0 && max_borrows_fraction > 0).then(|| {
+            let max_borrows = max_deposits as u128 * max_borrows_fraction as u128 / 10_000;
+            liquidity_normalizer.normalize_amount(max_borrows).unwrap()
+        });
 
         LendingReserve {
             protocol_name: "Drift".to_string(),
             market_name: wrapper.market_name.trim().replace('\0', "").to_string(),
-            total_supply: liquidity_normalizer
-                .normalize_amount(wrapper.market.get_deposits().unwrap_or(0))
-                .unwrap(),
-            total_borrows: liquidity_normalizer
-                .normalize_amount(wrapper.market.get_borrows().unwrap_or(0))
-                .unwrap(),
+            reserve_pubkey: wrapper.market.pubkey.to_string(),
+            market_pubkey: wrapper.state.to_string(),
+            total_supply,
+            total_borrows,
+            deposit_cap,
+            borrow_cap,
+            remaining_deposit_capacity: remaining_capacity(deposit_cap, total_supply),
+            remaining_borrow_capacity: remaining_capacity(borrow_cap, total_borrows),
             supply_rate: rate_normalizer.normalize_rate(supply_rate).unwrap(),
             borrow_rate: rate_normalizer.normalize_rate(borrow_rate).unwrap(),
             borrow_apy: rate_normalizer.normalize_rate(borrow_apy).unwrap(),
-            supply_apy: rate_normalizer.normalize_rate(supply_apy).unwrap(),
-            collateral_assets: vec![],
+            supply_apy,
+            reward_apy: None,
+            borrow_reward_apy: None,
+            total_apy: supply_apy,
+            collateral_assets: wrapper.collateral_assets,
+            exit_liquidity: normalize_exit_liquidity(wrapper.exit_liquidity, |amount| {
+                liquidity_normalizer.normalize_amount(amount).unwrap()
+            }),
+            risk: drift_risk(wrapper.market),
             slot: wrapper.slot,
         }
     }
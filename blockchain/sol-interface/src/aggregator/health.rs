@@ -0,0 +1,124 @@
+use crate::{
+    aggregator::client::{collect_protocol_results, LendingMarketAggregator},
+    common::client_trait::ClientError,
+};
+use common::{ObligationHealth, ResponseEnvelope};
+use log::info;
+
+// Type alias for results
+type ArrayResult<T> = Result<T, ClientError>;
+
+impl LendingMarketAggregator {
+    /// Fetches the health summary of every borrowing account owned by the wallet
+    ///
+    /// A protocol that fails leaves its accounts out and reports the failure in its status. Fails
+    /// only for an invalid wallet.
+    pub async fn get_obligation_health_async(
+        &self,
+        wallet_pubkey: &str,
+    ) -> ArrayResult<ResponseEnvelope<Vec<ObligationHealth>>> {
+        Self::parse_wallet(wallet_pubkey)?;
+        info!("Fetching obligation health for {}", wallet_pubkey);
+
+        let (slot, save, marginfi, kamino, drift) = futures::join!(
+            self.read_slot(),
+            self.save_client.get_obligation_health(wallet_pubkey),
+            self.marginfi_client.get_obligation_health(wallet_pubkey),
+            self.kamino_client.get_obligation_health(wallet_pubkey),
+            self.drift_client.get_obligation_health(wallet_pubkey),
+        );
+
+        Ok(collect_protocol_results(
+            "accounts with health data",
+            slot,
+            [save, marginfi, kamino, drift],
+        ))
+    }
+
+    pub fn print_obligation_health(&self, health: &[ObligationHealth]) {
+        use prettytable::{row, Table};
+
+        if health.is_empty() {
+            info!("No obligation health data found");
+            return;
+        }
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "Protocol",
+            "Market",
+            "Account",
+            "Deposited",
+            "Borrowed",
+            "LTV",
+            "Max LTV",
+            "Liq. LTV",
+            "Borrowable"
+        ]);
+
+        for entry in health {
+            table.add_row(row![
+                entry.protocol_name,
+                entry.market_name,
+                entry.account,
+                format!("${:.2}", entry.deposited_value),
+                format!("${:.2}", entry.borrowed_value),
+                format!("{:.2}%", entry.loan_to_value * 100.0),
+                format!("{:.2}%", entry.max_loan_to_value * 100.0),
+                format!("{:.2}%", entry.liquidation_loan_to_value * 100.0),
+                format!("${:.2}", entry.remaining_borrow_value)
+            ]);
+        }
+
+        table.printstd();
+
+        let mut withdraw_table = Table::new();
+        withdraw_table.add_row(row!["Protocol", "Account", "Symbol", "Max Withdraw"]);
+
+        for entry in health {
+            for limit in &entry.max_withdraw {
+                withdraw_table.add_row(row![
+                    entry.protocol_name,
+                    entry.account,
+                    limit.symbol,
+                    format!("{:.4}", limit.amount as f64 / 10_f64.powi(limit.mint_decimals as i32))
+                ]);
+            }
+        }
+
+        if withdraw_table.len() > 1 {
+            withdraw_table.printstd();
+        }
+
+        let mut margin_table = Table::new();
+        margin_table.add_row(row![
+            "Protocol",
+            "Market",
+            "Account",
+            "Total Collateral",
+            "Initial Margin",
+            "Maint. Margin",
+            "Free Collateral",
+            "Health"
+        ]);
+
+        for entry in health {
+            if let Some(margin) = &entry.margin {
+                margin_table.add_row(row![
+                    entry.protocol_name,
+                    entry.market_name,
+                    entry.account,
+                    format!("${:.2}", margin.total_collateral),
+                    format!("${:.2}", margin.initial_margin_requirement),
+                    format!("${:.2}", margin.maintenance_margin_requirement),
+                    format!("${:.2}", margin.free_collateral),
+                    format!("{}%", margin.health)
+                ]);
+            }
+        }
+
+        if margin_table.len() > 1 {
+            margin_table.printstd();
+        }
+    }
+}
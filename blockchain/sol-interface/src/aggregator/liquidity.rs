@@ -0,0 +1,233 @@
+use crate::{
+    kamino::{
+        models::reserve::{Reserve as KaminoReserve, WithdrawalCaps},
+        utils::consts::DEFAULT_SLOT_DURATION_MS,
+    },
+    marginfi::models::group::Bank,
+    save::{
+        math::{Decimal, TryDiv, TryMul},
+        models::{RateLimiter, Reserve as SaveReserve},
+    },
+};
+use common::{ExitLiquidity, OutflowCap};
+use drift::models::idl::accounts::SpotMarket;
+use fixed::types::I80F48;
+
+// Outflow caps below are `(remaining outflow, cap)` pairs in native token units
+
+fn slots_to_seconds(slots: u64) -> u64 {
+    slots.saturating_mul(DEFAULT_SLOT_DURATION_MS) / 1000
+}
+
+/// Combines the available liquidity with the caps limiting its outflow. The cap refilling the
+/// slowest is the one reported.
+fn limit_outflow(available: u128, caps: &[Option<(u128, OutflowCap)>]) -> ExitLiquidity {
+    let caps: Vec<_> = caps.iter().flatten().collect();
+    let withdrawable_now = caps.iter().map(|(remaining, _)| *remaining).fold(available, u128::min);
+    let refill_rate = |cap: &OutflowCap| cap.max_outflow as f64 / cap.window_seconds.max(1) as f64;
+
+    ExitLiquidity {
+        available_liquidity: available,
+        withdrawable_now,
+        outflow_cap: caps
+            .into_iter()
+            .map(|(_, cap)| *cap)
+            .min_by(|a, b| refill_rate(a).total_cmp(&refill_rate(b))),
+    }
+}
+
+/// Outflow left in a Save rate limiter at `slot`, `to_tokens` converting from the limiter's unit.
+/// None when the limiter is disabled.
+///
+/// The limiter is a sliding window: the outflow of the previous window is counted with a weight
+/// falling linearly to 0 over the current window, so the cap frees up gradually.
+fn save_outflow_cap(
+    limiter: &RateLimiter,
+    slot: u64,
+    to_tokens: impl Fn(Decimal) -> Option<u128>,
+) -> Option<(u128, OutflowCap)> {
+    let config = limiter.config;
+    if config.window_duration == 0 || config.max_outflow == u64::MAX {
+        return None;
+    }
+
+    // Refreshing the windows mutates the limiter, work on a copy. A slot read before the limiter's
+    // last update is treated as the start of its window, which the program rejects.
+    let mut limiter = *limiter;
+    let slot = slot.max(limiter.window_start());
+    let remaining = limiter.remaining_outflow(slot).ok()?;
+    let window_end = limiter.window_start() + config.window_duration;
+    // Only the outflow of the current window still counts in its last slot
+    let remaining_at_window_end = limiter.remaining_outflow(window_end - 1).ok()?;
+
+    Some((
+        to_tokens(remaining)?,
+        OutflowCap {
+            max_outflow: to_tokens(Decimal::from(config.max_outflow))?,
+            window_seconds: slots_to_seconds(config.window_duration),
+            next_refill_seconds: slots_to_seconds(window_end.saturating_sub(slot)),
+            sliding_refill: Some(
+                to_tokens(remaining_at_window_end)?.saturating_sub(to_tokens(remaining)?),
+            ),
+        },
+    ))
+}
+
+/// Exit liquidity of a Save reserve in native tokens, limited by the reserve's own rate limiter
+/// and by the market-wide one
+pub fn save_exit_liquidity(
+    reserve: &SaveReserve,
+    market_limiter: &RateLimiter,
+    slot: u64,
+) -> ExitLiquidity {
+    let reserve_cap = save_outflow_cap(&reserve.rate_limiter, slot, |amount| {
+        amount.try_floor_u64().ok().map(u128::from)
+    });
+
+    // The market limiter counts outflows in USD, valued at the upper bound of the reserve price
+    let price = reserve.price_upper_bound();
+    let decimals = 10u64.pow(reserve.liquidity.mint_decimals as u32);
+    let market_cap = save_outflow_cap(market_limiter, slot, |value| {
+        let tokens = value.try_mul(decimals).ok()?.try_div(price).ok()?;
+        tokens.try_floor_u64().ok().map(u128::from)
+    });
+
+    limit_outflow(reserve.liquidity.available_amount as u128, &[reserve_cap, market_cap])
+}
+
+/// Outflow left under a Kamino withdrawal cap at `now`, None when the cap is disabled
+fn kamino_outflow_cap(caps: &WithdrawalCaps, now: u64) -> Option<(u128, OutflowCap)> {
+    let interval = caps.config_interval_length_seconds;
+    if caps.config_capacity <= 0 || interval == 0 {
+        return None;
+    }
+
+    // The accumulated total restarts with the first withdrawal after the interval ended
+    let interval_end = caps.last_interval_start_timestamp.saturating_add(interval);
+    let (remaining, next_refill_seconds) = if now >= interval_end {
+        (caps.config_capacity, interval)
+    } else {
+        (caps.config_capacity.saturating_sub(caps.current_total).max(0), interval_end - now)
+    };
+
+    Some((
+        remaining as u128,
+        OutflowCap {
+            max_outflow: caps.config_capacity as u128,
+            window_seconds: interval,
+            next_refill_seconds,
+            sliding_refill: None,
+        },
+    ))
+}
+
+/// Exit liquidity of a Kamino reserve in native tokens, limited by its withdrawal cap
+pub fn kamino_exit_liquidity(reserve: &KaminoReserve, now: u64) -> ExitLiquidity {
+    let withdrawal_cap = kamino_outflow_cap(&reserve.config.deposit_withdrawal_cap, now);
+
+    limit_outflow(reserve.liquidity.available_amount as u128, &[withdrawal_cap])
+}
+
+/// Exit liquidity of a Marginfi bank in native tokens, banks have no outflow limit
+pub fn marginfi_exit_liquidity(bank: &Bank) -> ExitLiquidity {
+    let available = match (bank.get_total_supply(), bank.get_total_borrowed()) {
+        (Ok(supply), Ok(borrowed)) => (supply - borrowed).max(I80F48::ZERO).to_num::<u128>(),
+        _ => 0,
+    };
+
+    limit_outflow(available, &[])
+}
+
+/// Exit liquidity of a Drift spot market in native tokens, spot markets have no outflow limit
+pub fn drift_exit_liquidity(market: &SpotMarket) -> ExitLiquidity {
+    limit_outflow(market.get_available_deposits().unwrap_or(0), &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::program_pack::Pack;
+
+    #[test]
+    fn kamino_cap_refills_after_interval() {
+        let caps = WithdrawalCaps {
+            config_capacity: 1_000,
+            current_total: 800,
+            last_interval_start_timestamp: 100,
+            config_interval_length_seconds: 60,
+        };
+
+        let (remaining, cap) = kamino_outflow_cap(&caps, 130).unwrap();
+        assert_eq!(remaining, 200);
+        assert_eq!(cap.next_refill_seconds, 30);
+
+        let (remaining, cap) = kamino_outflow_cap(&caps, 200).unwrap();
+        assert_eq!(remaining, 1_000);
+        assert_eq!(cap.next_refill_seconds, 60);
+    }
+
+    #[test]
+    fn seconds_to_withdraw_waits_for_refills() {
+        let exit = limit_outflow(
+            5_000,
+            &[Some((
+                200,
+                OutflowCap {
+                    max_outflow: 1_000,
+                    window_seconds: 60,
+                    next_refill_seconds: 30,
+                    sliding_refill: None,
+                },
+            ))],
+        );
+
+        assert_eq!(exit.withdrawable_now, 200);
+        assert_eq!(exit.seconds_to_withdraw(200), Some(0));
+        assert_eq!(exit.seconds_to_withdraw(1_200), Some(30));
+        assert_eq!(exit.seconds_to_withdraw(2_500), Some(30 + 2 * 60));
+        assert_eq!(exit.seconds_to_withdraw(5_001), None);
+    }
+
+    /// A limiter of 1000 tokens per 100 slots, its state set through its packed layout
+    fn save_limiter(prev_qty: u64, cur_qty: u64, window_start: u64) -> RateLimiter {
+        let scaled = |qty: u64| Decimal::from(qty).to_scaled_val().unwrap().to_le_bytes();
+        let mut data = [0u8; RateLimiter::LEN];
+        data[0..8].copy_from_slice(&1_000u64.to_le_bytes());
+        data[8..16].copy_from_slice(&100u64.to_le_bytes());
+        data[16..32].copy_from_slice(&scaled(prev_qty));
+        data[32..40].copy_from_slice(&window_start.to_le_bytes());
+        data[40..56].copy_from_slice(&scaled(cur_qty));
+        RateLimiter::unpack_from_slice(&data).unwrap()
+    }
+
+    fn tokens(amount: Decimal) -> Option<u128> {
+        amount.try_floor_u64().ok().map(u128::from)
+    }
+
+    #[test]
+    fn save_cap_frees_previous_window_gradually() {
+        // Half way through the window, 400 of the previous window's 800 still count
+        let limiter = save_limiter(800, 300, 1_000);
+
+        let (remaining, cap) = save_outflow_cap(&limiter, 1_049, tokens).unwrap();
+        assert_eq!(remaining, 300);
+        assert_eq!(cap.sliding_refill, Some(400));
+
+        let exit = limit_outflow(10_000, &[Some((remaining, cap))]);
+        let window = cap.window_seconds;
+        assert_eq!(exit.seconds_to_withdraw(300), Some(0));
+        assert_eq!(exit.seconds_to_withdraw(500), Some(cap.next_refill_seconds.div_ceil(2)));
+        assert_eq!(exit.seconds_to_withdraw(700), Some(cap.next_refill_seconds));
+        assert_eq!(exit.seconds_to_withdraw(2_700), Some(cap.next_refill_seconds + 2 * window));
+    }
+
+    #[test]
+    fn save_cap_clamps_stale_slot() {
+        let limiter = save_limiter(0, 600, 1_000);
+
+        let (remaining, cap) = save_outflow_cap(&limiter, 900, tokens).unwrap();
+        assert_eq!(remaining, 400);
+        assert_eq!(cap.next_refill_seconds, slots_to_seconds(100));
+        assert_eq!(cap.sliding_refill, Some(0));
+    }
+}
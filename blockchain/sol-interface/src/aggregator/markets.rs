@@ -1,141 +1,54 @@
 use crate::{
     aggregator::{
         client::LendingMarketAggregator,
+        collateral::{drift_collateral, kamino_collateral, marginfi_collateral, save_collateral},
         from::{
             DriftReserveWrapper, KaminoReserveWrapper, MarginfiReserveWrapper, SaveReserveWrapper,
         },
+        liquidity::{
+            drift_exit_liquidity, kamino_exit_liquidity, marginfi_exit_liquidity,
+            save_exit_liquidity,
+        },
+        rewards::RewardApys,
         utils::extract_market_name,
     },
-    common::client_trait::ClientError,
-    marginfi::models::group::MarginfiGroup,
+    common::{client_trait::ClientError, rpc_utils::create_rpc_client},
+    kamino::client::{market_name as kamino_market_name, KaminoClient, KaminoMarkets},
+    transactions,
 };
-use common::{lending::LendingClient, LendingReserve};
-use common_rpc;
+use common::{lending::LendingClient, LendingReserve, MintAsset, ProtocolState, ProtocolStatus};
 use log::{info, warn};
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
 use std::str::FromStr;
 
 // Type alias for results
 type ArrayResult<T> = Result<T, ClientError>;
 
 impl LendingMarketAggregator {
-    pub fn load_markets(&mut self) -> ArrayResult<()> {
-        // Use parallel execution by default
-        self.load_markets_parallel()
-    }
-
-    pub fn load_markets_parallel(&mut self) -> ArrayResult<()> {
-        // Create a runtime for executing the parallel tasks if we're not already in one
-        match tokio::runtime::Runtime::new() {
-            Ok(runtime) => {
-                // We're not in a runtime, so create one and use it
-                runtime.block_on(self.load_markets_async())
-            }
-            Err(_) => {
-                // We might be in a runtime already, fall back to sequential implementation
-                // to avoid the "Cannot start a runtime from within a runtime" error
-                warn!("Failed to create Tokio runtime, falling back to sequential implementation");
-                self.load_markets_sequential()
-            }
-        }
-    }
-
     pub async fn load_markets_async(&mut self) -> ArrayResult<()> {
-        info!("Loading all lending markets in parallel");
+        info!("Loading all lending markets concurrently");
 
         // Initialize supported tokens
         self.init_supported_tokens();
 
-        // Get the current slot using the pooled client approach
-        let current_slot = common_rpc::with_rpc_client(&self.rpc_url, |client| {
-            client.get_slot().map_err(|e| ClientError::RpcError(Box::new(e)))
-        })?;
-
-        // Clone clients for use in separate tasks
-        let save_client = self.save_client.clone();
-        let marginfi_client = self.marginfi_client.clone();
-        let kamino_client = self.kamino_client.clone();
-        let drift_client = self.drift_client.clone();
-
-        // Create futures for each protocol using the generic fetch_markets method
-        let save_future = tokio::spawn(async move {
-            info!("Loading Save reserves");
-            match save_client.fetch_markets() {
-                Ok(reserves) => reserves,
-                Err(e) => {
-                    warn!("Failed to load Save reserves: {}", e);
-                    Vec::new()
-                }
-            }
-        });
-
-        // Handle Kamino separately due to different return type
-        let kamino_future = tokio::spawn(async move {
-            info!("Loading Kamino reserves");
-            match kamino_client.fetch_markets() {
-                Ok(markets) => markets,
-                Err(e) => {
-                    warn!("Failed to load Kamino reserves: {}", e);
-                    Vec::new()
-                }
-            }
-        });
-
-        // Now we can use the generic fetch_markets for MarginfiClient too
-        let marginfi_future = tokio::spawn(async move {
-            info!("Loading Marginfi markets");
-            match marginfi_client.fetch_markets() {
-                Ok(data) => data,
-                Err(e) => {
-                    warn!("Failed to load Marginfi markets: {}", e);
-                    (Vec::new(), MarginfiGroup::default())
-                }
-            }
-        });
-
-        let drift_future = tokio::spawn(async move {
-            info!("Loading Drift reserves");
-            match drift_client.fetch_markets() {
-                Ok(markets) => markets,
-                Err(e) => {
-                    warn!("Failed to load Drift reserves: {}", e);
-                    Vec::new()
-                }
-            }
-        });
-
-        // Await futures separately to handle different types
-        let save_reserves = match save_future.await {
-            Ok(reserves) => reserves,
-            Err(e) => {
-                warn!("Failed to join Save task: {}", e);
-                Vec::new()
-            }
-        };
-
-        let kamino_markets = match kamino_future.await {
-            Ok(markets) => markets,
-            Err(e) => {
-                warn!("Failed to join Kamino task: {}", e);
-                Vec::new()
-            }
-        };
-
-        let marginfi_data = match marginfi_future.await {
-            Ok(data) => data,
-            Err(e) => {
-                warn!("Failed to join Marginfi task: {}", e);
-                (Vec::new(), MarginfiGroup::default())
-            }
-        };
-
-        let drift_markets = match drift_future.await {
-            Ok(markets) => markets,
-            Err(e) => {
-                warn!("Failed to join Drift task: {}", e);
-                Vec::new()
-            }
-        };
+        // Every protocol fetches its markets concurrently on the current runtime
+        let (
+            (save_reserves, save_status),
+            (marginfi_data, marginfi_status),
+            (kamino_markets, kamino_status),
+            (drift_markets, drift_status),
+        ) = futures::join!(
+            fetch_protocol_markets(&self.save_client, "Save"),
+            fetch_protocol_markets(&self.marginfi_client, "Marginfi"),
+            fetch_kamino_markets(&self.kamino_client),
+            fetch_protocol_markets(&self.drift_client, "Drift"),
+        );
+
+        // Get the current slot and time once the markets are read, so no reserve is newer than
+        // the slot interest is accrued up to
+        let clock = common_rpc::fetch_clock(&create_rpc_client(&self.rpc_url))
+            .await
+            .map_err(|e| ClientError::Other(format!("Failed to fetch the clock: {}", e)))?;
 
         // Update client state with fetched data using the generic set_market_data method
         self.save_client.set_market_data(save_reserves);
@@ -143,104 +56,124 @@ impl LendingMarketAggregator {
         self.marginfi_client.set_market_data(marginfi_data);
         self.drift_client.set_market_data(drift_markets);
 
+        // Rates and supplies are reported as of the current slot rather than the last update of
+        // each reserve
+        self.save_client.accrue_interest(&clock);
+        self.kamino_client.accrue_interest(&clock);
+        self.marginfi_client.accrue_interest(&clock);
+        self.drift_client.accrue_interest(&clock);
+        self.protocols = vec![save_status, marginfi_status, kamino_status, drift_status];
+        for status in &mut self.protocols {
+            if matches!(status.state, ProtocolState::Ok | ProtocolState::Partial) {
+                status.slot = Some(clock.slot);
+            }
+        }
+
         info!("Done loading all lending markets.");
 
         // Process reserves
-        self.process_all_reserves(current_slot);
+        self.process_all_reserves(&clock).await;
+
+        // Price supported assets from the oracles referenced by the reserves
+        if let Err(e) = self.load_asset_prices().await {
+            warn!("Failed to load oracle prices: {}", e);
+        }
 
         Ok(())
     }
 
+    /// Serves the reserves of the protocols that failed to load from an earlier load of the
+    /// markets, marking them stale with the slot they were read at
+    pub fn keep_stale_reserves(&mut self, previous: &LendingMarketAggregator) {
+        for status in &mut self.protocols {
+            if status.state != ProtocolState::Failed {
+                continue;
+            }
+            let Some(previous_status) = previous.protocols.iter().find(|previous_status| {
+                previous_status.protocol == status.protocol
+                    && previous_status.state != ProtocolState::Failed
+            }) else {
+                continue;
+            };
+
+            for (mint, previous_asset) in &previous.assets {
+                let stale_reserves: Vec<_> = previous_asset
+                    .lending_reserves
+                    .iter()
+                    .filter(|reserve| reserve.protocol_name == status.protocol)
+                    .cloned()
+                    .collect();
+                if stale_reserves.is_empty() {
+                    continue;
+                }
+
+                // Mints only the failed protocol lists are kept with its reserves alone
+                self.assets
+                    .entry(mint.clone())
+                    .or_insert_with(|| MintAsset {
+                        lending_reserves: Vec::new(),
+                        ..previous_asset.clone()
+                    })
+                    .lending_reserves
+                    .extend(stale_reserves);
+            }
+
+            info!(
+                "Serving stale {} reserves from slot {:?}",
+                status.protocol, previous_status.slot
+            );
+            status.state = ProtocolState::Stale;
+            status.slot = previous_status.slot;
+        }
+    }
+
     // New helper method to process all reserves
-    fn process_all_reserves(&mut self, current_slot: u64) {
+    async fn process_all_reserves(&mut self, clock: &Clock) {
+        let current_slot = clock.slot;
+        self.slot = current_slot;
+
+        // Reward APYs of Kamino farms and Marginfi emissions, keyed by reserve
+        let reward_apys = self.load_reward_apys(clock).await.unwrap_or_else(|e| {
+            warn!("Failed to load reward APYs: {}", e);
+            RewardApys::unavailable()
+        });
+
         // Process Save reserves
         self.process_save_reserves(current_slot);
 
         // Process Marginfi banks
-        self.process_marginfi_banks(current_slot);
+        self.process_marginfi_banks(current_slot, &reward_apys);
 
         // Process Kamino markets
-        self.process_kamino_markets(current_slot);
+        self.process_kamino_markets(clock, &reward_apys);
 
         // Process Drift markets
         self.process_drift_markets(current_slot);
     }
 
-    pub fn load_markets_sequential(&mut self) -> ArrayResult<()> {
-        info!("Loading all lending markets sequentially");
-
-        // Initialize supported tokens
-        self.init_supported_tokens();
-
-        // Get the current slot using the pooled client approach
-        let current_slot = common_rpc::with_rpc_client(&self.rpc_url, |client| {
-            client.get_slot().map_err(|e| ClientError::RpcError(Box::new(e)))
-        })?;
-
-        // Fetch markets for each protocol sequentially using the generic fetch_markets method
-        info!("Loading Save reserves");
-        let save_reserves = match self.save_client.fetch_markets() {
-            Ok(reserves) => reserves,
-            Err(e) => {
-                warn!("Failed to load Save reserves: {}", e);
-                Vec::new()
-            }
-        };
-
-        info!("Loading Kamino reserves");
-        let kamino_markets = match self.kamino_client.fetch_markets() {
-            Ok(markets) => markets,
-            Err(e) => {
-                warn!("Failed to load Kamino reserves: {}", e);
-                Vec::new()
-            }
-        };
-
-        // Now we can use the generic fetch_markets for MarginfiClient too
-        info!("Loading Marginfi markets");
-        let marginfi_data = match self.marginfi_client.fetch_markets() {
-            Ok(data) => data,
-            Err(e) => {
-                warn!("Failed to load Marginfi markets: {}", e);
-                (Vec::new(), MarginfiGroup::default())
-            }
-        };
-
-        info!("Loading Drift reserves");
-        let drift_markets = match self.drift_client.fetch_markets() {
-            Ok(markets) => markets,
-            Err(e) => {
-                warn!("Failed to load Drift reserves: {}", e);
-                Vec::new()
-            }
-        };
-
-        // Update client state with fetched data using the generic set_market_data method
-        self.save_client.set_market_data(save_reserves);
-        self.kamino_client.set_market_data(kamino_markets);
-        self.marginfi_client.set_market_data(marginfi_data);
-        self.drift_client.set_market_data(drift_markets);
-
-        info!("Done loading all lending markets.");
-
-        // Process reserves
-        self.process_all_reserves(current_slot);
-
-        Ok(())
-    }
-
     // Helper methods to process each protocol's reserves
     fn process_save_reserves(&mut self, current_slot: u64) {
         for pool in &self.save_client.pools {
-            for reserve in &pool.reserves {
+            for (reserve_pubkey, reserve) in &pool.reserves {
                 if let Ok(mint_pubkey) =
                     Pubkey::from_str(&reserve.liquidity.mint_pubkey.to_string())
                 {
                     let mint_str = mint_pubkey.to_string();
                     if let Some(asset) = self.assets.get_mut(&mint_str) {
                         asset.lending_reserves.push(LendingReserve::from(SaveReserveWrapper {
+                            pubkey: reserve_pubkey,
                             reserve,
                             market_name: &pool.name,
+                            collateral_assets: save_collateral(
+                                &pool.reserves,
+                                reserve_pubkey,
+                                reserve,
+                            ),
+                            exit_liquidity: save_exit_liquidity(
+                                reserve,
+                                &pool.rate_limiter,
+                                current_slot,
+                            ),
                             slot: current_slot,
                         }));
                     }
@@ -249,34 +182,52 @@ impl LendingMarketAggregator {
         }
     }
 
-    fn process_marginfi_banks(&mut self, current_slot: u64) {
-        for (_, bank) in &self.marginfi_client.banks {
+    fn process_marginfi_banks(&mut self, current_slot: u64, reward_apys: &RewardApys) {
+        for (market, bank_pubkey, bank) in self.marginfi_client.banks() {
             let mint_str = bank.mint.to_string();
             if let Some(asset) = self.assets.get_mut(&mint_str) {
                 asset.lending_reserves.push(LendingReserve::from(MarginfiReserveWrapper {
+                    pubkey: bank_pubkey,
                     bank,
-                    group: &self.marginfi_client.group,
-                    market_name: "Global Pool",
+                    group: &market.group,
+                    market_name: &market.name,
+                    reward_apy: reward_apys.supply(bank_pubkey),
+                    borrow_reward_apy: reward_apys.borrow(bank_pubkey),
+                    collateral_assets: marginfi_collateral(&market.banks, bank_pubkey, bank),
+                    exit_liquidity: marginfi_exit_liquidity(bank),
                     slot: current_slot,
                 }));
             }
         }
     }
 
-    fn process_kamino_markets(&mut self, current_slot: u64) {
-        for (_, market, reserves) in &self.kamino_client.markets {
-            let market_name = extract_market_name(&market.name);
+    fn process_kamino_markets(&mut self, clock: &Clock, reward_apys: &RewardApys) {
+        // Withdrawal caps are tracked in unix seconds
+        let now_ts = clock.unix_timestamp.max(0) as u64;
 
-            for (_, reserve) in reserves {
+        for (market_pubkey, market, reserves) in &self.kamino_client.markets {
+            let market_name = kamino_market_name(market_pubkey, market);
+
+            for (reserve_pubkey, reserve) in reserves {
                 if let Ok(mint_pubkey) =
                     Pubkey::from_str(&reserve.liquidity.mint_pubkey.to_string())
                 {
                     let mint_str = mint_pubkey.to_string();
                     if let Some(asset) = self.assets.get_mut(&mint_str) {
                         asset.lending_reserves.push(LendingReserve::from(KaminoReserveWrapper {
+                            pubkey: reserve_pubkey,
                             reserve,
                             market_name: &market_name,
-                            slot: current_slot,
+                            reward_apy: reward_apys.supply(reserve_pubkey),
+                            borrow_reward_apy: reward_apys.borrow(reserve_pubkey),
+                            collateral_assets: kamino_collateral(
+                                market,
+                                reserves,
+                                reserve_pubkey,
+                                reserve,
+                            ),
+                            exit_liquidity: kamino_exit_liquidity(reserve, now_ts),
+                            slot: clock.slot,
                         }));
                     }
                 }
@@ -285,13 +236,18 @@ impl LendingMarketAggregator {
     }
 
     fn process_drift_markets(&mut self, current_slot: u64) {
+        let state = transactions::drift::state_address(&self.drift_client.program_id());
+
         for (_, market) in &self.drift_client.spot_markets {
             let mint_str = market.mint.to_string();
             if let Some(asset) = self.assets.get_mut(&mint_str) {
                 let market_name = extract_market_name(&market.name);
                 asset.lending_reserves.push(LendingReserve::from(DriftReserveWrapper {
                     market,
+                    state: &state,
                     market_name: &market_name,
+                    collateral_assets: drift_collateral(&self.drift_client.spot_markets, market),
+                    exit_liquidity: drift_exit_liquidity(market),
                     slot: current_slot,
                 }));
             }
@@ -314,6 +270,7 @@ impl LendingMarketAggregator {
             "Total Supply",
             "Total Borrows",
             "Supply APY",
+            "Reward APY",
             "Borrow APY",
             "Valid Collateral"
         ]);
@@ -353,6 +310,13 @@ impl LendingMarketAggregator {
                         "{:.2}%",
                         reserve.supply_apy as f64 / (1u64 << SCALE_SHIFT) as f64 * 100.0
                     ),
+                    reserve.reward_apy.map_or_else(
+                        || "unknown".to_string(),
+                        |reward_apy| format!(
+                            "{:.2}%",
+                            reward_apy as f64 / (1u64 << SCALE_SHIFT) as f64 * 100.0
+                        )
+                    ),
                     format!(
                         "{:.2}%",
                         reserve.borrow_apy as f64 / (1u64 << SCALE_SHIFT) as f64 * 100.0
@@ -365,3 +329,131 @@ impl LendingMarketAggregator {
         table.printstd();
     }
 }
+
+/// Fetches the markets of a protocol with its status, returning no markets when it fails
+async fn fetch_protocol_markets<C, M>(client: &C, protocol: &str) -> (M, ProtocolStatus)
+where
+    C: LendingClient<Pubkey, M>,
+    M: Default,
+{
+    info!("Loading {} markets", protocol);
+    match client.fetch_markets().await {
+        Ok(markets) => (markets, ProtocolStatus::ok(protocol, None)),
+        Err(e) => {
+            warn!("Failed to load {} markets: {}", protocol, e);
+            (M::default(), ProtocolStatus::failed(protocol, e.kind()))
+        }
+    }
+}
+
+/// Kamino markets with their status, partial when the reserves of some markets failed to load
+async fn fetch_kamino_markets(client: &KaminoClient) -> (Vec<KaminoMarkets>, ProtocolStatus) {
+    info!("Loading Kamino markets");
+    match client.fetch_kamino_markets_impl().await {
+        Ok((markets, skipped)) => {
+            let status = match skipped.first() {
+                Some(e) => ProtocolStatus::partial("Kamino", None, e.kind(), skipped.len()),
+                None => ProtocolStatus::ok("Kamino", None),
+            };
+            (markets, status)
+        }
+        Err(e) => {
+            warn!("Failed to load Kamino markets: {}", e);
+            (Vec::new(), ProtocolStatus::failed("Kamino", e.kind()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{ErrorKind, ExitLiquidity, IsolationTier, ReserveStatus, RiskParameters};
+
+    fn reserve(protocol: &str, slot: u64) -> LendingReserve {
+        LendingReserve {
+            protocol_name: protocol.to_string(),
+            market_name: "Main".to_string(),
+            reserve_pubkey: Pubkey::new_unique().to_string(),
+            market_pubkey: Pubkey::new_unique().to_string(),
+            total_supply: 1_000,
+            total_borrows: 500,
+            deposit_cap: None,
+            borrow_cap: None,
+            remaining_deposit_capacity: None,
+            remaining_borrow_capacity: None,
+            borrow_rate: 0,
+            supply_rate: 0,
+            borrow_apy: 0,
+            supply_apy: 0,
+            reward_apy: None,
+            borrow_reward_apy: None,
+            total_apy: 0,
+            slot,
+            collateral_assets: vec![],
+            exit_liquidity: ExitLiquidity::default(),
+            risk: RiskParameters {
+                loan_to_value: 0.8,
+                liquidation_threshold: 0.9,
+                liquidation_bonus: 0.05,
+                borrow_factor: 1.0,
+                isolation_tier: IsolationTier::Cross,
+                status: ReserveStatus::Active,
+            },
+        }
+    }
+
+    fn asset(mint: &str, reserves: Vec<LendingReserve>) -> (String, MintAsset) {
+        let asset = MintAsset {
+            name: mint.to_string(),
+            symbol: mint.to_string(),
+            market_price_sf: 0,
+            mint: mint.to_string(),
+            lending_reserves: reserves,
+        };
+        (mint.to_string(), asset)
+    }
+
+    fn aggregator(
+        protocols: Vec<ProtocolStatus>,
+        assets: Vec<(String, MintAsset)>,
+    ) -> LendingMarketAggregator {
+        let mut aggregator = LendingMarketAggregator::new("http://localhost:8899");
+        aggregator.protocols = protocols;
+        aggregator.assets = assets.into_iter().collect();
+        aggregator
+    }
+
+    #[test]
+    fn test_keep_stale_reserves_of_failed_protocols() {
+        let previous = aggregator(
+            vec![ProtocolStatus::ok("Save", Some(10)), ProtocolStatus::ok("Kamino", Some(10))],
+            vec![
+                asset("SOL", vec![reserve("Save", 10), reserve("Kamino", 10)]),
+                asset("JTO", vec![reserve("Save", 10)]),
+            ],
+        );
+        let mut current = aggregator(
+            vec![
+                ProtocolStatus::failed("Save", ErrorKind::Rpc),
+                ProtocolStatus::ok("Kamino", Some(20)),
+            ],
+            vec![asset("SOL", vec![reserve("Kamino", 20)])],
+        );
+
+        current.keep_stale_reserves(&previous);
+
+        let slots = |mint: &str| -> Vec<(String, u64)> {
+            current.assets[mint]
+                .lending_reserves
+                .iter()
+                .map(|reserve| (reserve.protocol_name.clone(), reserve.slot))
+                .collect()
+        };
+        assert_eq!(slots("SOL"), [("Kamino".to_string(), 20), ("Save".to_string(), 10)]);
+        assert_eq!(slots("JTO"), [("Save".to_string(), 10)]);
+
+        assert_eq!(current.protocols[0].state, ProtocolState::Stale);
+        assert_eq!(current.protocols[0].slot, Some(10));
+        assert_eq!(current.protocols[1].state, ProtocolState::Ok);
+    }
+}
@@ -1,8 +1,21 @@
+pub mod allocation;
 pub mod client;
+pub mod collateral;
 pub mod from;
+pub mod health;
+pub mod liquidity;
 pub mod markets;
 pub mod normalize;
 pub mod obligations;
+pub mod prices;
+#[cfg(test)]
+mod replay_tests;
+pub mod rewards;
+pub mod risk;
+pub mod simulate;
+#[cfg(test)]
+mod synthetic_chain;
+pub mod transactions;
 pub mod utils;
 pub mod wallet;
 
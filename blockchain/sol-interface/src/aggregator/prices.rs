@@ -0,0 +1,107 @@
+use crate::{
+    aggregator::client::LendingMarketAggregator,
+    common::rpc_utils::{create_rpc_client, LendingErrorConverter},
+    oracle::{
+        feeds::{drift_feeds, kamino_feeds, marginfi_feeds, save_feeds},
+        select_price, OracleFeed, OraclePrice,
+    },
+};
+use common::lending::LendingError;
+use log::{debug, info};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+
+impl LendingMarketAggregator {
+    /// Collects the oracle feeds referenced by the loaded reserves, keyed by mint
+    pub fn collect_oracle_feeds(&self) -> HashMap<String, Vec<OracleFeed>> {
+        self.collect_mint_feeds(|mint| self.assets.contains_key(mint))
+    }
+
+    /// Collects the oracle feeds the loaded reserves reference for the mints accepted by `wanted`
+    pub fn collect_mint_feeds(
+        &self,
+        wanted: impl Fn(&str) -> bool,
+    ) -> HashMap<String, Vec<OracleFeed>> {
+        let mut feeds: HashMap<String, Vec<OracleFeed>> = HashMap::new();
+        let mut add = |mint: String, mint_feeds: Vec<OracleFeed>| {
+            if wanted(&mint) {
+                feeds.entry(mint).or_default().extend(mint_feeds);
+            }
+        };
+
+        for pool in &self.save_client.pools {
+            for (_, reserve) in &pool.reserves {
+                add(reserve.liquidity.mint_pubkey.to_string(), save_feeds(reserve));
+            }
+        }
+
+        for (_, _, bank) in self.marginfi_client.banks() {
+            add(bank.mint.to_string(), marginfi_feeds(bank));
+        }
+
+        for (_, _, reserves) in &self.kamino_client.markets {
+            for (_, reserve) in reserves {
+                add(reserve.liquidity.mint_pubkey.to_string(), kamino_feeds(reserve));
+            }
+        }
+
+        for (_, market) in &self.drift_client.spot_markets {
+            add(market.mint.to_string(), drift_feeds(market));
+        }
+
+        feeds
+    }
+
+    /// Reads the oracle accounts of every supported asset and sets `market_price_sf` to the
+    /// most recently published price
+    pub async fn load_asset_prices(&mut self) -> Result<(), LendingError> {
+        let prices = self.fetch_mint_prices(self.collect_oracle_feeds()).await?;
+
+        for (mint, price) in prices {
+            if let Some(asset) = self.assets.get_mut(&mint) {
+                asset.market_price_sf = price.to_market_price_sf().unwrap_or_default();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes `feeds` and keeps the most recently published price of each mint
+    pub async fn fetch_mint_prices(
+        &self,
+        feeds: HashMap<String, Vec<OracleFeed>>,
+    ) -> Result<HashMap<String, OraclePrice>, LendingError> {
+        let oracle_accounts: Vec<Pubkey> = feeds
+            .values()
+            .flatten()
+            .flat_map(OracleFeed::accounts)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        info!("Loading {} oracle accounts", oracle_accounts.len());
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, &oracle_accounts)
+        .await?;
+
+        let mut prices = HashMap::new();
+        for (mint, mint_feeds) in feeds {
+            let decoded = mint_feeds.iter().filter_map(|feed| match feed.decode(&accounts) {
+                Ok(price) => Some(price),
+                Err(e) => {
+                    debug!("Failed to decode oracle feed {:?} for {}: {}", feed, mint, e);
+                    None
+                }
+            });
+
+            if let Some(price) = select_price(decoded) {
+                prices.insert(mint, price);
+            }
+        }
+
+        Ok(prices)
+    }
+}
@@ -0,0 +1,215 @@
+//! End-to-end tests of the aggregator against recorded RPC responses
+//!
+//! The golden tests replay `fixtures/mainnet/replay.json`, captured from mainnet by
+//! `RPC_URL=<endpoint> REPLAY_WALLET=<wallet> cargo test record_mainnet_fixture -- --ignored`
+//! for a wallet with positions in the four protocols, which is saved next to the capture. Set
+//! `UPDATE_GOLDEN=1` to rewrite the golden outputs after a new capture.
+//!
+//! The accounts of [`SyntheticChain`] are served directly to the Save unit tests, they are not
+//! recorded.
+
+use crate::aggregator::{
+    client::LendingMarketAggregator,
+    synthetic_chain::{SyntheticChain, SLOT, SOL_MINT, USDC_MINT, WALLET},
+    wallet::fetch_token_balances,
+};
+use common::{ObligationType, ProtocolState};
+use common_rpc::{
+    assert_golden, RecordingSender, RpcFixture, RpcRecorder, CONNECTION_POOL, REPLAY_PREFIX,
+};
+use serde_json::{json, Value};
+use solana_account_decoder::{
+    parse_account_data::AccountAdditionalData, UiAccount, UiAccountEncoding,
+};
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcTokenAccountsFilter};
+use solana_program::program_pack::Pack;
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use spl_token::state::{Account as TokenAccount, AccountState};
+use std::{collections::BTreeMap, future::Future, path::PathBuf, str::FromStr, sync::Arc};
+
+/// Endpoint spec the synthetic accounts are served under
+const SYNTHETIC_URL: &str = "synthetic";
+/// Endpoint spec the mainnet responses are recorded under
+const MAINNET_URL: &str = "mainnet";
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name)
+}
+
+fn replay_url(path: &std::path::Path) -> String {
+    format!("{}{}", REPLAY_PREFIX, path.display())
+}
+
+/// The aggregator replaying the mainnet capture, with the wallet it was recorded for
+fn mainnet_replay() -> (LendingMarketAggregator, String) {
+    let wallet = std::fs::read_to_string(fixture_path("mainnet/wallet.txt")).unwrap();
+    let aggregator =
+        LendingMarketAggregator::new(&replay_url(&fixture_path("mainnet/replay.json")));
+    (aggregator, wallet.trim().to_string())
+}
+
+fn synthetic_aggregator() -> LendingMarketAggregator {
+    CONNECTION_POOL.register_sender(SYNTHETIC_URL, SyntheticChain::new());
+    LendingMarketAggregator::new(SYNTHETIC_URL)
+}
+
+#[test]
+fn test_wallet_balances_replay_recorded_token_accounts() {
+    let wallet = Pubkey::from_str(WALLET).unwrap();
+    let mint = Pubkey::from_str(USDC_MINT).unwrap();
+    let token_account = Pubkey::new_unique();
+
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount {
+        mint,
+        owner: wallet,
+        amount: 1_250_000,
+        state: AccountState::Initialized,
+        ..Default::default()
+    }
+    .pack_into_slice(&mut data);
+    let account =
+        Account { lamports: 2_039_280, data, owner: spl_token::id(), ..Default::default() };
+    let ui_account = UiAccount::encode(
+        &token_account,
+        &account,
+        UiAccountEncoding::JsonParsed,
+        Some(AccountAdditionalData { spl_token_decimals: Some(6) }),
+        None,
+    );
+
+    let token_accounts_config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::JsonParsed),
+        commitment: Some(CommitmentConfig::default()),
+        data_slice: None,
+        min_context_slot: None,
+    };
+    let mut fixture = RpcFixture::default();
+    fixture.record(
+        "getVersion".to_string(),
+        Value::Null,
+        json!({ "solana-core": "1.18.26", "feature-set": 0 }),
+    );
+    fixture.record(
+        "getTokenAccountsByOwner".to_string(),
+        json!([WALLET, RpcTokenAccountsFilter::Mint(USDC_MINT.to_string()), token_accounts_config]),
+        json!({
+            "context": { "slot": 1 },
+            "value": [{ "pubkey": token_account.to_string(), "account": ui_account }],
+        }),
+    );
+
+    let path = std::env::temp_dir().join(format!("wallet-replay-{}.json", token_account));
+    fixture.save(&path).unwrap();
+
+    let balances = block_on(fetch_token_balances(
+        &replay_url(&path),
+        WALLET,
+        &[(USDC_MINT.to_string(), "USDC".to_string())],
+    ))
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].amount, 1_250_000);
+    assert_eq!(balances[0].decimals, 6);
+    assert_eq!(balances[0].token_account, token_account.to_string());
+}
+
+#[test]
+#[ignore = "records fixtures/mainnet from the endpoint in RPC_URL"]
+fn record_mainnet_fixture() {
+    let rpc_url = std::env::var("RPC_URL").expect("RPC_URL is not set");
+    let wallet = std::env::var("REPLAY_WALLET").expect("REPLAY_WALLET is not set");
+    let path = fixture_path("mainnet/replay.json");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let recorder = Arc::new(RpcRecorder::new(&path));
+    CONNECTION_POOL.register_sender(
+        MAINNET_URL,
+        RecordingSender::new(HttpSender::new(rpc_url), recorder.clone()),
+    );
+
+    let mut aggregator = LendingMarketAggregator::new(MAINNET_URL);
+    block_on(aggregator.load_markets_async()).unwrap();
+    let obligations = block_on(aggregator.get_user_obligations_async(&wallet)).unwrap();
+    recorder.flush().unwrap();
+    std::fs::write(fixture_path("mainnet/wallet.txt"), &wallet).unwrap();
+
+    // Save positions are reported under its former name
+    for protocol in ["Solend", "Marginfi", "Kamino", "Drift"] {
+        assert!(
+            obligations.data.iter().any(|o| o.protocol_name == protocol),
+            "{} has no {} position to cover",
+            wallet,
+            protocol
+        );
+    }
+}
+
+#[test]
+#[ignore = "replays fixtures/mainnet/replay.json, recorded by record_mainnet_fixture"]
+fn test_mainnet_markets_match_golden() {
+    let (mut aggregator, _) = mainnet_replay();
+    block_on(aggregator.load_markets_async()).unwrap();
+
+    let assets: BTreeMap<_, _> = aggregator.assets.iter().collect();
+    assert_golden(fixture_path("mainnet/markets.golden.json"), &assets);
+    assert_golden(fixture_path("mainnet/protocols.golden.json"), &aggregator.protocols);
+}
+
+#[test]
+#[ignore = "replays fixtures/mainnet/replay.json, recorded by record_mainnet_fixture"]
+fn test_mainnet_obligations_match_golden() {
+    let (mut aggregator, wallet) = mainnet_replay();
+    block_on(aggregator.load_markets_async()).unwrap();
+
+    let obligations = block_on(aggregator.get_user_obligations_async(&wallet)).unwrap();
+    assert_golden(fixture_path("mainnet/obligations.golden.json"), &obligations.data);
+}
+
+#[test]
+fn test_synthetic_save_market_loads() {
+    let mut aggregator = synthetic_aggregator();
+    block_on(aggregator.load_markets_async()).unwrap();
+
+    assert_eq!(aggregator.slot, SLOT);
+    assert!(aggregator.protocols.iter().all(|status| status.state == ProtocolState::Ok));
+    // SOL is not a supported asset, it is only listed as collateral
+    let reserves = &aggregator.assets[USDC_MINT].lending_reserves;
+    assert_eq!(reserves.len(), 1);
+    assert_eq!(reserves[0].protocol_name, "Save");
+    assert_eq!(reserves[0].slot, SLOT);
+    assert!(reserves[0].total_borrows < reserves[0].total_supply);
+    assert!(reserves[0].supply_apy > 0);
+    let collateral: Vec<&str> =
+        reserves[0].collateral_assets.iter().map(|asset| asset.mint.as_str()).collect();
+    assert_eq!(collateral, [SOL_MINT]);
+}
+
+#[test]
+fn test_synthetic_save_obligation_loads() {
+    let mut aggregator = synthetic_aggregator();
+    block_on(aggregator.load_markets_async()).unwrap();
+
+    let obligations = block_on(aggregator.get_user_obligations_async(WALLET)).unwrap().data;
+    assert_eq!(obligations.len(), 2);
+    let deposit =
+        obligations.iter().find(|o| matches!(o.obligation_type, ObligationType::Asset)).unwrap();
+    let borrow = obligations
+        .iter()
+        .find(|o| matches!(o.obligation_type, ObligationType::Liability))
+        .unwrap();
+
+    // Interest accrued to the clock slot adds to both positions
+    assert_eq!(deposit.mint, SOL_MINT);
+    assert!(deposit.amount >= 10_000_000_000);
+    assert_eq!(borrow.mint, USDC_MINT);
+    assert!(borrow.amount >= 500_000_000);
+}
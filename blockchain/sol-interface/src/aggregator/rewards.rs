@@ -0,0 +1,275 @@
+use crate::{
+    aggregator::client::LendingMarketAggregator,
+    common::rpc_utils::{create_rpc_client, LendingErrorConverter},
+    kamino::{
+        models::reserve::ReserveFarmKind,
+        utils::{consts::NULL_PUBKEY, fraction::Fraction},
+    },
+    marginfi::utils::constants::{EMISSIONS_FLAG_BORROW_ACTIVE, EMISSIONS_FLAG_LENDING_ACTIVE},
+    rewards::{
+        emissions::{marginfi_borrowing_emission, marginfi_lending_emission},
+        farms::decode_farm_state,
+        reward_apy, RewardEmission,
+    },
+};
+use common::lending::LendingError;
+use log::{debug, info};
+use solana_program::program_pack::Pack;
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
+use spl_token::state::Mint;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// Side of a reserve rewards are paid to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RewardSide {
+    Supply,
+    Borrow,
+}
+
+/// Rewards paid on a reserve together with the supplied or borrowed amount, in whole tokens,
+/// they are shared between
+struct ReserveRewards {
+    reserve: Pubkey,
+    side: RewardSide,
+    mint: Pubkey,
+    staked: f64,
+    emissions: Vec<RewardEmission>,
+}
+
+/// APYs Kamino farms and Marginfi emissions pay, keyed by Kamino reserve or Marginfi bank address.
+///
+/// An APY is None when it is unknown: a reward token has no price or the reward accounts could
+/// not be read. Reserves without rewards get 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RewardApys {
+    supply: HashMap<Pubkey, Option<f64>>,
+    borrow: HashMap<Pubkey, Option<f64>>,
+    unavailable: bool,
+}
+
+impl RewardApys {
+    /// Rewards that could not be loaded, every APY is unknown
+    pub fn unavailable() -> Self {
+        Self { unavailable: true, ..Self::default() }
+    }
+
+    /// APY paid in reward tokens to the suppliers of a reserve
+    pub fn supply(&self, reserve: &Pubkey) -> Option<f64> {
+        self.get(&self.supply, reserve)
+    }
+
+    /// APY paid in reward tokens to the borrowers of a reserve
+    pub fn borrow(&self, reserve: &Pubkey) -> Option<f64> {
+        self.get(&self.borrow, reserve)
+    }
+
+    fn get(&self, apys: &HashMap<Pubkey, Option<f64>>, reserve: &Pubkey) -> Option<f64> {
+        if self.unavailable {
+            return None;
+        }
+        apys.get(reserve).copied().unwrap_or(Some(0.0))
+    }
+
+    fn insert(&mut self, side: RewardSide, reserve: Pubkey, apy: Option<f64>) {
+        match side {
+            RewardSide::Supply => self.supply.insert(reserve, apy),
+            RewardSide::Borrow => self.borrow.insert(reserve, apy),
+        };
+    }
+}
+
+impl LendingMarketAggregator {
+    /// Computes the APY Kamino farms and Marginfi emissions pay to suppliers and borrowers, with
+    /// the rewards running at the time of the clock
+    pub async fn load_reward_apys(&self, clock: &Clock) -> Result<RewardApys, LendingError> {
+        let kamino_farms: Vec<_> = self
+            .kamino_client
+            .markets
+            .iter()
+            .flat_map(|(_, _, reserves)| reserves)
+            .flat_map(|(pubkey, reserve)| {
+                [
+                    (RewardSide::Supply, reserve.get_farm(ReserveFarmKind::Collateral)),
+                    (RewardSide::Borrow, reserve.get_farm(ReserveFarmKind::Debt)),
+                ]
+                .map(|(side, farm)| (pubkey, reserve, side, farm))
+            })
+            .filter(|(_, _, _, farm)| *farm != Pubkey::default() && *farm != NULL_PUBKEY)
+            .collect();
+
+        let marginfi_banks: Vec<_> = self
+            .marginfi_client
+            .banks()
+            .filter(|(_, _, bank)| {
+                bank.get_flag(EMISSIONS_FLAG_LENDING_ACTIVE)
+                    || bank.get_flag(EMISSIONS_FLAG_BORROW_ACTIVE)
+            })
+            .map(|(_, pubkey, bank)| (pubkey, bank))
+            .collect();
+
+        let mut apys = RewardApys::default();
+        if kamino_farms.is_empty() && marginfi_banks.is_empty() {
+            return Ok(apys);
+        }
+
+        let reward_accounts: Vec<Pubkey> = kamino_farms
+            .iter()
+            .map(|(_, _, _, farm)| *farm)
+            .chain(marginfi_banks.iter().map(|(_, bank)| bank.emissions_mint))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        info!("Loading {} reward accounts", reward_accounts.len());
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, &reward_accounts)
+        .await?;
+
+        let now_ts = clock.unix_timestamp.max(0) as u64;
+        let mut rewards = Vec::new();
+
+        for (pubkey, reserve, side, farm) in kamino_farms {
+            let Some(account) = accounts.get(&farm) else {
+                debug!("Farm {} of Kamino reserve {} not found", farm, pubkey);
+                apys.insert(side, *pubkey, None);
+                continue;
+            };
+            let farm_state = match decode_farm_state(&account.data) {
+                Ok(farm_state) => farm_state,
+                Err(e) => {
+                    debug!("Failed to decode farm {} of Kamino reserve {}: {}", farm, pubkey, e);
+                    apys.insert(side, *pubkey, None);
+                    continue;
+                }
+            };
+
+            // Collateral farms stake the deposited collateral tokens, value them as liquidity.
+            // Debt farms stake the borrowed liquidity itself
+            let staked = Fraction::from_num(farm_state.total_staked_amount);
+            let staked = match side {
+                RewardSide::Supply => match reserve.collateral_exchange_rate() {
+                    Ok(exchange_rate) => exchange_rate.fraction_collateral_to_liquidity(staked),
+                    Err(_) => {
+                        apys.insert(side, *pubkey, None);
+                        continue;
+                    }
+                },
+                RewardSide::Borrow => staked,
+            };
+
+            rewards.push(ReserveRewards {
+                reserve: *pubkey,
+                side,
+                mint: reserve.liquidity.mint_pubkey,
+                staked: staked.to_num::<f64>() / 10f64.powi(reserve.liquidity.mint_decimals as i32),
+                emissions: farm_state.emissions(now_ts, clock.slot),
+            });
+        }
+
+        for (pubkey, bank) in marginfi_banks {
+            let decimals = accounts.get(&bank.emissions_mint).and_then(|account| {
+                Mint::unpack_from_slice(account.data.get(..Mint::LEN)?).ok().map(|m| m.decimals)
+            });
+
+            for (side, flag) in [
+                (RewardSide::Supply, EMISSIONS_FLAG_LENDING_ACTIVE),
+                (RewardSide::Borrow, EMISSIONS_FLAG_BORROW_ACTIVE),
+            ] {
+                if !bank.get_flag(flag) {
+                    continue;
+                }
+                let Some(decimals) = decimals else {
+                    debug!(
+                        "Emissions mint {} of Marginfi bank {} not found",
+                        bank.emissions_mint, pubkey
+                    );
+                    apys.insert(side, *pubkey, None);
+                    continue;
+                };
+                let (emission, staked) = match side {
+                    RewardSide::Supply => {
+                        (marginfi_lending_emission(bank, decimals), bank.get_total_supply())
+                    }
+                    RewardSide::Borrow => {
+                        (marginfi_borrowing_emission(bank, decimals), bank.get_total_borrowed())
+                    }
+                };
+                let (Some(emission), Ok(staked)) = (emission, staked) else {
+                    continue;
+                };
+
+                rewards.push(ReserveRewards {
+                    reserve: *pubkey,
+                    side,
+                    mint: bank.mint,
+                    staked: staked.to_num::<f64>() / 10f64.powi(bank.mint_decimals as i32),
+                    emissions: vec![emission],
+                });
+            }
+        }
+
+        // Reward tokens are priced from the oracles of the reserves that list them and from the
+        // configured feeds of the tokens no reserve lists
+        let priced_mints: HashSet<String> = rewards
+            .iter()
+            .flat_map(|r| std::iter::once(r.mint).chain(r.emissions.iter().map(|e| e.mint)))
+            .map(|mint| mint.to_string())
+            .collect();
+        let mut feeds = self.collect_mint_feeds(|mint| priced_mints.contains(mint));
+        for (mint, feed) in &self.reward_prices.feeds {
+            if priced_mints.contains(&mint.to_string()) {
+                feeds.entry(mint.to_string()).or_default().push(feed.clone());
+            }
+        }
+        let prices: HashMap<Pubkey, f64> = self
+            .fetch_mint_prices(feeds)
+            .await?
+            .into_iter()
+            .filter_map(|(mint, price)| Some((Pubkey::from_str(&mint).ok()?, price.as_f64())))
+            .collect();
+
+        for r in rewards {
+            // A farm whose rewards have all ended pays nothing, priced or not
+            let apy = if r.emissions.is_empty() {
+                Some(0.0)
+            } else {
+                prices
+                    .get(&r.mint)
+                    .and_then(|price| reward_apy(&r.emissions, &prices, r.staked * price))
+            };
+            if apy.is_none() {
+                debug!("Rewards of reserve {} are unpriced", r.reserve);
+            }
+            apys.insert(r.side, r.reserve, apy);
+        }
+
+        Ok(apys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reward_apys_tell_unknown_from_no_rewards() {
+        let (farmed, unpriced, plain) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut apys = RewardApys::default();
+        apys.insert(RewardSide::Supply, farmed, Some(0.05));
+        apys.insert(RewardSide::Borrow, unpriced, None);
+
+        assert_eq!(apys.supply(&farmed), Some(0.05));
+        assert_eq!(apys.borrow(&farmed), Some(0.0));
+        assert_eq!(apys.supply(&unpriced), Some(0.0));
+        assert_eq!(apys.borrow(&unpriced), None);
+        assert_eq!(apys.supply(&plain), Some(0.0));
+        assert_eq!(RewardApys::unavailable().supply(&plain), None);
+    }
+}
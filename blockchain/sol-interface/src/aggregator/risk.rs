@@ -0,0 +1,219 @@
+use crate::{
+    kamino::models::reserve::{
+        AssetTier as KaminoAssetTier, Reserve as KaminoReserve, ReserveStatus as KaminoStatus,
+    },
+    marginfi::{
+        models::group::{Bank, BankOperationalState, RiskTier},
+        utils::constants::LIQUIDATION_LIQUIDATOR_FEE,
+    },
+    save::models::{Reserve as SaveReserve, ReserveType},
+};
+use common::{IsolationTier, ReserveStatus, RiskParameters};
+use drift::{
+    math::constants::{LIQUIDATION_FEE_PRECISION, SPOT_WEIGHT_PRECISION},
+    models::idl::{
+        accounts::SpotMarket,
+        types::{AssetTier as DriftAssetTier, MarketStatus},
+    },
+};
+use fixed::types::I80F48;
+
+pub fn save_risk(reserve: &SaveReserve) -> RiskParameters {
+    let config = &reserve.config;
+
+    // Save has no reserve status, a reserve without deposit nor borrow limit only winds down
+    let status = if config.deposit_limit == 0 && config.borrow_limit == 0 {
+        ReserveStatus::ReduceOnly
+    } else {
+        ReserveStatus::Active
+    };
+
+    RiskParameters {
+        loan_to_value: config.loan_to_value_ratio as f64 / 100.0,
+        liquidation_threshold: config.liquidation_threshold as f64 / 100.0,
+        // The bonus grows from `liquidation_bonus` as the position gets closer to insolvency
+        liquidation_bonus: config.max_liquidation_bonus.max(config.liquidation_bonus) as f64
+            / 100.0,
+        borrow_factor: 1.0 + config.added_borrow_weight_bps as f64 / 10_000.0,
+        isolation_tier: match config.reserve_type {
+            ReserveType::Regular => IsolationTier::Cross,
+            ReserveType::Isolated => IsolationTier::IsolatedDebt,
+        },
+        status,
+    }
+}
+
+/// Marginfi weighs deposits and borrows instead of using ratios, the initial weights set the
+/// borrow limit and the maintenance weights the liquidation threshold.
+///
+/// The liquidation bonus is the liquidator fee alone. The liquidatee also pays the insurance fee
+/// (`LIQUIDATION_INSURANCE_FEE`), which goes to the bank's insurance fund, not to the liquidator
+pub fn marginfi_risk(bank: &Bank) -> RiskParameters {
+    let config = &bank.config;
+
+    RiskParameters {
+        loan_to_value: I80F48::from(config.asset_weight_init).to_num::<f64>(),
+        liquidation_threshold: I80F48::from(config.asset_weight_maint).to_num::<f64>(),
+        liquidation_bonus: LIQUIDATION_LIQUIDATOR_FEE.to_num::<f64>(),
+        borrow_factor: I80F48::from(config.liability_weight_init).to_num::<f64>(),
+        isolation_tier: match config.risk_tier {
+            RiskTier::Collateral => IsolationTier::Cross,
+            RiskTier::Isolated => IsolationTier::IsolatedDebt,
+        },
+        status: match config.operational_state {
+            BankOperationalState::Operational => ReserveStatus::Active,
+            BankOperationalState::ReduceOnly => ReserveStatus::ReduceOnly,
+            BankOperationalState::Paused => ReserveStatus::Paused,
+        },
+    }
+}
+
+pub fn kamino_risk(reserve: &KaminoReserve) -> RiskParameters {
+    let config = &reserve.config;
+
+    RiskParameters {
+        loan_to_value: config.loan_to_value_pct as f64 / 100.0,
+        liquidation_threshold: config.liquidation_threshold_pct as f64 / 100.0,
+        liquidation_bonus: config.max_liquidation_bonus_bps as f64 / 10_000.0,
+        borrow_factor: config.get_borrow_factor().to_num::<f64>(),
+        isolation_tier: match config.get_asset_tier() {
+            KaminoAssetTier::Regular => IsolationTier::Cross,
+            KaminoAssetTier::IsolatedCollateral => IsolationTier::IsolatedCollateral,
+            KaminoAssetTier::IsolatedDebt => IsolationTier::IsolatedDebt,
+        },
+        // Hidden reserves are only left out of the Kamino app, they still operate
+        status: match config.status() {
+            KaminoStatus::Active | KaminoStatus::Hidden => ReserveStatus::Active,
+            KaminoStatus::Obsolete => ReserveStatus::Delisted,
+        },
+    }
+}
+
+/// Drift weighs deposits and borrows instead of using ratios, the initial weights set the borrow
+/// limit and the maintenance weights the liquidation threshold
+pub fn drift_risk(market: &SpotMarket) -> RiskParameters {
+    let precision = SPOT_WEIGHT_PRECISION as f64;
+
+    RiskParameters {
+        loan_to_value: market.initial_asset_weight as f64 / precision,
+        liquidation_threshold: market.maintenance_asset_weight as f64 / precision,
+        liquidation_bonus: market.liquidator_fee as f64 / LIQUIDATION_FEE_PRECISION as f64,
+        borrow_factor: market.initial_liability_weight as f64 / precision,
+        // Protected and cross tier markets can be held with any other market, the cross tier
+        // ones only without counting as collateral, which their zero asset weight tells
+        isolation_tier: match market.asset_tier {
+            DriftAssetTier::Collateral | DriftAssetTier::Protected | DriftAssetTier::Cross => {
+                IsolationTier::Cross
+            }
+            DriftAssetTier::Isolated | DriftAssetTier::Unlisted => IsolationTier::IsolatedDebt,
+        },
+        // The funding, AMM and fill pauses only concern perp markets
+        status: match market.status {
+            MarketStatus::Active
+            | MarketStatus::FundingPaused
+            | MarketStatus::AmmPaused
+            | MarketStatus::FillPaused => ReserveStatus::Active,
+            MarketStatus::ReduceOnly => ReserveStatus::ReduceOnly,
+            MarketStatus::Initialized | MarketStatus::WithdrawPaused => ReserveStatus::Paused,
+            MarketStatus::Settlement | MarketStatus::Delisted => ReserveStatus::Delisted,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_isolated_reserve_borrows_alone() {
+        let mut reserve = SaveReserve::default();
+        reserve.config.loan_to_value_ratio = 75;
+        reserve.config.liquidation_threshold = 80;
+        reserve.config.liquidation_bonus = 5;
+        reserve.config.max_liquidation_bonus = 10;
+        reserve.config.added_borrow_weight_bps = 2_500;
+        reserve.config.deposit_limit = 1_000;
+        reserve.config.reserve_type = ReserveType::Isolated;
+
+        let risk = save_risk(&reserve);
+        assert_eq!(risk.loan_to_value, 0.75);
+        assert_eq!(risk.liquidation_threshold, 0.8);
+        assert_eq!(risk.liquidation_bonus, 0.1);
+        assert_eq!(risk.borrow_factor, 1.25);
+        assert_eq!(risk.isolation_tier, IsolationTier::IsolatedDebt);
+        assert_eq!(risk.status, ReserveStatus::Active);
+    }
+
+    #[test]
+    fn marginfi_weights_become_ratios() {
+        let mut bank = Bank::default();
+        bank.config.asset_weight_init = I80F48::from_num(0.75).into();
+        bank.config.asset_weight_maint = I80F48::from_num(0.875).into();
+        bank.config.liability_weight_init = I80F48::from_num(1.25).into();
+        bank.config.risk_tier = RiskTier::Isolated;
+
+        let risk = marginfi_risk(&bank);
+        assert_eq!(risk.loan_to_value, 0.75);
+        assert_eq!(risk.liquidation_threshold, 0.875);
+        assert_eq!(risk.liquidation_bonus, LIQUIDATION_LIQUIDATOR_FEE.to_num::<f64>());
+        assert_eq!(risk.borrow_factor, 1.25);
+        assert_eq!(risk.isolation_tier, IsolationTier::IsolatedDebt);
+
+        for (state, status) in [
+            (BankOperationalState::Operational, ReserveStatus::Active),
+            (BankOperationalState::ReduceOnly, ReserveStatus::ReduceOnly),
+            (BankOperationalState::Paused, ReserveStatus::Paused),
+        ] {
+            bank.config.operational_state = state;
+            assert_eq!(marginfi_risk(&bank).status, status);
+        }
+    }
+
+    #[test]
+    fn kamino_hidden_reserve_stays_active() {
+        let mut reserve = KaminoReserve::default();
+        reserve.config.loan_to_value_pct = 70;
+        reserve.config.liquidation_threshold_pct = 85;
+        reserve.config.max_liquidation_bonus_bps = 1_000;
+        reserve.config.borrow_factor_pct = 150;
+        reserve.config.asset_tier = KaminoAssetTier::IsolatedCollateral as u8;
+        reserve.config.status = KaminoStatus::Hidden as u8;
+
+        let risk = kamino_risk(&reserve);
+        assert_eq!(risk.loan_to_value, 0.7);
+        assert_eq!(risk.liquidation_threshold, 0.85);
+        assert_eq!(risk.liquidation_bonus, 0.1);
+        assert_eq!(risk.borrow_factor, 1.5);
+        assert_eq!(risk.isolation_tier, IsolationTier::IsolatedCollateral);
+        assert_eq!(risk.status, ReserveStatus::Active);
+
+        // A borrow factor under 100% counts borrows at their value
+        reserve.config.borrow_factor_pct = 0;
+        reserve.config.asset_tier = KaminoAssetTier::IsolatedDebt as u8;
+        reserve.config.status = KaminoStatus::Obsolete as u8;
+        let risk = kamino_risk(&reserve);
+        assert_eq!(risk.borrow_factor, 1.0);
+        assert_eq!(risk.isolation_tier, IsolationTier::IsolatedDebt);
+        assert_eq!(risk.status, ReserveStatus::Delisted);
+    }
+
+    #[test]
+    fn drift_weights_become_ratios() {
+        let market = SpotMarket {
+            initial_asset_weight: 8_000,
+            maintenance_asset_weight: 9_000,
+            initial_liability_weight: 12_000,
+            liquidator_fee: 10_000,
+            status: MarketStatus::ReduceOnly,
+            ..Default::default()
+        };
+
+        let risk = drift_risk(&market);
+        assert_eq!(risk.loan_to_value, 0.8);
+        assert_eq!(risk.liquidation_threshold, 0.9);
+        assert_eq!(risk.liquidation_bonus, 0.01);
+        assert_eq!(risk.borrow_factor, 1.2);
+        assert_eq!(risk.isolation_tier, IsolationTier::Cross);
+        assert_eq!(risk.status, ReserveStatus::ReduceOnly);
+    }
+}
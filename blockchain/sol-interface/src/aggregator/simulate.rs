@@ -0,0 +1,439 @@
+use crate::{
+    aggregator::{
+        client::LendingMarketAggregator,
+        from::{
+            DriftReserveWrapper, KaminoReserveWrapper, MarginfiReserveWrapper, SaveReserveWrapper,
+        },
+        utils::extract_market_name,
+    },
+    common::client_trait::ClientError,
+    kamino::{
+        client::market_name as kamino_market_name, models::reserve::Reserve as KaminoReserve,
+        utils::fraction::Fraction,
+    },
+    marginfi::{client::MarginfiMarket, models::group::Bank},
+    save::{
+        client::SolendPool,
+        math::{Decimal, TryAdd},
+        models::Reserve as SaveReserve,
+    },
+    transactions,
+};
+use common::{lending::LendingClient, ExitLiquidity, LendingReserve, RateImpact};
+use drift::models::{
+    idl::{accounts::SpotMarket, types::SpotBalanceType},
+    spot_market::get_spot_balance,
+};
+use fixed::types::I80F48;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+// Type alias for results
+type ArrayResult<T> = Result<T, ClientError>;
+
+/// A hypothetical change to the liquidity of a reserve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiquidityAction {
+    Deposit,
+    Borrow,
+}
+
+fn overflow() -> ClientError {
+    ClientError::ProtocolError("Simulated liquidity overflows".to_string())
+}
+
+fn insufficient_liquidity(amount: u64, available: impl std::fmt::Display) -> ClientError {
+    ClientError::ProtocolError(format!("Cannot borrow {} with {} available", amount, available))
+}
+
+/// Save reserve after depositing or borrowing `amount` native tokens
+pub fn simulate_save(
+    reserve: &SaveReserve,
+    action: LiquidityAction,
+    amount: u64,
+) -> ArrayResult<SaveReserve> {
+    let mut reserve = reserve.clone();
+    let liquidity = &mut reserve.liquidity;
+
+    match action {
+        LiquidityAction::Deposit => {
+            liquidity.available_amount =
+                liquidity.available_amount.checked_add(amount).ok_or_else(overflow)?;
+        }
+        LiquidityAction::Borrow => {
+            liquidity.available_amount = liquidity
+                .available_amount
+                .checked_sub(amount)
+                .ok_or_else(|| insufficient_liquidity(amount, liquidity.available_amount))?;
+            liquidity.borrowed_amount_wads = liquidity
+                .borrowed_amount_wads
+                .try_add(Decimal::from(amount))
+                .map_err(|_| overflow())?;
+        }
+    }
+
+    Ok(reserve)
+}
+
+/// Kamino reserve after depositing or borrowing `amount` native tokens
+pub fn simulate_kamino(
+    reserve: &KaminoReserve,
+    action: LiquidityAction,
+    amount: u64,
+) -> ArrayResult<KaminoReserve> {
+    let mut reserve = reserve.clone();
+    let liquidity = &mut reserve.liquidity;
+
+    match action {
+        LiquidityAction::Deposit => {
+            liquidity.available_amount =
+                liquidity.available_amount.checked_add(amount).ok_or_else(overflow)?;
+        }
+        LiquidityAction::Borrow => {
+            liquidity.available_amount = liquidity
+                .available_amount
+                .checked_sub(amount)
+                .ok_or_else(|| insufficient_liquidity(amount, liquidity.available_amount))?;
+            liquidity.borrowed_amount_sf = Fraction::from_bits(liquidity.borrowed_amount_sf)
+                .checked_add(Fraction::from(amount))
+                .ok_or_else(overflow)?
+                .to_bits();
+        }
+    }
+
+    Ok(reserve)
+}
+
+/// Marginfi bank after depositing or borrowing `amount` native tokens
+pub fn simulate_marginfi(bank: &Bank, action: LiquidityAction, amount: u64) -> ArrayResult<Bank> {
+    let mut bank = bank.clone();
+    let value = I80F48::from_num(amount);
+
+    match action {
+        LiquidityAction::Deposit => {
+            let shares = bank.get_asset_shares(value).map_err(|_| overflow())?;
+            bank.total_asset_shares = I80F48::from(bank.total_asset_shares)
+                .checked_add(shares)
+                .ok_or_else(overflow)?
+                .into();
+        }
+        LiquidityAction::Borrow => {
+            let total_supply = bank.get_total_supply().map_err(|_| overflow())?;
+            let total_borrowed = bank.get_total_borrowed().map_err(|_| overflow())?;
+            // Bad debt can leave more borrowed than supplied
+            let available = (total_supply - total_borrowed).max(I80F48::ZERO);
+            if value > available {
+                return Err(insufficient_liquidity(amount, available.to_num::<u64>()));
+            }
+
+            let shares = bank.get_liability_shares(value).map_err(|_| overflow())?;
+            bank.total_liability_shares = I80F48::from(bank.total_liability_shares)
+                .checked_add(shares)
+                .ok_or_else(overflow)?
+                .into();
+        }
+    }
+
+    Ok(bank)
+}
+
+/// Drift spot market after depositing or borrowing `amount` native tokens
+pub fn simulate_drift(
+    market: &SpotMarket,
+    action: LiquidityAction,
+    amount: u64,
+) -> ArrayResult<SpotMarket> {
+    let mut market = *market;
+
+    match action {
+        LiquidityAction::Deposit => {
+            let balance = get_spot_balance(amount as u128, &market, &SpotBalanceType::Deposit)
+                .map_err(|_| overflow())?;
+            market.deposit_balance =
+                market.deposit_balance.checked_add(balance).ok_or_else(overflow)?;
+        }
+        LiquidityAction::Borrow => {
+            let available = market.get_available_deposits().map_err(|_| overflow())?;
+            if amount as u128 > available {
+                return Err(insufficient_liquidity(amount, available));
+            }
+
+            let balance = get_spot_balance(amount as u128, &market, &SpotBalanceType::Borrow)
+                .map_err(|_| overflow())?;
+            market.borrow_balance =
+                market.borrow_balance.checked_add(balance).ok_or_else(overflow)?;
+        }
+    }
+
+    Ok(market)
+}
+
+/// A loaded reserve whose rates can be recomputed after a liquidity change
+pub enum ReserveModel<'a> {
+    Save { pool: &'a SolendPool, address: &'a Pubkey, reserve: &'a SaveReserve },
+    Marginfi { market: &'a MarginfiMarket, address: &'a Pubkey, bank: &'a Bank },
+    Kamino { market_name: String, address: &'a Pubkey, reserve: &'a KaminoReserve },
+    Drift { market_name: String, state: Pubkey, market: &'a SpotMarket },
+}
+
+impl ReserveModel<'_> {
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            ReserveModel::Save { .. } => "Save",
+            ReserveModel::Marginfi { .. } => "Marginfi",
+            ReserveModel::Kamino { .. } => "Kamino",
+            ReserveModel::Drift { .. } => "Drift",
+        }
+    }
+
+    /// Market name as reported in `LendingReserve`
+    pub fn market_name(&self) -> &str {
+        match self {
+            ReserveModel::Save { pool, .. } => &pool.name,
+            ReserveModel::Marginfi { market, .. } => &market.name,
+            ReserveModel::Kamino { market_name, .. } | ReserveModel::Drift { market_name, .. } => {
+                market_name
+            }
+        }
+    }
+
+    /// The reserve after depositing or borrowing `amount` native tokens. Only the liquidity,
+    /// caps and rates are filled in.
+    pub fn simulate(&self, action: LiquidityAction, amount: u64) -> ArrayResult<LendingReserve> {
+        let reserve = match self {
+            ReserveModel::Save { pool, address, reserve } => {
+                LendingReserve::from(SaveReserveWrapper {
+                    pubkey: address,
+                    reserve: &simulate_save(reserve, action, amount)?,
+                    market_name: &pool.name,
+                    collateral_assets: Vec::new(),
+                    exit_liquidity: ExitLiquidity::default(),
+                    slot: 0,
+                })
+            }
+            ReserveModel::Marginfi { market, address, bank } => {
+                LendingReserve::from(MarginfiReserveWrapper {
+                    pubkey: address,
+                    bank: &simulate_marginfi(bank, action, amount)?,
+                    group: &market.group,
+                    market_name: &market.name,
+                    reward_apy: None,
+                    borrow_reward_apy: None,
+                    collateral_assets: Vec::new(),
+                    exit_liquidity: ExitLiquidity::default(),
+                    slot: 0,
+                })
+            }
+            ReserveModel::Kamino { market_name, address, reserve } => {
+                LendingReserve::from(KaminoReserveWrapper {
+                    pubkey: address,
+                    reserve: &simulate_kamino(reserve, action, amount)?,
+                    market_name,
+                    reward_apy: None,
+                    borrow_reward_apy: None,
+                    collateral_assets: Vec::new(),
+                    exit_liquidity: ExitLiquidity::default(),
+                    slot: 0,
+                })
+            }
+            ReserveModel::Drift { market_name, state, market } => {
+                LendingReserve::from(DriftReserveWrapper {
+                    market: &simulate_drift(market, action, amount)?,
+                    state,
+                    market_name,
+                    collateral_assets: Vec::new(),
+                    exit_liquidity: ExitLiquidity::default(),
+                    slot: 0,
+                })
+            }
+        };
+
+        Ok(reserve)
+    }
+}
+
+impl LendingMarketAggregator {
+    /// Loaded reserves of `mint` across every protocol
+    pub fn reserve_models(&self, mint: &str) -> Vec<ReserveModel<'_>> {
+        let save = self.save_client.pools.iter().flat_map(|pool| {
+            pool.reserves
+                .iter()
+                .filter(|(_, reserve)| reserve.liquidity.mint_pubkey.to_string() == mint)
+                .map(move |(address, reserve)| ReserveModel::Save { pool, address, reserve })
+        });
+
+        let marginfi = self
+            .marginfi_client
+            .banks()
+            .filter(|(_, _, bank)| bank.mint.to_string() == mint)
+            .map(|(market, address, bank)| ReserveModel::Marginfi { market, address, bank });
+
+        let kamino =
+            self.kamino_client.markets.iter().flat_map(|(market_pubkey, market, reserves)| {
+                let market_name = kamino_market_name(market_pubkey, market);
+                reserves
+                    .iter()
+                    .filter(|(_, reserve)| reserve.liquidity.mint_pubkey.to_string() == mint)
+                    .map(move |(address, reserve)| ReserveModel::Kamino {
+                        market_name: market_name.clone(),
+                        address,
+                        reserve,
+                    })
+            });
+
+        let drift_state = transactions::drift::state_address(&self.drift_client.program_id());
+        let drift = self
+            .drift_client
+            .spot_markets
+            .iter()
+            .filter(|(_, market)| market.mint.to_string() == mint)
+            .map(|(_, market)| ReserveModel::Drift {
+                market_name: extract_market_name(&market.name).trim().replace('\0', ""),
+                state: drift_state,
+                market,
+            });
+
+        save.chain(marginfi).chain(kamino).chain(drift).collect()
+    }
+
+    /// The loaded reserve of `mint` in a market, `market` being the market name reported in
+    /// `LendingReserve`
+    pub fn find_reserve_model(
+        &self,
+        mint: &str,
+        protocol: &str,
+        market: &str,
+    ) -> ArrayResult<ReserveModel<'_>> {
+        self.reserve_models(mint)
+            .into_iter()
+            .find(|model| {
+                model.protocol_name().eq_ignore_ascii_case(protocol)
+                    && model.market_name().eq_ignore_ascii_case(market)
+            })
+            .ok_or_else(|| {
+                ClientError::MarketNotFound(format!("{} {} reserve of {}", protocol, market, mint))
+            })
+    }
+
+    /// Recomputes the rates of a loaded reserve after depositing or borrowing `amount` native
+    /// tokens, using the interest rate model of its protocol. `market` is the market name
+    /// reported in `LendingReserve`.
+    pub fn simulate_rate_impact(
+        &self,
+        mint: &str,
+        protocol: &str,
+        market: &str,
+        action: LiquidityAction,
+        amount: u64,
+    ) -> ArrayResult<RateImpact> {
+        let model = self.find_reserve_model(mint, protocol, market)?;
+
+        // A zero deposit leaves the reserve as loaded
+        let before = model.simulate(LiquidityAction::Deposit, 0)?;
+        let after = model.simulate(action, amount)?;
+
+        Ok(RateImpact {
+            protocol_name: before.protocol_name.clone(),
+            market_name: before.market_name.clone(),
+            mint: mint.to_string(),
+            before: (&before).into(),
+            after: (&after).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_borrow_moves_available_liquidity() {
+        let mut reserve = SaveReserve::default();
+        reserve.liquidity.available_amount = 1_000;
+
+        let borrowed = simulate_save(&reserve, LiquidityAction::Borrow, 400).unwrap();
+        assert_eq!(borrowed.liquidity.available_amount, 600);
+        assert_eq!(borrowed.liquidity.borrowed_amount_wads, Decimal::from(400u64));
+
+        let deposited = simulate_save(&reserve, LiquidityAction::Deposit, 400).unwrap();
+        assert_eq!(deposited.liquidity.available_amount, 1_400);
+
+        assert!(simulate_save(&reserve, LiquidityAction::Borrow, 1_001).is_err());
+    }
+
+    #[test]
+    fn kamino_borrow_moves_available_liquidity() {
+        let mut reserve = KaminoReserve::default();
+        reserve.liquidity.available_amount = 1_000;
+
+        let borrowed = simulate_kamino(&reserve, LiquidityAction::Borrow, 400).unwrap();
+        assert_eq!(borrowed.liquidity.available_amount, 600);
+        assert_eq!(
+            Fraction::from_bits(borrowed.liquidity.borrowed_amount_sf),
+            Fraction::from(400u64)
+        );
+
+        let deposited = simulate_kamino(&reserve, LiquidityAction::Deposit, 400).unwrap();
+        assert_eq!(deposited.liquidity.available_amount, 1_400);
+
+        assert!(simulate_kamino(&reserve, LiquidityAction::Borrow, 1_001).is_err());
+    }
+
+    fn marginfi_bank(supplied: u64, borrowed: u64) -> Bank {
+        Bank {
+            asset_share_value: I80F48::from_num(2).into(),
+            liability_share_value: I80F48::from_num(2).into(),
+            total_asset_shares: I80F48::from_num(supplied / 2).into(),
+            total_liability_shares: I80F48::from_num(borrowed / 2).into(),
+            ..Bank::default()
+        }
+    }
+
+    #[test]
+    fn marginfi_simulation_converts_to_shares() {
+        let bank = marginfi_bank(1_000, 300);
+
+        let borrowed = simulate_marginfi(&bank, LiquidityAction::Borrow, 400).unwrap();
+        assert_eq!(I80F48::from(borrowed.total_liability_shares), I80F48::from_num(350));
+
+        let deposited = simulate_marginfi(&bank, LiquidityAction::Deposit, 400).unwrap();
+        assert_eq!(I80F48::from(deposited.total_asset_shares), I80F48::from_num(700));
+
+        assert!(simulate_marginfi(&bank, LiquidityAction::Borrow, 701).is_err());
+    }
+
+    #[test]
+    fn marginfi_borrow_from_bank_with_bad_debt_fails() {
+        let bank = marginfi_bank(1_000, 1_200);
+
+        let error = simulate_marginfi(&bank, LiquidityAction::Borrow, 1).unwrap_err();
+        assert!(error.to_string().contains("with 0 available"));
+    }
+
+    fn drift_market() -> SpotMarket {
+        SpotMarket {
+            decimals: 6,
+            deposit_balance: 1_000_000_000_000,
+            borrow_balance: 300_000_000_000,
+            cumulative_deposit_interest: 10_000_000_000,
+            cumulative_borrow_interest: 10_000_000_000,
+            ..SpotMarket::default()
+        }
+    }
+
+    #[test]
+    fn drift_simulation_converts_to_balances() {
+        let market = drift_market();
+        assert_eq!(market.get_available_deposits().unwrap(), 700_000_000);
+
+        let borrowed = simulate_drift(&market, LiquidityAction::Borrow, 400_000_000).unwrap();
+        assert_eq!(borrowed.borrow_balance, 700_000_000_000);
+        assert_eq!(borrowed.get_available_deposits().unwrap(), 300_000_000);
+
+        let deposited = simulate_drift(&market, LiquidityAction::Deposit, 400_000_000).unwrap();
+        assert_eq!(deposited.deposit_balance, 1_400_000_000_000);
+
+        assert!(simulate_drift(&market, LiquidityAction::Borrow, 700_000_001).is_err());
+    }
+}
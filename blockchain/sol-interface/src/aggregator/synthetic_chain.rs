@@ -0,0 +1,258 @@
+//! Accounts laid out like the ones of the lending programs, served over RPC to the unit tests of
+//! the Save paths without a mainnet endpoint
+//!
+//! Save has one market listing a USDC and a SOL reserve, and [`WALLET`] has an obligation that
+//! supplies SOL and borrows USDC in it. The other protocols have no accounts, so they load empty,
+//! the golden tests cover them from a mainnet capture. The amounts are made up but consistent
+//! with each other.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    client_error::{ClientErrorKind, Result as ClientResult},
+    rpc_config::RpcProgramAccountsConfig,
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_program::program_pack::Pack;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    clock::Clock,
+    pubkey::Pubkey,
+    sysvar,
+};
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::save::{
+    math::Decimal,
+    models::{
+        LastUpdate, LendingMarket as SaveLendingMarket, Obligation as SaveObligation,
+        ObligationCollateral as SaveObligationCollateral,
+        ObligationLiquidity as SaveObligationLiquidity, Reserve as SaveReserve,
+        ReserveCollateral as SaveReserveCollateral, ReserveConfig as SaveReserveConfig,
+        ReserveLiquidity as SaveReserveLiquidity, PROGRAM_VERSION as SAVE_PROGRAM_VERSION,
+    },
+};
+
+pub const WALLET: &str = "AmrekAq6s3n2frDi67WUaZnbPkBb1h4xaid1Y8QLMAYN";
+pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+pub const SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+/// Slot and time of the clock the accounts are read at
+pub const SLOT: u64 = 300_000_000;
+pub const UNIX_TIMESTAMP: i64 = 1_730_000_000;
+
+const SAVE_PROGRAM: &str = "So1endDq2YkqhipRh3WViPa8hdiSpxWy6z3Z6tMCpAo";
+
+fn pubkey(address: &str) -> Pubkey {
+    Pubkey::from_str(address).unwrap()
+}
+
+/// An address derived from `seed`, so the accounts keep their address across recordings
+fn address(seed: &str) -> Pubkey {
+    Pubkey::create_with_seed(&Pubkey::default(), seed, &Pubkey::default()).unwrap()
+}
+
+/// A token reserve of the synthetic markets, amounts in native units
+struct Token {
+    mint: Pubkey,
+    decimals: u8,
+    price: f64,
+    supplied: u64,
+    borrowed: u64,
+}
+
+fn tokens() -> [Token; 2] {
+    [
+        Token {
+            mint: pubkey(USDC_MINT),
+            decimals: 6,
+            price: 1.0,
+            supplied: 50_000_000_000_000,
+            borrowed: 35_000_000_000_000,
+        },
+        Token {
+            mint: pubkey(SOL_MINT),
+            decimals: 9,
+            price: 150.0,
+            supplied: 200_000_000_000_000,
+            borrowed: 60_000_000_000_000,
+        },
+    ]
+}
+
+/// Accounts served by address and by owning program
+pub struct SyntheticChain {
+    accounts: BTreeMap<Pubkey, Account>,
+}
+
+impl SyntheticChain {
+    pub fn new() -> Self {
+        let mut chain = Self { accounts: BTreeMap::new() };
+        chain.add_clock();
+        chain.add_save();
+        chain
+    }
+
+    fn insert(&mut self, pubkey: Pubkey, owner: Pubkey, data: Vec<u8>) {
+        let account = Account { lamports: 1_000_000_000, data, owner, ..Default::default() };
+        self.accounts.insert(pubkey, account);
+    }
+
+    fn add_clock(&mut self) {
+        let clock = Clock { slot: SLOT, unix_timestamp: UNIX_TIMESTAMP, ..Default::default() };
+        self.insert(sysvar::clock::id(), sysvar::id(), bincode::serialize(&clock).unwrap());
+    }
+
+    fn add_save(&mut self) {
+        let program_id = pubkey(SAVE_PROGRAM);
+        let market = address("save-market");
+
+        let lending_market =
+            SaveLendingMarket { version: SAVE_PROGRAM_VERSION, ..SaveLendingMarket::default() };
+        let mut data = vec![0; SaveLendingMarket::LEN];
+        SaveLendingMarket::pack(lending_market, &mut data).unwrap();
+        self.insert(market, program_id, data);
+
+        for token in tokens() {
+            let symbol = if token.decimals == 6 { "usdc" } else { "sol" };
+            let reserve = SaveReserve {
+                version: SAVE_PROGRAM_VERSION,
+                last_update: LastUpdate { slot: SLOT - 100, stale: false },
+                lending_market: market,
+                liquidity: SaveReserveLiquidity {
+                    mint_pubkey: token.mint,
+                    mint_decimals: token.decimals,
+                    supply_pubkey: address(&format!("save-{symbol}-supply")),
+                    pyth_oracle_pubkey: address(&format!("save-{symbol}-pyth")),
+                    available_amount: token.supplied - token.borrowed,
+                    borrowed_amount_wads: Decimal::from(token.borrowed),
+                    cumulative_borrow_rate_wads: Decimal::one(),
+                    market_price: Decimal::from_scaled_val((token.price * 1e18) as u128),
+                    smoothed_market_price: Decimal::from_scaled_val((token.price * 1e18) as u128),
+                    ..SaveReserveLiquidity::default()
+                },
+                collateral: SaveReserveCollateral {
+                    mint_pubkey: address(&format!("save-{symbol}-collateral")),
+                    mint_total_supply: token.supplied,
+                    supply_pubkey: address(&format!("save-{symbol}-collateral-supply")),
+                },
+                config: SaveReserveConfig {
+                    optimal_utilization_rate: 80,
+                    max_utilization_rate: 90,
+                    loan_to_value_ratio: 75,
+                    liquidation_bonus: 5,
+                    max_liquidation_bonus: 10,
+                    liquidation_threshold: 80,
+                    max_liquidation_threshold: 85,
+                    min_borrow_rate: 0,
+                    optimal_borrow_rate: 8,
+                    max_borrow_rate: 30,
+                    super_max_borrow_rate: 100,
+                    deposit_limit: u64::MAX,
+                    borrow_limit: u64::MAX,
+                    protocol_take_rate: 10,
+                    ..SaveReserveConfig::default()
+                },
+                ..SaveReserve::default()
+            };
+            let mut data = vec![0; SaveReserve::LEN];
+            SaveReserve::pack(reserve, &mut data).unwrap();
+            self.insert(address(&format!("save-{symbol}-reserve")), program_id, data);
+        }
+
+        // 10 SOL supplied against 500 USDC borrowed
+        let obligation = SaveObligation {
+            version: SAVE_PROGRAM_VERSION,
+            last_update: LastUpdate { slot: SLOT - 100, stale: false },
+            lending_market: market,
+            owner: pubkey(WALLET),
+            deposits: vec![SaveObligationCollateral {
+                deposit_reserve: address("save-sol-reserve"),
+                deposited_amount: 10_000_000_000,
+                ..SaveObligationCollateral::default()
+            }],
+            borrows: vec![SaveObligationLiquidity {
+                borrow_reserve: address("save-usdc-reserve"),
+                cumulative_borrow_rate_wads: Decimal::one(),
+                borrowed_amount_wads: Decimal::from(500_000_000u64),
+                ..SaveObligationLiquidity::default()
+            }],
+            ..SaveObligation::default()
+        };
+        let mut data = vec![0; SaveObligation::LEN];
+        SaveObligation::pack(obligation, &mut data).unwrap();
+        self.insert(address("save-obligation"), program_id, data);
+    }
+
+    fn ui_account(&self, pubkey: &Pubkey) -> Value {
+        match self.accounts.get(pubkey) {
+            Some(account) => {
+                json!(UiAccount::encode(pubkey, account, UiAccountEncoding::Base64, None, None))
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn program_accounts(&self, params: &Value) -> Option<Value> {
+        let program_id = Pubkey::from_str(params[0].as_str()?).ok()?;
+        let config: RpcProgramAccountsConfig = match params.get(1) {
+            Some(config) => serde_json::from_value(config.clone()).ok()?,
+            None => RpcProgramAccountsConfig::default(),
+        };
+        let filters = config.filters.unwrap_or_default();
+
+        let accounts = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.owner == program_id)
+            .filter(|(_, account)| {
+                let account = AccountSharedData::from((*account).clone());
+                filters.iter().all(|filter| filter.allows(&account))
+            })
+            .map(|(pubkey, _)| json!({ "pubkey": pubkey.to_string(), "account": self.ui_account(pubkey) }))
+            .collect();
+
+        Some(Value::Array(accounts))
+    }
+
+    fn answer(&self, request: RpcRequest, params: &Value) -> Option<Value> {
+        let context = json!({ "slot": SLOT });
+        let address = |value: &Value| Pubkey::from_str(value.as_str()?).ok();
+
+        match request {
+            RpcRequest::GetAccountInfo => {
+                Some(json!({ "context": context, "value": self.ui_account(&address(&params[0])?) }))
+            }
+            RpcRequest::GetMultipleAccounts => {
+                let accounts = params[0]
+                    .as_array()?
+                    .iter()
+                    .map(|value| address(value).map(|pubkey| self.ui_account(&pubkey)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(json!({ "context": context, "value": accounts }))
+            }
+            RpcRequest::GetProgramAccounts => self.program_accounts(params),
+            RpcRequest::GetVersion => Some(json!({ "solana-core": "1.18.26", "feature-set": 0 })),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl RpcSender for SyntheticChain {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        self.answer(request, &params).ok_or_else(|| {
+            ClientErrorKind::Custom(format!("Unsupported request {request} {params}")).into()
+        })
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "synthetic".to_string()
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    aggregator::{client::LendingMarketAggregator, simulate::ReserveModel},
+    common::{
+        client_trait::ClientError,
+        rpc_utils::{create_rpc_client, LendingErrorConverter},
+    },
+    kamino::models::obligation::Obligation as KaminoObligation,
+    save::{client::SolendPool, models::Obligation as SaveObligation},
+    transactions::{
+        self, drift::DriftUserState, kamino::KaminoUserState, marginfi::MarginfiUserState,
+        save::SaveUserState, TransactionAction,
+    },
+};
+use anchor_lang::AccountDeserialize;
+use borsh::BorshDeserialize;
+use common::{
+    lending::{LendingClient, LendingError},
+    UnsignedTransaction,
+};
+use drift::models::idl::accounts::UserStats as DriftUserStats;
+use log::info;
+use solana_program::program_pack::Pack;
+use solana_sdk::{
+    account::Account,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::{collections::HashMap, str::FromStr};
+
+// Type alias for results
+type ArrayResult<T> = Result<T, ClientError>;
+
+fn lending_error(error: LendingError) -> ClientError {
+    match error {
+        LendingError::RpcError(e) => ClientError::Other(e.to_string()),
+        LendingError::DeserializationError(e) => ClientError::DeserializationError(e),
+        LendingError::InvalidAddress(e) => ClientError::InvalidPubkey(e),
+        LendingError::MarketNotFound(e) => ClientError::MarketNotFound(e),
+        LendingError::AccountNotFound(e) => ClientError::AccountNotFound(e),
+        LendingError::ProtocolError(e) => ClientError::ProtocolError(e),
+    }
+}
+
+fn deserialization_error(pubkey: &Pubkey, error: impl std::fmt::Display) -> ClientError {
+    ClientError::DeserializationError(format!("Failed to deserialize {}: {}", pubkey, error))
+}
+
+/// Instructions of a transaction, with the new accounts that have to sign it
+struct PreparedInstructions {
+    instructions: Vec<Instruction>,
+    token_program: Pubkey,
+    signers: Vec<Keypair>,
+}
+
+impl LendingMarketAggregator {
+    /// Fetches the accounts that exist among `pubkeys`
+    async fn fetch_existing_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> ArrayResult<HashMap<Pubkey, Account>> {
+        let client = create_rpc_client(&self.rpc_url);
+        common_rpc::get_multiple_accounts_with_conversion::<LendingError, LendingErrorConverter>(
+            &client, pubkeys,
+        )
+        .await
+        .map_err(lending_error)
+    }
+
+    /// Builds the transaction applying `action` with `amount` native tokens of `mint` to a loaded
+    /// reserve, for `wallet` to sign and pay. `market` is the market name reported in
+    /// `LendingReserve`. User accounts the protocol needs are created in the same transaction.
+    /// `sub_account` picks the Drift sub-account, by default the wallet's only one, or 0 for a
+    /// wallet new to Drift.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn build_transaction(
+        &self,
+        wallet: &str,
+        mint: &str,
+        protocol: &str,
+        market: &str,
+        sub_account: Option<u16>,
+        action: TransactionAction,
+        amount: u64,
+    ) -> ArrayResult<UnsignedTransaction> {
+        let owner = Pubkey::from_str(wallet)
+            .map_err(|e| ClientError::InvalidPubkey(format!("Invalid wallet {}: {}", wallet, e)))?;
+        let mint_pubkey = Pubkey::from_str(mint)
+            .map_err(|e| ClientError::InvalidPubkey(format!("Invalid mint {}: {}", mint, e)))?;
+        if amount == 0 {
+            return Err(ClientError::ProtocolError("Amount must be positive".to_string()));
+        }
+
+        let model = self.find_reserve_model(mint, protocol, market)?;
+        info!("Building {} {} of {} for {}", model.protocol_name(), action, mint, wallet);
+
+        let prepared = match &model {
+            ReserveModel::Save { pool, address, .. } => {
+                self.prepare_save(&owner, pool, address, action, amount).await?
+            }
+            ReserveModel::Kamino { address, reserve, .. } => {
+                self.prepare_kamino(&owner, address, &reserve.lending_market, action, amount)
+                    .await?
+            }
+            ReserveModel::Marginfi { market, address, .. } => {
+                self.prepare_marginfi(wallet, &owner, &market.pubkey, address, action, amount)
+                    .await?
+            }
+            ReserveModel::Drift { market, .. } => {
+                self.prepare_drift(wallet, &owner, market.market_index, sub_account, action, amount)
+                    .await?
+            }
+        };
+
+        // Only a wrapped SOL account the transaction creates is closed afterwards
+        let token_account_exists = if mint_pubkey == spl_token::native_mint::id() {
+            let token_account = transactions::associated_token_address(
+                &owner,
+                &mint_pubkey,
+                &prepared.token_program,
+            );
+            self.fetch_existing_accounts(&[token_account]).await?.contains_key(&token_account)
+        } else {
+            false
+        };
+        let instructions = transactions::with_token_account(
+            &owner,
+            &mint_pubkey,
+            &prepared.token_program,
+            action,
+            amount,
+            token_account_exists,
+            prepared.instructions,
+        )?;
+
+        let recent_blockhash = create_rpc_client(&self.rpc_url)
+            .get_latest_blockhash()
+            .await
+            .map_err(|e| ClientError::RpcError(Box::new(e)))?;
+        let signers: Vec<&Keypair> = prepared.signers.iter().collect();
+        let transaction =
+            transactions::serialize_transaction(&instructions, &owner, recent_blockhash, &signers)?;
+
+        Ok(UnsignedTransaction {
+            protocol_name: model.protocol_name().to_string(),
+            market_name: model.market_name().to_string(),
+            mint: mint.to_string(),
+            action: action.to_string(),
+            amount,
+            transaction,
+            recent_blockhash: recent_blockhash.to_string(),
+        })
+    }
+
+    async fn prepare_save(
+        &self,
+        owner: &Pubkey,
+        pool: &SolendPool,
+        address: &Pubkey,
+        action: TransactionAction,
+        amount: u64,
+    ) -> ArrayResult<PreparedInstructions> {
+        let program_id = self.save_client.program_id;
+        let reserves: HashMap<Pubkey, _> =
+            pool.reserves.iter().map(|(address, reserve)| (*address, reserve)).collect();
+
+        let obligation_address =
+            transactions::save::obligation_address(&program_id, owner, &pool.pubkey);
+        let accounts = self.fetch_existing_accounts(&[obligation_address]).await?;
+        let obligation = accounts
+            .get(&obligation_address)
+            .map(|account| SaveObligation::unpack(&account.data))
+            .transpose()
+            .map_err(|e| deserialization_error(&obligation_address, e))?;
+
+        let user = SaveUserState { obligation };
+        let instructions = transactions::save::build_instructions(
+            &program_id,
+            owner,
+            address,
+            &reserves,
+            &user,
+            action,
+            amount,
+        )?;
+
+        // Save markets only list SPL Token mints
+        Ok(PreparedInstructions {
+            instructions,
+            token_program: spl_token::id(),
+            signers: Vec::new(),
+        })
+    }
+
+    async fn prepare_kamino(
+        &self,
+        owner: &Pubkey,
+        address: &Pubkey,
+        market: &Pubkey,
+        action: TransactionAction,
+        amount: u64,
+    ) -> ArrayResult<PreparedInstructions> {
+        let program_id = self.kamino_client.program_id();
+        let reserves: HashMap<Pubkey, _> = self
+            .kamino_client
+            .markets
+            .iter()
+            .filter(|(market_pubkey, _, _)| market_pubkey == market)
+            .flat_map(|(_, _, reserves)| {
+                reserves.iter().map(|(address, reserve)| (*address, reserve))
+            })
+            .collect();
+        let reserve = reserves
+            .get(address)
+            .ok_or_else(|| ClientError::MarketNotFound(format!("Kamino reserve {}", address)))?;
+
+        let obligation_address =
+            transactions::kamino::obligation_address(&program_id, owner, market);
+        let user_metadata = transactions::kamino::user_metadata_address(&program_id, owner);
+        let obligation_farm =
+            transactions::kamino::action_farm(reserve, action).map(|(_, farm)| {
+                transactions::kamino::obligation_farm_address(&farm, &obligation_address)
+            });
+
+        let mut pubkeys = vec![obligation_address, user_metadata];
+        pubkeys.extend(obligation_farm);
+        let accounts = self.fetch_existing_accounts(&pubkeys).await?;
+
+        let obligation = accounts
+            .get(&obligation_address)
+            .map(|account| KaminoObligation::try_from_slice(&account.data[8..]))
+            .transpose()
+            .map_err(|e| deserialization_error(&obligation_address, e))?;
+        let user = KaminoUserState {
+            obligation,
+            has_user_metadata: accounts.contains_key(&user_metadata),
+            has_obligation_farm: obligation_farm.is_some_and(|farm| accounts.contains_key(&farm)),
+        };
+
+        let instructions = transactions::kamino::build_instructions(
+            &program_id,
+            owner,
+            address,
+            &reserves,
+            &user,
+            action,
+            amount,
+        )?;
+
+        Ok(PreparedInstructions {
+            instructions,
+            token_program: reserve.liquidity.token_program,
+            signers: Vec::new(),
+        })
+    }
+
+    async fn prepare_marginfi(
+        &self,
+        wallet: &str,
+        owner: &Pubkey,
+        group: &Pubkey,
+        address: &Pubkey,
+        action: TransactionAction,
+        amount: u64,
+    ) -> ArrayResult<PreparedInstructions> {
+        let program_id = self.marginfi_client.program_id;
+        let banks: HashMap<Pubkey, _> = self
+            .marginfi_client
+            .banks()
+            .filter(|(market, _, _)| market.pubkey == *group)
+            .map(|(_, address, bank)| (*address, bank))
+            .collect();
+
+        let bank = banks
+            .get(address)
+            .ok_or_else(|| ClientError::MarketNotFound(format!("Marginfi bank {}", address)))?;
+
+        // Banks do not record the token program, the mint's owner is
+        let mint_accounts = self.fetch_existing_accounts(&[bank.mint]).await?;
+        let token_program = mint_accounts
+            .get(&bank.mint)
+            .map(|mint| mint.owner)
+            .ok_or_else(|| ClientError::AccountNotFound(format!("Mint {}", bank.mint)))?;
+
+        let account = self
+            .marginfi_client
+            .fetch_marginfi_accounts(wallet)
+            .await
+            .map_err(lending_error)?
+            .into_iter()
+            .find(|(_, account)| account.group == *group);
+
+        // A new account is a fresh keypair signing its own creation
+        let mut signers = Vec::new();
+        let user = match account {
+            Some((address, account)) => MarginfiUserState { address, account: Some(account) },
+            None => {
+                let keypair = Keypair::new();
+                let address = keypair.pubkey();
+                signers.push(keypair);
+                MarginfiUserState { address, account: None }
+            }
+        };
+
+        let instructions = transactions::marginfi::build_instructions(
+            &program_id,
+            owner,
+            address,
+            &banks,
+            &token_program,
+            &user,
+            action,
+            amount,
+        )?;
+
+        Ok(PreparedInstructions { instructions, token_program, signers })
+    }
+
+    async fn prepare_drift(
+        &self,
+        wallet: &str,
+        owner: &Pubkey,
+        market_index: u16,
+        sub_account: Option<u16>,
+        action: TransactionAction,
+        amount: u64,
+    ) -> ArrayResult<PreparedInstructions> {
+        let program_id = self.drift_client.program_id();
+        let spot_markets: HashMap<u16, _> = self
+            .drift_client
+            .spot_markets
+            .iter()
+            .map(|(_, market)| (market.market_index, market))
+            .collect();
+        let market = spot_markets.get(&market_index).ok_or_else(|| {
+            ClientError::MarketNotFound(format!("Drift spot market {}", market_index))
+        })?;
+        let token_program = transactions::drift::token_program(market);
+
+        let users = self.drift_client.fetch_users(wallet).await.map_err(lending_error)?;
+        let sub_account_id = match (sub_account, users.as_slice()) {
+            (Some(sub_account_id), _) => sub_account_id,
+            (None, []) => 0,
+            (None, [(_, user)]) => user.sub_account_id,
+            (None, _) => {
+                return Err(ClientError::ProtocolError(format!(
+                    "{} has several Drift sub-accounts, pick one",
+                    wallet
+                )))
+            }
+        };
+        let user = users
+            .into_iter()
+            .find(|(_, user)| user.sub_account_id == sub_account_id)
+            .map(|(_, user)| user);
+
+        let user_stats_address = transactions::drift::user_stats_address(&program_id, owner);
+        let accounts = self.fetch_existing_accounts(&[user_stats_address]).await?;
+        let user_stats = accounts
+            .get(&user_stats_address)
+            .map(|account| DriftUserStats::try_deserialize(&mut account.data.as_slice()))
+            .transpose()
+            .map_err(|e| deserialization_error(&user_stats_address, e))?;
+
+        let perp_markets = match &user {
+            Some(user) => {
+                let user_address =
+                    transactions::drift::user_address(&program_id, owner, sub_account_id);
+                let (_, perp_markets) = self
+                    .drift_client
+                    .fetch_user_markets(&[(user_address, *user)])
+                    .await
+                    .map_err(lending_error)?;
+                perp_markets
+            }
+            None => HashMap::new(),
+        };
+        let user = DriftUserState { sub_account_id, user, user_stats, perp_markets };
+
+        let instructions = transactions::drift::build_instructions(
+            &program_id,
+            owner,
+            market_index,
+            &spot_markets,
+            &user,
+            action,
+            amount,
+        )?;
+
+        Ok(PreparedInstructions { instructions, token_program, signers: Vec::new() })
+    }
+}
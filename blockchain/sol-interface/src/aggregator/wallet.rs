@@ -1,36 +1,43 @@
 use crate::aggregator::client::LendingMarketAggregator;
-use crate::common::rpc_utils::create_rpc_client;
-use common::TokenBalance;
+use crate::common::{client_trait::ClientError, rpc_utils::create_rpc_client};
+use common::{ResponseEnvelope, TokenBalance};
+use futures::future::try_join_all;
 use log::debug;
-use solana_client::rpc_request::TokenAccountsFilter;
-use solana_program::program_pack::Pack;
+use solana_account_decoder::{UiAccount, UiAccountData};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
 use solana_sdk::pubkey::Pubkey;
-use spl_token::state::Account as TokenAccount;
 use std::{error::Error, str::FromStr};
 
 impl LendingMarketAggregator {
     /// Fetch token balances for all supported assets for a specific wallet
     ///
     /// This function retrieves token balances for all supported assets in the aggregator,
-    /// using the RPC client pool for better performance.
+    /// querying the mints concurrently, along with the slot they were read at.
     pub async fn fetch_wallet_token_balances(
         &self,
         wallet_pubkey_str: &str,
-    ) -> Result<Vec<TokenBalance>, Box<dyn Error>> {
+    ) -> Result<ResponseEnvelope<Vec<TokenBalance>>, ClientError> {
+        Self::parse_wallet(wallet_pubkey_str)?;
+
         // Prepare token info from supported assets
         let token_info: Vec<(String, String)> =
             self.assets.iter().map(|(mint, asset)| (mint.clone(), asset.symbol.clone())).collect();
 
         // Call the fetch_token_balances function with our token info
-        fetch_token_balances(&self.rpc_url, wallet_pubkey_str, &token_info).await
+        let (slot, balances) = futures::join!(self.read_slot(), async {
+            fetch_token_balances(&self.rpc_url, wallet_pubkey_str, &token_info)
+                .await
+                .map_err(|e| ClientError::Other(format!("Failed to fetch wallet balances: {}", e)))
+        });
+
+        Ok(ResponseEnvelope { data: balances?, slot, protocols: Vec::new() })
     }
 }
 
 /// Fetch token balances for a specific wallet and multiple token mints
 ///
 /// This function retrieves token balances for multiple token mints in a single call,
-/// using the RPC client pool for better performance.
-/// TODO: parallelize the token account queries
+/// querying the token accounts of every mint concurrently.
 pub async fn fetch_token_balances(
     rpc_url: &str,
     wallet_pubkey_str: &str,
@@ -39,93 +46,92 @@ pub async fn fetch_token_balances(
     // Parse the wallet pubkey
     let wallet_pubkey = Pubkey::from_str(wallet_pubkey_str)?;
 
-    // Get a client from the connection pool
+    // Get the shared client from the connection pool
     let client = create_rpc_client(rpc_url);
 
-    // Prepare the result vector
-    let mut token_balances = Vec::with_capacity(token_info.len());
-
     // Process each token mint
-    for (token_mint_str, token_symbol) in token_info {
-        // Parse the token mint pubkey
-        let token_mint = match Pubkey::from_str(token_mint_str) {
-            Ok(pubkey) => pubkey,
-            Err(err) => {
-                debug!("Failed to parse token mint {}: {}", token_mint_str, err);
-                // Add a zero balance for invalid mints
-                token_balances.push(TokenBalance {
-                    symbol: token_symbol.to_string(),
-                    mint: token_mint_str.to_string(),
-                    amount: 0,
-                    decimals: 6, // Default to 6 decimals
-                    token_account: String::new(),
-                });
-                continue;
-            }
-        };
+    let mint_balances = try_join_all(token_info.iter().map(|(token_mint_str, token_symbol)| {
+        fetch_mint_balances(&client, &wallet_pubkey, token_mint_str, token_symbol)
+    }))
+    .await
+    .map_err(|e| e as Box<dyn Error>)?;
 
-        // Query token accounts by owner filtering with the token mint
-        let token_accounts = match client
-            .get_token_accounts_by_owner(&wallet_pubkey, TokenAccountsFilter::Mint(token_mint))
-        {
-            Ok(accounts) => accounts,
-            Err(err) => {
-                debug!("Failed to get token accounts for {}: {}", token_symbol, err);
-                // Add a zero balance for failed queries
-                token_balances.push(TokenBalance {
-                    symbol: token_symbol.to_string(),
-                    mint: token_mint_str.to_string(),
-                    amount: 0,
-                    decimals: 6,
-                    token_account: String::new(),
-                });
-                continue;
-            }
-        };
+    Ok(mint_balances.into_iter().flatten().collect())
+}
 
-        // If no accounts found, add zero balance
-        if token_accounts.is_empty() {
-            token_balances.push(TokenBalance {
-                symbol: token_symbol.to_string(),
-                mint: token_mint_str.to_string(),
-                amount: 0,
-                decimals: 6,
-                token_account: String::new(),
-            });
-            continue;
+/// Balances of the wallet's token accounts of one mint, a single zero balance when it has none
+async fn fetch_mint_balances(
+    client: &RpcClient,
+    wallet_pubkey: &Pubkey,
+    token_mint_str: &str,
+    token_symbol: &str,
+) -> Result<Vec<TokenBalance>, Box<dyn Error + Send + Sync>> {
+    let zero_balance = || {
+        vec![TokenBalance {
+            symbol: token_symbol.to_string(),
+            mint: token_mint_str.to_string(),
+            amount: 0,
+            decimals: 6, // Default to 6 decimals
+            token_account: String::new(),
+        }]
+    };
+
+    // Parse the token mint pubkey
+    let token_mint = match Pubkey::from_str(token_mint_str) {
+        Ok(pubkey) => pubkey,
+        Err(err) => {
+            debug!("Failed to parse token mint {}: {}", token_mint_str, err);
+            // Add a zero balance for invalid mints
+            return Ok(zero_balance());
         }
+    };
 
-        // For each token account, create a TokenBalance
-        for account in &token_accounts {
-            // Fetch account data
-            let pubkey = Pubkey::from_str(&account.pubkey)?;
-            let account_data = match client.get_account_data(&pubkey) {
-                Ok(data) => data,
-                Err(err) => {
-                    debug!("Failed to get account data for {}: {}", account.pubkey, err);
-                    continue;
-                }
-            };
-
-            // Decode account data
-            let token_account = match TokenAccount::unpack(&account_data) {
-                Ok(account) => account,
-                Err(err) => {
-                    debug!("Failed to unpack token account {}: {}", account.pubkey, err);
-                    continue;
-                }
-            };
-
-            // Add the token balance to our results
-            token_balances.push(TokenBalance {
-                symbol: token_symbol.to_string(),
-                mint: token_mint_str.to_string(),
-                amount: token_account.amount,
-                decimals: token_account.mint.to_string().parse().unwrap_or(6),
-                token_account: account.pubkey.clone(),
-            });
+    // Query token accounts by owner filtering with the token mint
+    let token_accounts = match client
+        .get_token_accounts_by_owner(wallet_pubkey, TokenAccountsFilter::Mint(token_mint))
+        .await
+    {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            debug!("Failed to get token accounts for {}: {}", token_symbol, err);
+            // Add a zero balance for failed queries
+            return Ok(zero_balance());
         }
+    };
+
+    // If no accounts found, add zero balance
+    if token_accounts.is_empty() {
+        return Ok(zero_balance());
+    }
+
+    // Token accounts come back parsed, with the amount and the decimals of the mint
+    let mut token_balances = Vec::with_capacity(token_accounts.len());
+    for account in &token_accounts {
+        let Some((amount, decimals)) = parsed_token_amount(&account.account) else {
+            debug!("Token account {} is not parsed", account.pubkey);
+            continue;
+        };
+
+        token_balances.push(TokenBalance {
+            symbol: token_symbol.to_string(),
+            mint: token_mint_str.to_string(),
+            amount,
+            decimals,
+            token_account: account.pubkey.clone(),
+        });
     }
 
     Ok(token_balances)
 }
+
+/// Amount and mint decimals of a token account returned with the `jsonParsed` encoding
+fn parsed_token_amount(account: &UiAccount) -> Option<(u64, u8)> {
+    let UiAccountData::Json(data) = &account.data else {
+        return None;
+    };
+    let token_amount = data.parsed.get("info")?.get("tokenAmount")?;
+    let amount = token_amount.get("amount")?.as_str()?.parse().ok()?;
+    let decimals = token_amount.get("decimals")?.as_u64()?.try_into().ok()?;
+
+    Some((amount, decimals))
+}
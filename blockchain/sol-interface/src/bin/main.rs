@@ -1,6 +1,8 @@
 #![allow(clippy::empty_line_after_doc_comments)]
 
-use sol_interface::aggregator::client::LendingMarketAggregator;
+use sol_interface::aggregator::{
+    allocation::AllocationConstraints, client::LendingMarketAggregator,
+};
 
 #[tokio::main]
 async fn main() {
@@ -14,13 +16,30 @@ async fn main() {
     let mut aggregator = LendingMarketAggregator::new(&rpc_url);
 
     // Load markets and print them
-    let _ = aggregator.load_markets();
+    let _ = aggregator.load_markets_async().await;
     aggregator.print_markets();
 
+    // Split a 100,000 USDC deposit across the loaded reserves
+    let allocation = aggregator.solve_allocation(
+        "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+        100_000_000_000,
+        &AllocationConstraints { max_protocol_share: Some(0.5), ..Default::default() },
+    );
+    match allocation {
+        Ok(plan) => aggregator.print_allocation(&plan),
+        Err(e) => println!("Failed to allocate the USDC deposit: {}", e),
+    }
+
     // Get and print user obligations
     let obligations =
-        aggregator.get_user_obligations("AmrekAq6s3n2frDi67WUaZnbPkBb1h4xaid1Y8QLMAYN");
-    aggregator.print_obligations(&obligations.unwrap());
+        aggregator.get_user_obligations_async("AmrekAq6s3n2frDi67WUaZnbPkBb1h4xaid1Y8QLMAYN").await;
+    aggregator.print_obligations(&obligations.unwrap().data);
+
+    // Get and print the health of the borrowing accounts
+    let health = aggregator
+        .get_obligation_health_async("AmrekAq6s3n2frDi67WUaZnbPkBb1h4xaid1Y8QLMAYN")
+        .await;
+    aggregator.print_obligation_health(&health.unwrap().data);
 
     // USDC mainnet token mint address
     let usdc_mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
@@ -35,4 +54,7 @@ async fn main() {
     .unwrap();
 
     println!("USDC Balance: {:?}", token_balance);
+
+    // Write the responses recorded when `RPC_RECORD_FILE` is set
+    sol_interface::common::rpc_utils::flush_rpc_recording();
 }
@@ -1,4 +1,5 @@
-use common::UserObligation;
+use common::{ErrorKind, UserObligation};
+use std::future::Future;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -25,21 +26,37 @@ pub enum ClientError {
     Other(String),
 }
 
+impl ClientError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ClientError::RpcError(_) => ErrorKind::Rpc,
+            ClientError::DeserializationError(_) => ErrorKind::Deserialization,
+            ClientError::InvalidPubkey(_) => ErrorKind::InvalidPubkey,
+            ClientError::MarketNotFound(_) => ErrorKind::MarketNotFound,
+            ClientError::AccountNotFound(_) => ErrorKind::AccountNotFound,
+            ClientError::ProtocolError(_) => ErrorKind::Protocol,
+            ClientError::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
 pub trait LendingClient<T> {
     /// The type of market data returned by fetch_markets
     type MarketData;
 
-    fn load_markets(&mut self) -> Result<(), ClientError>;
+    fn load_markets(&mut self) -> impl Future<Output = Result<(), ClientError>> + Send;
 
     /// Fetches markets without modifying the client's state
     /// Returns the market data that can be used to update the client's state
-    fn fetch_markets(&self) -> Result<Self::MarketData, ClientError>;
+    fn fetch_markets(&self) -> impl Future<Output = Result<Self::MarketData, ClientError>> + Send;
 
     /// Updates the client's state with the fetched market data
     fn set_market_data(&mut self, data: Self::MarketData);
 
-    fn get_user_obligations(&self, wallet_pubkey: &str)
-        -> Result<Vec<UserObligation>, ClientError>;
+    fn get_user_obligations(
+        &self,
+        wallet_pubkey: &str,
+    ) -> impl Future<Output = Result<Vec<UserObligation>, ClientError>> + Send;
     fn program_id(&self) -> T;
     fn protocol_name(&self) -> &'static str;
     fn print_markets(&self) {
@@ -1,8 +1,9 @@
-use common::lending::LendingError;
-use common_rpc::{with_rpc_client, RpcError, RpcErrorConverter, CONNECTION_POOL};
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::pubkey::Pubkey;
-use std::time::Duration;
+use common::{lending::LendingError, RpcCounters, RpcEndpointHealth};
+use common_rpc::{RpcError, RpcErrorConverter, CONNECTION_POOL};
+use log::warn;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
+use std::{sync::Arc, time::Duration};
 
 /// Centralized error converter for Lending clients
 pub struct LendingErrorConverter;
@@ -18,12 +19,41 @@ impl RpcErrorConverter<LendingError> for LendingErrorConverter {
     }
 }
 
-/// Helper function to create an RPC client with the given URL
+/// Helper function to get the RPC client of the given URL
 /// Uses the connection pool for better performance
-pub fn create_rpc_client(rpc_url: &str) -> RpcClient {
+pub fn create_rpc_client(rpc_url: &str) -> Arc<RpcClient> {
     CONNECTION_POOL.get_client(rpc_url)
 }
 
+/// The cluster clock at the RPC URL, which protocol state read from it is accrued to
+pub async fn fetch_clock(rpc_url: &str) -> Result<Clock, LendingError> {
+    common_rpc::fetch_clock(&create_rpc_client(rpc_url))
+        .await
+        .map_err(LendingErrorConverter::convert_error)
+}
+
+/// Health of the endpoints behind the RPC URL, which may list several weighted endpoints
+pub fn rpc_endpoint_health(rpc_url: &str) -> Vec<RpcEndpointHealth> {
+    CONNECTION_POOL.endpoint_health(rpc_url)
+}
+
+/// Retries and throttling of the RPC calls made by this process
+pub fn rpc_counters() -> RpcCounters {
+    common_rpc::rpc_counters()
+}
+
+/// Write the RPC responses recorded so far to the file set in `RPC_RECORD_FILE`, if any
+pub fn flush_rpc_recording() {
+    if let Err(e) = CONNECTION_POOL.flush_recording() {
+        warn!("Failed to write the recorded RPC responses: {}", e);
+    }
+}
+
+/// Probe the endpoints behind the RPC URL to refresh their health
+pub async fn probe_rpc_endpoints(rpc_url: &str) {
+    CONNECTION_POOL.probe(rpc_url).await
+}
+
 /// Helper function to create an RPC client with the given URL and timeout
 /// Uses the connection pool for better performance
 pub fn create_rpc_client_with_timeout(rpc_url: &str, timeout: Duration) -> RpcClient {
@@ -32,14 +62,6 @@ pub fn create_rpc_client_with_timeout(rpc_url: &str, timeout: Duration) -> RpcCl
     RpcClient::new_with_timeout(rpc_url.to_string(), timeout)
 }
 
-/// Helper function to execute a function with an RPC client and automatically return it to the pool
-pub fn with_pooled_client<F, R>(rpc_url: &str, f: F) -> R
-where
-    F: FnOnce(&RpcClient) -> R,
-{
-    with_rpc_client(rpc_url, f)
-}
-
 /// Helper function to format a pubkey for error messages
 pub fn format_pubkey_for_error(pubkey: &Pubkey) -> String {
     format!("{} ({:.8})", pubkey, pubkey)
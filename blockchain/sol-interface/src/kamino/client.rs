@@ -1,39 +1,99 @@
 use crate::common::rpc_utils::{
-    format_pubkey_for_error, with_pooled_client, LendingErrorConverter,
+    create_rpc_client, fetch_clock, format_pubkey_for_error, LendingErrorConverter,
 };
 use borsh::BorshDeserialize;
 use common::{
     asset_utils::get_symbol_for_mint,
     lending::{LendingClient, LendingError},
-    ObligationType, UserObligation,
+    ObligationHealth, ObligationType, UserObligation,
 };
 use common_rpc::SolanaRpcBuilder;
-use log::info;
-use solana_sdk::{account::Account, pubkey::Pubkey};
-use std::{collections::HashMap, str::FromStr};
+use futures::{stream, StreamExt};
+use log::{info, warn};
+use solana_sdk::{
+    account::Account,
+    clock::{Clock, Slot},
+    pubkey::Pubkey,
+};
+use std::str::FromStr;
 
 use crate::kamino::{
+    config::KaminoMarketConfig,
     models::{lending_market::LendingMarket, reserve::Reserve},
-    utils::{consts::OBLIGATION_SIZE, fraction::Fraction},
+    utils::{
+        consts::{LENDING_MARKET_SIZE, OBLIGATION_SIZE},
+        fraction::{BigFraction, Fraction, FractionExtra},
+    },
 };
 use crate::{
     debug,
-    kamino::{models::obligation::Obligation, utils::consts::RESERVE_SIZE},
+    kamino::{
+        models::obligation::{Obligation, ObligationLiquidity},
+        utils::{consts::RESERVE_SIZE, errors::LendingError as KaminoLendingError},
+    },
 };
 
-type KaminoMarkets = (Pubkey, LendingMarket, Vec<(Pubkey, Reserve)>);
-type MarketNameMap = HashMap<String, &'static str>;
+pub type KaminoMarkets = (Pubkey, LendingMarket, Vec<(Pubkey, Reserve)>);
+
+/// Borrowed amount compounded from the obligation's last refresh to the reserve's cumulative
+/// borrow rate, in liquidity already. A reserve behind the obligation leaves the debt as recorded.
+fn compounded_borrow(
+    borrow: &ObligationLiquidity,
+    reserve: &Reserve,
+) -> Result<Fraction, KaminoLendingError> {
+    let mut borrow = *borrow;
+    match borrow.accrue_interest(BigFraction::from(reserve.liquidity.cumulative_borrow_rate_bsf)) {
+        Ok(()) | Err(KaminoLendingError::NegativeInterestRate) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(Fraction::from_bits(borrow.borrowed_amount_sf))
+}
+
+/// Debt of a borrow compounded to the reserve's cumulative borrow rate, in native units
+fn accrued_debt(
+    borrow: &ObligationLiquidity,
+    reserve: &Reserve,
+) -> Result<u64, KaminoLendingError> {
+    Ok(compounded_borrow(borrow, reserve)?.to_num::<u64>())
+}
+
+/// Value of an amount of a reserve's liquidity in native units, at the reserve's cached price
+fn market_value(reserve: &Reserve, amount: Fraction) -> Fraction {
+    amount / Fraction::from_num(10u64.pow(reserve.liquidity.mint_decimals as u32))
+        * reserve.liquidity.get_market_price_f()
+}
+
+/// Values RefreshObligation writes to an obligation, recomputed off-chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ObligationValues {
+    deposited: Fraction,
+    borrow_factor_adjusted_debt: Fraction,
+    allowed_borrow: Fraction,
+    unhealthy_borrow: Fraction,
+}
+
+fn accrual_error(reserve_pubkey: &Pubkey, error: impl std::fmt::Display) -> LendingError {
+    LendingError::ProtocolError(format!(
+        "Failed to accrue interest of reserve {}: {}",
+        format_pubkey_for_error(reserve_pubkey),
+        error
+    ))
+}
 
 // Define discriminators as constants
+const KAMINO_LENDING_MARKET_DISCRIMINATOR: [u8; 8] = [246, 114, 50, 98, 72, 157, 28, 120];
 const KAMINO_RESERVE_DISCRIMINATOR: [u8; 8] = [43, 242, 204, 202, 26, 247, 59, 127];
 const KAMINO_OBLIGATION_DISCRIMINATOR: [u8; 8] = [168, 206, 141, 106, 88, 76, 172, 167];
 
+/// Markets whose reserves are fetched at the same time
+const MAX_CONCURRENT_RESERVE_FETCHES: usize = 8;
+
 pub struct KaminoClient {
     program_id: Pubkey,
     rpc_url: String,
-    market_pubkeys: Vec<String>,
+    market_config: KaminoMarketConfig,
     pub markets: Vec<KaminoMarkets>,
-    pub market_names: MarketNameMap,
 }
 
 impl Clone for KaminoClient {
@@ -41,37 +101,22 @@ impl Clone for KaminoClient {
         Self {
             program_id: self.program_id,
             rpc_url: self.rpc_url.clone(),
-            market_pubkeys: self.market_pubkeys.clone(),
+            market_config: self.market_config.clone(),
             markets: self.markets.clone(),
-            market_names: self.market_names.clone(),
         }
     }
 }
 
 impl KaminoClient {
     pub fn new(rpc_url: &str) -> Self {
+        Self::with_market_config(rpc_url, KaminoMarketConfig::from_env())
+    }
+
+    pub fn with_market_config(rpc_url: &str, market_config: KaminoMarketConfig) -> Self {
         let program_id = Pubkey::from_str("KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD")
             .expect("Invalid Kamino Lending Program ID");
 
-        let market_names: MarketNameMap = [
-            ("7u3HeHxYDLhnCoErrtycNokbQYbWGzLs6JSDqGAv5PfF".to_string(), "Main"),
-            ("H6rHXmXoCQvq8Ue81MqNh7ow5ysPa1dSozwW3PU1dDH6".to_string(), "JITO"),
-            ("DxXdAyU3kCjnyggvHmY5nAwg5cRbbmdyX3npfDMjjMek".to_string(), "JLP"),
-            ("ByYiZxp8QrdN9qbdtaAiePN8AAr3qvTPppNJDpf5DVJ5".to_string(), "Altcoin"),
-            ("BJnbcRHqvppTyGesLzWASGKnmnF1wq9jZu6ExrjT7wvF".to_string(), "Ethena"),
-        ]
-        .into_iter()
-        .collect();
-
-        let market_pubkeys: Vec<String> = market_names.keys().cloned().collect();
-
-        Self {
-            program_id,
-            rpc_url: rpc_url.to_string(),
-            market_pubkeys,
-            markets: Vec::new(),
-            market_names,
-        }
+        Self { program_id, rpc_url: rpc_url.to_string(), market_config, markets: Vec::new() }
     }
 
     /// Updates the client's state with the fetched market data
@@ -79,37 +124,57 @@ impl KaminoClient {
         self.markets = markets;
     }
 
-    pub fn fetch_kamino_markets_impl(&self) -> Result<Vec<KaminoMarkets>, LendingError> {
-        let markets = self
-            .market_pubkeys
-            .iter()
-            .filter_map(|pubkey_str| {
-                let pubkey = match Pubkey::from_str(pubkey_str) {
-                    Ok(pubkey) => pubkey,
-                    Err(_) => return None,
-                };
-
-                // Get the lending market account
-                let account_data = match with_pooled_client(&self.rpc_url, |client| {
-                    SolanaRpcBuilder::new(client, self.program_id)
-                        .get_account_with_conversion::<LendingError, LendingErrorConverter>(&pubkey)
-                }) {
-                    Ok(data) => data,
-                    Err(_) => return None,
-                };
-
-                let lending_market = match LendingMarket::try_from_slice(&account_data.data[8..]) {
-                    Ok(market) => market,
-                    Err(_) => return None,
-                };
+    /// Accrue the interest of the loaded reserves up to the slot of the clock
+    pub fn accrue_interest(&mut self, clock: &Clock) {
+        for (_, _, reserves) in &mut self.markets {
+            for (pubkey, reserve) in reserves {
+                if let Err(e) = reserve.accrue_interest(clock.slot) {
+                    warn!("{}", accrual_error(pubkey, e));
+                }
+            }
+        }
+    }
 
+    /// Fetches the allowed markets with their reserves, along with the errors of the markets
+    /// whose reserves failed to load, which are left out
+    pub async fn fetch_kamino_markets_impl(
+        &self,
+    ) -> Result<(Vec<KaminoMarkets>, Vec<LendingError>), LendingError> {
+        let lending_markets = self.discover_lending_markets().await?;
+        info!("Discovered {} Kamino lending markets", lending_markets.len());
+
+        // Fetch the reserves of the allowed markets, a few markets at a time
+        let market_reserves: Vec<_> = stream::iter(
+            lending_markets
+                .into_iter()
+                .filter(|(pubkey, _)| self.market_config.is_market_allowed(pubkey)),
+        )
+        .map(|(pubkey, lending_market)| async move {
+            let reserves = self.get_reserves(&pubkey).await;
+            (pubkey, lending_market, reserves)
+        })
+        .buffer_unordered(MAX_CONCURRENT_RESERVE_FETCHES)
+        .collect()
+        .await;
+
+        let mut skipped = Vec::new();
+        let markets = market_reserves
+            .into_iter()
+            .filter_map(|(pubkey, lending_market, reserves)| {
                 // Get the reserves for this market
-                let reserves = match self.get_reserves(&pubkey) {
+                let reserves = match reserves {
                     Ok(reserves) => reserves,
-                    Err(_) => return None,
+                    Err(e) => {
+                        warn!(
+                            "Skipping Kamino market {}, its reserves failed to load: {}",
+                            pubkey, e
+                        );
+                        skipped.push(e);
+                        return None;
+                    }
                 };
 
-                let parsed_reserves = reserves
+                let parsed_reserves: Vec<(Pubkey, Reserve)> = reserves
                     .into_iter()
                     .filter_map(|(pubkey, account)| {
                         Reserve::try_from_slice(&account.data[8..])
@@ -120,13 +185,63 @@ impl KaminoClient {
 
                 Some((pubkey, lending_market, parsed_reserves))
             })
+            .filter(|(pubkey, lending_market, reserves)| {
+                let tvl = market_tvl(reserves);
+                if tvl < self.market_config.min_tvl {
+                    debug!(
+                        "Skipping Kamino market {} ({}) with TVL ${:.0}",
+                        market_name(pubkey, lending_market),
+                        pubkey,
+                        tvl
+                    );
+                    return false;
+                }
+                true
+            })
             .collect();
 
-        Ok(markets)
+        Ok((markets, skipped))
     }
 
-    pub fn load_markets(&mut self) -> Result<(), LendingError> {
-        self.markets = self.fetch_kamino_markets_impl()?;
+    /// Every lending market account owned by the KLend program
+    async fn discover_lending_markets(&self) -> Result<Vec<(Pubkey, LendingMarket)>, LendingError> {
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(LENDING_MARKET_SIZE as u64 + 8)
+            .with_memcmp(0, KAMINO_LENDING_MARKET_DISCRIMINATOR.to_vec())
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
+
+        Ok(accounts
+            .into_iter()
+            .filter_map(|(pubkey, account)| {
+                match LendingMarket::try_from_slice(&account.data[8..]) {
+                    Ok(market) => Some((pubkey, market)),
+                    Err(e) => {
+                        warn!(
+                            "Failed to deserialize Kamino lending market {}: {}",
+                            format_pubkey_for_error(&pubkey),
+                            e
+                        );
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Name of a loaded lending market, "Unknown" when the market isn't loaded
+    fn market_name(&self, market: &Pubkey) -> String {
+        self.markets
+            .iter()
+            .find(|(pubkey, _, _)| pubkey == market)
+            .map(|(pubkey, lending_market, _)| market_name(pubkey, lending_market))
+            .unwrap_or_else(|| "Unknown".to_string())
+    }
+
+    pub async fn load_markets(&mut self) -> Result<(), LendingError> {
+        (self.markets, _) = self.fetch_kamino_markets_impl().await?;
         Ok(())
     }
 
@@ -141,39 +256,54 @@ impl KaminoClient {
         Ok(None)
     }
 
-    fn get_reserves(
+    /// A loaded reserve with its interest accrued up to `slot`
+    fn get_accrued_reserve(
+        &self,
+        pubkey: &Pubkey,
+        slot: Slot,
+    ) -> Result<Option<Reserve>, LendingError> {
+        let Some(reserve) = self.get_reserve_by_pubkey(pubkey)? else {
+            return Ok(None);
+        };
+
+        let mut reserve = reserve.clone();
+        reserve.accrue_interest(slot).map_err(|e| accrual_error(pubkey, e))?;
+        Ok(Some(reserve))
+    }
+
+    async fn get_reserves(
         &self,
         market_address: &Pubkey,
     ) -> Result<Vec<(Pubkey, Account)>, LendingError> {
         // Use the RPC builder with optimized filters
-        with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_data_size(RESERVE_SIZE as u64 + 8)
-                .with_memcmp(0, KAMINO_RESERVE_DISCRIMINATOR.to_vec())
-                .with_memcmp_base58(32, market_address.to_string())
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })
+        let client = create_rpc_client(&self.rpc_url);
+        SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(RESERVE_SIZE as u64 + 8)
+            .with_memcmp(0, KAMINO_RESERVE_DISCRIMINATOR.to_vec())
+            .with_memcmp_base58(32, market_address.to_string())
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await
     }
 
-    pub fn get_user_obligations(
+    pub async fn get_user_obligations(
         &self,
         owner_pubkey: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        let obligations = self.fetch_raw_obligations(owner_pubkey)?;
+        let (obligations, clock) = futures::try_join!(
+            self.fetch_raw_obligations(owner_pubkey),
+            fetch_clock(&self.rpc_url)
+        )?;
         info!("Found {} Kamino obligations", obligations.len());
         let mut user_obligations = Vec::new();
 
         // Cache protocol name to avoid repeated allocations
         let protocol_name = self.protocol_name().to_string();
 
-        for (_, obligation) in obligations {
-            // Get market name once per obligation
-            let market_name = self
-                .market_names
-                .get(&obligation.lending_market.to_string())
-                .unwrap_or(&"Unknown")
-                .to_string();
+        for (obligation_pubkey, obligation) in obligations {
+            // Get market name and account once per obligation
+            let market_name = self.market_name(&obligation.lending_market);
+            let account = obligation_pubkey.to_string();
 
             // Process deposits
             for deposit in obligation.deposits.iter() {
@@ -182,7 +312,9 @@ impl KaminoClient {
                 }
 
                 let deposit_reserve_pubkey = Pubkey::from(deposit.deposit_reserve.to_bytes());
-                if let Some(reserve) = self.get_reserve_by_pubkey(&deposit_reserve_pubkey)? {
+                if let Some(reserve) =
+                    self.get_accrued_reserve(&deposit_reserve_pubkey, clock.slot)?
+                {
                     // Get reserve token symbol
                     let reserve_symbol = reserve.token_symbol().to_string();
 
@@ -206,8 +338,16 @@ impl KaminoClient {
                         amount,
                         protocol_name: protocol_name.clone(),
                         market_name: market_name.clone(),
+                        account: account.clone(),
+                        sub_account: Some(obligation.tag),
+                        reserve_pubkey: deposit_reserve_pubkey.to_string(),
                         obligation_type: ObligationType::Asset,
                     });
+                } else {
+                    warn!(
+                        "Reserve {} of market {} is not loaded, skipping position",
+                        deposit_reserve_pubkey, obligation.lending_market
+                    );
                 }
             }
 
@@ -219,7 +359,9 @@ impl KaminoClient {
                 }
 
                 let borrow_reserve_pubkey = Pubkey::from(borrow.borrow_reserve.to_bytes());
-                if let Some(reserve) = self.get_reserve_by_pubkey(&borrow_reserve_pubkey)? {
+                if let Some(reserve) =
+                    self.get_accrued_reserve(&borrow_reserve_pubkey, clock.slot)?
+                {
                     // Get reserve token symbol
                     let reserve_symbol = reserve.token_symbol().to_string();
 
@@ -229,14 +371,8 @@ impl KaminoClient {
                     // Look up symbol from asset map, fallback to reserve_symbol
                     let symbol = get_symbol_for_mint(&mint).unwrap_or(reserve_symbol);
 
-                    let exchange_rate = reserve
-                        .collateral_exchange_rate()
-                        .map_err(|e| LendingError::RpcError(Box::new(e)))?;
-                    let amount = exchange_rate
-                        .fraction_collateral_to_liquidity(Fraction::from_bits(
-                            borrow.borrowed_amount_sf,
-                        ))
-                        .to_num::<u64>();
+                    let amount = accrued_debt(borrow, &reserve)
+                        .map_err(|e| accrual_error(&borrow_reserve_pubkey, e))?;
                     user_obligations.push(UserObligation {
                         symbol,
                         mint,
@@ -244,8 +380,16 @@ impl KaminoClient {
                         amount,
                         protocol_name: protocol_name.clone(),
                         market_name: market_name.clone(),
+                        account: account.clone(),
+                        sub_account: Some(obligation.tag),
+                        reserve_pubkey: borrow_reserve_pubkey.to_string(),
                         obligation_type: ObligationType::Liability,
                     });
+                } else {
+                    warn!(
+                        "Reserve {} of market {} is not loaded, skipping position",
+                        borrow_reserve_pubkey, obligation.lending_market
+                    );
                 }
             }
         }
@@ -253,7 +397,150 @@ impl KaminoClient {
         Ok(user_obligations)
     }
 
-    fn fetch_raw_obligations(
+    /// Health summary of each obligation owned by the wallet. The obligation values are
+    /// recomputed from its reserves accrued to the current slot, as RefreshObligation would
+    pub async fn get_obligation_health(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<ObligationHealth>, LendingError> {
+        let (obligations, clock) = futures::try_join!(
+            self.fetch_raw_obligations(owner_pubkey),
+            fetch_clock(&self.rpc_url)
+        )?;
+
+        obligations
+            .iter()
+            .filter(|(_, obligation)| !obligation.deposits_empty())
+            .map(|(pubkey, obligation)| {
+                let values = self.refreshed_values(obligation, clock.slot)?;
+                Ok(self.obligation_health(pubkey, obligation, &values))
+            })
+            .collect()
+    }
+
+    /// Recomputes the values of an obligation the way RefreshObligation does, from its reserves
+    /// accrued to `slot` at their cached prices. Positions in reserves that aren't loaded keep
+    /// the values of the last refresh.
+    fn refreshed_values(
+        &self,
+        obligation: &Obligation,
+        slot: Slot,
+    ) -> Result<ObligationValues, LendingError> {
+        // An elevation group replaces the LTVs of the deposits and the borrow factors
+        let elevation_group = self
+            .markets
+            .iter()
+            .find(|(pubkey, _, _)| *pubkey == obligation.lending_market)
+            .and_then(|(_, market, _)| {
+                let index = (obligation.elevation_group as usize).checked_sub(1)?;
+                market.elevation_groups.get(index)
+            });
+        let pct = |pct: u8| Fraction::from_percent(pct as u64);
+
+        let mut values = ObligationValues {
+            deposited: Fraction::ZERO,
+            borrow_factor_adjusted_debt: Fraction::ZERO,
+            allowed_borrow: Fraction::ZERO,
+            unhealthy_borrow: Fraction::ZERO,
+        };
+        // Weighted like the obligation's own values when a deposit reserve isn't loaded
+        let stored_deposited = Fraction::from_bits(obligation.deposited_value_sf);
+        let stored_ratio = |value_sf: u128| {
+            if stored_deposited == Fraction::ZERO {
+                Fraction::ZERO
+            } else {
+                Fraction::from_bits(value_sf) / stored_deposited
+            }
+        };
+
+        for deposit in obligation.deposits.iter().filter(|deposit| deposit.deposited_amount > 0) {
+            let Some(reserve) = self.get_accrued_reserve(&deposit.deposit_reserve, slot)? else {
+                let value = Fraction::from_bits(deposit.market_value_sf);
+                values.deposited += value;
+                values.allowed_borrow += value * stored_ratio(obligation.allowed_borrow_value_sf);
+                values.unhealthy_borrow +=
+                    value * stored_ratio(obligation.unhealthy_borrow_value_sf);
+                continue;
+            };
+
+            let exchange_rate = reserve
+                .collateral_exchange_rate()
+                .map_err(|e| accrual_error(&deposit.deposit_reserve, e))?;
+            let liquidity =
+                exchange_rate.fraction_collateral_to_liquidity(deposit.deposited_amount.into());
+            let value = market_value(&reserve, liquidity);
+            let (ltv, liquidation_threshold) = match elevation_group {
+                Some(group) => (group.ltv_pct, group.liquidation_threshold_pct),
+                None => {
+                    (reserve.config.loan_to_value_pct, reserve.config.liquidation_threshold_pct)
+                }
+            };
+
+            values.deposited += value;
+            values.allowed_borrow += value * pct(ltv);
+            values.unhealthy_borrow += value * pct(liquidation_threshold);
+        }
+
+        for borrow in obligation.borrows.iter().filter(|borrow| borrow.borrowed_amount_sf > 0) {
+            let Some(reserve) = self.get_accrued_reserve(&borrow.borrow_reserve, slot)? else {
+                values.borrow_factor_adjusted_debt +=
+                    Fraction::from_bits(borrow.borrow_factor_adjusted_market_value_sf);
+                continue;
+            };
+
+            let amount = compounded_borrow(borrow, &reserve)
+                .map_err(|e| accrual_error(&borrow.borrow_reserve, e))?;
+            values.borrow_factor_adjusted_debt +=
+                market_value(&reserve, amount) * reserve.borrow_factor_f(elevation_group.is_some());
+        }
+
+        Ok(values)
+    }
+
+    fn obligation_health(
+        &self,
+        pubkey: &Pubkey,
+        obligation: &Obligation,
+        values: &ObligationValues,
+    ) -> ObligationHealth {
+        let market_name = self.market_name(&obligation.lending_market);
+
+        let deposited_value = values.deposited;
+        let debt_value = values.borrow_factor_adjusted_debt;
+        let allowed_borrow_value = values.allowed_borrow;
+        let unhealthy_borrow_value = values.unhealthy_borrow;
+
+        // The obligation helpers divide by the deposited value
+        let ratio = |value: Fraction| -> f64 {
+            if deposited_value == Fraction::ZERO {
+                0.0
+            } else {
+                (value / deposited_value).to_num::<f64>()
+            }
+        };
+
+        ObligationHealth {
+            protocol_name: self.protocol_name().to_string(),
+            market_name,
+            account: pubkey.to_string(),
+            deposited_value: deposited_value.to_num::<f64>(),
+            borrowed_value: debt_value.to_num::<f64>(),
+            loan_to_value: ratio(debt_value),
+            max_loan_to_value: ratio(allowed_borrow_value),
+            liquidation_loan_to_value: ratio(unhealthy_borrow_value),
+            remaining_borrow_value: allowed_borrow_value.saturating_sub(debt_value).to_num::<f64>(),
+            health_factor: (debt_value > Fraction::ZERO)
+                .then(|| (unhealthy_borrow_value / debt_value).to_num::<f64>()),
+            elevation_group: (obligation.elevation_group != 0)
+                .then_some(obligation.elevation_group),
+            initial_health: None,
+            maintenance_health: None,
+            max_withdraw: Vec::new(),
+            margin: None,
+        }
+    }
+
+    async fn fetch_raw_obligations(
         &self,
         owner_pubkey: &str,
     ) -> Result<Vec<(Pubkey, Obligation)>, LendingError> {
@@ -262,14 +549,14 @@ impl KaminoClient {
         })?;
 
         // Use the RPC builder with optimized filters
-        let accounts = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_memcmp(0, KAMINO_OBLIGATION_DISCRIMINATOR.to_vec())
-                .with_data_size(OBLIGATION_SIZE as u64 + 8)
-                .with_memcmp_base58(8 + 8 + 16 + 32, owner.to_string())
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_memcmp(0, KAMINO_OBLIGATION_DISCRIMINATOR.to_vec())
+            .with_data_size(OBLIGATION_SIZE as u64 + 8)
+            .with_memcmp_base58(8 + 8 + 16 + 32, owner.to_string())
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
 
         if accounts.is_empty() {
             debug!("No current obligations found for {}", owner_pubkey);
@@ -297,25 +584,49 @@ impl KaminoClient {
     }
 }
 
+/// Name stored on the lending market account, the market address when it has none
+pub fn market_name(pubkey: &Pubkey, lending_market: &LendingMarket) -> String {
+    let name =
+        String::from_utf8_lossy(&lending_market.name).trim_end_matches('\0').trim().to_string();
+    if name.is_empty() {
+        pubkey.to_string()
+    } else {
+        name
+    }
+}
+
+/// USD value of the liquidity supplied to the market, at the reserves' cached prices
+fn market_tvl(reserves: &[(Pubkey, Reserve)]) -> f64 {
+    reserves
+        .iter()
+        .map(|(_, reserve)| {
+            let supply = reserve.liquidity.total_supply().unwrap_or(Fraction::ZERO).to_num::<f64>();
+            let price = reserve.liquidity.get_market_price_f().to_num::<f64>();
+            supply * price / 10f64.powi(reserve.liquidity.mint_decimals as i32)
+        })
+        .sum()
+}
+
 impl LendingClient<Pubkey, Vec<KaminoMarkets>> for KaminoClient {
-    fn load_markets(&mut self) -> Result<(), LendingError> {
-        self.markets = self.fetch_kamino_markets_impl()?;
+    async fn load_markets(&mut self) -> Result<(), LendingError> {
+        (self.markets, _) = self.fetch_kamino_markets_impl().await?;
         Ok(())
     }
 
-    fn fetch_markets(&self) -> Result<Vec<KaminoMarkets>, LendingError> {
-        self.fetch_kamino_markets_impl()
+    async fn fetch_markets(&self) -> Result<Vec<KaminoMarkets>, LendingError> {
+        let (markets, _) = self.fetch_kamino_markets_impl().await?;
+        Ok(markets)
     }
 
     fn set_market_data(&mut self, data: Vec<KaminoMarkets>) {
         self.markets = data;
     }
 
-    fn get_user_obligations(
+    async fn get_user_obligations(
         &self,
         wallet_address: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        self.get_user_obligations(wallet_address)
+        self.get_user_obligations(wallet_address).await
     }
 
     fn program_id(&self) -> Pubkey {
@@ -326,3 +637,107 @@ impl LendingClient<Pubkey, Vec<KaminoMarkets>> for KaminoClient {
         "Kamino"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kamino::models::reserve::BigFractionBytes;
+
+    fn cumulative_rate(rate: f64) -> BigFractionBytes {
+        BigFraction::from(Fraction::from_num(rate)).into()
+    }
+
+    #[test]
+    fn accrued_debt_compounds_to_reserve_rate() {
+        let borrow = ObligationLiquidity {
+            borrowed_amount_sf: Fraction::from_num(1_000_000).to_bits(),
+            cumulative_borrow_rate_bsf: cumulative_rate(1.0),
+            ..Default::default()
+        };
+        let mut reserve = Reserve::default();
+        reserve.liquidity.cumulative_borrow_rate_bsf = cumulative_rate(1.05);
+        // The collateral exchange rate has no bearing on the debt
+        reserve.collateral.mint_total_supply = 2_000_000;
+        reserve.liquidity.available_amount = 1_000_000;
+
+        assert_eq!(accrued_debt(&borrow, &reserve), Ok(1_050_000));
+    }
+
+    #[test]
+    fn accrued_debt_keeps_debt_of_reserve_behind_obligation() {
+        let borrow = ObligationLiquidity {
+            borrowed_amount_sf: Fraction::from_num(1_000_000).to_bits(),
+            cumulative_borrow_rate_bsf: cumulative_rate(1.05),
+            ..Default::default()
+        };
+        let mut reserve = Reserve::default();
+        reserve.liquidity.cumulative_borrow_rate_bsf = cumulative_rate(1.0);
+
+        assert_eq!(accrued_debt(&borrow, &reserve), Ok(1_000_000));
+    }
+
+    /// A client with one market holding a SOL reserve worth 2 per token, lent against at 50%
+    /// LTV, and a USDC reserve worth 1 with a 150% borrow factor, both with 6 decimals
+    fn refresh_fixture() -> (KaminoClient, Obligation) {
+        let (market, sol, usdc) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut sol_reserve = Reserve::default();
+        sol_reserve.liquidity.mint_decimals = 6;
+        sol_reserve.liquidity.market_price_sf = Fraction::from_num(2).to_bits();
+        sol_reserve.liquidity.available_amount = 1_000_000;
+        sol_reserve.collateral.mint_total_supply = 1_000_000;
+        sol_reserve.config.loan_to_value_pct = 50;
+        sol_reserve.config.liquidation_threshold_pct = 75;
+
+        let mut usdc_reserve = Reserve::default();
+        usdc_reserve.liquidity.mint_decimals = 6;
+        usdc_reserve.liquidity.market_price_sf = Fraction::ONE.to_bits();
+        usdc_reserve.liquidity.cumulative_borrow_rate_bsf = cumulative_rate(1.25);
+        usdc_reserve.config.borrow_factor_pct = 150;
+
+        let mut lending_market = LendingMarket::default();
+        lending_market.elevation_groups[0].ltv_pct = 75;
+        lending_market.elevation_groups[0].liquidation_threshold_pct = 100;
+
+        let mut client = KaminoClient::new("replay:unused");
+        client.markets =
+            vec![(market, lending_market, vec![(sol, sol_reserve), (usdc, usdc_reserve)])];
+
+        // 1 SOL deposited and 0.5 USDC borrowed when the cumulative borrow rate was 1
+        let mut obligation = Obligation { lending_market: market, ..Default::default() };
+        obligation.deposits[0].deposit_reserve = sol;
+        obligation.deposits[0].deposited_amount = 1_000_000;
+        obligation.borrows[0] = ObligationLiquidity {
+            borrow_reserve: usdc,
+            borrowed_amount_sf: Fraction::from_num(500_000).to_bits(),
+            cumulative_borrow_rate_bsf: cumulative_rate(1.0),
+            ..Default::default()
+        };
+
+        (client, obligation)
+    }
+
+    #[test]
+    fn refreshed_values_compound_debt_at_reserve_prices() {
+        let (client, obligation) = refresh_fixture();
+        let values = client.refreshed_values(&obligation, 0).unwrap();
+
+        // 0.5 USDC compounded by 25% and weighted by the 150% borrow factor
+        assert_eq!(values.deposited, Fraction::from_num(2));
+        assert_eq!(values.borrow_factor_adjusted_debt, Fraction::from_num(0.9375));
+        assert_eq!(values.allowed_borrow, Fraction::ONE);
+        assert_eq!(values.unhealthy_borrow, Fraction::from_num(1.5));
+    }
+
+    #[test]
+    fn refreshed_values_apply_elevation_group() {
+        let (client, mut obligation) = refresh_fixture();
+        obligation.elevation_group = 1;
+        let values = client.refreshed_values(&obligation, 0).unwrap();
+
+        assert_eq!(values.borrow_factor_adjusted_debt, Fraction::from_num(0.625));
+        assert_eq!(values.allowed_borrow, Fraction::from_num(1.5));
+        assert_eq!(values.unhealthy_borrow, Fraction::from_num(2));
+    }
+}
@@ -0,0 +1,90 @@
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+/// Comma separated lending market addresses to load, every discovered market when unset
+pub const MARKET_ALLOWLIST_ENV: &str = "KAMINO_MARKET_ALLOWLIST";
+/// Comma separated lending market addresses to skip
+pub const MARKET_DENYLIST_ENV: &str = "KAMINO_MARKET_DENYLIST";
+/// Markets with a smaller total supply value (USD) are skipped
+pub const MIN_MARKET_TVL_ENV: &str = "KAMINO_MIN_MARKET_TVL";
+
+/// Selects which of the discovered Kamino lending markets are loaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KaminoMarketConfig {
+    /// When not empty, only these markets are loaded
+    pub allowlist: Vec<Pubkey>,
+    pub denylist: Vec<Pubkey>,
+    /// Minimum total supply value in USD, 0 to keep every market
+    pub min_tvl: f64,
+}
+
+impl KaminoMarketConfig {
+    /// Reads the configuration from the environment, ignoring malformed entries
+    pub fn from_env() -> Self {
+        Self {
+            allowlist: parse_pubkeys(MARKET_ALLOWLIST_ENV),
+            denylist: parse_pubkeys(MARKET_DENYLIST_ENV),
+            min_tvl: std::env::var(MIN_MARKET_TVL_ENV)
+                .ok()
+                .and_then(|value| match value.trim().parse::<f64>() {
+                    Ok(min_tvl) => Some(min_tvl),
+                    Err(e) => {
+                        warn!("Ignoring invalid {} {:?}: {}", MIN_MARKET_TVL_ENV, value, e);
+                        None
+                    }
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether the market passes the allow and deny lists
+    pub fn is_market_allowed(&self, market: &Pubkey) -> bool {
+        (self.allowlist.is_empty() || self.allowlist.contains(market))
+            && !self.denylist.contains(market)
+    }
+}
+
+fn parse_pubkeys(var: &str) -> Vec<Pubkey> {
+    let Ok(value) = std::env::var(var) else {
+        return Vec::new();
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match Pubkey::from_str(entry) {
+            Ok(pubkey) => Some(pubkey),
+            Err(e) => {
+                warn!("Ignoring invalid market {:?} in {}: {}", entry, var, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let main = Pubkey::new_unique();
+        let jlp = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+
+        let config = KaminoMarketConfig::default();
+        assert!(config.is_market_allowed(&other));
+
+        let config = KaminoMarketConfig { denylist: vec![jlp], ..Default::default() };
+        assert!(config.is_market_allowed(&main));
+        assert!(!config.is_market_allowed(&jlp));
+
+        let config =
+            KaminoMarketConfig { allowlist: vec![main, jlp], denylist: vec![jlp], min_tvl: 0.0 };
+        assert!(config.is_market_allowed(&main));
+        assert!(!config.is_market_allowed(&jlp));
+        assert!(!config.is_market_allowed(&other));
+    }
+}
@@ -1,3 +1,4 @@
 pub mod models;
 pub mod utils;
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod config;
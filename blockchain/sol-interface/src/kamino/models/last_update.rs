@@ -76,6 +76,11 @@ impl LastUpdate {
         Ok(slots_elapsed)
     }
 
+    /// Move the last update to `slot`, keeping the stale flag and the price status
+    pub fn set_slot(&mut self, slot: Slot) {
+        self.slot = slot;
+    }
+
     pub fn get_price_status(&self) -> PriceStatusFlags {
         PriceStatusFlags::from_bits_truncate(self.price_status)
     }
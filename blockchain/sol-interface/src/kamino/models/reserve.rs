@@ -228,6 +228,24 @@ impl Reserve {
         Ok(compounded - Fraction::ONE)
     }
 
+    /// Accrue interest up to `current_slot` like RefreshReserve does, leaving the price as it
+    /// is. The last update moves to that slot, so accruing to it again does nothing, and a
+    /// reserve updated at or after that slot is left as it is.
+    pub fn accrue_interest(&mut self, current_slot: Slot) -> LendingResult {
+        let slots_elapsed = self.last_update.slots_elapsed(current_slot).unwrap_or(0);
+        if slots_elapsed > 0 {
+            let current_borrow_rate = self.current_borrow_rate_unadjusted()?;
+            self.liquidity.compound_interest(
+                current_borrow_rate,
+                self.get_fixed_interest_rate(),
+                slots_elapsed,
+                self.get_protocol_take_rate(),
+            );
+            self.last_update.set_slot(current_slot);
+        }
+        Ok(())
+    }
+
     pub fn get_fixed_interest_rate(&self) -> Fraction {
         Fraction::from_bps(self.config.host_fixed_interest_rate_bps)
     }
@@ -438,6 +456,41 @@ impl ReserveLiquidity {
             - Fraction::from_bits(self.pending_referrer_fees_sf))
     }
 
+    /// Compound the borrow rate and the host fixed rate over the elapsed slots. Referrer fees are
+    /// counted with the protocol fees, the split doesn't change the total supply.
+    fn compound_interest(
+        &mut self,
+        current_borrow_rate: Fraction,
+        host_fixed_interest_rate: Fraction,
+        slots_elapsed: u64,
+        protocol_take_rate: Fraction,
+    ) {
+        let previous_cumulative_borrow_rate = BigFraction::from(self.cumulative_borrow_rate_bsf);
+        let previous_debt_f = Fraction::from_bits(self.borrowed_amount_sf);
+        let acc_protocol_fees_f = Fraction::from_bits(self.accumulated_protocol_fees_sf);
+
+        let compounded_interest_rate = approximate_compounded_interest(
+            current_borrow_rate + host_fixed_interest_rate,
+            slots_elapsed,
+        );
+        let compounded_fixed_rate =
+            approximate_compounded_interest(host_fixed_interest_rate, slots_elapsed);
+
+        let new_cumulative_borrow_rate =
+            previous_cumulative_borrow_rate * BigFraction::from(compounded_interest_rate);
+        let new_debt_f = previous_debt_f * compounded_interest_rate;
+
+        // The host fixed rate goes to the protocol entirely, the protocol takes its share of the rest
+        let fixed_host_fee = previous_debt_f * compounded_fixed_rate - previous_debt_f;
+        let net_new_variable_debt_f = new_debt_f - previous_debt_f - fixed_host_fee;
+        let variable_protocol_fee = net_new_variable_debt_f * protocol_take_rate;
+
+        self.cumulative_borrow_rate_bsf = new_cumulative_borrow_rate.into();
+        self.accumulated_protocol_fees_sf =
+            (acc_protocol_fees_f + variable_protocol_fee + fixed_host_fee).to_bits();
+        self.borrowed_amount_sf = new_debt_f.to_bits();
+    }
+
     pub fn total_borrow(&self) -> Fraction {
         Fraction::from_bits(self.borrowed_amount_sf)
     }
@@ -923,3 +976,81 @@ pub fn approximate_compounded_interest(rate: Fraction, elapsed_slots: u64) -> Fr
 
     Fraction::ONE + first_term + second_term + third_term
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A day of slots
+    const CURRENT_SLOT: Slot = 100 + SLOTS_PER_YEAR / 365;
+
+    fn borrowed_reserve(host_fixed_interest_rate_bps: u16) -> Reserve {
+        let mut reserve = Reserve { last_update: LastUpdate::new(100), ..Reserve::default() };
+        reserve.config.borrow_rate_curve = BorrowRateCurve::new_flat(1_000);
+        reserve.config.protocol_take_rate_pct = 10;
+        reserve.config.host_fixed_interest_rate_bps = host_fixed_interest_rate_bps;
+        reserve.liquidity.available_amount = 1_000_000;
+        reserve.liquidity.borrowed_amount_sf = Fraction::from_num(1_000_000).to_bits();
+        reserve
+    }
+
+    fn debt_and_fees(reserve: &Reserve) -> (Fraction, Fraction) {
+        (
+            Fraction::from_bits(reserve.liquidity.borrowed_amount_sf),
+            Fraction::from_bits(reserve.liquidity.accumulated_protocol_fees_sf),
+        )
+    }
+
+    #[test]
+    fn accrue_interest_compounds_debt_and_protocol_fees() {
+        let mut reserve = borrowed_reserve(0);
+
+        // A day at 10% compounds the debt by about 0.0274%, of which the protocol takes 10%
+        reserve.accrue_interest(CURRENT_SLOT).unwrap();
+        let (debt, fees) = debt_and_fees(&reserve);
+        assert_eq!(debt.to_num::<u64>(), 1_000_273);
+        assert_eq!(fees.to_num::<u64>(), 27);
+
+        let cumulative_rate =
+            Fraction::try_from(BigFraction::from(reserve.liquidity.cumulative_borrow_rate_bsf))
+                .unwrap();
+        assert_eq!((cumulative_rate * Fraction::from_num(1_000_000)).to_num::<u64>(), 1_000_273);
+        assert_eq!(reserve.last_update.slots_elapsed(CURRENT_SLOT), Ok(0));
+
+        // Accruing again to the same slot changes nothing
+        let accrued = reserve.clone();
+        reserve.accrue_interest(CURRENT_SLOT).unwrap();
+        assert!(reserve == accrued);
+    }
+
+    #[test]
+    fn accrue_interest_gives_host_fixed_rate_to_protocol() {
+        let mut reserve = borrowed_reserve(100);
+        reserve.accrue_interest(CURRENT_SLOT).unwrap();
+
+        let (debt, fees) = debt_and_fees(&reserve);
+        assert_eq!(debt.to_num::<u64>(), 1_000_301);
+        assert_eq!(fees.to_num::<u64>(), 54);
+
+        // The day of the 1% host rate goes to the protocol entirely, about 27.4, and the
+        // protocol takes 10% of the rest of the interest
+        let principal = Fraction::from_num(1_000_000);
+        let host_fee = principal
+            * (approximate_compounded_interest(Fraction::from_bps(100), CURRENT_SLOT - 100)
+                - Fraction::ONE);
+        assert_eq!(host_fee.to_num::<u64>(), 27);
+        let variable_fee = (debt - principal - host_fee) * Fraction::from_percent(10);
+        assert!(fees.abs_diff(host_fee + variable_fee) < Fraction::from_num(0.000_001));
+    }
+
+    #[test]
+    fn accrue_interest_leaves_reserve_newer_than_slot() {
+        let mut reserve = borrowed_reserve(100);
+        reserve.last_update = LastUpdate::new(1_000);
+
+        // A clock read behind the reserve's last refresh has nothing to accrue
+        let refreshed = reserve.clone();
+        reserve.accrue_interest(900).unwrap();
+        assert!(reserve == refreshed);
+    }
+}
@@ -2,4 +2,7 @@ pub mod aggregator;
 pub mod common;
 pub mod kamino;
 pub mod marginfi;
+pub mod oracle;
+pub mod rewards;
 pub mod save;
+pub mod transactions;
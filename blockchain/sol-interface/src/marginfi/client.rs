@@ -1,35 +1,48 @@
 use std::mem::size_of;
 
-use crate::common::rpc_utils::{
-    format_pubkey_for_error, with_pooled_client, LendingErrorConverter,
-};
+use crate::common::rpc_utils::{create_rpc_client, format_pubkey_for_error, LendingErrorConverter};
+use crate::math_error;
+use crate::oracle::{feeds::marginfi_feeds, select_price, OracleFeed};
 use anchor_lang::AnchorDeserialize;
 use common::{
     asset_utils::get_symbol_for_mint,
     lending::{LendingClient, LendingError},
-    ObligationType, UserObligation,
+    ObligationHealth, ObligationType, UserObligation, WithdrawLimit,
 };
-use common_rpc::SolanaRpcBuilder;
+use common_rpc::{RpcErrorConverter, SolanaRpcBuilder};
 use fixed::types::I80F48;
-use log::debug;
-use solana_sdk::pubkey::Pubkey;
+use futures::future::try_join_all;
+use log::{debug, warn};
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
+use super::config::MarginfiGroupConfig;
 use super::models::{
-    account::{Balance, BalanceSide, MarginfiAccount},
+    account::{calc_amount, Balance, BalanceSide, MarginfiAccount, RequirementType},
     group::{Bank, MarginfiGroup},
+    price::{OraclePriceFeed, PriceAdapter, PriceBias},
 };
+use super::utils::prelude::MarginfiResult;
 
 // Define discriminators as constants
 const MARGINFI_ACCOUNT_DISCRIMINATOR: [u8; 8] = [67, 178, 130, 109, 126, 114, 28, 42];
 const MARGINFI_BANK_DISCRIMINATOR: [u8; 8] = [142, 49, 166, 242, 50, 66, 97, 188];
 
+/// A Marginfi group with its banks, reported as a separate market
+#[derive(Debug, Clone)]
+pub struct MarginfiMarket {
+    pub pubkey: Pubkey,
+    pub name: String,
+    pub group: MarginfiGroup,
+    pub banks: Vec<(Pubkey, Bank)>,
+}
+
 pub struct MarginfiClient {
     pub program_id: Pubkey,
     pub rpc_url: String,
-    pub group_pubkeys: Vec<Pubkey>,
-    pub banks: Vec<(Pubkey, Bank)>,
-    pub group: MarginfiGroup,
+    pub group_config: MarginfiGroupConfig,
+    pub markets: Vec<MarginfiMarket>,
 }
 
 impl Clone for MarginfiClient {
@@ -37,50 +50,132 @@ impl Clone for MarginfiClient {
         Self {
             program_id: self.program_id,
             rpc_url: self.rpc_url.clone(),
-            group_pubkeys: self.group_pubkeys.clone(),
-            banks: self.banks.clone(),
-            group: self.group.clone(),
+            group_config: self.group_config.clone(),
+            markets: self.markets.clone(),
         }
     }
 }
 
 impl MarginfiClient {
     pub fn new(rpc_url: &str) -> Self {
+        Self::with_group_config(rpc_url, MarginfiGroupConfig::from_env())
+    }
+
+    pub fn with_group_config(rpc_url: &str, group_config: MarginfiGroupConfig) -> Self {
         let program_id = Pubkey::from_str("MFv2hWf31Z9kbCa1snEPYctwafyhdvnV7FZnsebVacA")
             .expect("Invalid Marginfi Lending Program ID");
 
-        let group_pubkeys: Vec<Pubkey> = vec![
-            "4qp6Fx6tnZkY5Wropq9wUYgtFxXKwE6viZxFHg3rdAG8".parse().unwrap(), //main market
-        ];
+        Self { program_id, rpc_url: rpc_url.to_string(), group_config, markets: Vec::new() }
+    }
 
-        Self {
-            program_id,
-            rpc_url: rpc_url.to_string(),
-            group_pubkeys,
-            banks: Vec::new(),
-            group: MarginfiGroup::default(),
+    /// Updates the client's state with the fetched market data
+    pub fn set_market_data(&mut self, markets: Vec<MarginfiMarket>) {
+        self.markets = markets;
+    }
+
+    /// Accrue the interest of the loaded banks up to the time of the clock
+    pub fn accrue_interest(&mut self, clock: &Clock) {
+        for market in &mut self.markets {
+            for (pubkey, bank) in &mut market.banks {
+                if let Err(e) = bank.accrue_interest(clock.unix_timestamp, &market.group) {
+                    warn!(
+                        "Failed to accrue interest of bank {}: {}",
+                        format_pubkey_for_error(pubkey),
+                        e
+                    );
+                }
+            }
         }
     }
 
-    /// Updates the client's state with the fetched market data
-    pub fn set_market_data(&mut self, data: (Vec<(Pubkey, Bank)>, MarginfiGroup)) {
-        let (banks, group) = data;
-        self.banks = banks;
-        self.group = group;
+    /// Every loaded bank along with the group it belongs to
+    pub fn banks(&self) -> impl Iterator<Item = (&MarginfiMarket, &Pubkey, &Bank)> {
+        self.markets.iter().flat_map(|market| {
+            market.banks.iter().map(move |(pubkey, bank)| (market, pubkey, bank))
+        })
+    }
+
+    /// Loads the configured groups, and every other group with banks when discovery is on
+    pub async fn fetch_marginfi_markets(&self) -> Result<Vec<MarginfiMarket>, LendingError> {
+        let mut banks_by_group: HashMap<Pubkey, Vec<(Pubkey, Bank)>> = HashMap::new();
+
+        if self.group_config.discover {
+            for (pubkey, bank) in self.fetch_program_banks(None).await? {
+                banks_by_group.entry(bank.group).or_default().push((pubkey, bank));
+            }
+        } else {
+            // Fetch the banks of every configured group concurrently
+            let group_banks = try_join_all(
+                self.group_config
+                    .groups
+                    .iter()
+                    .map(|(group_pubkey, _)| self.fetch_banks_for_group(group_pubkey)),
+            )
+            .await?;
+            for ((group_pubkey, _), banks) in self.group_config.groups.iter().zip(group_banks) {
+                banks_by_group.insert(*group_pubkey, banks);
+            }
+        }
+
+        // Configured groups first, in their configured order
+        let mut group_pubkeys: Vec<Pubkey> =
+            self.group_config.groups.iter().map(|(pubkey, _)| *pubkey).collect();
+        let mut discovered: Vec<Pubkey> = banks_by_group
+            .keys()
+            .filter(|pubkey| !group_pubkeys.contains(pubkey))
+            .copied()
+            .collect();
+        discovered.sort();
+        group_pubkeys.extend(discovered);
+
+        let groups = self.fetch_marginfi_groups(&group_pubkeys).await?;
+
+        Ok(group_pubkeys
+            .into_iter()
+            .filter_map(|pubkey| {
+                let group = groups.get(&pubkey)?.clone();
+                Some(MarginfiMarket {
+                    pubkey,
+                    name: self.group_config.group_name(&pubkey),
+                    group,
+                    banks: banks_by_group.remove(&pubkey).unwrap_or_default(),
+                })
+            })
+            .collect())
     }
 
-    pub fn fetch_banks_for_group(&self) -> Result<Vec<(Pubkey, Bank)>, LendingError> {
+    pub async fn load_marginfi_markets(&mut self) -> Result<(), LendingError> {
+        self.markets = self.fetch_marginfi_markets().await?;
+        Ok(())
+    }
+
+    pub async fn fetch_banks_for_group(
+        &self,
+        group_pubkey: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Bank)>, LendingError> {
+        self.fetch_program_banks(Some(group_pubkey)).await
+    }
+
+    /// Fetches the banks of a group, or of every group when none is given
+    async fn fetch_program_banks(
+        &self,
+        group_pubkey: Option<&Pubkey>,
+    ) -> Result<Vec<(Pubkey, Bank)>, LendingError> {
         // Use the RPC builder with optimized filters
-        let accounts = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_memcmp(0, MARGINFI_BANK_DISCRIMINATOR.to_vec())
-                .with_memcmp_pubkey(
-                    8 + size_of::<Pubkey>() + size_of::<u8>(),
-                    &self.group_pubkeys[0],
-                )
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let builder = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_memcmp(0, MARGINFI_BANK_DISCRIMINATOR.to_vec());
+        let builder = match group_pubkey {
+            Some(group_pubkey) => {
+                builder.with_memcmp_pubkey(8 + size_of::<Pubkey>() + size_of::<u8>(), group_pubkey)
+            }
+            None => builder,
+        };
+
+        let accounts = builder
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
 
         // Pre-allocate with capacity
         let mut banks = Vec::with_capacity(accounts.len());
@@ -101,20 +196,15 @@ impl MarginfiClient {
         Ok(banks)
     }
 
-    pub fn load_banks_for_group(&mut self) -> Result<(), LendingError> {
-        self.banks = self.fetch_banks_for_group()?;
-        Ok(())
-    }
-
-    pub fn fetch_marginfi_group(
+    pub async fn fetch_marginfi_group(
         &self,
         group_pubkey: &Pubkey,
     ) -> Result<MarginfiGroup, LendingError> {
         // Use the RPC builder to get the group account
-        let group_account = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .get_account_with_conversion::<LendingError, LendingErrorConverter>(group_pubkey)
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let group_account = SolanaRpcBuilder::new(&client, self.program_id)
+            .get_account_with_conversion::<LendingError, LendingErrorConverter>(group_pubkey)
+            .await?;
 
         let group = MarginfiGroup::try_from_slice(&group_account.data[8..]).map_err(|e| {
             LendingError::DeserializationError(format!(
@@ -127,24 +217,48 @@ impl MarginfiClient {
         Ok(group)
     }
 
-    pub fn load_marginfi_group(&mut self, group_pubkey: &Pubkey) -> Result<(), LendingError> {
-        self.group = self.fetch_marginfi_group(group_pubkey)?;
-        Ok(())
+    /// Fetches the given groups in a single batch, skipping the ones that fail
+    async fn fetch_marginfi_groups(
+        &self,
+        group_pubkeys: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, MarginfiGroup>, LendingError> {
+        let client = create_rpc_client(&self.rpc_url);
+        let group_accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, group_pubkeys)
+        .await?;
+
+        let mut groups = HashMap::with_capacity(group_accounts.len());
+        for (pubkey, group_account) in group_accounts {
+            match MarginfiGroup::try_from_slice(&group_account.data[8..]) {
+                Ok(group) => {
+                    groups.insert(pubkey, group);
+                }
+                Err(e) => {
+                    debug!(
+                        "Failed to deserialize marginfi group {}: {}",
+                        format_pubkey_for_error(&pubkey),
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(groups)
     }
 
-    pub fn get_user_obligations(
+    pub async fn get_user_obligations(
         &self,
         wallet_pubkey: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
         let mut obligations = Vec::new();
-        let marginfi_accounts = self.fetch_raw_obligations(wallet_pubkey)?;
+        let marginfi_accounts = self.fetch_raw_obligations(wallet_pubkey).await?;
 
         // Cache protocol name to avoid repeated allocations
         let protocol_name = self.protocol_name().to_string();
-        // Use a constant market name
-        let market_name = "General".to_string();
 
-        for (balance, bank) in marginfi_accounts {
+        for (account, balance, bank) in marginfi_accounts {
             // Process active balances
             if let Some(side) = balance.get_side() {
                 let amount = match side {
@@ -169,7 +283,10 @@ impl MarginfiClient {
                     mint_decimals: bank.mint_decimals as u32,
                     amount: I80F48::to_num(amount),
                     protocol_name: protocol_name.clone(),
-                    market_name: market_name.clone(),
+                    market_name: self.group_config.group_name(&bank.group),
+                    account: account.to_string(),
+                    sub_account: None,
+                    reserve_pubkey: balance.bank_pk.to_string(),
                     obligation_type: match side {
                         BalanceSide::Assets => ObligationType::Asset,
                         BalanceSide::Liabilities => ObligationType::Liability,
@@ -181,31 +298,254 @@ impl MarginfiClient {
         Ok(obligations)
     }
 
-    fn fetch_raw_obligations(
+    /// Health of each marginfi account owned by the wallet, valued the way Marginfi's risk
+    /// engine does with the bank weights and the current oracle prices. Fails when a position's
+    /// bank or price is missing rather than leaving its account out.
+    pub async fn get_obligation_health(
+        &self,
+        wallet_pubkey: &str,
+    ) -> Result<Vec<ObligationHealth>, LendingError> {
+        let marginfi_accounts = self.fetch_marginfi_accounts(wallet_pubkey).await?;
+        if marginfi_accounts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let bank_pubkeys: Vec<Pubkey> = marginfi_accounts
+            .iter()
+            .flat_map(|(_, account)| account.lending_account.get_active_balances_iter())
+            .map(|balance| balance.bank_pk)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let banks = self.fetch_banks(&bank_pubkeys).await?;
+        let price_feeds = self.fetch_price_feeds(&banks).await?;
+
+        let mut health = Vec::with_capacity(marginfi_accounts.len());
+        for (pubkey, marginfi_account) in marginfi_accounts {
+            let has_balances = marginfi_account
+                .lending_account
+                .get_active_balances_iter()
+                .any(|balance| balance.get_side().is_some());
+            if !has_balances {
+                continue;
+            }
+
+            // An account can't be valued without the price of every position
+            health.push(self.account_health(&pubkey, &marginfi_account, &banks, &price_feeds)?);
+        }
+
+        Ok(health)
+    }
+
+    fn account_health(
+        &self,
+        pubkey: &Pubkey,
+        marginfi_account: &MarginfiAccount,
+        banks: &HashMap<Pubkey, Bank>,
+        price_feeds: &HashMap<Pubkey, OraclePriceFeed>,
+    ) -> Result<ObligationHealth, LendingError> {
+        let protocol_error = |e: anchor_lang::error::Error| {
+            LendingError::ProtocolError(format!(
+                "Failed to value marginfi account {}: {}",
+                format_pubkey_for_error(pubkey),
+                e
+            ))
+        };
+
+        let mut positions = Vec::new();
+        for balance in marginfi_account.lending_account.get_active_balances_iter() {
+            let bank = banks.get(&balance.bank_pk).ok_or_else(|| {
+                LendingError::AccountNotFound(format!(
+                    "Bank {} not found",
+                    format_pubkey_for_error(&balance.bank_pk)
+                ))
+            })?;
+            let price_feed = price_feeds.get(&balance.bank_pk).ok_or_else(|| {
+                LendingError::ProtocolError(format!(
+                    "No oracle price for bank {}",
+                    format_pubkey_for_error(&balance.bank_pk)
+                ))
+            })?;
+            positions.push((balance, bank, price_feed));
+        }
+
+        // Sums the weighted assets and liabilities of every position
+        let weighted_values =
+            |requirement_type: RequirementType| -> Result<(I80F48, I80F48), LendingError> {
+                positions.iter().try_fold(
+                    (I80F48::ZERO, I80F48::ZERO),
+                    |(assets, liabilities), (balance, bank, price_feed)| {
+                        let (asset_value, liability_value) = balance
+                            .calc_weighted_values(bank, *price_feed, requirement_type)
+                            .map_err(protocol_error)?;
+                        Ok((assets + asset_value, liabilities + liability_value))
+                    },
+                )
+            };
+
+        let (initial_assets, initial_liabilities) = weighted_values(RequirementType::Initial)?;
+        let (maint_assets, maint_liabilities) = weighted_values(RequirementType::Maintenance)?;
+        let (equity_assets, equity_liabilities) = weighted_values(RequirementType::Equity)?;
+
+        let free_collateral = (initial_assets - initial_liabilities).max(I80F48::ZERO);
+
+        let ratio = |value: I80F48| -> f64 {
+            if equity_assets == I80F48::ZERO {
+                0.0
+            } else {
+                (value / equity_assets).to_num::<f64>()
+            }
+        };
+
+        let mut max_withdraw = Vec::new();
+        for (balance, bank, price_feed) in &positions {
+            if !matches!(balance.get_side(), Some(BalanceSide::Assets)) {
+                continue;
+            }
+
+            let amount = self
+                .max_withdraw_amount(
+                    balance,
+                    bank,
+                    price_feed,
+                    free_collateral,
+                    initial_liabilities,
+                )
+                .map_err(protocol_error)?;
+
+            let mint = bank.mint.to_string();
+            max_withdraw.push(WithdrawLimit {
+                symbol: get_symbol_for_mint(&mint).unwrap_or_default(),
+                mint,
+                mint_decimals: bank.mint_decimals as u32,
+                amount: amount.to_num::<u64>(),
+            });
+        }
+
+        Ok(ObligationHealth {
+            protocol_name: self.protocol_name().to_string(),
+            market_name: self.group_config.group_name(&marginfi_account.group),
+            account: pubkey.to_string(),
+            deposited_value: equity_assets.to_num::<f64>(),
+            borrowed_value: equity_liabilities.to_num::<f64>(),
+            loan_to_value: ratio(equity_liabilities),
+            max_loan_to_value: ratio(initial_assets),
+            liquidation_loan_to_value: ratio(maint_assets),
+            remaining_borrow_value: free_collateral.to_num::<f64>(),
+            health_factor: (maint_liabilities > I80F48::ZERO)
+                .then(|| (maint_assets / maint_liabilities).to_num::<f64>()),
+            elevation_group: None,
+            initial_health: Some((initial_assets - initial_liabilities).to_num::<f64>()),
+            maintenance_health: Some((maint_assets - maint_liabilities).to_num::<f64>()),
+            max_withdraw,
+            margin: None,
+        })
+    }
+
+    /// Amount of a deposit that can be withdrawn before the free collateral runs out
+    fn max_withdraw_amount(
+        &self,
+        balance: &Balance,
+        bank: &Bank,
+        price_feed: &OraclePriceFeed,
+        free_collateral: I80F48,
+        initial_liabilities: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        let deposit_amount = bank.get_asset_amount(balance.asset_shares.into())?;
+
+        let price_type = RequirementType::Initial.get_oracle_price_type();
+        let lower_price = price_feed.get_price_of_type(price_type, Some(PriceBias::Low))?;
+        let asset_weight = bank.get_asset_weight(RequirementType::Initial, lower_price)?;
+
+        // Deposits that don't count as collateral, or accounts without debt, are free to leave
+        if initial_liabilities == I80F48::ZERO || asset_weight == I80F48::ZERO {
+            return Ok(deposit_amount);
+        }
+
+        let weighted_price = lower_price.checked_mul(asset_weight).ok_or_else(math_error!())?;
+        let withdrawable = calc_amount(free_collateral, weighted_price, bank.mint_decimals)?;
+
+        Ok(withdrawable.min(deposit_amount))
+    }
+
+    /// Reads the oracle accounts of each bank and builds the price feed used to value balances
+    async fn fetch_price_feeds(
+        &self,
+        banks: &HashMap<Pubkey, Bank>,
+    ) -> Result<HashMap<Pubkey, OraclePriceFeed>, LendingError> {
+        let feeds: Vec<(Pubkey, Vec<OracleFeed>)> =
+            banks.iter().map(|(pubkey, bank)| (*pubkey, marginfi_feeds(bank))).collect();
+
+        let oracle_pubkeys: Vec<Pubkey> = feeds
+            .iter()
+            .flat_map(|(_, bank_feeds)| bank_feeds.iter().flat_map(OracleFeed::accounts))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let client = create_rpc_client(&self.rpc_url);
+        let oracle_accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, &oracle_pubkeys)
+        .await?;
+
+        let mut price_feeds = HashMap::with_capacity(feeds.len());
+        for (pubkey, bank_feeds) in feeds {
+            let prices = bank_feeds.iter().filter_map(|feed| feed.decode(&oracle_accounts).ok());
+            let Some(price) = select_price(prices) else {
+                debug!("No oracle price for bank {}", format_pubkey_for_error(&pubkey));
+                continue;
+            };
+            let ema_prices =
+                bank_feeds.iter().filter_map(|feed| feed.decode_ema(&oracle_accounts).ok());
+            let Some(ema_price) = select_price(ema_prices) else {
+                debug!("No oracle EMA price for bank {}", format_pubkey_for_error(&pubkey));
+                continue;
+            };
+
+            match OraclePriceFeed::new(&price, &ema_price, banks[&pubkey].config.oracle_setup) {
+                Ok(price_feed) => {
+                    price_feeds.insert(pubkey, price_feed);
+                }
+                Err(e) => {
+                    debug!(
+                        "Invalid oracle price for bank {}: {}",
+                        format_pubkey_for_error(&pubkey),
+                        e
+                    )
+                }
+            }
+        }
+
+        Ok(price_feeds)
+    }
+
+    /// Every Marginfi account owned by the wallet, across groups
+    pub async fn fetch_marginfi_accounts(
         &self,
         owner_pubkey: &str,
-    ) -> Result<Vec<(Balance, Bank)>, LendingError> {
+    ) -> Result<Vec<(Pubkey, MarginfiAccount)>, LendingError> {
         let owner = Pubkey::from_str(owner_pubkey).map_err(|e| {
             LendingError::InvalidAddress(format!("Invalid owner pubkey {}: {}", owner_pubkey, e))
         })?;
 
         // Use the RPC builder with optimized filters
-        let accounts = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_memcmp(0, MARGINFI_ACCOUNT_DISCRIMINATOR.to_vec())
-                .with_data_size(2304 + 8) // Size of MarginfiAccount
-                .with_memcmp_pubkey(8 + 32, &owner) // Skip discriminator (8) and group pubkey (32) to get to authority
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_memcmp(0, MARGINFI_ACCOUNT_DISCRIMINATOR.to_vec())
+            .with_data_size(2304 + 8) // Size of MarginfiAccount
+            .with_memcmp_pubkey(8 + 32, &owner) // Skip discriminator (8) and group pubkey (32) to get to authority
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
 
         if accounts.is_empty() {
             debug!("No marginfi accounts found for {}", owner_pubkey);
             return Ok(Vec::new());
         }
 
-        // Collect all bank pubkeys first
-        let mut bank_pubkeys = Vec::new();
         let mut marginfi_accounts = Vec::with_capacity(accounts.len());
 
         for (pubkey, account) in accounts {
@@ -218,7 +558,73 @@ impl MarginfiClient {
                     ))
                 })?;
 
-            // Collect bank pubkeys for batch fetching
+            marginfi_accounts.push((pubkey, marginfi_account));
+        }
+
+        Ok(marginfi_accounts)
+    }
+
+    /// Fetches and deserializes the given banks in a single batch, skipping the ones that fail.
+    /// Their interest is accrued up to the clock read along with them so share values are
+    /// current, except for the banks of groups that are not loaded, whose fee settings are
+    /// unknown.
+    async fn fetch_banks(
+        &self,
+        bank_pubkeys: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, Bank>, LendingError> {
+        let client = create_rpc_client(&self.rpc_url);
+        let (bank_accounts, clock) =
+            common_rpc::get_multiple_accounts_with_clock(&client, bank_pubkeys)
+                .await
+                .map_err(LendingErrorConverter::convert_error)?;
+
+        let mut banks = HashMap::with_capacity(bank_accounts.len());
+        for (pubkey, bank_account) in bank_accounts {
+            match Bank::try_from_slice(&bank_account.data[8..]) {
+                Ok(mut bank) => {
+                    // The interest rate fees depend on the group's settings
+                    match self.markets.iter().find(|market| market.pubkey == bank.group) {
+                        Some(market) => {
+                            bank.accrue_interest(clock.unix_timestamp, &market.group).map_err(
+                                |e| {
+                                    LendingError::ProtocolError(format!(
+                                        "Failed to accrue interest of bank {}: {}",
+                                        format_pubkey_for_error(&pubkey),
+                                        e
+                                    ))
+                                },
+                            )?;
+                        }
+                        None => debug!(
+                            "Not accruing interest of bank {}, group {} is not loaded",
+                            format_pubkey_for_error(&pubkey),
+                            format_pubkey_for_error(&bank.group)
+                        ),
+                    }
+                    banks.insert(pubkey, bank);
+                }
+                Err(e) => {
+                    debug!(
+                        "Failed to deserialize bank {}: {}",
+                        format_pubkey_for_error(&pubkey),
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(banks)
+    }
+
+    async fn fetch_raw_obligations(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<(Pubkey, Balance, Bank)>, LendingError> {
+        let marginfi_accounts = self.fetch_marginfi_accounts(owner_pubkey).await?;
+
+        // Collect bank pubkeys for batch fetching
+        let mut bank_pubkeys = Vec::new();
+        for (_, marginfi_account) in &marginfi_accounts {
             for balance in marginfi_account.lending_account.get_active_balances_iter() {
                 if !balance.is_empty(BalanceSide::Assets)
                     || !balance.is_empty(BalanceSide::Liabilities)
@@ -226,39 +632,26 @@ impl MarginfiClient {
                     bank_pubkeys.push(balance.bank_pk);
                 }
             }
+        }
 
-            marginfi_accounts.push(marginfi_account);
+        if bank_pubkeys.is_empty() {
+            return Ok(Vec::new());
         }
 
         // Fetch all bank accounts in a single batch
-        let bank_accounts = with_pooled_client(&self.rpc_url, |client| {
-            common_rpc::get_multiple_accounts_with_conversion::<LendingError, LendingErrorConverter>(
-                client,
-                &bank_pubkeys,
-            )
-        })?;
+        let banks = self.fetch_banks(&bank_pubkeys).await?;
 
         // Process the results
         let mut result = Vec::with_capacity(bank_pubkeys.len());
 
-        for marginfi_account in marginfi_accounts {
+        for (pubkey, marginfi_account) in marginfi_accounts {
             // Process active balances
             for balance in marginfi_account.lending_account.get_active_balances_iter() {
                 if !balance.is_empty(BalanceSide::Assets)
                     || !balance.is_empty(BalanceSide::Liabilities)
                 {
-                    if let Some(bank_account) = bank_accounts.get(&balance.bank_pk) {
-                        match Bank::try_from_slice(&bank_account.data[8..]) {
-                            Ok(bank) => result.push((balance.clone(), bank)),
-                            Err(e) => {
-                                debug!(
-                                    "Failed to deserialize bank {}: {}",
-                                    format_pubkey_for_error(&balance.bank_pk),
-                                    e
-                                );
-                                continue;
-                            }
-                        }
+                    if let Some(bank) = banks.get(&balance.bank_pk) {
+                        result.push((pubkey, balance.clone(), bank.clone()));
                     } else {
                         debug!(
                             "Failed to fetch bank account {}",
@@ -273,38 +666,24 @@ impl MarginfiClient {
     }
 }
 
-impl LendingClient<Pubkey, (Vec<(Pubkey, Bank)>, MarginfiGroup)> for MarginfiClient {
-    fn load_markets(&mut self) -> Result<(), LendingError> {
-        // Fetch the data first
-        let (banks, group) = self.fetch_markets()?;
-
-        // Then update the state
-        self.banks = banks;
-        self.group = group;
-
-        Ok(())
+impl LendingClient<Pubkey, Vec<MarginfiMarket>> for MarginfiClient {
+    async fn load_markets(&mut self) -> Result<(), LendingError> {
+        self.load_marginfi_markets().await
     }
 
-    fn fetch_markets(&self) -> Result<(Vec<(Pubkey, Bank)>, MarginfiGroup), LendingError> {
-        // Fetch banks and group in separate calls
-        let banks = self.fetch_banks_for_group()?;
-        let group = self.fetch_marginfi_group(&self.group_pubkeys[0])?;
-
-        // Return as a tuple
-        Ok((banks, group))
+    async fn fetch_markets(&self) -> Result<Vec<MarginfiMarket>, LendingError> {
+        self.fetch_marginfi_markets().await
     }
 
-    fn set_market_data(&mut self, data: (Vec<(Pubkey, Bank)>, MarginfiGroup)) {
-        let (banks, group) = data;
-        self.banks = banks;
-        self.group = group;
+    fn set_market_data(&mut self, data: Vec<MarginfiMarket>) {
+        self.markets = data;
     }
 
-    fn get_user_obligations(
+    async fn get_user_obligations(
         &self,
         wallet_address: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        self.get_user_obligations(wallet_address)
+        self.get_user_obligations(wallet_address).await
     }
 
     fn program_id(&self) -> Pubkey {
@@ -0,0 +1,101 @@
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+/// Comma separated `address=name` entries of the groups to load, the main group when unset
+pub const GROUPS_ENV: &str = "MARGINFI_GROUPS";
+/// When `true`, every group that has banks is loaded in addition to the configured ones
+pub const DISCOVER_GROUPS_ENV: &str = "MARGINFI_DISCOVER_GROUPS";
+
+pub const MAIN_GROUP: &str = "4qp6Fx6tnZkY5Wropq9wUYgtFxXKwE6viZxFHg3rdAG8";
+pub const MAIN_GROUP_NAME: &str = "Global Pool";
+
+/// Selects which Marginfi groups are loaded and how they are named
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginfiGroupConfig {
+    /// Groups that are always loaded, with their display name
+    pub groups: Vec<(Pubkey, String)>,
+    /// Also load every other group, named by its address
+    pub discover: bool,
+}
+
+impl Default for MarginfiGroupConfig {
+    fn default() -> Self {
+        Self {
+            groups: vec![(
+                Pubkey::from_str(MAIN_GROUP).expect("Invalid Marginfi main group"),
+                MAIN_GROUP_NAME.to_string(),
+            )],
+            discover: false,
+        }
+    }
+}
+
+impl MarginfiGroupConfig {
+    /// Reads the configuration from the environment, ignoring malformed entries
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = std::env::var(GROUPS_ENV) {
+            let groups = parse_groups(&value);
+            if !groups.is_empty() {
+                config.groups = groups;
+            }
+        }
+
+        config.discover = std::env::var(DISCOVER_GROUPS_ENV)
+            .map(|value| value.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        config
+    }
+
+    /// Display name of a group, its address when it isn't configured
+    pub fn group_name(&self, group: &Pubkey) -> String {
+        self.groups
+            .iter()
+            .find(|(pubkey, _)| pubkey == group)
+            .map_or_else(|| group.to_string(), |(_, name)| name.clone())
+    }
+}
+
+fn parse_groups(value: &str) -> Vec<(Pubkey, String)> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let (address, name) = entry.split_once('=').unwrap_or((entry, ""));
+            match Pubkey::from_str(address.trim()) {
+                Ok(pubkey) => {
+                    let name = name.trim();
+                    let name = if name.is_empty() { pubkey.to_string() } else { name.to_string() };
+                    Some((pubkey, name))
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid group {:?} in {}: {}", entry, GROUPS_ENV, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_group_entries() {
+        let main = Pubkey::from_str(MAIN_GROUP).unwrap();
+        let other = Pubkey::new_unique();
+
+        let groups = parse_groups(&format!("{}=Main, {} ,not-a-key=Broken,", main, other));
+        assert_eq!(groups, vec![(main, "Main".to_string()), (other, other.to_string())]);
+
+        let config = MarginfiGroupConfig { groups, discover: false };
+        assert_eq!(config.group_name(&main), "Main");
+        let unknown = Pubkey::new_unique();
+        assert_eq!(config.group_name(&unknown), unknown.to_string());
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
     group::{Bank, WrappedI80F48},
-    price::OraclePriceType,
+    price::{OraclePriceType, PriceAdapter, PriceBias},
 };
 use crate::{
     assert_struct_align, assert_struct_size,
@@ -237,6 +237,47 @@ impl Balance {
         }
     }
 
+    /// Weighted (assets, liabilities) value of the balance for the requirement type, the way
+    /// the risk engine values it: assets at the low end of the price confidence interval and
+    /// liabilities at the high end
+    pub fn calc_weighted_values(
+        &self,
+        bank: &Bank,
+        price_feed: &impl PriceAdapter,
+        requirement_type: RequirementType,
+    ) -> MarginfiResult<(I80F48, I80F48)> {
+        let price_type = requirement_type.get_oracle_price_type();
+
+        match self.get_side() {
+            Some(BalanceSide::Assets) => {
+                let lower_price = price_feed.get_price_of_type(price_type, Some(PriceBias::Low))?;
+                let asset_weight = bank.get_asset_weight(requirement_type, lower_price)?;
+                let asset_amount = bank.get_asset_amount(self.asset_shares.into())?;
+
+                let value =
+                    calc_value(asset_amount, lower_price, bank.mint_decimals, Some(asset_weight))?;
+
+                Ok((value, I80F48::ZERO))
+            }
+            Some(BalanceSide::Liabilities) => {
+                let higher_price =
+                    price_feed.get_price_of_type(price_type, Some(PriceBias::High))?;
+                let (_, liability_weight) = bank.config.get_weights(requirement_type);
+                let liability_amount = bank.get_liability_amount(self.liability_shares.into())?;
+
+                let value = calc_value(
+                    liability_amount,
+                    higher_price,
+                    bank.mint_decimals,
+                    Some(liability_weight),
+                )?;
+
+                Ok((I80F48::ZERO, value))
+            }
+            None => Ok((I80F48::ZERO, I80F48::ZERO)),
+        }
+    }
+
     pub fn empty_deactivated() -> Self {
         Balance {
             active: false,
@@ -1,5 +1,5 @@
 use super::{
-    account::{BalanceSide, RequirementType},
+    account::{calc_value, BalanceSide, RequirementType},
     price::OracleSetup,
 };
 
@@ -9,7 +9,8 @@ use crate::{
         constants::{
             ASSET_TAG_DEFAULT, FEE_VAULT_AUTHORITY_SEED, FEE_VAULT_SEED,
             INSURANCE_VAULT_AUTHORITY_SEED, INSURANCE_VAULT_SEED, LIQUIDITY_VAULT_AUTHORITY_SEED,
-            LIQUIDITY_VAULT_SEED, MAX_ORACLE_KEYS, TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE,
+            LIQUIDITY_VAULT_SEED, MAX_ORACLE_KEYS, SECONDS_PER_YEAR,
+            TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE,
         },
         prelude::{MarginfiError, MarginfiResult},
     },
@@ -538,6 +539,112 @@ impl Bank {
     pub fn get_flag(&self, flag: u64) -> bool {
         (self.flags & flag) == flag
     }
+
+    /// Asset weight for the requirement type, including the initial weight discount
+    pub fn get_asset_weight(
+        &self,
+        requirement_type: RequirementType,
+        lower_price: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        let (asset_weight, _) = self.config.get_weights(requirement_type);
+
+        match requirement_type {
+            RequirementType::Initial => {
+                match self.maybe_get_asset_weight_init_discount(lower_price)? {
+                    Some(discount) => {
+                        Ok(asset_weight.checked_mul(discount).ok_or_else(math_error!())?)
+                    }
+                    None => Ok(asset_weight),
+                }
+            }
+            _ => Ok(asset_weight),
+        }
+    }
+
+    /// Discount applied to the initial asset weight once the bank's total deposits are worth
+    /// more than `total_asset_value_init_limit`
+    pub fn maybe_get_asset_weight_init_discount(
+        &self,
+        price: I80F48,
+    ) -> MarginfiResult<Option<I80F48>> {
+        if self.config.usd_init_limit_active() {
+            let bank_total_assets_value = calc_value(
+                self.get_asset_amount(self.total_asset_shares.into())?,
+                price,
+                self.mint_decimals,
+                None,
+            )?;
+
+            let total_asset_value_init_limit =
+                I80F48::from_num(self.config.total_asset_value_init_limit);
+
+            if bank_total_assets_value > total_asset_value_init_limit {
+                let discount = total_asset_value_init_limit
+                    .checked_div(bank_total_assets_value)
+                    .ok_or_else(math_error!())?;
+
+                return Ok(Some(discount));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Accrue the interest since the last update into the share values and the outstanding fees,
+    /// the way the program does before any balance change of the bank
+    pub fn accrue_interest(
+        &mut self,
+        current_timestamp: i64,
+        group: &MarginfiGroup,
+    ) -> MarginfiResult<()> {
+        let time_delta: u64 = current_timestamp.saturating_sub(self.last_update).max(0) as u64;
+        if time_delta == 0 {
+            return Ok(());
+        }
+
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+
+        self.last_update = current_timestamp;
+
+        if total_assets == I80F48::ZERO || total_liabilities == I80F48::ZERO {
+            return Ok(());
+        }
+
+        let ir_calc = self.config.interest_rate_config.create_interest_rate_calculator(group);
+        let InterestRateStateChanges {
+            new_asset_share_value,
+            new_liability_share_value,
+            insurance_fees_collected,
+            group_fees_collected,
+            protocol_fees_collected,
+        } = calc_interest_rate_accrual_state_changes(
+            time_delta,
+            total_assets,
+            total_liabilities,
+            &ir_calc,
+            self.asset_share_value.into(),
+            self.liability_share_value.into(),
+        )
+        .ok_or_else(math_error!())?;
+
+        let collect = |outstanding: WrappedI80F48, collected: I80F48| {
+            I80F48::from(outstanding).checked_add(collected).map(WrappedI80F48::from)
+        };
+        self.asset_share_value = new_asset_share_value.into();
+        self.liability_share_value = new_liability_share_value.into();
+        self.collected_insurance_fees_outstanding =
+            collect(self.collected_insurance_fees_outstanding, insurance_fees_collected)
+                .ok_or_else(math_error!())?;
+        self.collected_group_fees_outstanding =
+            collect(self.collected_group_fees_outstanding, group_fees_collected)
+                .ok_or_else(math_error!())?;
+        self.collected_program_fees_outstanding =
+            collect(self.collected_program_fees_outstanding, protocol_fees_collected)
+                .ok_or_else(math_error!())?;
+
+        Ok(())
+    }
 }
 
 /// We use a simple interest rate model that auto settles the accrued interest into the lending account balances.
@@ -567,64 +674,61 @@ impl Bank {
 ///
 /// `i_b = i * (1 + f_i) + f_f`
 ///
-// fn calc_interest_rate_accrual_state_changes(
-//     time_delta: u64,
-//     total_assets_amount: I80F48,
-//     total_liabilities_amount: I80F48,
-//     interest_rate_calc: &InterestRateCalc,
-//     asset_share_value: I80F48,
-//     liability_share_value: I80F48,
-// ) -> Option<InterestRateStateChanges> {
-//     let utilization_rate = total_liabilities_amount.checked_div(total_assets_amount)?;
-//     let computed_rates = interest_rate_calc.calc_interest_rate(utilization_rate)?;
-
-//     debug!("Utilization rate: {}, time delta {}s", utilization_rate, time_delta);
-//     debug!("{:#?}", computed_rates);
-
-//     let ComputedInterestRates {
-//         lending_rate_apr,
-//         borrowing_rate_apr,
-//         group_fee_apr,
-//         insurance_fee_apr,
-//         protocol_fee_apr,
-//     } = computed_rates;
-
-//     Some(InterestRateStateChanges {
-//         new_asset_share_value: calc_accrued_interest_payment_per_period(
-//             lending_rate_apr,
-//             time_delta,
-//             asset_share_value,
-//         )?,
-//         new_liability_share_value: calc_accrued_interest_payment_per_period(
-//             borrowing_rate_apr,
-//             time_delta,
-//             liability_share_value,
-//         )?,
-//         insurance_fees_collected: calc_interest_payment_for_period(
-//             insurance_fee_apr,
-//             time_delta,
-//             total_liabilities_amount,
-//         )?,
-//         group_fees_collected: calc_interest_payment_for_period(
-//             group_fee_apr,
-//             time_delta,
-//             total_liabilities_amount,
-//         )?,
-//         protocol_fees_collected: calc_interest_payment_for_period(
-//             protocol_fee_apr,
-//             time_delta,
-//             total_liabilities_amount,
-//         )?,
-//     })
-// }
-
-// struct InterestRateStateChanges {
-//     new_asset_share_value: I80F48,
-//     new_liability_share_value: I80F48,
-//     insurance_fees_collected: I80F48,
-//     group_fees_collected: I80F48,
-//     protocol_fees_collected: I80F48,
-// }
+fn calc_interest_rate_accrual_state_changes(
+    time_delta: u64,
+    total_assets_amount: I80F48,
+    total_liabilities_amount: I80F48,
+    interest_rate_calc: &InterestRateCalc,
+    asset_share_value: I80F48,
+    liability_share_value: I80F48,
+) -> Option<InterestRateStateChanges> {
+    let utilization_rate = total_liabilities_amount.checked_div(total_assets_amount)?;
+    let computed_rates = interest_rate_calc.calc_interest_rate(utilization_rate)?;
+
+    let ComputedInterestRates {
+        lending_rate_apr,
+        borrowing_rate_apr,
+        group_fee_apr,
+        insurance_fee_apr,
+        protocol_fee_apr,
+    } = computed_rates;
+
+    Some(InterestRateStateChanges {
+        new_asset_share_value: calc_accrued_interest_payment_per_period(
+            lending_rate_apr,
+            time_delta,
+            asset_share_value,
+        )?,
+        new_liability_share_value: calc_accrued_interest_payment_per_period(
+            borrowing_rate_apr,
+            time_delta,
+            liability_share_value,
+        )?,
+        insurance_fees_collected: calc_interest_payment_for_period(
+            insurance_fee_apr,
+            time_delta,
+            total_liabilities_amount,
+        )?,
+        group_fees_collected: calc_interest_payment_for_period(
+            group_fee_apr,
+            time_delta,
+            total_liabilities_amount,
+        )?,
+        protocol_fees_collected: calc_interest_payment_for_period(
+            protocol_fee_apr,
+            time_delta,
+            total_liabilities_amount,
+        )?,
+    })
+}
+
+struct InterestRateStateChanges {
+    new_asset_share_value: I80F48,
+    new_liability_share_value: I80F48,
+    insurance_fees_collected: I80F48,
+    group_fees_collected: I80F48,
+    protocol_fees_collected: I80F48,
+}
 
 /// Calculates the fee rate for a given base rate and fees specified.
 /// The returned rate is only the fee rate without the base rate.
@@ -640,30 +744,30 @@ fn calc_fee_rate(base_rate: I80F48, rate_fees: I80F48, fixed_fees: I80F48) -> Op
 
 /// Calculates the accrued interest payment per period `time_delta` in a principal value `value` for interest rate (in APR) `arp`.
 /// Result is the new principal value.
-// fn calc_accrued_interest_payment_per_period(
-//     apr: I80F48,
-//     time_delta: u64,
-//     value: I80F48,
-// ) -> Option<I80F48> {
-//     let ir_per_period = apr.checked_mul(time_delta.into())?.checked_div(SECONDS_PER_YEAR)?;
+fn calc_accrued_interest_payment_per_period(
+    apr: I80F48,
+    time_delta: u64,
+    value: I80F48,
+) -> Option<I80F48> {
+    let ir_per_period = apr.checked_mul(time_delta.into())?.checked_div(SECONDS_PER_YEAR)?;
 
-//     let new_value = value.checked_mul(I80F48::ONE.checked_add(ir_per_period)?)?;
+    let new_value = value.checked_mul(I80F48::ONE.checked_add(ir_per_period)?)?;
 
-//     Some(new_value)
-// }
+    Some(new_value)
+}
 
-// /// Calculates the interest payment for a given period `time_delta` in a principal value `value` for interest rate (in APR) `arp`.
-// /// Result is the interest payment.
-// fn calc_interest_payment_for_period(apr: I80F48, time_delta: u64, value: I80F48) -> Option<I80F48> {
-//     if apr.is_zero() {
-//         return Some(I80F48::ZERO);
-//     }
+/// Calculates the interest payment for a given period `time_delta` in a principal value `value` for interest rate (in APR) `arp`.
+/// Result is the interest payment.
+fn calc_interest_payment_for_period(apr: I80F48, time_delta: u64, value: I80F48) -> Option<I80F48> {
+    if apr.is_zero() {
+        return Some(I80F48::ZERO);
+    }
 
-//     let interest_payment =
-//         value.checked_mul(apr)?.checked_mul(time_delta.into())?.checked_div(SECONDS_PER_YEAR)?;
+    let interest_payment =
+        value.checked_mul(apr)?.checked_mul(time_delta.into())?.checked_div(SECONDS_PER_YEAR)?;
 
-//     Some(interest_payment)
-// }
+    Some(interest_payment)
+}
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Zeroable)]
@@ -1171,4 +1275,146 @@ mod tests {
         assert_eq_with_tolerance!(group_fees_apr, I80F48!(0.01), I80F48!(0.001));
         assert_eq_with_tolerance!(insurance_apr, I80F48!(0.17), I80F48!(0.001));
     }
+
+    fn bank_with_deposits(total_asset_shares: I80F48) -> Bank {
+        Bank {
+            asset_share_value: I80F48!(1).into(),
+            liability_share_value: I80F48!(1).into(),
+            total_asset_shares: total_asset_shares.into(),
+            config: BankConfig {
+                asset_weight_init: I80F48!(0.8).into(),
+                asset_weight_maint: I80F48!(0.9).into(),
+                liability_weight_init: I80F48!(1.25).into(),
+                liability_weight_maint: I80F48!(1.1).into(),
+                oracle_setup: OracleSetup::PythPushOracle,
+                ..BankConfig::default()
+            },
+            ..Bank::default()
+        }
+    }
+
+    #[test]
+    fn asset_weight_init_discount() {
+        let mut bank = bank_with_deposits(I80F48!(2000));
+
+        // No limit configured
+        assert_eq!(
+            bank.get_asset_weight(RequirementType::Initial, I80F48!(1)).unwrap(),
+            I80F48!(0.8)
+        );
+
+        // $2000 deposited against a $1000 limit halves the initial weight
+        bank.config.total_asset_value_init_limit = 1000;
+        assert_eq!(
+            bank.get_asset_weight(RequirementType::Initial, I80F48!(1)).unwrap(),
+            I80F48!(0.4)
+        );
+        assert_eq!(
+            bank.get_asset_weight(RequirementType::Maintenance, I80F48!(1)).unwrap(),
+            I80F48!(0.9)
+        );
+
+        // Under the limit
+        assert_eq!(
+            bank.get_asset_weight(RequirementType::Initial, I80F48!(0.25)).unwrap(),
+            I80F48!(0.8)
+        );
+    }
+
+    #[test]
+    fn weighted_balance_values() {
+        use crate::marginfi::models::{account::Balance, price::OraclePriceFeed};
+        use crate::oracle::OraclePrice;
+
+        let bank = bank_with_deposits(I80F48!(0));
+
+        // $1.00 +/- 0.01, widened to 0.0212 by the confidence multiple
+        let price = OraclePrice::new(100, 1, -2, 0);
+        let price_feed = OraclePriceFeed::new(&price, &price, OracleSetup::PythPushOracle).unwrap();
+
+        let deposit = Balance {
+            active: true,
+            asset_shares: I80F48!(10).into(),
+            ..Balance::empty_deactivated()
+        };
+        let (assets, liabilities) =
+            deposit.calc_weighted_values(&bank, &price_feed, RequirementType::Initial).unwrap();
+        assert!((assets.to_num::<f64>() - 10.0 * 0.9788 * 0.8).abs() < 1e-9);
+        assert_eq!(liabilities, I80F48::ZERO);
+
+        let borrow = Balance {
+            active: true,
+            liability_shares: I80F48!(10).into(),
+            ..Balance::empty_deactivated()
+        };
+        let (assets, liabilities) =
+            borrow.calc_weighted_values(&bank, &price_feed, RequirementType::Maintenance).unwrap();
+        assert_eq!(assets, I80F48::ZERO);
+        assert!((liabilities.to_num::<f64>() - 10.0 * 1.0212 * 1.1).abs() < 1e-9);
+
+        // A wide confidence interval is capped at 5% of the price
+        let price = OraclePrice::new(100, 10, -2, 0);
+        let price_feed = OraclePriceFeed::new(&price, &price, OracleSetup::PythPushOracle).unwrap();
+        let (_, liabilities) =
+            borrow.calc_weighted_values(&bank, &price_feed, RequirementType::Equity).unwrap();
+        assert!((liabilities.to_num::<f64>() - 10.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn initial_requirement_uses_the_ema_price() {
+        use crate::marginfi::models::{account::Balance, price::OraclePriceFeed};
+        use crate::oracle::OraclePrice;
+
+        let bank = bank_with_deposits(I80F48!(0));
+        // $1.00 spot, $0.50 EMA
+        let price_feed = OraclePriceFeed::new(
+            &OraclePrice::new(100, 0, -2, 0),
+            &OraclePrice::new(50, 0, -2, 0),
+            OracleSetup::PythPushOracle,
+        )
+        .unwrap();
+
+        let deposit = Balance {
+            active: true,
+            asset_shares: I80F48!(10).into(),
+            ..Balance::empty_deactivated()
+        };
+        let value = |requirement_type| {
+            deposit.calc_weighted_values(&bank, &price_feed, requirement_type).unwrap().0
+        };
+        assert!((value(RequirementType::Initial).to_num::<f64>() - 10.0 * 0.5 * 0.8).abs() < 1e-9);
+        assert!((value(RequirementType::Maintenance).to_num::<f64>() - 10.0 * 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn accrue_interest_grows_share_values() {
+        let mut bank = bank_with_deposits(I80F48!(1000));
+        bank.total_liability_shares = I80F48!(500).into();
+        bank.config.interest_rate_config = InterestRateConfig {
+            optimal_utilization_rate: I80F48!(0.5).into(),
+            plateau_interest_rate: I80F48!(0.1).into(),
+            max_interest_rate: I80F48!(1).into(),
+            ..Default::default()
+        };
+
+        // A year at 50% utilization pays borrowers' 10% to the depositors
+        let group = MarginfiGroup::default();
+        bank.accrue_interest(31_536_000, &group).unwrap();
+        assert_eq_with_tolerance!(
+            I80F48::from(bank.asset_share_value),
+            I80F48!(1.05),
+            I80F48!(0.0001)
+        );
+        assert_eq_with_tolerance!(
+            I80F48::from(bank.liability_share_value),
+            I80F48!(1.1),
+            I80F48!(0.0001)
+        );
+        assert_eq!(bank.last_update, 31_536_000);
+
+        // Nothing accrues without time passing
+        let accrued = bank.clone();
+        bank.accrue_interest(31_536_000, &group).unwrap();
+        assert_eq!(bank, accrued);
+    }
 }
@@ -3,7 +3,14 @@ use bytemuck::Zeroable;
 use enum_dispatch::enum_dispatch;
 use fixed::types::I80F48;
 
-use crate::marginfi::utils::prelude::*;
+use crate::{
+    marginfi::utils::{
+        constants::{CONF_INTERVAL_MULTIPLE, EXP_10_I80F48, MAX_CONF_INTERVAL, STD_DEV_MULTIPLE},
+        prelude::*,
+    },
+    math_error,
+    oracle::OraclePrice,
+};
 
 use anchor_lang::prelude::borsh;
 
@@ -57,3 +64,87 @@ pub struct Price {
 }
 
 pub type UnixTimestamp = i64;
+
+/// Price adapter over the prices decoded from the bank's oracle accounts, used to value balances
+/// off-chain. Time weighted requests get the EMA price, real time requests the spot price.
+#[derive(Copy, Clone, Debug)]
+pub struct OraclePriceFeed {
+    price: BiasedPrice,
+    ema_price: BiasedPrice,
+}
+
+/// A price with the confidence interval its bias moves it by
+#[derive(Copy, Clone, Debug)]
+struct BiasedPrice {
+    price: I80F48,
+    conf_interval: I80F48,
+}
+
+impl BiasedPrice {
+    fn new(price: &OraclePrice, oracle_setup: OracleSetup) -> MarginfiResult<Self> {
+        let value = oracle_price_to_i80f48(I80F48::from_num(price.price), price.expo)?;
+        let conf = oracle_price_to_i80f48(I80F48::from_num(price.conf), price.expo)?;
+
+        // Switchboard reports a standard deviation rather than a confidence interval
+        let conf_multiple = match oracle_setup {
+            OracleSetup::SwitchboardPull | OracleSetup::SwitchboardV2 => STD_DEV_MULTIPLE,
+            _ => CONF_INTERVAL_MULTIPLE,
+        };
+
+        let conf_interval = conf.checked_mul(conf_multiple).ok_or_else(math_error!())?;
+        let max_conf_interval = value.checked_mul(MAX_CONF_INTERVAL).ok_or_else(math_error!())?;
+
+        Ok(Self { price: value, conf_interval: conf_interval.min(max_conf_interval) })
+    }
+
+    fn with_bias(&self, bias: Option<PriceBias>) -> MarginfiResult<I80F48> {
+        match bias {
+            None => Ok(self.price),
+            Some(PriceBias::Low) => {
+                Ok(self.price.checked_sub(self.conf_interval).ok_or_else(math_error!())?)
+            }
+            Some(PriceBias::High) => {
+                Ok(self.price.checked_add(self.conf_interval).ok_or_else(math_error!())?)
+            }
+        }
+    }
+}
+
+impl OraclePriceFeed {
+    pub fn new(
+        price: &OraclePrice,
+        ema_price: &OraclePrice,
+        oracle_setup: OracleSetup,
+    ) -> MarginfiResult<Self> {
+        Ok(Self {
+            price: BiasedPrice::new(price, oracle_setup)?,
+            ema_price: BiasedPrice::new(ema_price, oracle_setup)?,
+        })
+    }
+}
+
+impl PriceAdapter for OraclePriceFeed {
+    fn get_price_of_type(
+        &self,
+        oracle_price_type: OraclePriceType,
+        bias: Option<PriceBias>,
+    ) -> MarginfiResult<I80F48> {
+        match oracle_price_type {
+            OraclePriceType::TimeWeighted => self.ema_price.with_bias(bias),
+            OraclePriceType::RealTime => self.price.with_bias(bias),
+        }
+    }
+}
+
+fn oracle_price_to_i80f48(value: I80F48, expo: i32) -> MarginfiResult<I80F48> {
+    let scaling_factor =
+        *EXP_10_I80F48.get(expo.unsigned_abs() as usize).ok_or_else(math_error!())?;
+
+    let price = if expo < 0 {
+        value.checked_div(scaling_factor)
+    } else {
+        value.checked_mul(scaling_factor)
+    };
+
+    Ok(price.ok_or_else(math_error!())?)
+}
@@ -0,0 +1,126 @@
+use drift::{
+    math::constants::PRICE_PRECISION_I64,
+    models::idl::{accounts::SpotMarket, types::OracleSource},
+};
+use solana_sdk::pubkey::Pubkey;
+
+use super::{OracleFeed, OraclePrice};
+use crate::{
+    kamino::{models::reserve::Reserve as KaminoReserve, utils::consts::NULL_PUBKEY},
+    marginfi::models::{group::Bank, price::OracleSetup},
+    save::models::Reserve as SaveReserve,
+};
+
+const DRIFT_PRICE_EXPO: i32 = -6;
+const KAMINO_FRACTION_BITS: u32 = 60;
+const WAD_DECIMALS: u32 = 18;
+
+fn is_set(pubkey: &Pubkey) -> bool {
+    *pubkey != Pubkey::default() && *pubkey != NULL_PUBKEY
+}
+
+/// Oracle feeds configured on a Kamino reserve, in the order the program prefers them
+pub fn kamino_feeds(reserve: &KaminoReserve) -> Vec<OracleFeed> {
+    let token_info = &reserve.config.token_info;
+    let mut feeds = Vec::new();
+
+    if token_info.scope_configuration.is_enabled() {
+        feeds.push(OracleFeed::Scope {
+            prices: token_info.scope_configuration.price_feed,
+            chain: token_info.scope_configuration.price_chain,
+        });
+    }
+    if token_info.pyth_configuration.is_enabled() {
+        feeds.push(OracleFeed::Pyth(token_info.pyth_configuration.price));
+    }
+    if token_info.switchboard_configuration.is_enabled() {
+        feeds.push(OracleFeed::SwitchboardPull(
+            token_info.switchboard_configuration.price_aggregator,
+        ));
+    }
+
+    // Price written by the last RefreshReserve, stored as a fraction with 60 fractional bits
+    let cached = reserve
+        .liquidity
+        .market_price_sf
+        .checked_mul(1_000_000_000)
+        .map(|scaled| scaled >> KAMINO_FRACTION_BITS)
+        .and_then(|value| {
+            OraclePrice::from_fixed(value, 9, reserve.liquidity.market_price_last_updated_ts as i64)
+        });
+    feeds.extend(cached.map(OracleFeed::Cached));
+
+    feeds
+}
+
+/// Oracle feeds configured on a Marginfi bank
+pub fn marginfi_feeds(bank: &Bank) -> Vec<OracleFeed> {
+    let keys = &bank.config.oracle_keys;
+
+    match bank.config.oracle_setup {
+        OracleSetup::PythLegacy => vec![OracleFeed::Pyth(keys[0])],
+        OracleSetup::PythPushOracle => vec![OracleFeed::PythPushFeed(keys[0].to_bytes())],
+        OracleSetup::SwitchboardPull => vec![OracleFeed::SwitchboardPull(keys[0])],
+        OracleSetup::StakedWithPythPush => vec![OracleFeed::StakedWithPythPush {
+            feed_id: keys[0].to_bytes(),
+            lst_mint: keys[1],
+            sol_pool: keys[2],
+        }],
+        // Switchboard V2 aggregators have been shut down
+        OracleSetup::SwitchboardV2 | OracleSetup::None => Vec::new(),
+    }
+}
+
+/// Oracle feeds configured on a Save reserve
+pub fn save_feeds(reserve: &SaveReserve) -> Vec<OracleFeed> {
+    let mut feeds = Vec::new();
+
+    if is_set(&reserve.liquidity.pyth_oracle_pubkey) {
+        feeds.push(OracleFeed::Pyth(reserve.liquidity.pyth_oracle_pubkey));
+    }
+    if is_set(&reserve.liquidity.switchboard_oracle_pubkey) {
+        feeds.push(OracleFeed::SwitchboardPull(reserve.liquidity.switchboard_oracle_pubkey));
+    }
+
+    // Save does not record when the cached price was written
+    let cached = reserve
+        .liquidity
+        .market_price
+        .to_scaled_val()
+        .ok()
+        .and_then(|value| OraclePrice::from_fixed(value, WAD_DECIMALS, 0));
+    feeds.extend(cached.map(OracleFeed::Cached));
+
+    feeds
+}
+
+/// Oracle feeds configured on a Drift spot market
+pub fn drift_feeds(market: &SpotMarket) -> Vec<OracleFeed> {
+    let mut feeds = Vec::new();
+
+    match market.oracle_source {
+        OracleSource::Pyth
+        | OracleSource::PythStableCoin
+        | OracleSource::PythPull
+        | OracleSource::PythStableCoinPull => feeds.push(OracleFeed::Pyth(market.oracle)),
+        OracleSource::SwitchboardOnDemand => feeds.push(OracleFeed::SwitchboardPull(market.oracle)),
+        OracleSource::QuoteAsset => feeds.push(OracleFeed::Cached(OraclePrice::new(
+            PRICE_PRECISION_I64,
+            0,
+            DRIFT_PRICE_EXPO,
+            0,
+        ))),
+        // Scaled, prelaunch and lazer oracles only back perp markets, rely on the cached price
+        _ => {}
+    }
+
+    let oracle_data = &market.historical_oracle_data;
+    feeds.push(OracleFeed::Cached(OraclePrice::new(
+        oracle_data.last_oracle_price,
+        oracle_data.last_oracle_conf,
+        DRIFT_PRICE_EXPO,
+        oracle_data.last_oracle_price_twap_ts,
+    )));
+
+    feeds
+}
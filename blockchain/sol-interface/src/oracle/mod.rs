@@ -0,0 +1,273 @@
+pub mod feeds;
+pub mod pyth;
+pub mod scope;
+pub mod switchboard;
+
+use common::{lending::LendingError, MARKET_PRICE_DECIMALS};
+use solana_program::program_pack::Pack;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use spl_token::state::Mint;
+use std::collections::HashMap;
+
+use crate::common::rpc_utils::format_pubkey_for_error;
+
+/// A decoded oracle price, independent of the oracle provider.
+///
+/// The price is `price * 10^expo`, the confidence interval uses the same exponent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    /// Unix timestamp of the update, 0 when the source does not record one
+    pub publish_time: i64,
+}
+
+impl OraclePrice {
+    pub fn new(price: i64, conf: u64, expo: i32, publish_time: i64) -> Self {
+        Self { price, conf, expo, publish_time }
+    }
+
+    /// Builds a price from an unsigned fixed-point value with `decimals` decimals, keeping at
+    /// most 9 of them so the result fits an i64
+    pub fn from_fixed(value: u128, decimals: u32, publish_time: i64) -> Option<Self> {
+        let dropped = decimals.saturating_sub(9);
+        let price = i64::try_from(value / 10u128.pow(dropped)).ok()?;
+        Some(Self::new(price, 0, -((decimals - dropped) as i32), publish_time))
+    }
+
+    /// Converts the price to the fixed-point representation used by `MintAsset.market_price_sf`
+    pub fn to_market_price_sf(&self) -> Option<u64> {
+        if self.price <= 0 {
+            return None;
+        }
+
+        let shift = self.expo + MARKET_PRICE_DECIMALS as i32;
+        let price = self.price as u128;
+        let scaled = if shift >= 0 {
+            price.checked_mul(10u128.checked_pow(shift as u32)?)?
+        } else {
+            price / 10u128.checked_pow(shift.unsigned_abs())?
+        };
+
+        u64::try_from(scaled).ok()
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.price as f64 * 10f64.powi(self.expo)
+    }
+}
+
+/// Where the price of a reserve's token can be read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OracleFeed {
+    /// Pyth price account, either a legacy price account or a `PriceUpdateV2` account
+    Pyth(Pubkey),
+    /// Pyth push oracle feed id, resolved to the sponsored feed accounts
+    PythPushFeed([u8; 32]),
+    /// Switchboard on-demand pull feed
+    SwitchboardPull(Pubkey),
+    /// Scope prices account and the chain of entries that are multiplied together
+    Scope { prices: Pubkey, chain: [u16; 4] },
+    /// Liquid staking token priced from the SOL Pyth feed and its stake pool backing
+    StakedWithPythPush { feed_id: [u8; 32], lst_mint: Pubkey, sol_pool: Pubkey },
+    /// Price already cached by the protocol on the reserve account
+    Cached(OraclePrice),
+}
+
+impl OracleFeed {
+    /// Accounts that must be fetched to decode this feed
+    pub fn accounts(&self) -> Vec<Pubkey> {
+        match self {
+            OracleFeed::Pyth(pubkey) | OracleFeed::SwitchboardPull(pubkey) => vec![*pubkey],
+            OracleFeed::PythPushFeed(feed_id) => pyth::push_oracle_addresses(feed_id).to_vec(),
+            OracleFeed::Scope { prices, .. } => vec![*prices],
+            OracleFeed::StakedWithPythPush { feed_id, lst_mint, sol_pool } => {
+                let mut accounts = pyth::push_oracle_addresses(feed_id).to_vec();
+                accounts.extend([*lst_mint, *sol_pool]);
+                accounts
+            }
+            OracleFeed::Cached(_) => Vec::new(),
+        }
+    }
+
+    /// Decodes the feed from previously fetched accounts
+    pub fn decode(&self, accounts: &HashMap<Pubkey, Account>) -> Result<OraclePrice, LendingError> {
+        match self {
+            OracleFeed::Pyth(pubkey) => pyth::decode_price(&get_account(accounts, pubkey)?.data),
+            OracleFeed::PythPushFeed(feed_id) => pyth::decode_push_feed(feed_id, accounts),
+            OracleFeed::SwitchboardPull(pubkey) => {
+                switchboard::decode_pull_feed(&get_account(accounts, pubkey)?.data)
+            }
+            OracleFeed::Scope { prices, chain } => {
+                scope::decode_price_chain(&get_account(accounts, prices)?.data, chain)
+            }
+            OracleFeed::StakedWithPythPush { feed_id, lst_mint, sol_pool } => {
+                let sol_price = pyth::decode_push_feed(feed_id, accounts)?;
+                decode_staked_price(sol_price, lst_mint, sol_pool, accounts)
+            }
+            OracleFeed::Cached(price) => Ok(*price),
+        }
+    }
+
+    /// Decodes the exponential moving average price of the feed from previously fetched
+    /// accounts. Feeds without one, Switchboard, Scope and cached prices, give their spot price.
+    pub fn decode_ema(
+        &self,
+        accounts: &HashMap<Pubkey, Account>,
+    ) -> Result<OraclePrice, LendingError> {
+        match self {
+            OracleFeed::Pyth(pubkey) => {
+                pyth::decode_ema_price(&get_account(accounts, pubkey)?.data)
+            }
+            OracleFeed::PythPushFeed(feed_id) => pyth::decode_push_feed_ema(feed_id, accounts),
+            OracleFeed::StakedWithPythPush { feed_id, lst_mint, sol_pool } => {
+                let sol_price = pyth::decode_push_feed_ema(feed_id, accounts)?;
+                decode_staked_price(sol_price, lst_mint, sol_pool, accounts)
+            }
+            _ => self.decode(accounts),
+        }
+    }
+}
+
+fn decode_staked_price(
+    sol_price: OraclePrice,
+    lst_mint: &Pubkey,
+    sol_pool: &Pubkey,
+    accounts: &HashMap<Pubkey, Account>,
+) -> Result<OraclePrice, LendingError> {
+    let lst_supply = decode_mint_supply(&get_account(accounts, lst_mint)?.data)?;
+    let pool_lamports = get_account(accounts, sol_pool)?.lamports;
+    staked_price(sol_price, pool_lamports, lst_supply)
+}
+
+fn get_account<'a>(
+    accounts: &'a HashMap<Pubkey, Account>,
+    pubkey: &Pubkey,
+) -> Result<&'a Account, LendingError> {
+    accounts.get(pubkey).ok_or_else(|| {
+        LendingError::AccountNotFound(format!(
+            "Oracle account {} not found",
+            format_pubkey_for_error(pubkey)
+        ))
+    })
+}
+
+fn decode_mint_supply(data: &[u8]) -> Result<u64, LendingError> {
+    // Token-2022 mints carry extensions after the base layout, only the base is needed here
+    let base = data.get(..Mint::LEN).ok_or_else(|| {
+        LendingError::DeserializationError("Mint account data too short".to_string())
+    })?;
+
+    Mint::unpack_from_slice(base)
+        .map(|mint| mint.supply)
+        .map_err(|e| LendingError::DeserializationError(format!("Invalid mint account: {}", e)))
+}
+
+/// Prices one LST as `sol_price * pool_lamports / lst_supply`, mirroring Marginfi's
+/// `StakedWithPythPush` oracle setup
+fn staked_price(
+    sol_price: OraclePrice,
+    pool_lamports: u64,
+    lst_supply: u64,
+) -> Result<OraclePrice, LendingError> {
+    if lst_supply == 0 {
+        return Err(LendingError::ProtocolError("LST mint has no supply".to_string()));
+    }
+
+    let scale = |value: u128| -> Result<u128, LendingError> {
+        value
+            .checked_mul(pool_lamports as u128)
+            .map(|v| v / lst_supply as u128)
+            .ok_or_else(|| LendingError::ProtocolError("Staked price overflow".to_string()))
+    };
+
+    let price = i64::try_from(scale(sol_price.price.max(0) as u128)?)
+        .map_err(|_| LendingError::ProtocolError("Staked price overflow".to_string()))?;
+    let conf = u64::try_from(scale(sol_price.conf as u128)?)
+        .map_err(|_| LendingError::ProtocolError("Staked price overflow".to_string()))?;
+
+    Ok(OraclePrice { price, conf, ..sol_price })
+}
+
+/// Picks the most recently published price out of the decoded candidates
+pub fn select_price(prices: impl IntoIterator<Item = OraclePrice>) -> Option<OraclePrice> {
+    prices.into_iter().filter(|p| p.price > 0).max_by_key(|p| p.publish_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn market_price_sf_rescales_exponent() {
+        assert_eq!(
+            OraclePrice::new(150_000_000, 0, -8, 0).to_market_price_sf(),
+            Some(1_500_000_000)
+        );
+        assert_eq!(OraclePrice::new(15, 0, 2, 0).to_market_price_sf(), Some(1_500_000_000_000));
+        assert_eq!(OraclePrice::new(1, 0, -12, 0).to_market_price_sf(), Some(0));
+        assert_eq!(OraclePrice::new(-1, 0, -8, 0).to_market_price_sf(), None);
+    }
+
+    #[test]
+    fn from_fixed_keeps_nine_decimals() {
+        let price = OraclePrice::from_fixed(1_234_567_890_123_456_789, 18, 7).unwrap();
+        assert_eq!(price, OraclePrice::new(1_234_567_890, 0, -9, 7));
+    }
+
+    #[test]
+    fn staked_price_scales_by_pool_backing() {
+        let sol = OraclePrice::new(200_000_000, 100_000, -6, 10);
+        let price = staked_price(sol, 110, 100).unwrap();
+        assert_eq!(price, OraclePrice::new(220_000_000, 110_000, -6, 10));
+        assert!(staked_price(sol, 110, 0).is_err());
+    }
+
+    #[test]
+    fn select_price_prefers_latest_publish_time() {
+        let old = OraclePrice::new(100, 0, 0, 1);
+        let new = OraclePrice::new(101, 0, 0, 2);
+        let broken = OraclePrice::new(0, 0, 0, 3);
+        assert_eq!(select_price([old, new, broken]), Some(new));
+    }
+
+    #[test]
+    fn decode_scope_price_chain() {
+        let mut data = vec![0u8; 8 + 32 + 56 * 3];
+        data[..8].copy_from_slice(&[89, 128, 118, 221, 6, 72, 180, 146]);
+        let mut write = |index: usize, value: u64, exp: u64, ts: u64| {
+            let offset = 8 + 32 + index * 56;
+            data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            data[offset + 8..offset + 16].copy_from_slice(&exp.to_le_bytes());
+            data[offset + 24..offset + 32].copy_from_slice(&ts.to_le_bytes());
+        };
+        // jitoSOL/SOL = 1.2, SOL/USD = 150
+        write(1, 12, 1, 100);
+        write(2, 15_000, 2, 90);
+
+        let price = scope::decode_price_chain(&data, &[1, 2, u16::MAX, u16::MAX]).unwrap();
+        assert_eq!(price.to_market_price_sf(), Some(180_000_000_000));
+        assert_eq!(price.publish_time, 90);
+    }
+
+    #[test]
+    fn decode_pyth_legacy_price() {
+        let mut data = vec![0u8; 240];
+        data[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        data[8..12].copy_from_slice(&3u32.to_le_bytes());
+        data[20..24].copy_from_slice(&(-8i32).to_le_bytes());
+        data[96..104].copy_from_slice(&1_700_000_000i64.to_le_bytes());
+        data[208..216].copy_from_slice(&99_990_000i64.to_le_bytes());
+        data[216..224].copy_from_slice(&5_000u64.to_le_bytes());
+
+        data[48..56].copy_from_slice(&99_500_000i64.to_le_bytes());
+        data[72..80].copy_from_slice(&7_000u64.to_le_bytes());
+
+        let price = pyth::decode_price(&data).unwrap();
+        assert_eq!(price, OraclePrice::new(99_990_000, 5_000, -8, 1_700_000_000));
+
+        let ema = pyth::decode_ema_price(&data).unwrap();
+        assert_eq!(ema, OraclePrice::new(99_500_000, 7_000, -8, 1_700_000_000));
+    }
+}
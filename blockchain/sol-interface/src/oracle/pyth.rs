@@ -0,0 +1,177 @@
+use borsh::BorshDeserialize;
+use common::lending::LendingError;
+use solana_sdk::{account::Account, pubkey, pubkey::Pubkey};
+use std::collections::HashMap;
+
+use super::OraclePrice;
+
+pub const PYTH_PUSH_ORACLE_PROGRAM_ID: Pubkey =
+    pubkey!("pythWSnswVUd12oZpeFP8e9CVaEqJg25g1Vtc2biRsT");
+
+// Pyth sponsors shard 0, Marginfi sponsors its own shard for feeds Pyth does not push
+const PYTH_SPONSORED_SHARD_ID: u16 = 0;
+const MARGINFI_SPONSORED_SHARD_ID: u16 = 3301;
+
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+// Legacy price account layout
+const LEGACY_MAGIC: u32 = 0xa1b2c3d4;
+const LEGACY_PRICE_ACCOUNT_TYPE: u32 = 3;
+const LEGACY_EXPO_OFFSET: usize = 20;
+const LEGACY_EMA_PRICE_OFFSET: usize = 48;
+const LEGACY_EMA_CONF_OFFSET: usize = 72;
+const LEGACY_TIMESTAMP_OFFSET: usize = 96;
+const LEGACY_AGG_PRICE_OFFSET: usize = 208;
+const LEGACY_AGG_CONF_OFFSET: usize = 216;
+const LEGACY_ACCOUNT_MIN_LEN: usize = 240;
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationLevel {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceFeedMessage {
+    pub feed_id: [u8; 32],
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub prev_publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+/// `PriceUpdateV2` account written by the Pyth receiver program
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceUpdateV2 {
+    pub write_authority: Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message: PriceFeedMessage,
+    pub posted_slot: u64,
+}
+
+impl From<&PriceUpdateV2> for OraclePrice {
+    fn from(update: &PriceUpdateV2) -> Self {
+        let message = &update.price_message;
+        OraclePrice::new(message.price, message.conf, message.exponent, message.publish_time)
+    }
+}
+
+/// Exponential moving average price of an update
+fn ema_price(update: &PriceUpdateV2) -> OraclePrice {
+    let message = &update.price_message;
+    OraclePrice::new(message.ema_price, message.ema_conf, message.exponent, message.publish_time)
+}
+
+/// Derives the push oracle accounts that may hold updates for a feed id
+pub fn push_oracle_addresses(feed_id: &[u8; 32]) -> [Pubkey; 2] {
+    [PYTH_SPONSORED_SHARD_ID, MARGINFI_SPONSORED_SHARD_ID].map(|shard_id| {
+        Pubkey::find_program_address(
+            &[&shard_id.to_le_bytes(), feed_id],
+            &PYTH_PUSH_ORACLE_PROGRAM_ID,
+        )
+        .0
+    })
+}
+
+pub fn decode_price_update(data: &[u8]) -> Result<PriceUpdateV2, LendingError> {
+    if data.len() < 8 || data[..8] != PRICE_UPDATE_V2_DISCRIMINATOR {
+        return Err(LendingError::DeserializationError(
+            "Not a Pyth PriceUpdateV2 account".to_string(),
+        ));
+    }
+
+    PriceUpdateV2::deserialize(&mut &data[8..]).map_err(|e| {
+        LendingError::DeserializationError(format!("Failed to deserialize PriceUpdateV2: {}", e))
+    })
+}
+
+/// Decodes a Pyth legacy price account using its aggregate price
+pub fn decode_legacy_price(data: &[u8]) -> Result<OraclePrice, LendingError> {
+    decode_legacy(data, LEGACY_AGG_PRICE_OFFSET, LEGACY_AGG_CONF_OFFSET)
+}
+
+fn decode_legacy(
+    data: &[u8],
+    price_offset: usize,
+    conf_offset: usize,
+) -> Result<OraclePrice, LendingError> {
+    if data.len() < LEGACY_ACCOUNT_MIN_LEN
+        || read_u32(data, 0) != LEGACY_MAGIC
+        || read_u32(data, 8) != LEGACY_PRICE_ACCOUNT_TYPE
+    {
+        return Err(LendingError::DeserializationError(
+            "Not a Pyth legacy price account".to_string(),
+        ));
+    }
+
+    Ok(OraclePrice::new(
+        read_u64(data, price_offset) as i64,
+        read_u64(data, conf_offset),
+        read_u32(data, LEGACY_EXPO_OFFSET) as i32,
+        read_u64(data, LEGACY_TIMESTAMP_OFFSET) as i64,
+    ))
+}
+
+/// Decodes either a `PriceUpdateV2` or a legacy price account
+pub fn decode_price(data: &[u8]) -> Result<OraclePrice, LendingError> {
+    match decode_price_update(data) {
+        Ok(update) => Ok(OraclePrice::from(&update)),
+        Err(_) => decode_legacy_price(data),
+    }
+}
+
+/// Decodes the exponential moving average price of a `PriceUpdateV2` or a legacy price account
+pub fn decode_ema_price(data: &[u8]) -> Result<OraclePrice, LendingError> {
+    match decode_price_update(data) {
+        Ok(update) => Ok(ema_price(&update)),
+        Err(_) => decode_legacy(data, LEGACY_EMA_PRICE_OFFSET, LEGACY_EMA_CONF_OFFSET),
+    }
+}
+
+/// Decodes the freshest push oracle update available for a feed id
+pub fn decode_push_feed(
+    feed_id: &[u8; 32],
+    accounts: &HashMap<Pubkey, Account>,
+) -> Result<OraclePrice, LendingError> {
+    decode_push_feed_with(feed_id, accounts, |update| OraclePrice::from(update))
+}
+
+/// Decodes the exponential moving average price of the freshest push oracle update available
+/// for a feed id
+pub fn decode_push_feed_ema(
+    feed_id: &[u8; 32],
+    accounts: &HashMap<Pubkey, Account>,
+) -> Result<OraclePrice, LendingError> {
+    decode_push_feed_with(feed_id, accounts, ema_price)
+}
+
+fn decode_push_feed_with(
+    feed_id: &[u8; 32],
+    accounts: &HashMap<Pubkey, Account>,
+    price: fn(&PriceUpdateV2) -> OraclePrice,
+) -> Result<OraclePrice, LendingError> {
+    push_oracle_addresses(feed_id)
+        .iter()
+        .filter_map(|address| accounts.get(address))
+        .filter_map(|account| decode_price_update(&account.data).ok())
+        .filter(|update| update.price_message.feed_id == *feed_id)
+        .map(|update| price(&update))
+        .max_by_key(|price| price.publish_time)
+        .ok_or_else(|| {
+            LendingError::AccountNotFound(format!(
+                "No Pyth push update for feed {}",
+                Pubkey::new_from_array(*feed_id)
+            ))
+        })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,75 @@
+use common::lending::LendingError;
+
+use super::OraclePrice;
+
+const ORACLE_PRICES_DISCRIMINATOR: [u8; 8] = [89, 128, 118, 221, 6, 72, 180, 146];
+
+// `OraclePrices` holds the oracle mappings pubkey followed by 512 `DatedPrice` entries
+const PRICES_OFFSET: usize = 8 + 32;
+const DATED_PRICE_SIZE: usize = 56;
+pub const MAX_ENTRIES: usize = 512;
+
+/// Marks unused positions in a Kamino `price_chain`
+const CHAIN_END: u16 = u16::MAX;
+
+/// A single entry of the Scope prices account, the price is `value * 10^-exp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatedPrice {
+    pub value: u64,
+    pub exp: u64,
+    pub last_updated_slot: u64,
+    pub unix_timestamp: u64,
+}
+
+pub fn decode_dated_price(data: &[u8], index: u16) -> Result<DatedPrice, LendingError> {
+    if data.len() < 8 || data[..8] != ORACLE_PRICES_DISCRIMINATOR {
+        return Err(LendingError::DeserializationError("Not a Scope prices account".to_string()));
+    }
+
+    let offset = PRICES_OFFSET + index as usize * DATED_PRICE_SIZE;
+    if index as usize >= MAX_ENTRIES || data.len() < offset + 32 {
+        return Err(LendingError::DeserializationError(format!(
+            "Scope price index {} out of range",
+            index
+        )));
+    }
+
+    let read = |at: usize| u64::from_le_bytes(data[offset + at..][..8].try_into().unwrap());
+    Ok(DatedPrice {
+        value: read(0),
+        exp: read(8),
+        last_updated_slot: read(16),
+        unix_timestamp: read(24),
+    })
+}
+
+/// Multiplies the prices of a Kamino scope chain, e.g. `JitoSOL/SOL * SOL/USD`
+pub fn decode_price_chain(data: &[u8], chain: &[u16; 4]) -> Result<OraclePrice, LendingError> {
+    let mut value: u128 = 1;
+    let mut exp: u32 = 0;
+    let mut publish_time = i64::MAX;
+    let mut entries = 0;
+
+    for &index in chain.iter().take_while(|&&index| index != CHAIN_END) {
+        let price = decode_dated_price(data, index)?;
+        value = value
+            .checked_mul(price.value as u128)
+            .ok_or_else(|| LendingError::ProtocolError("Scope price overflow".to_string()))?;
+        exp += price.exp as u32;
+        publish_time = publish_time.min(price.unix_timestamp as i64);
+        entries += 1;
+
+        // Drop precision to keep the running product small
+        while value > u64::MAX as u128 && exp > 0 {
+            value /= 10;
+            exp -= 1;
+        }
+    }
+
+    if entries == 0 {
+        return Err(LendingError::ProtocolError("Empty scope price chain".to_string()));
+    }
+
+    OraclePrice::from_fixed(value, exp, publish_time)
+        .ok_or_else(|| LendingError::ProtocolError("Scope price overflow".to_string()))
+}
@@ -0,0 +1,64 @@
+use common::lending::LendingError;
+
+use super::OraclePrice;
+
+const PULL_FEED_DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+
+// `PullFeedAccountData` offsets, including the discriminator. The feed holds 32 oracle
+// submissions of 64 bytes followed by its config and the aggregated `CurrentResult`.
+const LAST_UPDATE_TIMESTAMP_OFFSET: usize = 8 + 2208;
+const RESULT_OFFSET: usize = 8 + 2256;
+const RESULT_VALUE_OFFSET: usize = RESULT_OFFSET;
+const RESULT_STD_DEV_OFFSET: usize = RESULT_OFFSET + 16;
+const RESULT_SLOT_OFFSET: usize = RESULT_OFFSET + 104;
+
+/// Switchboard on-demand values are fixed point with 18 decimals
+const PULL_FEED_DECIMALS: u32 = 18;
+
+/// Aggregated result of a Switchboard on-demand pull feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PullFeedResult {
+    pub value: i128,
+    pub std_dev: i128,
+    pub slot: u64,
+    pub last_update_timestamp: i64,
+}
+
+pub fn decode_pull_feed_result(data: &[u8]) -> Result<PullFeedResult, LendingError> {
+    if data.len() < RESULT_SLOT_OFFSET + 8 || data[..8] != PULL_FEED_DISCRIMINATOR {
+        return Err(LendingError::DeserializationError(
+            "Not a Switchboard pull feed account".to_string(),
+        ));
+    }
+
+    Ok(PullFeedResult {
+        value: i128::from_le_bytes(data[RESULT_VALUE_OFFSET..][..16].try_into().unwrap()),
+        std_dev: i128::from_le_bytes(data[RESULT_STD_DEV_OFFSET..][..16].try_into().unwrap()),
+        slot: u64::from_le_bytes(data[RESULT_SLOT_OFFSET..][..8].try_into().unwrap()),
+        last_update_timestamp: i64::from_le_bytes(
+            data[LAST_UPDATE_TIMESTAMP_OFFSET..][..8].try_into().unwrap(),
+        ),
+    })
+}
+
+pub fn decode_pull_feed(data: &[u8]) -> Result<OraclePrice, LendingError> {
+    let result = decode_pull_feed_result(data)?;
+    if result.value <= 0 {
+        return Err(LendingError::ProtocolError(
+            "Switchboard pull feed has no positive value".to_string(),
+        ));
+    }
+
+    let mut price = OraclePrice::from_fixed(
+        result.value as u128,
+        PULL_FEED_DECIMALS,
+        result.last_update_timestamp,
+    )
+    .ok_or_else(|| LendingError::ProtocolError("Switchboard price overflow".to_string()))?;
+
+    // Express the standard deviation with the same exponent as the price
+    let std_dev = result.std_dev.unsigned_abs() / 10u128.pow(PULL_FEED_DECIMALS - 9);
+    price.conf = u64::try_from(std_dev).unwrap_or(u64::MAX);
+
+    Ok(price)
+}
@@ -0,0 +1,89 @@
+use crate::oracle::OracleFeed;
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashMap, str::FromStr};
+
+/// Comma separated `mint=feed_id` entries pricing reward tokens that no reserve lists, such as
+/// KMNO, `feed_id` being the hex id of the token's Pyth push feed
+pub const REWARD_PRICE_FEEDS_ENV: &str = "REWARD_PRICE_FEEDS";
+
+/// Oracle feeds of reward tokens, used alongside the oracles of the reserves listing them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RewardPriceConfig {
+    pub feeds: HashMap<Pubkey, OracleFeed>,
+}
+
+impl RewardPriceConfig {
+    /// Reads the configuration from the environment, ignoring malformed entries
+    pub fn from_env() -> Self {
+        let feeds = std::env::var(REWARD_PRICE_FEEDS_ENV)
+            .map(|value| parse_feeds(&value))
+            .unwrap_or_default();
+
+        Self { feeds }
+    }
+}
+
+fn parse_feeds(value: &str) -> HashMap<Pubkey, OracleFeed> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(mint, feed_id)| {
+                Some((Pubkey::from_str(mint.trim()).ok()?, parse_feed_id(feed_id.trim())?))
+            });
+            if parsed.is_none() {
+                warn!(
+                    "Ignoring invalid reward price feed {:?} in {}",
+                    entry, REWARD_PRICE_FEEDS_ENV
+                );
+            }
+            parsed.map(|(mint, feed_id)| (mint, OracleFeed::PythPushFeed(feed_id)))
+        })
+        .collect()
+}
+
+/// Decodes a 32 byte Pyth feed id written in hex, with or without a `0x` prefix
+fn parse_feed_id(value: &str) -> Option<[u8; 32]> {
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut feed_id = [0u8; 32];
+    for (byte, pair) in feed_id.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(feed_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_feed_entries() {
+        let mint = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let feed_id = format!("0x{}", "ab".repeat(32));
+
+        let feeds = parse_feeds(&format!(
+            "{}={}, {}={} ,not-a-key={},{}=0x12",
+            mint,
+            feed_id,
+            other,
+            "01".repeat(32),
+            feed_id,
+            other
+        ));
+        assert_eq!(
+            feeds,
+            HashMap::from([
+                (mint, OracleFeed::PythPushFeed([0xab; 32])),
+                (other, OracleFeed::PythPushFeed([0x01; 32])),
+            ])
+        );
+        assert_eq!(parse_feed_id(&"zz".repeat(32)), None);
+    }
+}
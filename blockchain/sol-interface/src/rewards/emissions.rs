@@ -0,0 +1,53 @@
+use fixed::types::I80F48;
+
+use super::RewardEmission;
+use crate::marginfi::{
+    models::group::Bank,
+    utils::constants::{EMISSIONS_FLAG_BORROW_ACTIVE, EMISSIONS_FLAG_LENDING_ACTIVE},
+};
+
+/// Tokens paid per year to the lenders of a Marginfi bank, `emission_decimals` being the decimals
+/// of the bank's `emissions_mint`
+pub fn marginfi_lending_emission(bank: &Bank, emission_decimals: u8) -> Option<RewardEmission> {
+    marginfi_emission(
+        bank,
+        EMISSIONS_FLAG_LENDING_ACTIVE,
+        bank.get_total_supply().ok()?,
+        emission_decimals,
+    )
+}
+
+/// Tokens paid per year to the borrowers of a Marginfi bank, `emission_decimals` being the
+/// decimals of the bank's `emissions_mint`
+pub fn marginfi_borrowing_emission(bank: &Bank, emission_decimals: u8) -> Option<RewardEmission> {
+    marginfi_emission(
+        bank,
+        EMISSIONS_FLAG_BORROW_ACTIVE,
+        bank.get_total_borrowed().ok()?,
+        emission_decimals,
+    )
+}
+
+fn marginfi_emission(
+    bank: &Bank,
+    flag: u64,
+    total_amount: I80F48,
+    emission_decimals: u8,
+) -> Option<RewardEmission> {
+    if !bank.get_flag(flag)
+        || bank.emissions_rate == 0
+        || I80F48::from(bank.emissions_remaining) <= I80F48::ZERO
+    {
+        return None;
+    }
+
+    // `emissions_rate` is paid per whole bank token per year, scale it by the supplied or
+    // borrowed tokens
+    let total_amount = total_amount.to_num::<f64>() / 10f64.powi(bank.mint_decimals as i32);
+    let tokens_per_whole_token = bank.emissions_rate as f64 / 10f64.powi(emission_decimals as i32);
+
+    Some(RewardEmission {
+        mint: bank.emissions_mint,
+        tokens_per_year: tokens_per_whole_token * total_amount,
+    })
+}
@@ -0,0 +1,146 @@
+use common::lending::LendingError;
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+use super::RewardEmission;
+use crate::kamino::utils::consts::SLOTS_PER_YEAR;
+
+pub const FARMS_PROGRAM_ID: Pubkey = pubkey!("FarmsPZpWu9i7Kky8tPN37rs2TpmMrAZrC7S7vJa91Hr");
+
+const FARM_STATE_DISCRIMINATOR: [u8; 8] = [198, 102, 216, 74, 63, 66, 163, 190];
+
+// `FarmState` starts with the admin, the global config and the staked `TokenInfo`, followed by
+// the fixed array of `RewardInfo` entries
+const REWARD_INFOS_OFFSET: usize = 8 + 32 + 32 + 120;
+const REWARD_INFO_SIZE: usize = 704;
+pub const MAX_REWARDS_TOKENS: usize = 10;
+const REWARD_CURVE_POINTS: usize = 20;
+
+// Fields of `RewardInfo`, relative to the start of the entry
+const REWARD_DECIMALS_OFFSET: usize = 32;
+const REWARDS_AVAILABLE_OFFSET: usize = 120 + 32;
+const REWARD_CURVE_OFFSET: usize = REWARDS_AVAILABLE_OFFSET + 8;
+const REWARDS_PER_SECOND_DECIMALS_OFFSET: usize =
+    REWARD_CURVE_OFFSET + REWARD_CURVE_POINTS * 16 + 57;
+
+// Fields following the reward infos: num_reward_tokens, num_users, total_staked_amount,
+// farm_vault, farm_vaults_authority, farm_vaults_authority_bump, delegate_authority, time_unit
+const TOTAL_STAKED_OFFSET: usize = REWARD_INFOS_OFFSET + MAX_REWARDS_TOKENS * REWARD_INFO_SIZE + 16;
+const TIME_UNIT_OFFSET: usize = TOTAL_STAKED_OFFSET + 8 + 32 + 32 + 8 + 32;
+
+const SECONDS_PER_YEAR: f64 = 31_536_000.0;
+
+/// Clock used by a farm's reward schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Slots,
+}
+
+/// A reward token distributed by a Kamino farm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FarmReward {
+    pub mint: Pubkey,
+    pub decimals: u64,
+    pub rewards_available: u64,
+    /// `(ts_start, reward_per_time_unit)` points, the rate applies from `ts_start` onwards
+    pub schedule: Vec<(u64, u64)>,
+    pub rewards_per_second_decimals: u8,
+}
+
+impl FarmReward {
+    /// Reward tokens, in native units, paid per time unit at `now`
+    pub fn reward_per_time_unit(&self, now: u64) -> f64 {
+        if self.rewards_available == 0 {
+            return 0.0;
+        }
+
+        let rate = self
+            .schedule
+            .iter()
+            .filter(|(ts_start, _)| *ts_start <= now)
+            .max_by_key(|(ts_start, _)| *ts_start)
+            .map_or(0, |(_, rate)| *rate);
+
+        rate as f64 / 10f64.powi(self.rewards_per_second_decimals as i32)
+    }
+}
+
+/// The parts of a Kamino `FarmState` needed to value its rewards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FarmState {
+    pub rewards: Vec<FarmReward>,
+    /// Staked amount, collateral tokens for reserve collateral farms and borrowed liquidity in
+    /// native units for reserve debt farms
+    pub total_staked_amount: u64,
+    pub time_unit: TimeUnit,
+}
+
+impl FarmState {
+    /// Reward tokens paid per year by the farm, at the rates active at `now_ts` / `now_slot`
+    pub fn emissions(&self, now_ts: u64, now_slot: u64) -> Vec<RewardEmission> {
+        let (now, units_per_year) = match self.time_unit {
+            TimeUnit::Seconds => (now_ts, SECONDS_PER_YEAR),
+            TimeUnit::Slots => (now_slot, SLOTS_PER_YEAR as f64),
+        };
+
+        self.rewards
+            .iter()
+            .map(|reward| RewardEmission {
+                mint: reward.mint,
+                tokens_per_year: reward.reward_per_time_unit(now) * units_per_year
+                    / 10f64.powi(reward.decimals as i32),
+            })
+            .filter(|emission| emission.tokens_per_year > 0.0)
+            .collect()
+    }
+}
+
+pub fn decode_farm_state(data: &[u8]) -> Result<FarmState, LendingError> {
+    if data.len() < 8 || data[..8] != FARM_STATE_DISCRIMINATOR {
+        return Err(LendingError::DeserializationError("Not a Kamino farm account".to_string()));
+    }
+    if data.len() <= TIME_UNIT_OFFSET {
+        return Err(LendingError::DeserializationError("Farm account data too short".to_string()));
+    }
+
+    let read = |at: usize| u64::from_le_bytes(data[at..][..8].try_into().unwrap());
+
+    let rewards = (0..MAX_REWARDS_TOKENS)
+        .map(|index| REWARD_INFOS_OFFSET + index * REWARD_INFO_SIZE)
+        .filter_map(|offset| {
+            let mint = Pubkey::try_from(&data[offset..offset + 32]).ok()?;
+            if mint == Pubkey::default() {
+                return None;
+            }
+
+            let schedule = (0..REWARD_CURVE_POINTS)
+                .map(|point| {
+                    let at = offset + REWARD_CURVE_OFFSET + point * 16;
+                    (read(at), read(at + 8))
+                })
+                .filter(|(ts_start, _)| *ts_start != u64::MAX)
+                .collect();
+
+            Some(FarmReward {
+                mint,
+                decimals: read(offset + REWARD_DECIMALS_OFFSET),
+                rewards_available: read(offset + REWARDS_AVAILABLE_OFFSET),
+                schedule,
+                rewards_per_second_decimals: data[offset + REWARDS_PER_SECOND_DECIMALS_OFFSET],
+            })
+        })
+        .collect();
+
+    let time_unit = match data[TIME_UNIT_OFFSET] {
+        0 => TimeUnit::Seconds,
+        1 => TimeUnit::Slots,
+        unit => {
+            return Err(LendingError::DeserializationError(format!(
+                "Unknown farm time unit {}",
+                unit
+            )))
+        }
+    };
+
+    Ok(FarmState { rewards, total_staked_amount: read(TOTAL_STAKED_OFFSET), time_unit })
+}
@@ -0,0 +1,86 @@
+pub mod config;
+pub mod emissions;
+pub mod farms;
+
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+
+/// A stream of reward tokens paid to the suppliers or borrowers of a reserve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RewardEmission {
+    pub mint: Pubkey,
+    /// Reward tokens paid per year, in whole tokens
+    pub tokens_per_year: f64,
+}
+
+/// Yearly value of the emissions relative to the value they are paid on.
+///
+/// Rewards are not compounded so this is also their APR. None when a token of the emissions has
+/// no price in `prices` (USD per whole token), the value of the rewards is then unknown.
+pub fn reward_apy(
+    emissions: &[RewardEmission],
+    prices: &HashMap<Pubkey, f64>,
+    staked_value: f64,
+) -> Option<f64> {
+    if staked_value <= 0.0 {
+        return Some(0.0);
+    }
+
+    let rewards_value: f64 = emissions
+        .iter()
+        .map(|emission| Some(emission.tokens_per_year * prices.get(&emission.mint)?))
+        .sum::<Option<f64>>()?;
+
+    Some(rewards_value / staked_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{farms::*, *};
+
+    #[test]
+    fn reward_apy_is_unknown_with_unpriced_tokens() {
+        let priced = Pubkey::new_unique();
+        let unpriced = RewardEmission { mint: Pubkey::new_unique(), tokens_per_year: 1_000.0 };
+        let emissions = [RewardEmission { mint: priced, tokens_per_year: 1_000.0 }, unpriced];
+        let prices = HashMap::from([(priced, 0.5)]);
+
+        assert_eq!(reward_apy(&emissions[..1], &prices, 10_000.0), Some(0.05));
+        assert_eq!(reward_apy(&emissions, &prices, 10_000.0), None);
+        assert_eq!(reward_apy(&emissions, &prices, 0.0), Some(0.0));
+        assert_eq!(reward_apy(&[], &prices, 10_000.0), Some(0.0));
+    }
+
+    #[test]
+    fn decode_farm_state_rewards() {
+        let mut data = vec![0u8; 8 + 184 + 10 * 704 + 16 + 8 + 32 + 32 + 8 + 32 + 8];
+        data[..8].copy_from_slice(&[198, 102, 216, 74, 63, 66, 163, 190]);
+        let mut write =
+            |at: usize, value: u64| data[at..at + 8].copy_from_slice(&value.to_le_bytes());
+
+        // Second reward slot: 6 decimals, 0.5 tokens per second from ts 100, 2 per second from 200
+        let reward = 8 + 184 + 704;
+        write(reward + 32, 6);
+        write(reward + 152, 1_000);
+        write(reward + 160, 100);
+        write(reward + 168, 500_000);
+        write(reward + 176, 200);
+        write(reward + 184, 2_000_000);
+        for point in 2..20 {
+            write(reward + 160 + point * 16, u64::MAX);
+        }
+        write(8 + 184 + 10 * 704 + 16, 42);
+
+        let mint = Pubkey::new_unique();
+        data[reward..reward + 32].copy_from_slice(mint.as_ref());
+
+        let farm = decode_farm_state(&data).unwrap();
+        assert_eq!(farm.total_staked_amount, 42);
+        assert_eq!(farm.time_unit, TimeUnit::Seconds);
+        assert_eq!(farm.rewards.len(), 1);
+        assert_eq!(farm.rewards[0].mint, mint);
+        assert_eq!(farm.rewards[0].reward_per_time_unit(50), 0.0);
+        assert_eq!(farm.rewards[0].reward_per_time_unit(150), 500_000.0);
+        assert_eq!(farm.emissions(250, 0)[0].tokens_per_year, 2.0 * 31_536_000.0);
+    }
+}
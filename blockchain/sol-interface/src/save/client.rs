@@ -1,23 +1,38 @@
-use crate::common::rpc_utils::{
-    format_pubkey_for_error, with_pooled_client, LendingErrorConverter,
-};
-use crate::save::models::{Obligation, Reserve};
+use crate::common::rpc_utils::{create_rpc_client, format_pubkey_for_error, LendingErrorConverter};
+use crate::oracle::{feeds::save_feeds, pyth, select_price, OracleFeed, OraclePrice};
+use crate::save::math::{Decimal, WAD};
+use crate::save::models::{LendingMarket, LendingMarketMetadata, Obligation, RateLimiter, Reserve};
 use common::{
     asset_utils::get_symbol_for_mint,
     lending::{LendingClient, LendingError},
-    ObligationType, UserObligation,
+    ObligationHealth, ObligationType, UserObligation, WithdrawLimit,
 };
-use common_rpc::SolanaRpcBuilder;
-use log::debug;
+use common_rpc::{RpcErrorConverter, SolanaRpcBuilder};
+use futures::try_join;
+use log::{debug, info, warn};
 use solana_program::program_pack::Pack;
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{clock::Clock, pubkey::Pubkey};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
+/// Seed of the lending market metadata PDA, after the lending market address
+const LENDING_MARKET_METADATA_SEED: &[u8] = b"MetaData";
+
+fn accrual_error(reserve_pubkey: &Pubkey, error: impl std::fmt::Display) -> LendingError {
+    LendingError::ProtocolError(format!(
+        "Failed to accrue interest of reserve {}: {}",
+        format_pubkey_for_error(reserve_pubkey),
+        error
+    ))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SolendPool {
     pub name: String,
     pub pubkey: Pubkey,
-    pub reserves: Vec<Reserve>,
+    /// Market-wide outflow limiter, measured in USD
+    pub rate_limiter: RateLimiter,
+    pub reserves: Vec<(Pubkey, Reserve)>,
 }
 
 pub struct SaveClient {
@@ -41,55 +56,51 @@ impl SaveClient {
         let program_id = Pubkey::from_str("So1endDq2YkqhipRh3WViPa8hdiSpxWy6z3Z6tMCpAo")
             .expect("Invalid Solend Program ID");
 
-        let pools = vec![
-            SolendPool {
-                name: "Main Pool".to_string(),
-                pubkey: "4UpD2fh7xH3VP9QQaXtsS1YY3bxzWhtfpks7FatyKvdY".parse().unwrap(),
-                reserves: Vec::new(),
-            },
-            SolendPool {
-                name: "JLP Pool".to_string(),
-                pubkey: "7XttJ7hp83u5euzT7ybC5zsjdgKA4WPbQHVS27CATAJH".parse().unwrap(),
-                reserves: Vec::new(),
-            },
-            SolendPool {
-                name: "JLP/SOL/USDC Pool".to_string(),
-                pubkey: "ErM46rCeAtGtEKjvZ3tuGrzL6L5nVq6pFuXukocbKqGX".parse().unwrap(),
-                reserves: Vec::new(),
-            },
-            SolendPool {
-                name: "Turbo SOL Pool".to_string(),
-                pubkey: "7RCz8wb6WXxUhAigok9ttgrVgDFFFbibcirECzWSBauM".parse().unwrap(),
-                reserves: Vec::new(),
-            },
-        ];
-
-        Self { program_id, rpc_url: rpc_url.to_string(), pools }
+        Self { program_id, rpc_url: rpc_url.to_string(), pools: Vec::new() }
     }
 
     /// Updates the client's state with the fetched market data
-    pub fn set_market_data(&mut self, reserves_data: Vec<(Pubkey, Vec<Reserve>)>) {
-        for (pubkey, reserves) in reserves_data {
-            if let Some(pool) = self.pools.iter_mut().find(|p| p.pubkey == pubkey) {
-                pool.reserves = reserves;
+    pub fn set_market_data(&mut self, pools: Vec<SolendPool>) {
+        self.pools = pools;
+    }
+
+    /// Accrue the interest of the loaded reserves up to the slot of the clock
+    pub fn accrue_interest(&mut self, clock: &Clock) {
+        for pool in &mut self.pools {
+            for (pubkey, reserve) in &mut pool.reserves {
+                if let Err(e) = reserve.accrue_interest(clock.slot) {
+                    warn!("{}", accrual_error(pubkey, e));
+                }
             }
         }
     }
 
-    pub fn load_reserves_for_pool(&self, pool: &SolendPool) -> Result<Vec<Reserve>, LendingError> {
+    pub async fn load_reserves_for_pool(
+        &self,
+        pool: &SolendPool,
+    ) -> Result<Vec<Reserve>, LendingError> {
+        let reserves = self.fetch_market_reserves(&pool.pubkey).await?;
+        Ok(reserves.into_iter().map(|(_, reserve)| reserve).collect())
+    }
+
+    /// Reserves of a lending market along with their addresses
+    pub async fn fetch_market_reserves(
+        &self,
+        market: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Reserve)>, LendingError> {
         // Use the RPC builder with optimized filters
-        let reserves = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_data_size(Reserve::LEN as u64)
-                .with_memcmp_base58(10, pool.pubkey.to_string())
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let reserves = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(Reserve::LEN as u64)
+            .with_memcmp_base58(10, market.to_string())
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
 
         let reserves = reserves
             .into_iter()
             .filter_map(|(pubkey, account)| match Reserve::unpack(&account.data) {
-                Ok(reserve) => Some(reserve),
+                Ok(reserve) => Some((pubkey, reserve)),
                 Err(e) => {
                     debug!("Failed to unpack reserve {}: {}", format_pubkey_for_error(&pubkey), e);
                     None
@@ -100,50 +111,161 @@ impl SaveClient {
         Ok(reserves)
     }
 
-    pub fn fetch_reserves(&self) -> Result<Vec<(Pubkey, Vec<Reserve>)>, LendingError> {
-        // Create a vector to hold all the reserves we'll load
-        let mut all_reserves = Vec::with_capacity(self.pools.len());
+    /// Discovers every lending market of the program along with its reserves. Markets without
+    /// any supplied liquidity are skipped.
+    pub async fn fetch_pools(&self) -> Result<Vec<SolendPool>, LendingError> {
+        // A single scan of every reserve is cheaper than one filtered scan per market
+        let client = create_rpc_client(&self.rpc_url);
+        let reserve_scan = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(Reserve::LEN as u64)
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>();
 
-        // Load all reserves without modifying self.pools
-        for pool in &self.pools {
-            let reserves = self.load_reserves_for_pool(pool)?;
-            all_reserves.push((pool.pubkey, reserves));
+        let (mut markets, accounts) = try_join!(self.discover_lending_markets(), reserve_scan)?;
+        info!("Discovered {} Save lending markets", markets.len());
+
+        let mut reserves_by_market: HashMap<Pubkey, Vec<(Pubkey, Reserve)>> = HashMap::new();
+        for (pubkey, account) in accounts {
+            match Reserve::unpack(&account.data) {
+                Ok(reserve) => reserves_by_market
+                    .entry(reserve.lending_market)
+                    .or_default()
+                    .push((pubkey, reserve)),
+                Err(e) => {
+                    debug!("Failed to unpack reserve {}: {}", format_pubkey_for_error(&pubkey), e);
+                }
+            }
         }
 
-        Ok(all_reserves)
+        let active_markets: Vec<Pubkey> = markets
+            .keys()
+            .copied()
+            .filter(|market| {
+                reserves_by_market.get(market).is_some_and(|reserves| {
+                    reserves.iter().any(|(_, reserve)| reserve.collateral.mint_total_supply > 0)
+                })
+            })
+            .collect();
+
+        let mut names = self.fetch_market_names(&active_markets).await?;
+
+        Ok(active_markets
+            .into_iter()
+            .map(|pubkey| SolendPool {
+                name: names.remove(&pubkey).unwrap_or_else(|| pubkey.to_string()),
+                pubkey,
+                rate_limiter: markets.remove(&pubkey).unwrap_or_default().rate_limiter,
+                reserves: reserves_by_market.remove(&pubkey).unwrap_or_default(),
+            })
+            .collect())
     }
 
-    pub fn load_reserves(&mut self) -> Result<(), LendingError> {
-        // Fetch reserves using the new method
-        let all_reserves = self.fetch_reserves()?;
+    pub async fn load_reserves(&mut self) -> Result<(), LendingError> {
+        self.pools = self.fetch_pools().await?;
+        Ok(())
+    }
 
-        // Update the pools with the loaded reserves
-        for pool in &mut self.pools {
-            if let Some((_, reserves)) =
-                all_reserves.iter().find(|(pubkey, _)| *pubkey == pool.pubkey)
-            {
-                pool.reserves = reserves.clone();
+    /// Every lending market owned by the program, keyed by address
+    async fn discover_lending_markets(
+        &self,
+    ) -> Result<HashMap<Pubkey, LendingMarket>, LendingError> {
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(LendingMarket::LEN as u64)
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
+
+        Ok(accounts
+            .into_iter()
+            .filter_map(|(pubkey, account)| {
+                LendingMarket::unpack(&account.data).ok().map(|market| (pubkey, market))
+            })
+            .collect())
+    }
+
+    /// Names of the given lending markets, read from their metadata accounts. Markets without
+    /// metadata are left out.
+    async fn fetch_market_names(
+        &self,
+        markets: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, String>, LendingError> {
+        let metadata_pubkeys: Vec<Pubkey> =
+            markets.iter().map(|market| self.market_metadata_address(market)).collect();
+
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, &metadata_pubkeys)
+        .await?;
+
+        Ok(markets
+            .iter()
+            .zip(&metadata_pubkeys)
+            .filter_map(|(market, metadata_pubkey)| {
+                let account = accounts.get(metadata_pubkey)?;
+                let name = decode_market_name(&account.data)?;
+                Some((*market, name))
+            })
+            .collect())
+    }
+
+    fn market_metadata_address(&self, market: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[market.as_ref(), LENDING_MARKET_METADATA_SEED],
+            &self.program_id,
+        )
+        .0
+    }
+
+    /// Pool name of each lending market the obligations belong to, from the loaded pools or the
+    /// market metadata when the pool isn't loaded
+    async fn obligation_market_names(
+        &self,
+        obligations: &[(Pubkey, Obligation)],
+    ) -> Result<HashMap<Pubkey, String>, LendingError> {
+        let mut names: HashMap<Pubkey, String> = HashMap::new();
+        let mut missing = Vec::new();
+
+        for (_, obligation) in obligations {
+            let market = obligation.lending_market;
+            if names.contains_key(&market) || missing.contains(&market) {
+                continue;
+            }
+
+            match self.pools.iter().find(|pool| pool.pubkey == market) {
+                Some(pool) => {
+                    names.insert(market, pool.name.clone());
+                }
+                None => missing.push(market),
             }
         }
 
-        Ok(())
+        if !missing.is_empty() {
+            let mut fetched = self.fetch_market_names(&missing).await?;
+            for market in missing {
+                let name = fetched.remove(&market).unwrap_or_else(|| market.to_string());
+                names.insert(market, name);
+            }
+        }
+
+        Ok(names)
     }
 
-    pub fn get_user_obligations(
+    pub async fn get_user_obligations(
         &self,
         owner_pubkey: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        let obligations = self.fetch_raw_obligations(owner_pubkey)?;
+        let obligations = self.fetch_raw_obligations(owner_pubkey).await?;
 
         // Pre-allocate with estimated capacity (deposits + borrows per obligation)
         let estimated_capacity =
-            obligations.iter().map(|obl| obl.deposits.len() + obl.borrows.len()).sum();
+            obligations.iter().map(|(_, obl)| obl.deposits.len() + obl.borrows.len()).sum();
         let mut user_obligations = Vec::with_capacity(estimated_capacity);
 
         // Collect all reserve pubkeys first
         let mut reserve_pubkeys = Vec::with_capacity(estimated_capacity);
 
-        for obligation in &obligations {
+        for (_, obligation) in &obligations {
             // Add deposit reserves
             for deposit in &obligation.deposits {
                 reserve_pubkeys.push(Pubkey::new_from_array(deposit.deposit_reserve.to_bytes()));
@@ -155,35 +277,42 @@ impl SaveClient {
             }
         }
 
-        // Fetch all reserves in a single batch operation
-        let reserves = with_pooled_client(&self.rpc_url, |client| {
-            common_rpc::get_multiple_accounts_with_conversion::<LendingError, LendingErrorConverter>(
-                client,
-                &reserve_pubkeys,
-            )
-        })?;
+        // Fetch all reserves in a single batch operation along with the clock they are current
+        // as of, and the market names
+        let client = create_rpc_client(&self.rpc_url);
+        let ((reserves, clock), market_names) = try_join!(
+            async {
+                common_rpc::get_multiple_accounts_with_clock(&client, &reserve_pubkeys)
+                    .await
+                    .map_err(LendingErrorConverter::convert_error)
+            },
+            self.obligation_market_names(&obligations)
+        )?;
 
         // Cache protocol name to avoid repeated allocations
         let protocol_name = self.protocol_name().to_string();
 
-        // Default market name
-        let default_market_name = "Main Pool".to_string();
+        // Now process obligations with all reserve data available, accrued to the current slot
+        for (obligation_pubkey, obligation) in obligations {
+            let market_name = &market_names[&obligation.lending_market];
+            let account = obligation_pubkey.to_string();
 
-        // Now process obligations with all reserve data available
-        for obligation in obligations {
             // Process deposits
             for deposit in obligation.deposits {
                 let deposit_reserve_pubkey =
                     Pubkey::new_from_array(deposit.deposit_reserve.to_bytes());
 
                 if let Some(reserve_account) = reserves.get(&deposit_reserve_pubkey) {
-                    let reserve = Reserve::unpack(&reserve_account.data).map_err(|e| {
+                    let mut reserve = Reserve::unpack(&reserve_account.data).map_err(|e| {
                         LendingError::DeserializationError(format!(
                             "Failed to unpack reserve {}: {}",
                             format_pubkey_for_error(&deposit_reserve_pubkey),
                             e
                         ))
                     })?;
+                    reserve
+                        .accrue_interest(clock.slot)
+                        .map_err(|e| accrual_error(&deposit_reserve_pubkey, e))?;
 
                     let exchange_rate = reserve.collateral_exchange_rate().map_err(|e| {
                         LendingError::ProtocolError(format!(
@@ -209,25 +338,36 @@ impl SaveClient {
                         mint_decimals: reserve.liquidity.mint_decimals as u32,
                         amount,
                         protocol_name: protocol_name.clone(), // Clone the cached value
-                        market_name: default_market_name.clone(),
+                        market_name: market_name.clone(),
+                        account: account.clone(),
+                        sub_account: None,
+                        reserve_pubkey: deposit_reserve_pubkey.to_string(),
                         obligation_type: ObligationType::Asset,
                     });
                 }
             }
 
             // Process borrows
-            for borrow in obligation.borrows {
+            for mut borrow in obligation.borrows {
                 let borrow_reserve_pubkey =
                     Pubkey::new_from_array(borrow.borrow_reserve.to_bytes());
 
                 if let Some(reserve_account) = reserves.get(&borrow_reserve_pubkey) {
-                    let reserve = Reserve::unpack(&reserve_account.data).map_err(|e| {
+                    let mut reserve = Reserve::unpack(&reserve_account.data).map_err(|e| {
                         LendingError::DeserializationError(format!(
                             "Failed to unpack reserve {}: {}",
                             format_pubkey_for_error(&borrow_reserve_pubkey),
                             e
                         ))
                     })?;
+                    reserve
+                        .accrue_interest(clock.slot)
+                        .map_err(|e| accrual_error(&borrow_reserve_pubkey, e))?;
+
+                    // Compound the debt from the obligation's last refresh to the current slot
+                    borrow
+                        .accrue_interest(reserve.liquidity.cumulative_borrow_rate_wads)
+                        .map_err(|e| accrual_error(&borrow_reserve_pubkey, e))?;
 
                     // Use the mint pubkey once
                     let mint_str = reserve.liquidity.mint_pubkey.to_string();
@@ -241,7 +381,10 @@ impl SaveClient {
                         mint_decimals: reserve.liquidity.mint_decimals as u32,
                         amount: borrow.borrowed_amount_wads.try_round_u64().unwrap_or(0),
                         protocol_name: protocol_name.clone(), // Clone the cached value
-                        market_name: default_market_name.clone(),
+                        market_name: market_name.clone(),
+                        account: account.clone(),
+                        sub_account: None,
+                        reserve_pubkey: borrow_reserve_pubkey.to_string(),
                         obligation_type: ObligationType::Liability,
                     });
                 }
@@ -251,20 +394,211 @@ impl SaveClient {
         Ok(user_obligations)
     }
 
-    fn fetch_raw_obligations(&self, owner_pubkey: &str) -> Result<Vec<Obligation>, LendingError> {
+    /// Risk view of each obligation owned by the wallet. Reserves are accrued to the current slot
+    /// and their prices refreshed from their oracles, and the obligation values recomputed, so
+    /// the numbers don't depend on when the obligation was last refreshed on-chain.
+    pub async fn get_obligation_health(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<ObligationHealth>, LendingError> {
+        let obligations = self.fetch_raw_obligations(owner_pubkey).await?;
+        if obligations.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let reserve_pubkeys: Vec<Pubkey> = obligations
+            .iter()
+            .flat_map(|(_, obligation)| {
+                obligation
+                    .deposits
+                    .iter()
+                    .map(|deposit| deposit.deposit_reserve)
+                    .chain(obligation.borrows.iter().map(|borrow| borrow.borrow_reserve))
+            })
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let (reserves, market_names) = try_join!(
+            self.fetch_refreshed_reserves(&reserve_pubkeys),
+            self.obligation_market_names(&obligations)
+        )?;
+
+        obligations
+            .into_iter()
+            .filter(|(_, obligation)| !obligation.deposits.is_empty())
+            .map(|(pubkey, mut obligation)| {
+                obligation.refresh(&reserves).map_err(|e| {
+                    LendingError::ProtocolError(format!(
+                        "Failed to refresh obligation {}: {}",
+                        format_pubkey_for_error(&pubkey),
+                        e
+                    ))
+                })?;
+
+                let market_name = market_names[&obligation.lending_market].clone();
+                self.obligation_health(&pubkey, &obligation, &reserves, market_name)
+            })
+            .collect()
+    }
+
+    /// Loads the given reserves, accrues their interest to the slot they were read at and replaces
+    /// their cached market and smoothed prices with the latest oracle prices, as the program's
+    /// reserve refresh does
+    async fn fetch_refreshed_reserves(
+        &self,
+        reserve_pubkeys: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, Reserve>, LendingError> {
+        let client = create_rpc_client(&self.rpc_url);
+        let (accounts, clock) =
+            common_rpc::get_multiple_accounts_with_clock(&client, reserve_pubkeys)
+                .await
+                .map_err(LendingErrorConverter::convert_error)?;
+
+        let mut reserves = HashMap::with_capacity(accounts.len());
+        for (pubkey, account) in accounts {
+            let mut reserve = Reserve::unpack(&account.data).map_err(|e| {
+                LendingError::DeserializationError(format!(
+                    "Failed to unpack reserve {}: {}",
+                    format_pubkey_for_error(&pubkey),
+                    e
+                ))
+            })?;
+            reserve.accrue_interest(clock.slot).map_err(|e| accrual_error(&pubkey, e))?;
+            reserves.insert(pubkey, reserve);
+        }
+
+        let feeds: Vec<(Pubkey, Vec<OracleFeed>)> =
+            reserves.iter().map(|(pubkey, reserve)| (*pubkey, save_feeds(reserve))).collect();
+
+        let oracle_pubkeys: Vec<Pubkey> = feeds
+            .iter()
+            .flat_map(|(_, reserve_feeds)| reserve_feeds.iter().flat_map(OracleFeed::accounts))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let oracle_accounts = common_rpc::get_multiple_accounts_with_conversion::<
+            LendingError,
+            LendingErrorConverter,
+        >(&client, &oracle_pubkeys)
+        .await?;
+
+        for (pubkey, reserve_feeds) in feeds {
+            // Without a live oracle price the reserve keeps both cached prices
+            let prices = reserve_feeds
+                .iter()
+                .filter(|feed| !matches!(feed, OracleFeed::Cached(_)))
+                .filter_map(|feed| feed.decode(&oracle_accounts).ok());
+            let Some(price) =
+                select_price(prices).and_then(|price| oracle_price_to_decimal(&price))
+            else {
+                continue;
+            };
+            let Some(reserve) = reserves.get_mut(&pubkey) else {
+                continue;
+            };
+
+            // The program smooths with the Pyth EMA price, and uses the spot price of Switchboard
+            let smoothed = oracle_accounts
+                .get(&reserve.liquidity.pyth_oracle_pubkey)
+                .and_then(|account| pyth::decode_ema_price(&account.data).ok())
+                .and_then(|price| oracle_price_to_decimal(&price));
+
+            reserve.liquidity.market_price = price;
+            reserve.liquidity.smoothed_market_price = smoothed.unwrap_or(price);
+        }
+
+        Ok(reserves)
+    }
+
+    fn obligation_health(
+        &self,
+        pubkey: &Pubkey,
+        obligation: &Obligation,
+        reserves: &HashMap<Pubkey, Reserve>,
+        market_name: String,
+    ) -> Result<ObligationHealth, LendingError> {
+        let protocol_error = |e| {
+            LendingError::ProtocolError(format!(
+                "Failed to compute health of obligation {}: {}",
+                format_pubkey_for_error(pubkey),
+                e
+            ))
+        };
+
+        // The obligation helpers divide by the deposited value
+        let ratio = |value: Decimal| -> f64 {
+            if obligation.deposited_value == Decimal::zero() {
+                0.0
+            } else {
+                decimal_to_f64(value) / decimal_to_f64(obligation.deposited_value)
+            }
+        };
+
+        let mut max_withdraw = Vec::with_capacity(obligation.deposits.len());
+        for deposit in &obligation.deposits {
+            // Obligation::refresh already checked every deposit reserve is loaded
+            let reserve = &reserves[&deposit.deposit_reserve];
+
+            let collateral_amount =
+                obligation.max_withdraw_amount(deposit, reserve).map_err(protocol_error)?;
+            let amount = reserve
+                .collateral_exchange_rate()
+                .and_then(|rate| rate.collateral_to_liquidity(collateral_amount))
+                .map_err(protocol_error)?;
+
+            let mint = reserve.liquidity.mint_pubkey.to_string();
+            max_withdraw.push(WithdrawLimit {
+                symbol: get_symbol_for_mint(&mint).unwrap_or_else(|| mint.clone()),
+                mint,
+                mint_decimals: reserve.liquidity.mint_decimals as u32,
+                amount,
+            });
+        }
+
+        Ok(ObligationHealth {
+            protocol_name: self.protocol_name().to_string(),
+            market_name,
+            account: pubkey.to_string(),
+            deposited_value: decimal_to_f64(obligation.deposited_value),
+            borrowed_value: decimal_to_f64(obligation.borrowed_value),
+            loan_to_value: ratio(obligation.borrowed_value),
+            max_loan_to_value: ratio(obligation.allowed_borrow_value),
+            liquidation_loan_to_value: ratio(obligation.unhealthy_borrow_value),
+            remaining_borrow_value: obligation
+                .remaining_borrow_value()
+                .map(decimal_to_f64)
+                .unwrap_or(0.0),
+            health_factor: (obligation.borrowed_value > Decimal::zero()).then(|| {
+                decimal_to_f64(obligation.unhealthy_borrow_value)
+                    / decimal_to_f64(obligation.borrowed_value)
+            }),
+            elevation_group: None,
+            initial_health: None,
+            maintenance_health: None,
+            max_withdraw,
+            margin: None,
+        })
+    }
+
+    async fn fetch_raw_obligations(
+        &self,
+        owner_pubkey: &str,
+    ) -> Result<Vec<(Pubkey, Obligation)>, LendingError> {
         let mut ret = Vec::new();
         let owner = owner_pubkey.parse::<Pubkey>().map_err(|e| {
             LendingError::InvalidAddress(format!("Invalid owner pubkey {}: {}", owner_pubkey, e))
         })?;
 
         // Use the RPC builder with optimized filters
-        let accounts = with_pooled_client(&self.rpc_url, |client| {
-            SolanaRpcBuilder::new(client, self.program_id)
-                .with_data_size(Obligation::LEN as u64)
-                .with_memcmp(1 + 8 + 1 + 32, owner.to_bytes().to_vec()) // Skip version(1) + last_update(8+1) + lending_market(32) to get to owner
-                .optimize_filters() // Apply filter optimization
-                .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
-        })?;
+        let client = create_rpc_client(&self.rpc_url);
+        let accounts = SolanaRpcBuilder::new(&client, self.program_id)
+            .with_data_size(Obligation::LEN as u64)
+            .with_memcmp(1 + 8 + 1 + 32, owner.to_bytes().to_vec()) // Skip version(1) + last_update(8+1) + lending_market(32) to get to owner
+            .optimize_filters() // Apply filter optimization
+            .get_program_accounts_with_conversion::<LendingError, LendingErrorConverter>()
+            .await?;
 
         if accounts.is_empty() {
             debug!("No current obligations found for {}", owner_pubkey);
@@ -284,7 +618,7 @@ impl SaveClient {
                         continue;
                     }
 
-                    ret.push(obligation);
+                    ret.push((pubkey, obligation));
                 }
                 Err(e) => {
                     debug!(
@@ -301,29 +635,55 @@ impl SaveClient {
     }
 }
 
-impl LendingClient<Pubkey, Vec<(Pubkey, Vec<Reserve>)>> for SaveClient {
-    fn load_markets(&mut self) -> Result<(), LendingError> {
-        self.load_reserves()
+/// Reads the market name out of a lending market metadata account, None when it is empty
+fn decode_market_name(data: &[u8]) -> Option<String> {
+    let data = data.get(..1 + std::mem::size_of::<LendingMarketMetadata>())?;
+    let metadata = LendingMarketMetadata::new_from_bytes(data).ok()?;
+
+    let name =
+        String::from_utf8_lossy(&metadata.market_name).trim_end_matches('\0').trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+fn decimal_to_f64(value: Decimal) -> f64 {
+    value.to_scaled_val().map_or(0.0, |scaled| scaled as f64 / WAD as f64)
+}
+
+/// Converts an oracle price to the WAD-scaled quote price stored on Save reserves
+fn oracle_price_to_decimal(price: &OraclePrice) -> Option<Decimal> {
+    if price.price <= 0 {
+        return None;
     }
 
-    fn fetch_markets(&self) -> Result<Vec<(Pubkey, Vec<Reserve>)>, LendingError> {
-        self.fetch_reserves()
+    let shift = price.expo + 18;
+    let value = price.price as u128;
+    let scaled = if shift >= 0 {
+        value.checked_mul(10u128.checked_pow(shift as u32)?)?
+    } else {
+        value / 10u128.checked_pow(shift.unsigned_abs())?
+    };
+
+    Some(Decimal::from_scaled_val(scaled))
+}
+
+impl LendingClient<Pubkey, Vec<SolendPool>> for SaveClient {
+    async fn load_markets(&mut self) -> Result<(), LendingError> {
+        self.load_reserves().await
     }
 
-    fn set_market_data(&mut self, data: Vec<(Pubkey, Vec<Reserve>)>) {
-        // Update the client's state with the fetched market data
-        for (pubkey, reserves) in data {
-            if let Some(pool) = self.pools.iter_mut().find(|p| p.pubkey == pubkey) {
-                pool.reserves = reserves;
-            }
-        }
+    async fn fetch_markets(&self) -> Result<Vec<SolendPool>, LendingError> {
+        self.fetch_pools().await
+    }
+
+    fn set_market_data(&mut self, data: Vec<SolendPool>) {
+        self.pools = data;
     }
 
-    fn get_user_obligations(
+    async fn get_user_obligations(
         &self,
         wallet_address: &str,
     ) -> Result<Vec<UserObligation>, LendingError> {
-        self.get_user_obligations(wallet_address)
+        self.get_user_obligations(wallet_address).await
     }
 
     fn program_id(&self) -> Pubkey {
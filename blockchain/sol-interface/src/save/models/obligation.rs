@@ -1,14 +1,19 @@
 use crate::save::error::LendingError;
-use crate::save::math::{Decimal, Rate, TryDiv, TryMul, TrySub};
+use crate::save::math::{Decimal, Rate, TryAdd, TryDiv, TryMul, TrySub};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
     clock::Slot,
+    entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::{Pubkey, PUBKEY_BYTES},
 };
-use std::{cmp::min, convert::TryFrom};
+use std::{
+    cmp::{min, Ordering},
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
 
 use super::{
     pack_bool, pack_decimal, unpack_bool, unpack_decimal, LastUpdate, Reserve,
@@ -82,6 +87,77 @@ impl Obligation {
         self.borrows = params.borrows;
     }
 
+    /// Recompute the deposit and borrow market values against the given reserves, the same way
+    /// RefreshObligation does on-chain. Every deposit and borrow reserve must be present.
+    pub fn refresh(&mut self, reserves: &HashMap<Pubkey, Reserve>) -> Result<(), ProgramError> {
+        let mut deposited_value = Decimal::zero();
+        let mut borrowed_value = Decimal::zero();
+        let mut unweighted_borrowed_value = Decimal::zero();
+        let mut borrowed_value_upper_bound = Decimal::zero();
+        let mut allowed_borrow_value = Decimal::zero();
+        let mut unhealthy_borrow_value = Decimal::zero();
+        let mut super_unhealthy_borrow_value = Decimal::zero();
+
+        for collateral in &mut self.deposits {
+            let deposit_reserve = reserves
+                .get(&collateral.deposit_reserve)
+                .ok_or(LendingError::InvalidAccountInput)?;
+
+            let liquidity_amount = deposit_reserve
+                .collateral_exchange_rate()?
+                .decimal_collateral_to_liquidity(collateral.deposited_amount.into())?;
+
+            let market_value = deposit_reserve.market_value(liquidity_amount)?;
+            let market_value_lower_bound =
+                deposit_reserve.market_value_lower_bound(liquidity_amount)?;
+
+            let loan_to_value_rate = Rate::from_percent(deposit_reserve.config.loan_to_value_ratio);
+            let liquidation_threshold_rate =
+                Rate::from_percent(deposit_reserve.config.liquidation_threshold);
+            let max_liquidation_threshold_rate =
+                Rate::from_percent(deposit_reserve.config.max_liquidation_threshold);
+
+            collateral.market_value = market_value;
+
+            deposited_value = deposited_value.try_add(market_value)?;
+            allowed_borrow_value = allowed_borrow_value
+                .try_add(market_value_lower_bound.try_mul(loan_to_value_rate)?)?;
+            unhealthy_borrow_value = unhealthy_borrow_value
+                .try_add(market_value.try_mul(liquidation_threshold_rate)?)?;
+            super_unhealthy_borrow_value = super_unhealthy_borrow_value
+                .try_add(market_value.try_mul(max_liquidation_threshold_rate)?)?;
+        }
+
+        for liquidity in &mut self.borrows {
+            let borrow_reserve =
+                reserves.get(&liquidity.borrow_reserve).ok_or(LendingError::InvalidAccountInput)?;
+
+            liquidity.accrue_interest(borrow_reserve.liquidity.cumulative_borrow_rate_wads)?;
+
+            let market_value = borrow_reserve.market_value(liquidity.borrowed_amount_wads)?;
+            let market_value_upper_bound =
+                borrow_reserve.market_value_upper_bound(liquidity.borrowed_amount_wads)?;
+
+            liquidity.market_value = market_value;
+
+            borrowed_value =
+                borrowed_value.try_add(market_value.try_mul(borrow_reserve.borrow_weight())?)?;
+            borrowed_value_upper_bound = borrowed_value_upper_bound
+                .try_add(market_value_upper_bound.try_mul(borrow_reserve.borrow_weight())?)?;
+            unweighted_borrowed_value = unweighted_borrowed_value.try_add(market_value)?;
+        }
+
+        self.deposited_value = deposited_value;
+        self.borrowed_value = borrowed_value;
+        self.unweighted_borrowed_value = unweighted_borrowed_value;
+        self.borrowed_value_upper_bound = borrowed_value_upper_bound;
+        self.allowed_borrow_value = allowed_borrow_value;
+        self.unhealthy_borrow_value = unhealthy_borrow_value;
+        self.super_unhealthy_borrow_value = super_unhealthy_borrow_value;
+
+        Ok(())
+    }
+
     /// Calculate the current ratio of borrowed value to deposited value
     pub fn loan_to_value(&self) -> Result<Decimal, ProgramError> {
         self.borrowed_value.try_div(self.deposited_value)
@@ -288,6 +364,28 @@ impl ObligationLiquidity {
             market_value: Decimal::zero(),
         }
     }
+
+    /// Accrue interest
+    pub fn accrue_interest(&mut self, cumulative_borrow_rate_wads: Decimal) -> ProgramResult {
+        match cumulative_borrow_rate_wads.cmp(&self.cumulative_borrow_rate_wads) {
+            Ordering::Less => {
+                msg!("Interest rate cannot be negative");
+                return Err(LendingError::NegativeInterestRate.into());
+            }
+            Ordering::Equal => {}
+            Ordering::Greater => {
+                let compounded_interest_rate: Rate = cumulative_borrow_rate_wads
+                    .try_div(self.cumulative_borrow_rate_wads)?
+                    .try_into()?;
+
+                self.borrowed_amount_wads =
+                    self.borrowed_amount_wads.try_mul(compounded_interest_rate)?;
+                self.cumulative_borrow_rate_wads = cumulative_borrow_rate_wads;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 const OBLIGATION_COLLATERAL_LEN: usize = 88; // 32 + 8 + 16 + 32
@@ -636,6 +734,77 @@ mod test {
         );
     }
 
+    #[test]
+    fn refresh_obligation() {
+        let sol_reserve = Reserve {
+            config: ReserveConfig {
+                loan_to_value_ratio: 50,
+                liquidation_threshold: 80,
+                max_liquidation_threshold: 90,
+                ..ReserveConfig::default()
+            },
+            liquidity: ReserveLiquidity {
+                available_amount: 100 * LAMPORTS_PER_SOL,
+                market_price: Decimal::from(10u64),
+                smoothed_market_price: Decimal::from(5u64),
+                mint_decimals: 9,
+                ..ReserveLiquidity::default()
+            },
+            collateral: ReserveCollateral {
+                mint_total_supply: 50 * LAMPORTS_PER_SOL,
+                ..ReserveCollateral::default()
+            },
+            ..Reserve::default()
+        };
+        let usdc_reserve = Reserve {
+            liquidity: ReserveLiquidity {
+                cumulative_borrow_rate_wads: Decimal::from_percent(110),
+                market_price: Decimal::one(),
+                smoothed_market_price: Decimal::one(),
+                mint_decimals: 6,
+                ..ReserveLiquidity::default()
+            },
+            ..Reserve::default()
+        };
+
+        let sol_pubkey = Pubkey::new_unique();
+        let usdc_pubkey = Pubkey::new_unique();
+        let reserves = HashMap::from([(sol_pubkey, sol_reserve), (usdc_pubkey, usdc_reserve)]);
+
+        let mut obligation = Obligation {
+            deposits: vec![ObligationCollateral {
+                deposit_reserve: sol_pubkey,
+                deposited_amount: 20 * LAMPORTS_PER_SOL,
+                ..ObligationCollateral::default()
+            }],
+            borrows: vec![ObligationLiquidity {
+                borrow_reserve: usdc_pubkey,
+                cumulative_borrow_rate_wads: Decimal::one(),
+                borrowed_amount_wads: Decimal::from(100_000_000u64),
+                ..ObligationLiquidity::default()
+            }],
+            ..Obligation::default()
+        };
+
+        obligation.refresh(&reserves).unwrap();
+
+        // 20 cSOL * 2(SOL/cSOL) * $10 = $400, valued at the $5 lower bound for borrowing
+        assert_eq!(obligation.deposited_value, Decimal::from(400u64));
+        assert_eq!(obligation.allowed_borrow_value, Decimal::from(100u64));
+        assert_eq!(obligation.unhealthy_borrow_value, Decimal::from(320u64));
+        assert_eq!(obligation.super_unhealthy_borrow_value, Decimal::from(360u64));
+
+        // 100 USDC borrowed plus 10% accrued interest
+        assert_eq!(obligation.borrows[0].borrowed_amount_wads, Decimal::from(110_000_000u64));
+        assert_eq!(obligation.borrowed_value, Decimal::from(110u64));
+        assert_eq!(obligation.borrowed_value_upper_bound, Decimal::from(110u64));
+        assert_eq!(obligation.unweighted_borrowed_value, Decimal::from(110u64));
+
+        // a reserve that wasn't provided can't be valued
+        obligation.deposits[0].deposit_reserve = Pubkey::new_unique();
+        assert!(obligation.refresh(&reserves).is_err());
+    }
+
     #[derive(Debug, Clone)]
     struct MaxWithdrawAmountTestCase {
         obligation: Obligation,
@@ -1,9 +1,13 @@
 use solana_program::program_pack::IsInitialized;
 use solana_program::{program_error::ProgramError, slot_history::Slot};
 
-use crate::save::math::Decimal;
+use crate::save::{
+    error::LendingError,
+    math::{Decimal, TryAdd, TryDiv, TryMul, TrySub},
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::program_pack::{Pack, Sealed};
+use std::cmp::Ordering;
 
 use super::{pack_decimal, unpack_decimal};
 
@@ -50,6 +54,76 @@ impl RateLimiter {
             cur_qty: Decimal::zero(),
         }
     }
+
+    /// Start of the current window, up to date once `remaining_outflow` has been called
+    pub fn window_start(&self) -> Slot {
+        self.window_start
+    }
+
+    fn _update(&mut self, cur_slot: u64) -> Result<(), ProgramError> {
+        if cur_slot < self.window_start {
+            return Err(LendingError::InvalidAccountInput.into());
+        }
+
+        // floor wrt window duration
+        let cur_slot_start = cur_slot / self.config.window_duration * self.config.window_duration;
+
+        // update prev window, current window
+        match cur_slot_start.cmp(&(self.window_start + self.config.window_duration)) {
+            // |<-prev window->|<-cur window (cur_slot is in here)->|
+            Ordering::Less => (),
+
+            // |<-prev window->|<-cur window->| (cur_slot is in here) |
+            Ordering::Equal => {
+                self.prev_qty = self.cur_qty;
+                self.window_start = cur_slot_start;
+                self.cur_qty = Decimal::zero();
+            }
+
+            // |<-prev window->|<-cur window->|<-cur window + 1->| ... | (cur_slot is in here) |
+            Ordering::Greater => {
+                self.prev_qty = Decimal::zero();
+                self.window_start = cur_slot_start;
+                self.cur_qty = Decimal::zero();
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Calculate current outflow. Must only be called after ._update()!
+    fn current_outflow(&self, cur_slot: u64) -> Result<Decimal, ProgramError> {
+        if self.config.window_duration == 0 {
+            return Err(LendingError::InvalidAccountInput.into());
+        }
+
+        // assume the prev_window's outflow is even distributed across the window
+        // this isn't true, but it's a good enough approximation
+        let prev_weight = Decimal::from(self.config.window_duration)
+            .try_sub(Decimal::from(cur_slot - self.window_start + 1))?
+            .try_div(self.config.window_duration)?;
+
+        prev_weight.try_mul(self.prev_qty)?.try_add(self.cur_qty)
+    }
+
+    /// Calculate remaining outflow for the current window
+    pub fn remaining_outflow(&mut self, cur_slot: u64) -> Result<Decimal, ProgramError> {
+        // rate limiter is disabled if window duration == 0. this is here because we don't want to
+        // brick borrows/withdraws in permissionless pools on program upgrade.
+        if self.config.window_duration == 0 {
+            return Ok(Decimal::from(u64::MAX));
+        }
+
+        self._update(cur_slot)?;
+
+        let cur_outflow = self.current_outflow(cur_slot)?;
+        if cur_outflow > Decimal::from(self.config.max_outflow) {
+            return Ok(Decimal::zero());
+        }
+
+        let diff = Decimal::from(self.config.max_outflow).try_sub(cur_outflow)?;
+        Ok(diff)
+    }
 }
 
 impl Default for RateLimiter {
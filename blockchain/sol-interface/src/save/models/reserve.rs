@@ -297,6 +297,20 @@ impl Reserve {
         }
     }
 
+    /// Accrue the interest of the slots since the last update, the way RefreshReserve does
+    /// without refreshing the price. The last update moves to `current_slot` so the interest
+    /// is not accrued twice. A reserve updated at or after `current_slot` is left as it is.
+    pub fn accrue_interest(&mut self, current_slot: Slot) -> ProgramResult {
+        let slots_elapsed = current_slot.saturating_sub(self.last_update.slot);
+        if slots_elapsed > 0 {
+            let current_borrow_rate = self.current_borrow_rate()?;
+            let take_rate = Rate::from_percent(self.config.protocol_take_rate);
+            self.liquidity.compound_interest(current_borrow_rate, slots_elapsed, take_rate)?;
+            self.last_update.slot = current_slot;
+        }
+        Ok(())
+    }
+
     /// Collateral exchange rate
     pub fn collateral_exchange_rate(&self) -> Result<CollateralExchangeRate, ProgramError> {
         let total_liquidity = self.liquidity.total_supply()?;
@@ -675,6 +689,30 @@ impl ReserveLiquidity {
             .try_sub(self.accumulated_protocol_fees_wads)
     }
 
+    /// Compound current borrow rate over elapsed slots
+    fn compound_interest(
+        &mut self,
+        current_borrow_rate: Rate,
+        slots_elapsed: u64,
+        take_rate: Rate,
+    ) -> ProgramResult {
+        let slot_interest_rate = current_borrow_rate.try_div(SLOTS_PER_YEAR)?;
+        let compounded_interest_rate =
+            Rate::one().try_add(slot_interest_rate)?.try_pow(slots_elapsed)?;
+        self.cumulative_borrow_rate_wads =
+            self.cumulative_borrow_rate_wads.try_mul(compounded_interest_rate)?;
+
+        let net_new_debt = self
+            .borrowed_amount_wads
+            .try_mul(compounded_interest_rate)?
+            .try_sub(self.borrowed_amount_wads)?;
+        self.accumulated_protocol_fees_wads =
+            net_new_debt.try_mul(take_rate)?.try_add(self.accumulated_protocol_fees_wads)?;
+        self.borrowed_amount_wads = self.borrowed_amount_wads.try_add(net_new_debt)?;
+
+        Ok(())
+    }
+
     /// Calculate the liquidity utilization rate of the reserve
     pub fn utilization_rate(&self) -> Result<Rate, ProgramError> {
         let total_supply = self.total_supply()?;
@@ -2753,4 +2791,62 @@ mod test {
             ), test_case.result);
         }
     }
+
+    #[test]
+    fn accrue_interest_compounds_debt_to_current_slot() {
+        let mut reserve = Reserve {
+            last_update: LastUpdate { slot: 100, stale: false },
+            config: ReserveConfig {
+                optimal_utilization_rate: 50,
+                max_utilization_rate: 100,
+                min_borrow_rate: 10,
+                optimal_borrow_rate: 10,
+                protocol_take_rate: 10,
+                ..ReserveConfig::default()
+            },
+            liquidity: ReserveLiquidity {
+                available_amount: 1_000_000,
+                borrowed_amount_wads: Decimal::from(1_000_000u64),
+                cumulative_borrow_rate_wads: Decimal::one(),
+                ..ReserveLiquidity::default()
+            },
+            ..Reserve::default()
+        };
+
+        // A day at 10% compounds the debt by about 0.0274%
+        let current_slot = 100 + SLOTS_PER_YEAR / 365;
+        reserve.accrue_interest(current_slot).unwrap();
+        assert_eq!(reserve.liquidity.borrowed_amount_wads.try_floor_u64().unwrap(), 1_000_274);
+        assert_eq!(reserve.liquidity.accumulated_protocol_fees_wads.try_floor_u64().unwrap(), 27);
+        assert_eq!(
+            reserve.liquidity.cumulative_borrow_rate_wads.try_mul(1_000_000u64).unwrap(),
+            reserve.liquidity.borrowed_amount_wads
+        );
+        assert_eq!(reserve.last_update.slot, current_slot);
+
+        // Accruing again to the same slot changes nothing
+        let accrued = reserve.clone();
+        reserve.accrue_interest(current_slot).unwrap();
+        assert_eq!(reserve, accrued);
+    }
+
+    #[test]
+    fn accrue_interest_leaves_reserve_newer_than_slot() {
+        let mut reserve = Reserve {
+            last_update: LastUpdate { slot: 1_000, stale: false },
+            config: ReserveConfig { min_borrow_rate: 10, ..ReserveConfig::default() },
+            liquidity: ReserveLiquidity {
+                available_amount: 1_000_000,
+                borrowed_amount_wads: Decimal::from(1_000_000u64),
+                cumulative_borrow_rate_wads: Decimal::one(),
+                ..ReserveLiquidity::default()
+            },
+            ..Reserve::default()
+        };
+
+        // A clock read behind the reserve's last refresh has nothing to accrue
+        let refreshed = reserve.clone();
+        reserve.accrue_interest(900).unwrap();
+        assert_eq!(reserve, refreshed);
+    }
 }
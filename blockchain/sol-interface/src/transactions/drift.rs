@@ -0,0 +1,396 @@
+use super::{associated_token_address, TransactionAction, TOKEN_2022_PROGRAM_ID};
+use crate::common::client_trait::ClientError;
+use anchor_lang::InstructionData;
+use drift::{
+    math::margin::is_perp_position_empty,
+    models::idl::{
+        accounts::{self, PerpMarket, SpotMarket, User, UserStats},
+        instructions,
+        traits::ToAccountMetas,
+    },
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use std::collections::HashMap;
+
+/// Index of the USDC spot market perp positions settle in
+const QUOTE_SPOT_MARKET_INDEX: u16 = 0;
+
+/// Name given to sub-account 0 when created here
+const SUB_ACCOUNT_NAME: &str = "Main Account";
+
+/// Bit of `SpotMarket::token_program` set for Token-2022 mints
+const TOKEN_2022_FLAG: u8 = 1;
+
+/// Accounts of the wallet in Drift, as found on chain
+#[derive(Default)]
+pub struct DriftUserState {
+    /// Sub-account the transaction uses
+    pub sub_account_id: u16,
+    /// The sub-account, None when it does not exist yet
+    pub user: Option<User>,
+    /// None when the wallet never created a Drift account
+    pub user_stats: Option<UserStats>,
+    /// Perp markets of the user's positions, keyed by market index
+    pub perp_markets: HashMap<u16, PerpMarket>,
+}
+
+pub fn state_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"drift_state"], program_id).0
+}
+
+pub fn signer_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"drift_signer"], program_id).0
+}
+
+pub fn user_address(program_id: &Pubkey, authority: &Pubkey, sub_account_id: u16) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"user", authority.as_ref(), &sub_account_id.to_le_bytes()],
+        program_id,
+    )
+    .0
+}
+
+pub fn user_stats_address(program_id: &Pubkey, authority: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_stats", authority.as_ref()], program_id).0
+}
+
+/// Token program of the spot market's mint
+pub fn token_program(market: &SpotMarket) -> Pubkey {
+    if market.token_program & TOKEN_2022_FLAG != 0 {
+        TOKEN_2022_PROGRAM_ID
+    } else {
+        spl_token::id()
+    }
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    data: impl InstructionData,
+    accounts: impl ToAccountMetas,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_account_metas(),
+        data: data.data(),
+    }
+}
+
+pub fn initialize_user_stats(program_id: &Pubkey, authority: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        instructions::InitializeUserStats {},
+        accounts::InitializeUserStats {
+            user_stats: user_stats_address(program_id, authority),
+            state: state_address(program_id),
+            authority: *authority,
+            payer: *authority,
+            rent: sysvar::rent::id(),
+            system_program: system_program::id(),
+        },
+    )
+}
+
+pub fn initialize_user(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    sub_account_id: u16,
+) -> Instruction {
+    let sub_account_name = match sub_account_id {
+        0 => SUB_ACCOUNT_NAME.to_string(),
+        id => format!("Sub-account {}", id),
+    };
+    let mut name = [b' '; 32];
+    name[..sub_account_name.len()].copy_from_slice(sub_account_name.as_bytes());
+
+    instruction(
+        program_id,
+        instructions::InitializeUser { sub_account_id, name },
+        accounts::InitializeUser {
+            user: user_address(program_id, authority, sub_account_id),
+            user_stats: user_stats_address(program_id, authority),
+            state: state_address(program_id),
+            authority: *authority,
+            payer: *authority,
+            rent: sysvar::rent::id(),
+            system_program: system_program::id(),
+        },
+    )
+}
+
+/// Oracles, then spot markets, then perp markets of the user's positions and of `market`, the
+/// order the program loads them in. Only `market` is writable. A Token-2022 mint follows them.
+fn remaining_accounts(
+    market: &SpotMarket,
+    spot_markets: &HashMap<u16, &SpotMarket>,
+    user: &DriftUserState,
+) -> Result<Vec<AccountMeta>, ClientError> {
+    let positions = user.user.iter().flat_map(|user| user.spot_positions.iter());
+    let mut spot_indexes: Vec<u16> = positions
+        .filter(|position| position.scaled_balance > 0 || position.open_orders > 0)
+        .map(|position| position.market_index)
+        .collect();
+    let mut perp_indexes: Vec<u16> = user
+        .user
+        .iter()
+        .flat_map(|user| user.perp_positions.iter())
+        .filter(|position| !is_perp_position_empty(position))
+        .map(|position| position.market_index)
+        .collect();
+    if !perp_indexes.is_empty() {
+        spot_indexes.push(QUOTE_SPOT_MARKET_INDEX);
+    }
+    spot_indexes.push(market.market_index);
+    spot_indexes.sort_unstable();
+    spot_indexes.dedup();
+    perp_indexes.sort_unstable();
+    perp_indexes.dedup();
+
+    let spot: Vec<&SpotMarket> = spot_indexes
+        .iter()
+        .map(|index| {
+            spot_markets
+                .get(index)
+                .copied()
+                .ok_or_else(|| ClientError::MarketNotFound(format!("Drift spot market {}", index)))
+        })
+        .collect::<Result<_, _>>()?;
+    let perp: Vec<&PerpMarket> = perp_indexes
+        .iter()
+        .map(|index| {
+            user.perp_markets
+                .get(index)
+                .ok_or_else(|| ClientError::MarketNotFound(format!("Drift perp market {}", index)))
+        })
+        .collect::<Result<_, _>>()?;
+
+    // The quote market has no oracle account
+    let mut oracles: Vec<Pubkey> = spot
+        .iter()
+        .map(|spot_market| spot_market.oracle)
+        .chain(perp.iter().map(|perp_market| perp_market.amm.oracle))
+        .filter(|oracle| *oracle != Pubkey::default())
+        .collect();
+    oracles.sort_unstable();
+    oracles.dedup();
+
+    let mut accounts: Vec<AccountMeta> =
+        oracles.into_iter().map(|oracle| AccountMeta::new_readonly(oracle, false)).collect();
+    accounts.extend(spot.iter().map(|spot_market| AccountMeta {
+        pubkey: spot_market.pubkey,
+        is_signer: false,
+        is_writable: spot_market.market_index == market.market_index,
+    }));
+    accounts.extend(
+        perp.iter().map(|perp_market| AccountMeta::new_readonly(perp_market.pubkey, false)),
+    );
+    if token_program(market) == TOKEN_2022_PROGRAM_ID {
+        accounts.push(AccountMeta::new_readonly(market.mint, false));
+    }
+
+    Ok(accounts)
+}
+
+/// Instructions to apply `action` with `amount` tokens to the spot market `market_index` from the
+/// user's sub-account, creating it first when missing. Borrows are withdrawals allowed to go below
+/// zero and repays are deposits that only reduce a borrow. `spot_markets` holds the loaded spot
+/// markets keyed by market index.
+pub fn build_instructions(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    market_index: u16,
+    spot_markets: &HashMap<u16, &SpotMarket>,
+    user: &DriftUserState,
+    action: TransactionAction,
+    amount: u64,
+) -> Result<Vec<Instruction>, ClientError> {
+    let market = spot_markets.get(&market_index).copied().ok_or_else(|| {
+        ClientError::MarketNotFound(format!("Drift spot market {}", market_index))
+    })?;
+    let mut instructions = Vec::new();
+
+    if user.user.is_none() {
+        if action != TransactionAction::Deposit {
+            return Err(ClientError::AccountNotFound(format!(
+                "No Drift account for {}",
+                authority
+            )));
+        }
+        // Sub-accounts are created in order
+        let next_sub_account_id =
+            user.user_stats.map_or(0, |stats| stats.number_of_sub_accounts_created);
+        if user.sub_account_id != next_sub_account_id {
+            return Err(ClientError::AccountNotFound(format!(
+                "No Drift sub-account {} for {}, the next one created is {}",
+                user.sub_account_id, authority, next_sub_account_id
+            )));
+        }
+        if user.user_stats.is_none() {
+            instructions.push(initialize_user_stats(program_id, authority));
+        }
+        instructions.push(initialize_user(program_id, authority, user.sub_account_id));
+    }
+
+    let state = state_address(program_id);
+    let user_address = user_address(program_id, authority, user.sub_account_id);
+    let user_stats = user_stats_address(program_id, authority);
+    let token_program = token_program(market);
+    let user_token_account = associated_token_address(authority, &market.mint, &token_program);
+
+    let mut action_instruction = match action {
+        TransactionAction::Deposit | TransactionAction::Repay => instruction(
+            program_id,
+            instructions::Deposit {
+                market_index,
+                amount,
+                reduce_only: action == TransactionAction::Repay,
+            },
+            accounts::Deposit {
+                state,
+                user: user_address,
+                user_stats,
+                authority: *authority,
+                spot_market_vault: market.vault,
+                user_token_account,
+                token_program,
+            },
+        ),
+        TransactionAction::Withdraw | TransactionAction::Borrow => instruction(
+            program_id,
+            instructions::Withdraw {
+                market_index,
+                amount,
+                reduce_only: action == TransactionAction::Withdraw,
+            },
+            accounts::Withdraw {
+                state,
+                user: user_address,
+                user_stats,
+                authority: *authority,
+                spot_market_vault: market.vault,
+                drift_signer: signer_address(program_id),
+                user_token_account,
+                token_program,
+            },
+        ),
+    };
+    action_instruction.accounts.extend(remaining_accounts(market, spot_markets, user)?);
+    instructions.push(action_instruction);
+
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+
+    fn spot_market(market_index: u16) -> SpotMarket {
+        SpotMarket {
+            pubkey: Pubkey::new_unique(),
+            oracle: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            market_index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn borrow_loads_position_markets_after_oracles() {
+        let program_id = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let (sol, usdc) = (spot_market(1), spot_market(0));
+        let spot_markets = HashMap::from([(1, &sol), (0, &usdc)]);
+
+        let mut account = User::default();
+        account.spot_positions[0].market_index = 1;
+        account.spot_positions[0].scaled_balance = 10;
+        let user = DriftUserState { user: Some(account), ..Default::default() };
+
+        let instructions = build_instructions(
+            &program_id,
+            &authority,
+            0,
+            &spot_markets,
+            &user,
+            TransactionAction::Borrow,
+            100,
+        )
+        .unwrap();
+
+        let withdraw = &instructions[0];
+        assert_eq!(withdraw.data[..8], instructions::Withdraw::DISCRIMINATOR);
+        // reduce_only is off for a borrow
+        assert_eq!(withdraw.data.last(), Some(&0));
+
+        let mut oracles = [usdc.oracle, sol.oracle];
+        oracles.sort_unstable();
+        let remaining = &withdraw.accounts[8..];
+        assert_eq!(remaining[..2].iter().map(|meta| meta.pubkey).collect::<Vec<_>>(), oracles);
+        assert_eq!(remaining[2].pubkey, usdc.pubkey);
+        assert!(remaining[2].is_writable);
+        assert_eq!(remaining[3].pubkey, sol.pubkey);
+        assert!(!remaining[3].is_writable);
+    }
+
+    #[test]
+    fn deposit_uses_the_sub_account_and_token_2022_mint() {
+        let program_id = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let market = SpotMarket { token_program: TOKEN_2022_FLAG, ..spot_market(0) };
+        let spot_markets = HashMap::from([(0, &market)]);
+        let user = DriftUserState {
+            sub_account_id: 1,
+            user: Some(User { sub_account_id: 1, ..Default::default() }),
+            ..Default::default()
+        };
+
+        let instructions = build_instructions(
+            &program_id,
+            &authority,
+            0,
+            &spot_markets,
+            &user,
+            TransactionAction::Deposit,
+            100,
+        )
+        .unwrap();
+
+        let deposit = &instructions[0];
+        assert_eq!(deposit.accounts[1].pubkey, user_address(&program_id, &authority, 1));
+        assert_eq!(
+            deposit.accounts[5].pubkey,
+            associated_token_address(&authority, &market.mint, &TOKEN_2022_PROGRAM_ID)
+        );
+        assert_eq!(deposit.accounts[6].pubkey, TOKEN_2022_PROGRAM_ID);
+        assert_eq!(deposit.accounts.last().map(|meta| meta.pubkey), Some(market.mint));
+    }
+
+    #[test]
+    fn new_sub_account_must_be_the_next_one() {
+        let market = spot_market(0);
+        let spot_markets = HashMap::from([(0, &market)]);
+        let stats = UserStats { number_of_sub_accounts_created: 1, ..Default::default() };
+        let build = |sub_account_id| {
+            let user =
+                DriftUserState { sub_account_id, user_stats: Some(stats), ..Default::default() };
+            build_instructions(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                0,
+                &spot_markets,
+                &user,
+                TransactionAction::Deposit,
+                100,
+            )
+        };
+
+        assert!(matches!(build(0), Err(ClientError::AccountNotFound(_))));
+        let instructions = build(1).unwrap();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[0].data[..8], instructions::InitializeUser::DISCRIMINATOR);
+    }
+}
@@ -0,0 +1,491 @@
+use super::{optional_account, TransactionAction};
+use crate::{
+    common::client_trait::ClientError,
+    kamino::models::{
+        obligation::Obligation,
+        reserve::{Reserve, ReserveFarmKind},
+    },
+    rewards::farms::FARMS_PROGRAM_ID,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use std::collections::HashMap;
+
+// Instruction discriminators from the Kamino lending IDL
+const INIT_USER_METADATA: [u8; 8] = [117, 169, 176, 69, 197, 23, 15, 162];
+const INIT_OBLIGATION: [u8; 8] = [251, 10, 231, 76, 27, 11, 159, 96];
+const INIT_OBLIGATION_FARMS_FOR_RESERVE: [u8; 8] = [136, 63, 15, 186, 211, 152, 168, 164];
+const REFRESH_RESERVE: [u8; 8] = [2, 218, 138, 235, 79, 201, 25, 102];
+const REFRESH_OBLIGATION: [u8; 8] = [33, 132, 147, 228, 151, 192, 72, 89];
+const REFRESH_OBLIGATION_FARMS_FOR_RESERVE: [u8; 8] = [140, 144, 253, 21, 10, 74, 248, 3];
+const DEPOSIT: [u8; 8] = [129, 199, 4, 2, 222, 39, 26, 46];
+const WITHDRAW: [u8; 8] = [75, 93, 93, 220, 34, 150, 218, 196];
+const BORROW: [u8; 8] = [121, 127, 18, 204, 73, 245, 225, 65];
+const REPAY: [u8; 8] = [145, 178, 13, 225, 76, 240, 147, 72];
+
+/// Accounts of the wallet in a Kamino market, as found on chain
+#[derive(Default)]
+pub struct KaminoUserState {
+    /// The vanilla obligation, None when it does not exist yet
+    pub obligation: Option<Obligation>,
+    pub has_user_metadata: bool,
+    /// Whether the obligation is registered with the farm of the reserve
+    pub has_obligation_farm: bool,
+}
+
+pub fn lending_market_authority(program_id: &Pubkey, market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"lma", market.as_ref()], program_id).0
+}
+
+pub fn user_metadata_address(program_id: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_meta", owner.as_ref()], program_id).0
+}
+
+/// The vanilla obligation of `owner`, tag and id 0 without seed accounts
+pub fn obligation_address(program_id: &Pubkey, owner: &Pubkey, market: &Pubkey) -> Pubkey {
+    let default = Pubkey::default();
+    Pubkey::find_program_address(
+        &[&[0], &[0], owner.as_ref(), market.as_ref(), default.as_ref(), default.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+/// State of the obligation in the farm of a reserve
+pub fn obligation_farm_address(farm: &Pubkey, obligation: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user", farm.as_ref(), obligation.as_ref()], &FARMS_PROGRAM_ID)
+        .0
+}
+
+/// Farm of the reserve rewarding `action`, None when the reserve has none
+pub fn action_farm(
+    reserve: &Reserve,
+    action: TransactionAction,
+) -> Option<(ReserveFarmKind, Pubkey)> {
+    let kind = match action {
+        TransactionAction::Deposit | TransactionAction::Withdraw => ReserveFarmKind::Collateral,
+        TransactionAction::Borrow | TransactionAction::Repay => ReserveFarmKind::Debt,
+    };
+    let farm = reserve.get_farm(kind);
+
+    (farm != Pubkey::default()).then_some((kind, farm))
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    discriminator: [u8; 8],
+    args: &[u8],
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction { program_id: *program_id, accounts, data: [&discriminator[..], args].concat() }
+}
+
+pub fn init_user_metadata(program_id: &Pubkey, owner: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        INIT_USER_METADATA,
+        // No lookup table
+        Pubkey::default().as_ref(),
+        vec![
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(user_metadata_address(program_id, owner), false),
+            AccountMeta::new_readonly(*program_id, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn init_obligation(program_id: &Pubkey, owner: &Pubkey, market: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        INIT_OBLIGATION,
+        // Vanilla obligation: tag 0, id 0
+        &[0, 0],
+        vec![
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(obligation_address(program_id, owner, market), false),
+            AccountMeta::new_readonly(*market, false),
+            AccountMeta::new_readonly(Pubkey::default(), false),
+            AccountMeta::new_readonly(Pubkey::default(), false),
+            AccountMeta::new_readonly(user_metadata_address(program_id, owner), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn refresh_reserve(program_id: &Pubkey, address: &Pubkey, reserve: &Reserve) -> Instruction {
+    let token_info = &reserve.config.token_info;
+    let oracle =
+        |pubkey: Pubkey| AccountMeta::new_readonly(optional_account(pubkey, *program_id), false);
+
+    instruction(
+        program_id,
+        REFRESH_RESERVE,
+        &[],
+        vec![
+            AccountMeta::new(*address, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            oracle(token_info.pyth_configuration.price),
+            oracle(token_info.switchboard_configuration.price_aggregator),
+            oracle(token_info.switchboard_configuration.twap_aggregator),
+            oracle(token_info.scope_configuration.price_feed),
+        ],
+    )
+}
+
+/// Refreshes the obligation, followed by its deposit reserves and then its borrow reserves
+pub fn refresh_obligation(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    obligation_address: &Pubkey,
+    obligation: Option<&Obligation>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new(*obligation_address, false),
+    ];
+    accounts.extend(
+        obligation_reserves(obligation)
+            .into_iter()
+            .map(|reserve| AccountMeta::new_readonly(reserve, false)),
+    );
+
+    instruction(program_id, REFRESH_OBLIGATION, &[], accounts)
+}
+
+/// Deposit reserves of the obligation followed by its borrow reserves
+fn obligation_reserves(obligation: Option<&Obligation>) -> Vec<Pubkey> {
+    let Some(obligation) = obligation else {
+        return Vec::new();
+    };
+
+    let deposits = obligation.deposits.iter().map(|deposit| deposit.deposit_reserve);
+    let borrows = obligation.borrows.iter().map(|borrow| borrow.borrow_reserve);
+    deposits.chain(borrows).filter(|reserve| *reserve != Pubkey::default()).collect()
+}
+
+fn farm_accounts(
+    program_id: &Pubkey,
+    payer: AccountMeta,
+    owner: Option<&Pubkey>,
+    obligation: &Pubkey,
+    address: &Pubkey,
+    reserve: &Reserve,
+    farm: &Pubkey,
+) -> Vec<AccountMeta> {
+    let market = reserve.lending_market;
+    let mut accounts = vec![payer];
+    accounts.extend(owner.map(|owner| AccountMeta::new_readonly(*owner, false)));
+    accounts.extend([
+        // Initializing the farm state writes to the obligation and reserve
+        AccountMeta { pubkey: *obligation, is_signer: false, is_writable: owner.is_some() },
+        AccountMeta::new(lending_market_authority(program_id, &market), false),
+        AccountMeta { pubkey: *address, is_signer: false, is_writable: owner.is_some() },
+        AccountMeta::new(*farm, false),
+        AccountMeta::new(obligation_farm_address(farm, obligation), false),
+        AccountMeta::new_readonly(market, false),
+        AccountMeta::new_readonly(FARMS_PROGRAM_ID, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]);
+    accounts
+}
+
+pub fn init_obligation_farms_for_reserve(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    obligation: &Pubkey,
+    address: &Pubkey,
+    reserve: &Reserve,
+    (kind, farm): (ReserveFarmKind, Pubkey),
+) -> Instruction {
+    let accounts = farm_accounts(
+        program_id,
+        AccountMeta::new(*owner, true),
+        Some(owner),
+        obligation,
+        address,
+        reserve,
+        &farm,
+    );
+
+    instruction(program_id, INIT_OBLIGATION_FARMS_FOR_RESERVE, &[kind as u8], accounts)
+}
+
+pub fn refresh_obligation_farms_for_reserve(
+    program_id: &Pubkey,
+    crank: &Pubkey,
+    obligation: &Pubkey,
+    address: &Pubkey,
+    reserve: &Reserve,
+    (kind, farm): (ReserveFarmKind, Pubkey),
+) -> Instruction {
+    let accounts = farm_accounts(
+        program_id,
+        AccountMeta::new(*crank, true),
+        None,
+        obligation,
+        address,
+        reserve,
+        &farm,
+    );
+
+    instruction(program_id, REFRESH_OBLIGATION_FARMS_FOR_RESERVE, &[kind as u8], accounts)
+}
+
+/// The deposit, withdraw, borrow or repay instruction itself, `amount` in collateral tokens for
+/// withdrawals and in liquidity otherwise
+pub fn action_instruction(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    obligation: &Pubkey,
+    address: &Pubkey,
+    reserve: &Reserve,
+    action: TransactionAction,
+    amount: u64,
+) -> Instruction {
+    let market = reserve.lending_market;
+    let liquidity = &reserve.liquidity;
+    let collateral = &reserve.collateral;
+    let user_token_account =
+        super::associated_token_address(owner, &liquidity.mint_pubkey, &liquidity.token_program);
+    let market_authority = lending_market_authority(program_id, &market);
+
+    let (discriminator, accounts) = match action {
+        TransactionAction::Deposit => (
+            DEPOSIT,
+            vec![
+                AccountMeta::new(*owner, true),
+                AccountMeta::new(*obligation, false),
+                AccountMeta::new_readonly(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(liquidity.mint_pubkey, false),
+                AccountMeta::new(liquidity.supply_vault, false),
+                AccountMeta::new(collateral.mint_pubkey, false),
+                AccountMeta::new(collateral.supply_vault, false),
+                AccountMeta::new(user_token_account, false),
+                AccountMeta::new_readonly(*program_id, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(liquidity.token_program, false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+        ),
+        TransactionAction::Withdraw => (
+            WITHDRAW,
+            vec![
+                AccountMeta::new(*owner, true),
+                AccountMeta::new(*obligation, false),
+                AccountMeta::new_readonly(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(liquidity.mint_pubkey, false),
+                AccountMeta::new(collateral.supply_vault, false),
+                AccountMeta::new(collateral.mint_pubkey, false),
+                AccountMeta::new(liquidity.supply_vault, false),
+                AccountMeta::new(user_token_account, false),
+                AccountMeta::new_readonly(*program_id, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(liquidity.token_program, false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+        ),
+        TransactionAction::Borrow => (
+            BORROW,
+            vec![
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new(*obligation, false),
+                AccountMeta::new_readonly(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(liquidity.mint_pubkey, false),
+                AccountMeta::new(liquidity.supply_vault, false),
+                AccountMeta::new(liquidity.fee_vault, false),
+                AccountMeta::new(user_token_account, false),
+                // No referrer
+                AccountMeta::new(*program_id, false),
+                AccountMeta::new_readonly(liquidity.token_program, false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+        ),
+        TransactionAction::Repay => (
+            REPAY,
+            vec![
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new(*obligation, false),
+                AccountMeta::new_readonly(market, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(liquidity.mint_pubkey, false),
+                AccountMeta::new(liquidity.supply_vault, false),
+                AccountMeta::new(user_token_account, false),
+                AccountMeta::new_readonly(liquidity.token_program, false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+        ),
+    };
+
+    instruction(program_id, discriminator, &amount.to_le_bytes(), accounts)
+}
+
+/// Instructions to apply `action` with `amount` liquidity tokens to the reserve at `address`.
+/// Missing user accounts are created first, then every reserve of the obligation is refreshed
+/// along with the obligation and the reserve farm, as the program requires. `reserves` holds the
+/// loaded reserves of the market.
+pub fn build_instructions(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    address: &Pubkey,
+    reserves: &HashMap<Pubkey, &Reserve>,
+    user: &KaminoUserState,
+    action: TransactionAction,
+    amount: u64,
+) -> Result<Vec<Instruction>, ClientError> {
+    let find_reserve = |address: &Pubkey| {
+        reserves
+            .get(address)
+            .copied()
+            .ok_or_else(|| ClientError::MarketNotFound(format!("Kamino reserve {}", address)))
+    };
+    let reserve = find_reserve(address)?;
+    let market = reserve.lending_market;
+    let obligation = obligation_address(program_id, owner, &market);
+    let farm = action_farm(reserve, action);
+    let mut instructions = Vec::new();
+
+    if user.obligation.is_none() {
+        if action != TransactionAction::Deposit {
+            return Err(ClientError::AccountNotFound(format!(
+                "No Kamino obligation for {} in market {}",
+                owner, market
+            )));
+        }
+        if !user.has_user_metadata {
+            instructions.push(init_user_metadata(program_id, owner));
+        }
+        instructions.push(init_obligation(program_id, owner, &market));
+    }
+    if let Some(farm) = farm.filter(|_| !user.has_obligation_farm) {
+        instructions.push(init_obligation_farms_for_reserve(
+            program_id,
+            owner,
+            &obligation,
+            address,
+            reserve,
+            farm,
+        ));
+    }
+
+    let mut refreshed = obligation_reserves(user.obligation.as_ref());
+    refreshed.push(*address);
+    refreshed.sort_unstable();
+    refreshed.dedup();
+    for reserve_address in &refreshed {
+        instructions.push(refresh_reserve(
+            program_id,
+            reserve_address,
+            find_reserve(reserve_address)?,
+        ));
+    }
+    instructions.push(refresh_obligation(
+        program_id,
+        &market,
+        &obligation,
+        user.obligation.as_ref(),
+    ));
+
+    let refresh_farm = farm.map(|farm| {
+        refresh_obligation_farms_for_reserve(program_id, owner, &obligation, address, reserve, farm)
+    });
+    instructions.extend(refresh_farm.clone());
+
+    // Withdrawals are made in collateral tokens
+    let amount = match action {
+        TransactionAction::Withdraw => reserve
+            .collateral_exchange_rate()
+            .map_err(|e| ClientError::ProtocolError(format!("{:?}", e)))?
+            .liquidity_to_collateral(amount),
+        _ => amount,
+    };
+    instructions.push(action_instruction(
+        program_id,
+        owner,
+        &obligation,
+        address,
+        reserve,
+        action,
+        amount,
+    ));
+    instructions.extend(refresh_farm);
+
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    #[test]
+    fn first_deposit_creates_obligation_before_refreshing() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let address = Pubkey::new_unique();
+        let mut reserve = Reserve::zeroed();
+        reserve.lending_market = Pubkey::new_unique();
+
+        let instructions = build_instructions(
+            &program_id,
+            &owner,
+            &address,
+            &HashMap::from([(address, &reserve)]),
+            &KaminoUserState::default(),
+            TransactionAction::Deposit,
+            1_000,
+        )
+        .unwrap();
+
+        let discriminators: Vec<&[u8]> = instructions.iter().map(|ix| &ix.data[..8]).collect();
+        assert_eq!(
+            discriminators,
+            [
+                &INIT_USER_METADATA[..],
+                &INIT_OBLIGATION,
+                &REFRESH_RESERVE,
+                &REFRESH_OBLIGATION,
+                &DEPOSIT
+            ]
+        );
+
+        let deposit = instructions.last().unwrap();
+        assert_eq!(deposit.data[8..], 1_000u64.to_le_bytes());
+        assert_eq!(
+            deposit.accounts[1].pubkey,
+            obligation_address(&program_id, &owner, &reserve.lending_market)
+        );
+        assert_eq!(deposit.accounts[4].pubkey, address);
+    }
+
+    #[test]
+    fn withdraw_needs_an_obligation() {
+        let address = Pubkey::new_unique();
+        let reserve = Reserve::zeroed();
+        let result = build_instructions(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &address,
+            &HashMap::from([(address, &reserve)]),
+            &KaminoUserState::default(),
+            TransactionAction::Withdraw,
+            1_000,
+        );
+
+        assert!(matches!(result, Err(ClientError::AccountNotFound(_))));
+    }
+}
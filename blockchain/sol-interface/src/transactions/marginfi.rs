@@ -0,0 +1,283 @@
+use super::{
+    anchor_discriminator, associated_token_address, TransactionAction, TOKEN_2022_PROGRAM_ID,
+};
+use crate::{
+    common::client_trait::ClientError,
+    marginfi::{
+        models::{account::MarginfiAccount, group::Bank, group::BankVaultType, price::OracleSetup},
+        utils::utilities::{find_bank_vault_authority_pda, find_bank_vault_pda},
+    },
+    oracle::pyth::push_oracle_addresses,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use std::collections::HashMap;
+
+/// Marginfi account of the wallet in a group
+pub struct MarginfiUserState {
+    /// Address of the account, a new keypair's address when it does not exist yet
+    pub address: Pubkey,
+    pub account: Option<MarginfiAccount>,
+}
+
+/// Oracle accounts the risk engine reads for a bank, Pyth push feeds through the sponsored shard
+pub fn oracle_accounts(bank: &Bank) -> Vec<Pubkey> {
+    let keys = &bank.config.oracle_keys;
+
+    match bank.config.oracle_setup {
+        OracleSetup::PythPushOracle => vec![push_oracle_addresses(&keys[0].to_bytes())[0]],
+        OracleSetup::StakedWithPythPush => {
+            vec![push_oracle_addresses(&keys[0].to_bytes())[0], keys[1], keys[2]]
+        }
+        _ => vec![keys[0]],
+    }
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    name: &str,
+    args: &[u8],
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: [&anchor_discriminator(name)[..], args].concat(),
+    }
+}
+
+pub fn initialize_account(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    group: &Pubkey,
+    account: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        "marginfi_account_initialize",
+        &[],
+        vec![
+            AccountMeta::new_readonly(*group, false),
+            AccountMeta::new(*account, true),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Banks and oracles of the account's balances, the risk engine checks them after tokens leave.
+/// A borrow opens a balance in `bank_address` when the account has none yet.
+fn risk_accounts(
+    banks: &HashMap<Pubkey, &Bank>,
+    account: Option<&MarginfiAccount>,
+    bank_address: &Pubkey,
+    action: TransactionAction,
+) -> Result<Vec<AccountMeta>, ClientError> {
+    let mut balance_banks: Vec<Pubkey> = account
+        .into_iter()
+        .flat_map(|account| account.lending_account.balances.iter())
+        .filter(|balance| balance.active)
+        .map(|balance| balance.bank_pk)
+        .collect();
+    if action == TransactionAction::Borrow && !balance_banks.contains(bank_address) {
+        balance_banks.push(*bank_address);
+    }
+
+    let mut accounts = Vec::new();
+    for address in balance_banks {
+        let bank = banks
+            .get(&address)
+            .ok_or_else(|| ClientError::MarketNotFound(format!("Marginfi bank {}", address)))?;
+        accounts.push(AccountMeta::new_readonly(address, false));
+        accounts.extend(
+            oracle_accounts(bank)
+                .into_iter()
+                .map(|oracle| AccountMeta::new_readonly(oracle, false)),
+        );
+    }
+
+    Ok(accounts)
+}
+
+/// Instructions to apply `action` with `amount` liquidity tokens to the bank at `bank_address`,
+/// creating the Marginfi account first when the wallet has none. `banks` holds the loaded banks
+/// of the group and `token_program` owns the bank's mint.
+#[allow(clippy::too_many_arguments)]
+pub fn build_instructions(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    bank_address: &Pubkey,
+    banks: &HashMap<Pubkey, &Bank>,
+    token_program: &Pubkey,
+    user: &MarginfiUserState,
+    action: TransactionAction,
+    amount: u64,
+) -> Result<Vec<Instruction>, ClientError> {
+    let bank = banks
+        .get(bank_address)
+        .ok_or_else(|| ClientError::MarketNotFound(format!("Marginfi bank {}", bank_address)))?;
+    let mut instructions = Vec::new();
+
+    if user.account.is_none() {
+        if action != TransactionAction::Deposit {
+            return Err(ClientError::AccountNotFound(format!(
+                "No Marginfi account for {} in group {}",
+                owner, bank.group
+            )));
+        }
+        instructions.push(initialize_account(program_id, owner, &bank.group, &user.address));
+    }
+
+    let user_token_account = associated_token_address(owner, &bank.mint, token_program);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(bank.group, false),
+        AccountMeta::new(user.address, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(*bank_address, false),
+        AccountMeta::new(user_token_account, false),
+    ];
+    let amount_arg = amount.to_le_bytes();
+    // Deposits, withdrawals and repays take an optional flag left unset
+    let flagged_amount = [&amount_arg[..], &[0]].concat();
+    // Token-2022 transfers check the mint, passed ahead of the other remaining accounts
+    let mint_account = (*token_program == TOKEN_2022_PROGRAM_ID)
+        .then(|| AccountMeta::new_readonly(bank.mint, false));
+
+    let (name, args) = match action {
+        TransactionAction::Deposit | TransactionAction::Repay => {
+            accounts.extend([
+                AccountMeta::new(bank.liquidity_vault, false),
+                AccountMeta::new_readonly(*token_program, false),
+            ]);
+            accounts.extend(mint_account);
+            let name = match action {
+                TransactionAction::Deposit => "lending_account_deposit",
+                _ => "lending_account_repay",
+            };
+            (name, flagged_amount)
+        }
+        TransactionAction::Withdraw | TransactionAction::Borrow => {
+            let (vault_authority, _) =
+                find_bank_vault_authority_pda(bank_address, BankVaultType::Liquidity);
+            let (vault, _) = find_bank_vault_pda(bank_address, BankVaultType::Liquidity);
+            accounts.extend([
+                AccountMeta::new_readonly(vault_authority, false),
+                AccountMeta::new(vault, false),
+                AccountMeta::new_readonly(*token_program, false),
+            ]);
+            accounts.extend(mint_account);
+            accounts.extend(risk_accounts(banks, user.account.as_ref(), bank_address, action)?);
+            match action {
+                TransactionAction::Withdraw => ("lending_account_withdraw", flagged_amount),
+                _ => ("lending_account_borrow", amount_arg.to_vec()),
+            }
+        }
+    };
+
+    instructions.push(instruction(program_id, name, &args, accounts));
+
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    #[test]
+    fn borrow_passes_every_balance_bank_with_its_oracle() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (collateral_address, borrow_address) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut collateral = Bank::default();
+        collateral.config.oracle_keys[0] = Pubkey::new_unique();
+        let borrow = Bank::default();
+        let banks = HashMap::from([(collateral_address, &collateral), (borrow_address, &borrow)]);
+
+        let mut account = MarginfiAccount::zeroed();
+        account.lending_account.balances[0].active = true;
+        account.lending_account.balances[0].bank_pk = collateral_address;
+        let user = MarginfiUserState { address: Pubkey::new_unique(), account: Some(account) };
+
+        let instructions = build_instructions(
+            &program_id,
+            &owner,
+            &borrow_address,
+            &banks,
+            &spl_token::id(),
+            &user,
+            TransactionAction::Borrow,
+            1_000,
+        )
+        .unwrap();
+
+        let borrow_ix = &instructions[0];
+        assert_eq!(borrow_ix.data[..8], anchor_discriminator("lending_account_borrow"));
+        assert_eq!(borrow_ix.data[8..], 1_000u64.to_le_bytes());
+        let remaining: Vec<Pubkey> =
+            borrow_ix.accounts[8..].iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(
+            remaining,
+            [
+                collateral_address,
+                collateral.config.oracle_keys[0],
+                borrow_address,
+                Pubkey::default()
+            ]
+        );
+    }
+
+    #[test]
+    fn first_deposit_initializes_the_account() {
+        let bank_address = Pubkey::new_unique();
+        let bank = Bank::default();
+        let user = MarginfiUserState { address: Pubkey::new_unique(), account: None };
+
+        let instructions = build_instructions(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &bank_address,
+            &HashMap::from([(bank_address, &bank)]),
+            &spl_token::id(),
+            &user,
+            TransactionAction::Deposit,
+            1_000,
+        )
+        .unwrap();
+
+        assert_eq!(instructions.len(), 2);
+        assert!(instructions[0].accounts[1].is_signer);
+        assert_eq!(instructions[1].accounts[1].pubkey, user.address);
+    }
+
+    #[test]
+    fn token_2022_withdraw_passes_the_mint_first() {
+        let bank_address = Pubkey::new_unique();
+        let bank = Bank { mint: Pubkey::new_unique(), ..Bank::default() };
+        let mut account = MarginfiAccount::zeroed();
+        account.lending_account.balances[0].active = true;
+        account.lending_account.balances[0].bank_pk = bank_address;
+        let user = MarginfiUserState { address: Pubkey::new_unique(), account: Some(account) };
+
+        let instructions = build_instructions(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &bank_address,
+            &HashMap::from([(bank_address, &bank)]),
+            &TOKEN_2022_PROGRAM_ID,
+            &user,
+            TransactionAction::Withdraw,
+            1_000,
+        )
+        .unwrap();
+
+        let accounts: Vec<Pubkey> =
+            instructions[0].accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(accounts[7], TOKEN_2022_PROGRAM_ID);
+        assert_eq!(accounts[8..], [bank.mint, bank_address, Pubkey::default()]);
+    }
+}
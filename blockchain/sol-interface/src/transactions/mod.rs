@@ -0,0 +1,240 @@
+pub mod drift;
+pub mod kamino;
+pub mod marginfi;
+pub mod save;
+
+use crate::common::client_trait::ClientError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use solana_sdk::{
+    hash::{hash, Hash},
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey,
+    pubkey::Pubkey,
+    signature::Keypair,
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// A change to a wallet's position in a reserve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum TransactionAction {
+    Deposit,
+    Withdraw,
+    Borrow,
+    Repay,
+}
+
+impl TransactionAction {
+    /// Whether the tokens move from the wallet into the reserve
+    pub fn is_inflow(self) -> bool {
+        matches!(self, TransactionAction::Deposit | TransactionAction::Repay)
+    }
+}
+
+/// First 8 bytes of the hash of an Anchor instruction name
+pub fn anchor_discriminator(name: &str) -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash(format!("global:{}", name).as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+/// Optional Anchor accounts left unset are passed as the program itself
+pub fn optional_account(pubkey: Pubkey, program_id: Pubkey) -> Pubkey {
+    if pubkey == Pubkey::default() {
+        program_id
+    } else {
+        pubkey
+    }
+}
+
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Creates the wallet's associated token account of `mint`, doing nothing when it exists
+pub fn create_associated_token_account_idempotent(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*wallet, true),
+            AccountMeta::new(associated_token_address(wallet, mint, token_program), false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        // CreateIdempotent
+        data: vec![1],
+    }
+}
+
+/// Wraps the instructions of `action` with the token account handling the wallet needs. Tokens
+/// leaving a reserve get their account created beforehand. Native SOL is wrapped before flowing
+/// in, and a wrapped SOL account the transaction creates is closed afterwards, returning its
+/// balance as SOL. An account that already exists is left open, as closing it would also unwrap
+/// the wrapped SOL the wallet held before.
+pub fn with_token_account(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    action: TransactionAction,
+    amount: u64,
+    token_account_exists: bool,
+    instructions: Vec<Instruction>,
+) -> Result<Vec<Instruction>, ClientError> {
+    let is_native = *mint == spl_token::native_mint::id();
+    let token_account = associated_token_address(wallet, mint, token_program);
+    let mut wrapped = Vec::with_capacity(instructions.len() + 4);
+
+    if !token_account_exists && (is_native || !action.is_inflow()) {
+        wrapped.push(create_associated_token_account_idempotent(wallet, mint, token_program));
+    }
+    if is_native && action.is_inflow() {
+        wrapped.push(system_instruction::transfer(wallet, &token_account, amount));
+        wrapped.push(
+            spl_token::instruction::sync_native(token_program, &token_account)
+                .map_err(|e| ClientError::Other(e.to_string()))?,
+        );
+    }
+
+    wrapped.extend(instructions);
+
+    if is_native && !token_account_exists {
+        wrapped.push(
+            spl_token::instruction::close_account(
+                token_program,
+                &token_account,
+                wallet,
+                wallet,
+                &[],
+            )
+            .map_err(|e| ClientError::Other(e.to_string()))?,
+        );
+    }
+
+    Ok(wrapped)
+}
+
+/// Base64 of the bincode serialized transaction paid by `payer`. Only the extra `signers`, such
+/// as new accounts, sign it, the payer signature is left to the wallet.
+pub fn serialize_transaction(
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    recent_blockhash: Hash,
+    signers: &[&Keypair],
+) -> Result<String, ClientError> {
+    let message = Message::new_with_blockhash(instructions, Some(payer), &recent_blockhash);
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction
+        .try_partial_sign(signers, recent_blockhash)
+        .map_err(|e| ClientError::Other(format!("Failed to sign transaction: {}", e)))?;
+
+    let bytes = bincode::serialize(&transaction)
+        .map_err(|e| ClientError::Other(format!("Failed to serialize transaction: {}", e)))?;
+
+    Ok(STANDARD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signer::Signer;
+
+    #[test]
+    fn anchor_discriminator_matches_idl() {
+        // From the Kamino lending IDL
+        assert_eq!(anchor_discriminator("refresh_reserve"), [2, 218, 138, 235, 79, 201, 25, 102]);
+    }
+
+    #[test]
+    fn native_deposit_is_wrapped_and_closed() {
+        let wallet = Pubkey::new_unique();
+        let instructions = with_token_account(
+            &wallet,
+            &spl_token::native_mint::id(),
+            &spl_token::id(),
+            TransactionAction::Deposit,
+            1_000,
+            false,
+            vec![],
+        )
+        .unwrap();
+
+        let programs: Vec<Pubkey> = instructions.iter().map(|ix| ix.program_id).collect();
+        assert_eq!(
+            programs,
+            [ASSOCIATED_TOKEN_PROGRAM_ID, system_program::id(), spl_token::id(), spl_token::id()]
+        );
+    }
+
+    #[test]
+    fn existing_wrapped_sol_account_is_left_open() {
+        let wallet = Pubkey::new_unique();
+        let native_mint = spl_token::native_mint::id();
+
+        // The SOL withdrawn lands next to the wrapped SOL the wallet already held
+        let withdraw = with_token_account(
+            &wallet,
+            &native_mint,
+            &spl_token::id(),
+            TransactionAction::Withdraw,
+            1_000,
+            true,
+            vec![],
+        )
+        .unwrap();
+        assert!(withdraw.is_empty());
+
+        let deposit = with_token_account(
+            &wallet,
+            &native_mint,
+            &spl_token::id(),
+            TransactionAction::Deposit,
+            1_000,
+            true,
+            vec![],
+        )
+        .unwrap();
+        let programs: Vec<Pubkey> = deposit.iter().map(|ix| ix.program_id).collect();
+        assert_eq!(programs, [system_program::id(), spl_token::id()]);
+    }
+
+    #[test]
+    fn serialized_transaction_keeps_payer_signature_empty() {
+        let payer = Pubkey::new_unique();
+        let account = Keypair::new();
+        let instruction = system_instruction::create_account(
+            &payer,
+            &account.pubkey(),
+            1,
+            0,
+            &system_program::id(),
+        );
+
+        let encoded =
+            serialize_transaction(&[instruction], &payer, Hash::default(), &[&account]).unwrap();
+        let transaction: Transaction =
+            bincode::deserialize(&STANDARD.decode(encoded).unwrap()).unwrap();
+
+        assert_eq!(transaction.message.account_keys[0], payer);
+        assert_eq!(transaction.signatures[0], Default::default());
+        assert_ne!(transaction.signatures[1], Default::default());
+    }
+}
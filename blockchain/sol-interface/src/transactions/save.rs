@@ -0,0 +1,342 @@
+use super::{
+    associated_token_address, create_associated_token_account_idempotent, TransactionAction,
+};
+use crate::{
+    common::client_trait::ClientError,
+    save::models::{Obligation, Reserve},
+};
+use solana_program::program_pack::Pack;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, sysvar,
+};
+use std::collections::HashMap;
+
+// Instruction tags of the Save lending program
+const REFRESH_RESERVE: u8 = 3;
+const INIT_OBLIGATION: u8 = 6;
+const REFRESH_OBLIGATION: u8 = 7;
+const BORROW_OBLIGATION_LIQUIDITY: u8 = 10;
+const REPAY_OBLIGATION_LIQUIDITY: u8 = 11;
+const DEPOSIT_RESERVE_LIQUIDITY_AND_OBLIGATION_COLLATERAL: u8 = 14;
+const WITHDRAW_OBLIGATION_COLLATERAL_AND_REDEEM_RESERVE_COLLATERAL: u8 = 15;
+
+/// Accounts of the wallet in a Save market, as found on chain
+#[derive(Default)]
+pub struct SaveUserState {
+    /// The obligation created by the Save app, None when it does not exist yet
+    pub obligation: Option<Obligation>,
+}
+
+/// Seed of the obligation the Save app creates, the start of the market address
+fn obligation_seed(market: &Pubkey) -> String {
+    market.to_string()[..32].to_string()
+}
+
+pub fn obligation_address(program_id: &Pubkey, owner: &Pubkey, market: &Pubkey) -> Pubkey {
+    Pubkey::create_with_seed(owner, &obligation_seed(market), program_id)
+        .expect("Obligation seed fits in 32 bytes")
+}
+
+pub fn lending_market_authority(program_id: &Pubkey, market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[market.as_ref()], program_id).0
+}
+
+fn instruction(program_id: &Pubkey, data: Vec<u8>, accounts: Vec<AccountMeta>) -> Instruction {
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+fn with_amount(tag: u8, amount: u64) -> Vec<u8> {
+    [&[tag][..], &amount.to_le_bytes()].concat()
+}
+
+/// Allocates the obligation account and initializes it
+pub fn create_obligation(program_id: &Pubkey, owner: &Pubkey, market: &Pubkey) -> Vec<Instruction> {
+    let obligation = obligation_address(program_id, owner, market);
+
+    vec![
+        system_instruction::create_account_with_seed(
+            owner,
+            &obligation,
+            owner,
+            &obligation_seed(market),
+            Rent::default().minimum_balance(Obligation::LEN),
+            Obligation::LEN as u64,
+            program_id,
+        ),
+        instruction(
+            program_id,
+            vec![INIT_OBLIGATION],
+            vec![
+                AccountMeta::new(obligation, false),
+                AccountMeta::new_readonly(*market, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        ),
+    ]
+}
+
+pub fn refresh_reserve(program_id: &Pubkey, address: &Pubkey, reserve: &Reserve) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*address, false),
+        AccountMeta::new_readonly(reserve.liquidity.pyth_oracle_pubkey, false),
+        AccountMeta::new_readonly(reserve.liquidity.switchboard_oracle_pubkey, false),
+    ];
+    accounts.extend(
+        reserve.config.extra_oracle_pubkey.map(|oracle| AccountMeta::new_readonly(oracle, false)),
+    );
+
+    instruction(program_id, vec![REFRESH_RESERVE], accounts)
+}
+
+/// Deposit reserves of the obligation followed by its borrow reserves
+fn obligation_reserves(obligation: Option<&Obligation>) -> Vec<Pubkey> {
+    let Some(obligation) = obligation else {
+        return Vec::new();
+    };
+
+    let deposits = obligation.deposits.iter().map(|deposit| deposit.deposit_reserve);
+    deposits.chain(obligation.borrows.iter().map(|borrow| borrow.borrow_reserve)).collect()
+}
+
+pub fn refresh_obligation(
+    program_id: &Pubkey,
+    obligation_address: &Pubkey,
+    obligation: Option<&Obligation>,
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*obligation_address, false)];
+    accounts.extend(
+        obligation_reserves(obligation)
+            .into_iter()
+            .map(|reserve| AccountMeta::new_readonly(reserve, false)),
+    );
+
+    instruction(program_id, vec![REFRESH_OBLIGATION], accounts)
+}
+
+/// The deposit, withdraw, borrow or repay instruction itself, `amount` in collateral tokens for
+/// withdrawals and in liquidity otherwise
+pub fn action_instruction(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    address: &Pubkey,
+    reserve: &Reserve,
+    obligation: Option<&Obligation>,
+    action: TransactionAction,
+    amount: u64,
+) -> Instruction {
+    let market = reserve.lending_market;
+    let market_authority = lending_market_authority(program_id, &market);
+    let obligation_address = obligation_address(program_id, owner, &market);
+    let liquidity = &reserve.liquidity;
+    let collateral = &reserve.collateral;
+    let user_liquidity = associated_token_address(owner, &liquidity.mint_pubkey, &spl_token::id());
+    let user_collateral =
+        associated_token_address(owner, &collateral.mint_pubkey, &spl_token::id());
+
+    match action {
+        TransactionAction::Deposit => instruction(
+            program_id,
+            with_amount(DEPOSIT_RESERVE_LIQUIDITY_AND_OBLIGATION_COLLATERAL, amount),
+            vec![
+                AccountMeta::new(user_liquidity, false),
+                AccountMeta::new(user_collateral, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(liquidity.supply_pubkey, false),
+                AccountMeta::new(collateral.mint_pubkey, false),
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(collateral.supply_pubkey, false),
+                AccountMeta::new(obligation_address, false),
+                AccountMeta::new(*owner, true),
+                AccountMeta::new_readonly(liquidity.pyth_oracle_pubkey, false),
+                AccountMeta::new_readonly(liquidity.switchboard_oracle_pubkey, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        ),
+        TransactionAction::Withdraw => {
+            let mut accounts = vec![
+                AccountMeta::new(collateral.supply_pubkey, false),
+                AccountMeta::new(user_collateral, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(obligation_address, false),
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(user_liquidity, false),
+                AccountMeta::new(collateral.mint_pubkey, false),
+                AccountMeta::new(liquidity.supply_pubkey, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ];
+            // The program values the remaining deposits to check the withdrawal
+            accounts.extend(obligation.into_iter().flat_map(|obligation| {
+                obligation
+                    .deposits
+                    .iter()
+                    .map(|deposit| AccountMeta::new(deposit.deposit_reserve, false))
+            }));
+
+            instruction(
+                program_id,
+                with_amount(WITHDRAW_OBLIGATION_COLLATERAL_AND_REDEEM_RESERVE_COLLATERAL, amount),
+                accounts,
+            )
+        }
+        TransactionAction::Borrow => instruction(
+            program_id,
+            with_amount(BORROW_OBLIGATION_LIQUIDITY, amount),
+            vec![
+                AccountMeta::new(liquidity.supply_pubkey, false),
+                AccountMeta::new(user_liquidity, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(reserve.config.fee_receiver, false),
+                AccountMeta::new(obligation_address, false),
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        ),
+        TransactionAction::Repay => instruction(
+            program_id,
+            with_amount(REPAY_OBLIGATION_LIQUIDITY, amount),
+            vec![
+                AccountMeta::new(user_liquidity, false),
+                AccountMeta::new(liquidity.supply_pubkey, false),
+                AccountMeta::new(*address, false),
+                AccountMeta::new(obligation_address, false),
+                AccountMeta::new_readonly(market, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        ),
+    }
+}
+
+/// Instructions to apply `action` with `amount` liquidity tokens to the reserve at `address`.
+/// The obligation and the collateral token account are created when missing, then every
+/// reserve of the obligation and the obligation itself are refreshed. `reserves` must hold the
+/// target reserve and the reserves of the obligation.
+pub fn build_instructions(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    address: &Pubkey,
+    reserves: &HashMap<Pubkey, &Reserve>,
+    user: &SaveUserState,
+    action: TransactionAction,
+    amount: u64,
+) -> Result<Vec<Instruction>, ClientError> {
+    let find_reserve = |address: &Pubkey| {
+        reserves
+            .get(address)
+            .copied()
+            .ok_or_else(|| ClientError::MarketNotFound(format!("Save reserve {}", address)))
+    };
+    let reserve = find_reserve(address)?;
+    let market = reserve.lending_market;
+    let obligation = user.obligation.as_ref();
+    let mut instructions = Vec::new();
+
+    if obligation.is_none() {
+        if action != TransactionAction::Deposit {
+            return Err(ClientError::AccountNotFound(format!(
+                "No Save obligation for {} in market {}",
+                owner, market
+            )));
+        }
+        instructions.extend(create_obligation(program_id, owner, &market));
+    }
+    // Deposits and withdrawals pass through the reserve's collateral token
+    if matches!(action, TransactionAction::Deposit | TransactionAction::Withdraw) {
+        instructions.push(create_associated_token_account_idempotent(
+            owner,
+            &reserve.collateral.mint_pubkey,
+            &spl_token::id(),
+        ));
+    }
+
+    let mut refreshed = obligation_reserves(obligation);
+    refreshed.push(*address);
+    refreshed.sort_unstable();
+    refreshed.dedup();
+    for reserve_address in &refreshed {
+        instructions.push(refresh_reserve(
+            program_id,
+            reserve_address,
+            find_reserve(reserve_address)?,
+        ));
+    }
+    instructions.push(refresh_obligation(
+        program_id,
+        &obligation_address(program_id, owner, &market),
+        obligation,
+    ));
+
+    // Withdrawals are made in collateral tokens
+    let amount = match action {
+        TransactionAction::Withdraw => reserve
+            .collateral_exchange_rate()
+            .and_then(|rate| rate.liquidity_to_collateral(amount))
+            .map_err(|e| ClientError::ProtocolError(e.to_string()))?,
+        _ => amount,
+    };
+    instructions
+        .push(action_instruction(program_id, owner, address, reserve, obligation, action, amount));
+
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obligation_seed_is_the_start_of_the_market() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+
+        let create = &create_obligation(&program_id, &owner, &market)[0];
+        assert_eq!(create.accounts[1].pubkey, obligation_address(&program_id, &owner, &market));
+        assert_eq!(obligation_seed(&market).len(), 32);
+    }
+
+    #[test]
+    fn borrow_refreshes_every_obligation_reserve() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (collateral, borrowed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let reserve = Reserve::default();
+        let reserves = HashMap::from([(collateral, &reserve), (borrowed, &reserve)]);
+
+        let mut obligation = Obligation::default();
+        obligation.deposits.push(Default::default());
+        obligation.deposits[0].deposit_reserve = collateral;
+        let user = SaveUserState { obligation: Some(obligation) };
+
+        let instructions = build_instructions(
+            &program_id,
+            &owner,
+            &borrowed,
+            &reserves,
+            &user,
+            TransactionAction::Borrow,
+            500,
+        )
+        .unwrap();
+
+        let tags: Vec<u8> = instructions.iter().map(|ix| ix.data[0]).collect();
+        assert_eq!(
+            tags,
+            [REFRESH_RESERVE, REFRESH_RESERVE, REFRESH_OBLIGATION, BORROW_OBLIGATION_LIQUIDITY]
+        );
+        assert_eq!(instructions[2].accounts[1].pubkey, collateral);
+        assert_eq!(instructions[3].data[1..], 500u64.to_le_bytes());
+    }
+}
@@ -1,10 +1,11 @@
-use crate::UserObligation;
+use crate::{ErrorKind, UserObligation};
+use std::future::Future;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum LendingError {
     #[error("RPC error: {0}")]
-    RpcError(#[from] Box<dyn std::error::Error>),
+    RpcError(#[from] Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Account deserialization error: {0}")]
     DeserializationError(String),
@@ -22,16 +23,31 @@ pub enum LendingError {
     ProtocolError(String),
 }
 
+impl LendingError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            LendingError::RpcError(_) => ErrorKind::Rpc,
+            LendingError::DeserializationError(_) => ErrorKind::Deserialization,
+            LendingError::InvalidAddress(_) => ErrorKind::InvalidPubkey,
+            LendingError::MarketNotFound(_) => ErrorKind::MarketNotFound,
+            LendingError::AccountNotFound(_) => ErrorKind::AccountNotFound,
+            LendingError::ProtocolError(_) => ErrorKind::Protocol,
+        }
+    }
+}
+
+/// A lending protocol client. Fetches go through the nonblocking RPC client, so many of them can
+/// run concurrently on the same runtime.
 pub trait LendingClient<Address, MarketData> {
     /// Loads markets into the client's internal state
-    fn load_markets(&mut self) -> Result<(), LendingError> {
+    fn load_markets(&mut self) -> impl Future<Output = Result<(), LendingError>> + Send {
         // Default implementation that can be overridden
-        Ok(())
+        async { Ok(()) }
     }
 
     /// Fetches markets without modifying the client's state
     /// Returns the market data that can be used to update the client's state
-    fn fetch_markets(&self) -> Result<MarketData, LendingError>;
+    fn fetch_markets(&self) -> impl Future<Output = Result<MarketData, LendingError>> + Send;
 
     /// Updates the client's state with the fetched market data
     fn set_market_data(&mut self, data: MarketData);
@@ -39,7 +55,7 @@ pub trait LendingClient<Address, MarketData> {
     fn get_user_obligations(
         &self,
         wallet_address: &str,
-    ) -> Result<Vec<UserObligation>, LendingError>;
+    ) -> impl Future<Output = Result<Vec<UserObligation>, LendingError>> + Send;
 
     fn program_id(&self) -> Address;
 
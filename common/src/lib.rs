@@ -9,24 +9,249 @@ pub use lending::*;
 pub struct LendingReserve {
     pub protocol_name: String,
     pub market_name: String,
+    // Address of the reserve (the bank for Marginfi, the spot market for Drift) and of its lending
+    // market (the group for Marginfi, the program state for Drift), stable across renames
+    pub reserve_pubkey: String,
+    pub market_pubkey: String,
     pub total_supply: u128,
     pub total_borrows: u128,
+    // Caps in the same unit as total_supply / total_borrows, None when the protocol sets no cap
+    pub deposit_cap: Option<u128>,
+    pub borrow_cap: Option<u128>,
+    // Amount that can still be deposited / borrowed before hitting the cap
+    pub remaining_deposit_capacity: Option<u128>,
+    pub remaining_borrow_capacity: Option<u128>,
 
     pub borrow_rate: u128,
     pub supply_rate: u128,
     pub borrow_apy: u128,
     pub supply_apy: u128,
+    // APY paid to suppliers in reward tokens, same unit as supply_apy, None when the rewards are
+    // unknown: a reward token has no price, or the protocol's rewards are not tracked (Save, Drift)
+    pub reward_apy: Option<u128>,
+    // APY paid to borrowers in reward tokens, lowering the cost of borrowing, None when unknown
+    pub borrow_reward_apy: Option<u128>,
+    // supply_apy + reward_apy, supply_apy alone when the rewards are unknown
+    pub total_apy: u128,
 
     pub slot: u64,
 
-    // i think we need to know the collateral assets available for each reserve
-    pub collateral_assets: Vec<MintAsset>,
+    // Other assets that can be deposited to borrow from this reserve
+    pub collateral_assets: Vec<CollateralAsset>,
+    // Liquidity a supplier can take out, now and over time
+    pub exit_liquidity: ExitLiquidity,
+    // Terms of borrowing against and from this reserve
+    pub risk: RiskParameters,
 }
 
+/// Risk parameters of a reserve, ratios as fractions (0.75 = 75%)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RiskParameters {
+    /// Share of a deposit's value that can be borrowed against
+    pub loan_to_value: f64,
+    /// Share of a deposit's value that can be borrowed before the position can be liquidated
+    pub liquidation_threshold: f64,
+    /// Share of the repaid value liquidators get on top of it, the highest one where it grows with
+    /// the shortfall
+    pub liquidation_bonus: f64,
+    /// Weight of a borrow's value against the borrow limit, 1.0 when it counts at its value
+    pub borrow_factor: f64,
+    pub isolation_tier: IsolationTier,
+    pub status: ReserveStatus,
+}
+
+/// Which positions a reserve can be part of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationTier {
+    /// Can be deposited and borrowed alongside any other reserve
+    Cross,
+    /// Can only be deposited as the sole collateral of a position
+    IsolatedCollateral,
+    /// Can only be borrowed as the sole debt of a position, and not used as collateral
+    IsolatedDebt,
+}
+
+/// Operations a reserve currently accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReserveStatus {
+    Active,
+    /// Only withdrawals and repayments are accepted
+    ReduceOnly,
+    /// No operation is accepted for now
+    Paused,
+    /// The reserve is being wound down and will not accept deposits or borrows again
+    Delisted,
+}
+
+/// An asset accepted as collateral when borrowing from a reserve
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollateralAsset {
+    pub name: String,
+    pub symbol: String,
+    pub mint: String,
+    /// Loan to value ratio of the pairing (0.75 = 75%), including the borrow weight of the reserve
+    pub loan_to_value: f64,
+    /// Loan to value ratio above which the pairing can be liquidated
+    pub liquidation_threshold: f64,
+}
+
+/// Liquidity suppliers can withdraw from a reserve, amounts in the same unit as total_supply
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExitLiquidity {
+    /// Liquidity not lent out
+    pub available_liquidity: u128,
+    /// Part of the available liquidity that can be withdrawn in the current slot
+    pub withdrawable_now: u128,
+    /// Outflow limit slowing down withdrawals, None when the protocol sets none
+    pub outflow_cap: Option<OutflowCap>,
+}
+
+/// A limit on the amount withdrawn from a reserve per time window
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OutflowCap {
+    /// Amount that can be withdrawn per window
+    pub max_outflow: u128,
+    pub window_seconds: u64,
+    /// Seconds until the current window ends
+    pub next_refill_seconds: u64,
+    /// For a sliding window, the amount that frees up evenly until the current window ends, after
+    /// which `max_outflow` frees up evenly over every window. None for a window refilling all at
+    /// once when it ends.
+    pub sliding_refill: Option<u128>,
+}
+
+impl ExitLiquidity {
+    /// Seconds to wait before `amount` has been withdrawn, withdrawing as much as allowed as soon
+    /// as it frees up. None when the reserve does not hold that much liquidity.
+    pub fn seconds_to_withdraw(&self, amount: u128) -> Option<u64> {
+        if amount > self.available_liquidity {
+            return None;
+        }
+        if amount <= self.withdrawable_now {
+            return Some(0);
+        }
+
+        let cap = self.outflow_cap?;
+        if cap.max_outflow == 0 {
+            return None;
+        }
+        let mut missing = amount - self.withdrawable_now;
+
+        let Some(window_refill) = cap.sliding_refill else {
+            let windows = missing.div_ceil(cap.max_outflow);
+            let extra_windows = u64::try_from(windows - 1).unwrap_or(u64::MAX);
+
+            return Some(
+                cap.next_refill_seconds
+                    .saturating_add(extra_windows.saturating_mul(cap.window_seconds)),
+            );
+        };
+
+        // Seconds until `missing` frees up at `refill` per `seconds`
+        let pro_rata = |missing: u128, refill: u128, seconds: u64| -> u64 {
+            let seconds = (missing.saturating_mul(seconds as u128)).div_ceil(refill);
+            u64::try_from(seconds).unwrap_or(u64::MAX)
+        };
+
+        if missing <= window_refill {
+            return Some(pro_rata(missing, window_refill, cap.next_refill_seconds));
+        }
+        missing -= window_refill;
+
+        Some(cap.next_refill_seconds.saturating_add(pro_rata(
+            missing,
+            cap.max_outflow,
+            cap.window_seconds,
+        )))
+    }
+}
+
+/// Rates of a reserve before and after a hypothetical deposit or borrow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateImpact {
+    pub protocol_name: String,
+    pub market_name: String,
+    pub mint: String,
+    pub before: ReserveRates,
+    pub after: ReserveRates,
+}
+
+/// Liquidity and rates of a reserve, in the units of `LendingReserve`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveRates {
+    pub total_supply: u128,
+    pub total_borrows: u128,
+    pub supply_rate: u128,
+    pub borrow_rate: u128,
+    pub supply_apy: u128,
+    pub borrow_apy: u128,
+}
+
+impl From<&LendingReserve> for ReserveRates {
+    fn from(reserve: &LendingReserve) -> Self {
+        Self {
+            total_supply: reserve.total_supply,
+            total_borrows: reserve.total_borrows,
+            supply_rate: reserve.supply_rate,
+            borrow_rate: reserve.borrow_rate,
+            supply_apy: reserve.supply_apy,
+            borrow_apy: reserve.borrow_apy,
+        }
+    }
+}
+
+/// Split of a deposit across reserves maximising the blended supply APY
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationPlan {
+    pub mint: String,
+    // Amounts in native token units
+    pub amount: u64,
+    // Part of the amount no reserve could take under the constraints
+    pub unallocated: u64,
+    // Supply APY earned on the allocated amount, same unit as LendingReserve.supply_apy
+    pub blended_supply_apy: u128,
+    // Every reserve considered, including the ones left without a deposit
+    pub allocations: Vec<ReserveAllocation>,
+}
+
+/// Deposit planned into one reserve
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveAllocation {
+    pub protocol_name: String,
+    pub market_name: String,
+    pub amount: u64,
+    /// Supply APY of the reserve once the deposit is made
+    pub supply_apy: u128,
+    /// Yield added by depositing one more token, net of the rate drop it causes on the deposit
+    pub marginal_rate: u128,
+}
+
+/// A transaction built for a wallet to sign and send
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsignedTransaction {
+    pub protocol_name: String,
+    pub market_name: String,
+    pub mint: String,
+    // deposit, withdraw, borrow or repay
+    pub action: String,
+    // Amount in native token units
+    pub amount: u64,
+    // Base64 of the bincode serialized transaction, fee payer signature left empty
+    pub transaction: String,
+    // Blockhash the transaction expires with
+    pub recent_blockhash: String,
+}
+
+/// Number of decimals of `MintAsset.market_price_sf`, a USD price of 1.5 is 1_500_000_000
+pub const MARKET_PRICE_DECIMALS: u32 = 9;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MintAsset {
     pub name: String,
     pub symbol: String,
+    // USD price scaled by 10^MARKET_PRICE_DECIMALS, 0 when no oracle price is available
     pub market_price_sf: u64,
     pub mint: String,
     pub lending_reserves: Vec<LendingReserve>,
@@ -52,9 +277,74 @@ pub struct UserObligation {
     pub amount: u64,
     pub protocol_name: String,
     pub market_name: String,
+    /// Address of the account holding the position: the obligation, margin account or Drift user
+    pub account: String,
+    /// Kamino obligation tag or Drift sub-account id, None for protocols without either
+    pub sub_account: Option<u64>,
+    /// Address of the reserve, Marginfi bank or Drift spot market the position is in
+    pub reserve_pubkey: String,
     pub obligation_type: ObligationType,
 }
 
+/// Risk summary of a single borrowing account, e.g. a Kamino obligation or Marginfi account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObligationHealth {
+    pub protocol_name: String,
+    pub market_name: String,
+    /// Address of the obligation or margin account
+    pub account: String,
+    /// USD value of the deposits
+    pub deposited_value: f64,
+    /// USD value of the borrows, borrow factor adjusted where the protocol applies one
+    pub borrowed_value: f64,
+    /// Current loan to value ratio (0.5 = 50%)
+    pub loan_to_value: f64,
+    /// Loan to value ratio above which no new borrows are allowed
+    pub max_loan_to_value: f64,
+    /// Loan to value ratio above which the account can be liquidated
+    pub liquidation_loan_to_value: f64,
+    /// USD that can still be borrowed before reaching the max loan to value
+    pub remaining_borrow_value: f64,
+    /// Liquidation threshold value divided by debt, None when there is no debt
+    pub health_factor: Option<f64>,
+    /// Kamino elevation group the obligation is in, if any
+    pub elevation_group: Option<u8>,
+    /// Weighted assets minus weighted liabilities at the initial requirement, for Marginfi
+    /// and Drift accounts. Borrows and withdrawals are blocked when it reaches zero.
+    pub initial_health: Option<f64>,
+    /// Weighted assets minus weighted liabilities at the maintenance requirement, for Marginfi
+    /// and Drift accounts. The account can be liquidated when it drops below zero.
+    pub maintenance_health: Option<f64>,
+    /// Largest amount of each deposit that can be withdrawn without exceeding the max loan to
+    /// value, empty when the protocol doesn't expose it
+    pub max_withdraw: Vec<WithdrawLimit>,
+    /// Cross margin breakdown for protocols that also hold perp positions (Drift)
+    pub margin: Option<MarginSummary>,
+}
+
+/// Collateral and margin requirement of a cross margin account, in USD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarginSummary {
+    /// Weighted deposits plus weighted unrealized perp pnl, at the initial requirement
+    pub total_collateral: f64,
+    pub initial_margin_requirement: f64,
+    pub maintenance_margin_requirement: f64,
+    /// Total collateral left after the initial margin requirement
+    pub free_collateral: f64,
+    /// From 100 (no maintenance requirement) down to 0, where the account can be liquidated
+    pub health: u8,
+}
+
+/// Maximum withdrawable amount of a single deposit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawLimit {
+    pub symbol: String,
+    pub mint: String,
+    pub mint_decimals: u32,
+    /// Amount in the token's native units
+    pub amount: u64,
+}
+
 /// Represents a token balance for a specific wallet
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenBalance {
@@ -91,3 +381,166 @@ pub enum RiskLevel {
     Medium,
     High,
 }
+
+/// Health of one endpoint of a failover RPC pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcEndpointHealth {
+    /// Endpoint URL without its path and query, which may hold an API key
+    pub url: String,
+    /// Preference for the endpoint relative to the others, which its routing score is multiplied
+    /// by. Requests go to the top ranked endpoint, the weight does not split the traffic.
+    pub weight: f64,
+    /// Whether requests try the endpoint before the unhealthy ones
+    pub healthy: bool,
+    /// Routing score from the weight, latency and error rate, higher is tried first
+    pub score: f64,
+    /// Moving average of the request latency in milliseconds, None before the first request
+    pub latency_ms: Option<f64>,
+    /// Moving average of the share of failed requests
+    pub error_rate: f64,
+    /// Latest slot reported by the endpoint
+    pub slot: Option<u64>,
+    /// Slots behind the most advanced endpoint
+    pub slot_lag: u64,
+    pub requests: u64,
+    pub errors: u64,
+    /// Requests held back by the endpoint's rate limit
+    pub throttled: u64,
+    pub last_error: Option<String>,
+}
+
+/// Retries and throttling of the outbound RPC calls since the process started
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpcCounters {
+    /// Failed calls that were attempted again
+    pub retries: u64,
+    /// Calls that still failed after their last retry
+    pub retries_exhausted: u64,
+    /// Requests held back by an endpoint's rate limit
+    pub throttled: u64,
+    /// Total time requests were held back, in milliseconds
+    pub throttled_ms: u64,
+}
+
+/// Kind of failure of a request or of one protocol's part of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The RPC node could not be reached, timed out or failed the call
+    Rpc,
+    /// An account could not be decoded
+    Deserialization,
+    InvalidPubkey,
+    MarketNotFound,
+    AccountNotFound,
+    /// The protocol rejected the request
+    Protocol,
+    Other,
+}
+
+impl ErrorKind {
+    /// Message reported to clients for an error of this kind. The error itself stays in the logs,
+    /// as the text of RPC errors holds the endpoint URL and its API key.
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorKind::Rpc => "The RPC node could not be reached or failed the request",
+            ErrorKind::Deserialization => "An account could not be decoded",
+            ErrorKind::InvalidPubkey => "Invalid public key",
+            ErrorKind::MarketNotFound => "Market not found",
+            ErrorKind::AccountNotFound => "Account not found",
+            ErrorKind::Protocol => "The protocol rejected the request",
+            ErrorKind::Other => "Internal error",
+        }
+    }
+}
+
+/// Body of a failed request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl From<ErrorKind> for ApiError {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, message: kind.message().to_string() }
+    }
+}
+
+/// State of one protocol's part of a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolState {
+    Ok,
+    /// Some markets of the protocol failed to load, the data holds the others
+    Partial,
+    Failed,
+    /// The protocol failed and its data from an earlier read is served instead
+    Stale,
+}
+
+/// Whether one protocol's part of a response succeeded, and why it did not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolStatus {
+    pub protocol: String,
+    pub state: ProtocolState,
+    /// Slot the protocol's data was read at, None when it has no data
+    pub slot: Option<u64>,
+    pub error_kind: Option<ErrorKind>,
+    pub error: Option<String>,
+}
+
+impl ProtocolStatus {
+    pub fn ok(protocol: &str, slot: Option<u64>) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            state: ProtocolState::Ok,
+            slot,
+            error_kind: None,
+            error: None,
+        }
+    }
+
+    pub fn failed(protocol: &str, error_kind: ErrorKind) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            state: ProtocolState::Failed,
+            slot: None,
+            error_kind: Some(error_kind),
+            error: Some(error_kind.message().to_string()),
+        }
+    }
+
+    /// `skipped` markets of the protocol failed to load with an error of `error_kind`
+    pub fn partial(
+        protocol: &str,
+        slot: Option<u64>,
+        error_kind: ErrorKind,
+        skipped: usize,
+    ) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            state: ProtocolState::Partial,
+            slot,
+            error_kind: Some(error_kind),
+            error: Some(format!("{} markets failed to load: {}", skipped, error_kind.message())),
+        }
+    }
+}
+
+/// A response holding the data of the protocols that succeeded and the status of every protocol,
+/// so an empty result can be told apart from a failed one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseEnvelope<T> {
+    pub data: T,
+    /// Slot the data was read at
+    pub slot: Option<u64>,
+    pub protocols: Vec<ProtocolStatus>,
+}
+
+impl<T> ResponseEnvelope<T> {
+    /// Apply `f` to the data, keeping the slot and the protocol statuses
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> ResponseEnvelope<U> {
+        ResponseEnvelope { data: f(self.data), slot: self.slot, protocols: self.protocols }
+    }
+}
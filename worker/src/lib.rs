@@ -1,7 +1,7 @@
 use anyhow::Result;
 use chrono::Utc;
-use common::{LendingReserve, MintAsset};
-use log::{debug, error, info};
+use common::{LendingReserve, MintAsset, ProtocolState, ResponseEnvelope};
+use log::{debug, error, info, warn};
 use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
 use std::path::Path;
 use tokio::fs;
@@ -20,11 +20,32 @@ impl Worker {
         info!("Successfully connected to database at {}", db_url);
 
         // Initialize tables
+        info!("Creating reserves table if it doesn't exist...");
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS reserves (
+                reserve_pubkey VARCHAR(64) PRIMARY KEY,
+                market_pubkey VARCHAR(64) NOT NULL,
+                protocol_name VARCHAR(64) NOT NULL,
+                market_name VARCHAR(64) NOT NULL,
+                token_name VARCHAR(64) NOT NULL,
+                token_symbol VARCHAR(10) NOT NULL,
+                token_mint VARCHAR(64) NOT NULL,
+                first_seen DATETIME NOT NULL,
+                last_seen DATETIME NOT NULL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        info!("Successfully created/verified reserves table schema");
+
         info!("Creating lending_markets table if it doesn't exist...");
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS lending_markets (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reserve_pubkey VARCHAR(64) REFERENCES reserves (reserve_pubkey),
                 protocol_name VARCHAR(64) NOT NULL,
                 market_name VARCHAR(64) NOT NULL,
                 token_name VARCHAR(64) NOT NULL,
@@ -44,6 +65,7 @@ impl Worker {
         )
         .execute(&pool)
         .await?;
+        Worker::migrate_lending_markets(&pool).await?;
         info!("Successfully created/verified lending_markets table schema");
 
         // Create users table
@@ -72,6 +94,37 @@ impl Worker {
         Ok(Self { db_pool: pool, schedule })
     }
 
+    /// Keys the rows of lending_markets by reserve and slot, adding the reserve column to tables
+    /// created before reserves had an identity. Their rows keep a NULL reserve, which the unique
+    /// index leaves alone.
+    async fn migrate_lending_markets(pool: &Pool<Sqlite>) -> Result<()> {
+        let has_reserve_column: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('lending_markets') \
+             WHERE name = 'reserve_pubkey'",
+        )
+        .fetch_one(pool)
+        .await?;
+
+        if !has_reserve_column {
+            info!("Adding reserve_pubkey column to lending_markets...");
+            sqlx::query(
+                "ALTER TABLE lending_markets ADD COLUMN reserve_pubkey VARCHAR(64) \
+                 REFERENCES reserves (reserve_pubkey)",
+            )
+            .execute(pool)
+            .await?;
+        }
+
+        sqlx::query(
+            "CREATE UNIQUE INDEX IF NOT EXISTS lending_markets_reserve_slot \
+             ON lending_markets (reserve_pubkey, slot)",
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
     async fn load_sample_data(pool: &Pool<Sqlite>) -> Result<()> {
         // First check if the table is empty
         info!("Checking if lending_markets table needs sample data...");
@@ -137,11 +190,38 @@ impl Worker {
                 debug!("Fetching current lending markets...");
                 // Fetch latest market data from API
                 match client.get("http://localhost:3000/current_lending_markets").send().await {
-                    Ok(response) => match response.json::<Vec<MintAsset>>().await {
-                        Ok(assets) => {
+                    Ok(response) => match response.json::<ResponseEnvelope<Vec<MintAsset>>>().await
+                    {
+                        Ok(envelope) => {
+                            // Stale reserves were stored when they were read, failed ones have none
+                            let is_fresh = |protocol: &str| {
+                                envelope.protocols.iter().any(|status| {
+                                    status.protocol == protocol
+                                        && matches!(
+                                            status.state,
+                                            ProtocolState::Ok | ProtocolState::Partial
+                                        )
+                                })
+                            };
+                            for status in &envelope.protocols {
+                                let error = status.error.as_deref().unwrap_or("no error");
+                                if !is_fresh(&status.protocol) {
+                                    error!("Skipping {} reserves: {}", status.protocol, error);
+                                } else if status.state == ProtocolState::Partial {
+                                    warn!(
+                                        "Storing partial {} reserves: {}",
+                                        status.protocol, error
+                                    );
+                                }
+                            }
+
                             let mut total_reserves = 0;
-                            for asset in &assets {
-                                for reserve in &asset.lending_reserves {
+                            for asset in &envelope.data {
+                                for reserve in asset
+                                    .lending_reserves
+                                    .iter()
+                                    .filter(|r| is_fresh(&r.protocol_name))
+                                {
                                     if let Err(e) =
                                         store_market_data(&db_pool, asset, reserve).await
                                     {
@@ -170,22 +250,63 @@ impl Worker {
     }
 }
 
-// Move store_market_data to a standalone function since we can't easily clone self
+/// Upsert the reserve into the reserves table and its snapshot at the reserve's slot, so storing
+/// the same snapshot again overwrites it instead of adding a row
 async fn store_market_data(
     db_pool: &Pool<Sqlite>,
     asset: &MintAsset,
     reserve: &LendingReserve,
 ) -> Result<()> {
+    let now = Utc::now();
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO reserves (
+            reserve_pubkey, market_pubkey, protocol_name, market_name, token_name, token_symbol,
+            token_mint, first_seen, last_seen
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (reserve_pubkey) DO UPDATE SET
+            market_pubkey = excluded.market_pubkey,
+            market_name = excluded.market_name,
+            token_name = excluded.token_name,
+            token_symbol = excluded.token_symbol,
+            last_seen = excluded.last_seen
+        "#,
+    )
+    .bind(&reserve.reserve_pubkey)
+    .bind(&reserve.market_pubkey)
+    .bind(&reserve.protocol_name)
+    .bind(&reserve.market_name)
+    .bind(&asset.name)
+    .bind(&asset.symbol)
+    .bind(&asset.mint)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
     sqlx::query(
         r#"
         INSERT INTO lending_markets (
-            protocol_name, market_name, token_name, token_symbol, token_mint,
+            reserve_pubkey, protocol_name, market_name, token_name, token_symbol, token_mint,
             market_price, total_supply, total_borrows, borrow_rate, supply_rate,
             borrow_apy, supply_apy, slot, timestamp
         )
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (reserve_pubkey, slot) DO UPDATE SET
+            market_name = excluded.market_name,
+            market_price = excluded.market_price,
+            total_supply = excluded.total_supply,
+            total_borrows = excluded.total_borrows,
+            borrow_rate = excluded.borrow_rate,
+            supply_rate = excluded.supply_rate,
+            borrow_apy = excluded.borrow_apy,
+            supply_apy = excluded.supply_apy
         "#,
     )
+    .bind(&reserve.reserve_pubkey)
     .bind(&reserve.protocol_name)
     .bind(&reserve.market_name)
     .bind(&asset.name)
@@ -199,16 +320,18 @@ async fn store_market_data(
     .bind(reserve.borrow_apy.to_string())
     .bind(reserve.supply_apy.to_string())
     .bind(reserve.slot as i64)
-    .bind(Utc::now())
-    .execute(db_pool)
+    .bind(now)
+    .execute(&mut *tx)
     .await?;
 
+    tx.commit().await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use common::{IsolationTier, ReserveStatus, RiskParameters};
 
     #[tokio::test]
     async fn test_database_initialization() {
@@ -224,4 +347,74 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    fn usdc_pool(reserve_pubkey: &str, slot: u64) -> LendingReserve {
+        LendingReserve {
+            protocol_name: "Drift".to_string(),
+            market_name: "USDC".to_string(),
+            reserve_pubkey: reserve_pubkey.to_string(),
+            market_pubkey: "state".to_string(),
+            total_supply: 1_000,
+            total_borrows: 500,
+            deposit_cap: None,
+            borrow_cap: None,
+            remaining_deposit_capacity: None,
+            remaining_borrow_capacity: None,
+            borrow_rate: 10,
+            supply_rate: 5,
+            borrow_apy: 10,
+            supply_apy: 5,
+            reward_apy: None,
+            borrow_reward_apy: None,
+            total_apy: 5,
+            slot,
+            collateral_assets: vec![],
+            exit_liquidity: Default::default(),
+            risk: RiskParameters {
+                loan_to_value: 0.8,
+                liquidation_threshold: 0.9,
+                liquidation_bonus: 0.05,
+                borrow_factor: 1.0,
+                isolation_tier: IsolationTier::Cross,
+                status: ReserveStatus::Active,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_market_data_upserts_by_reserve_and_slot() {
+        let worker = Worker::new("sqlite::memory:", "".to_string()).await.unwrap();
+        let asset = MintAsset {
+            name: "USD Coin".to_string(),
+            symbol: "USDC".to_string(),
+            market_price_sf: 1,
+            mint: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".to_string(),
+            lending_reserves: vec![],
+        };
+
+        // Two pools sharing protocol, market name and mint, one of them stored twice
+        for reserve in
+            [usdc_pool("pool-0", 100), usdc_pool("pool-0", 100), usdc_pool("pool-1", 100)]
+        {
+            store_market_data(&worker.db_pool, &asset, &reserve).await.unwrap();
+        }
+        let mut updated = usdc_pool("pool-0", 100);
+        updated.supply_rate = 7;
+        store_market_data(&worker.db_pool, &asset, &updated).await.unwrap();
+
+        let reserves: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reserves")
+            .fetch_one(&worker.db_pool)
+            .await
+            .unwrap();
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT reserve_pubkey, supply_rate FROM lending_markets \
+             WHERE reserve_pubkey IS NOT NULL ORDER BY reserve_pubkey",
+        )
+        .fetch_all(&worker.db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(reserves, 2);
+        assert_eq!(rows, [("pool-0".to_string(), 7), ("pool-1".to_string(), 5)]);
+    }
 }